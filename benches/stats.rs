@@ -0,0 +1,115 @@
+//! Recording throughput of the sharded request statistics versus a single global mutex
+//
+// Run with `cargo bench --bench stats`. Every thread stands in for a VU and records
+// requests as fast as it can, which is the worst case for lock contention.
+
+use forgy::stats::{ByteCounts, RequestRecord, Stats};
+use forgy::timing::PhaseTimings;
+use hdrhistogram::Histogram;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REQUESTS_PER_THREAD: usize = 200_000;
+
+// The previous layout: every counter and the histogram behind one lock each
+struct GlobalMutexStats {
+    histogram: Mutex<Histogram<u64>>,
+    status_codes: Mutex<HashMap<u16, usize>>,
+    total_requests: Mutex<usize>,
+    successful_requests: Mutex<usize>,
+    bytes_sent: Mutex<u64>,
+    bytes_received: Mutex<u64>,
+}
+
+impl GlobalMutexStats {
+    fn record(&self, request: &RequestRecord<'_>) {
+        self.histogram.lock().record(request.duration_micros).ok();
+        *self
+            .status_codes
+            .lock()
+            .entry(request.status_code)
+            .or_insert(0) += 1;
+        *self.total_requests.lock() += 1;
+        if request.success {
+            *self.successful_requests.lock() += 1;
+        }
+        *self.bytes_sent.lock() += request.bytes.sent();
+        *self.bytes_received.lock() += request.bytes.received();
+    }
+}
+
+fn run(
+    threads: usize,
+    record: impl Fn(usize, &RequestRecord<'_>) + Send + Sync + 'static,
+) -> Duration {
+    let record = Arc::new(record);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|vu| {
+            let record = record.clone();
+            thread::spawn(move || {
+                let phases = PhaseTimings::default();
+                for i in 0..REQUESTS_PER_THREAD {
+                    record(
+                        vu,
+                        &RequestRecord {
+                            target: "http://localhost/",
+                            endpoint: "GET /",
+                            status_code: 200,
+                            success: true,
+                            error_type: None,
+                            duration_micros: 1_000 + (i % 5_000) as u64,
+                            retry: false,
+                            bytes: ByteCounts {
+                                header_sent: 160,
+                                body_sent: 20,
+                                header_received: 200,
+                                body_received: 312,
+                                decoded_body_sent: 20,
+                                decoded_body_received: 312,
+                            },
+                            phases: &phases,
+                        },
+                    );
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>16} {:>16} {:>8}",
+        "threads", "global mutex", "sharded", "speedup"
+    );
+    for threads in [1, 4, 16, 64] {
+        let global = Arc::new(GlobalMutexStats {
+            histogram: Mutex::new(Histogram::new(3).unwrap()),
+            status_codes: Mutex::new(HashMap::new()),
+            total_requests: Mutex::new(0),
+            successful_requests: Mutex::new(0),
+            bytes_sent: Mutex::new(0),
+            bytes_received: Mutex::new(0),
+        });
+        let global_elapsed = run(threads, move |_, request| global.record(request));
+
+        let sharded = Arc::new(Stats::with_default_shards());
+        let sharded_elapsed = run(threads, move |vu, request| sharded.record(vu, request));
+
+        let total = (threads * REQUESTS_PER_THREAD) as f64;
+        println!(
+            "{:>8} {:>11.0} ns/op {:>11.0} ns/op {:>7.1}x",
+            threads,
+            global_elapsed.as_nanos() as f64 / total,
+            sharded_elapsed.as_nanos() as f64 / total,
+            global_elapsed.as_secs_f64() / sharded_elapsed.as_secs_f64()
+        );
+    }
+}
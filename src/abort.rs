@@ -0,0 +1,57 @@
+//! `--abort-on`: stop a run early once the target is clearly failing
+//
+// A condition is a threshold expression over one second of the time series, e.g.
+// "error_rate>50% for 30s": the run is aborted once every one of the last 30 seconds met it.
+// Only the metrics the time series samples can be used: error_rate, success_rate, p95, rps
+// and requests (per second).
+
+use crate::thresholds::Threshold;
+use crate::timeseries::TimeseriesPoint;
+use std::time::Duration;
+
+// Process exit code of an aborted run (threshold failures exit with 99)
+pub const ABORTED_EXIT_CODE: i32 = 98;
+
+#[derive(Debug, Clone)]
+pub struct AbortCondition {
+    pub expression: String,
+    threshold: Threshold,
+    // Consecutive seconds the condition must hold
+    seconds: usize,
+}
+
+impl AbortCondition {
+    // "EXPR" or "EXPR for DURATION"; without a duration a single second meeting it aborts
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let (condition, window) = match expression.rsplit_once(" for ") {
+            Some((condition, window)) => (
+                condition,
+                humantime::parse_duration(window.trim())
+                    .map_err(|e| format!("invalid duration in '{}': {}", expression, e))?,
+            ),
+            None => (expression, Duration::from_secs(1)),
+        };
+        let threshold = Threshold::parse(condition).map_err(|e| e.to_string())?;
+        if threshold.point_value(&TimeseriesPoint::default()).is_none() {
+            return Err(format!(
+                "--abort-on supports error_rate, success_rate, p95, rps and requests, got '{}'",
+                condition.trim()
+            ));
+        }
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            threshold,
+            seconds: (window.as_secs_f64().ceil() as usize).max(1),
+        })
+    }
+
+    // Whether the condition held for each of the last seconds of its window
+    pub fn triggered(&self, points: &[TimeseriesPoint]) -> bool {
+        points.len() >= self.seconds
+            && points[points.len() - self.seconds..].iter().all(|point| {
+                self.threshold
+                    .point_value(point)
+                    .is_some_and(|actual| self.threshold.compare(actual))
+            })
+    }
+}
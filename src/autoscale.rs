@@ -0,0 +1,203 @@
+//! `forgy autoscale`: find the highest load the target sustains within an SLO
+//
+// The load (VUs, or requests per second with --rps) is raised by a fixed step after every
+// step that met the SLO. After the first step that violated it, the gap between the last
+// passing and the first failing load is halved a few times, and the highest passing load
+// is reported with the throughput it achieved. --vus (or --rps) is the ceiling.
+
+use crate::thresholds::{Measurements, Threshold};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct AutoscaleOptions {
+    /// Objectives every step must meet, comma-separated, e.g. "p95<300ms,error_rate<1%"
+    #[clap(long, value_name = "EXPRS", value_parser = Slo::parse)]
+    pub slo: Slo,
+
+    /// Load of the first step: VUs, or requests per second with --rps
+    #[clap(long, value_name = "LOAD", default_value = "10")]
+    pub start: f64,
+
+    /// Load added after every step that met the SLO
+    #[clap(long, value_name = "LOAD", default_value = "10")]
+    pub step: f64,
+
+    /// How long each step runs
+    #[clap(long, value_name = "DURATION", default_value = "30s", value_parser = humantime::parse_duration)]
+    pub step_duration: Duration,
+
+    /// Steps halving the gap between the last passing and the first failing load
+    #[clap(long, value_name = "N", default_value = "3")]
+    pub refine: u32,
+}
+
+impl AutoscaleOptions {
+    // VUs, or requests per second when --rps sets the ceiling
+    pub fn unit(&self, rps: Option<f64>) -> LoadUnit {
+        if rps.is_some() {
+            LoadUnit::Rps
+        } else {
+            LoadUnit::Vus
+        }
+    }
+
+    // Longest the search can take, for the progress bar
+    pub fn max_duration(&self, max: f64) -> Duration {
+        self.step_duration
+            .saturating_mul(Search::max_steps(self.start, self.step, max, self.refine) as u32)
+    }
+
+    pub fn search(&self, unit: LoadUnit, max: f64) -> Search {
+        Search::new(unit, self.step, max, self.refine)
+    }
+}
+
+// Objectives every step must meet, e.g. "p95<300ms,error_rate<1%"
+#[derive(Debug, Clone)]
+pub struct Slo(pub Vec<Threshold>);
+
+impl Slo {
+    // Used as the clap value parser for --slo
+    pub fn parse(expressions: &str) -> Result<Self, String> {
+        let thresholds = expressions
+            .split(',')
+            .map(str::trim)
+            .filter(|expression| !expression.is_empty())
+            .map(|expression| Threshold::parse(expression).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if thresholds.is_empty() {
+            return Err("--slo needs at least one objective, e.g. p95<300ms".to_string());
+        }
+        Ok(Self(thresholds))
+    }
+
+    // Expressions of the objectives the measurements violate
+    pub fn violations(&self, measurements: &Measurements) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|threshold| threshold.evaluate_measurements(measurements))
+            .filter(|result| !result.passed)
+            .map(|result| result.threshold)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadUnit {
+    Vus,
+    Rps,
+}
+
+impl fmt::Display for LoadUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vus => write!(f, "VUs"),
+            Self::Rps => write!(f, "req/s"),
+        }
+    }
+}
+
+// Chooses the load of each step from the outcome of the previous ones
+#[derive(Debug, Clone)]
+pub struct Search {
+    step: f64,
+    max: f64,
+    refinements_left: u32,
+    // VU counts are whole numbers
+    whole: bool,
+    passed: Option<f64>,
+    failed: Option<f64>,
+}
+
+impl Search {
+    pub fn new(unit: LoadUnit, step: f64, max: f64, refinements: u32) -> Self {
+        Self {
+            step,
+            max,
+            refinements_left: refinements,
+            whole: unit == LoadUnit::Vus,
+            passed: None,
+            failed: None,
+        }
+    }
+
+    // The load of the next step after a step at `load`, or None when the search is done
+    pub fn next(&mut self, load: f64, passed: bool) -> Option<f64> {
+        if passed {
+            self.passed = Some(self.passed.map_or(load, |best| best.max(load)));
+        } else {
+            self.failed = Some(self.failed.map_or(load, |lowest| lowest.min(load)));
+        }
+        let Some(failed) = self.failed else {
+            return (load < self.max).then(|| (load + self.step).min(self.max));
+        };
+        if self.refinements_left == 0 {
+            return None;
+        }
+        self.refinements_left -= 1;
+        let low = self.passed.unwrap_or(0.0);
+        let mut middle = (low + failed) / 2.0;
+        if self.whole {
+            middle = middle.round();
+        }
+        (middle > low && middle < failed).then_some(middle)
+    }
+
+    // Upper bound on the number of steps, for the progress bar
+    pub fn max_steps(start: f64, step: f64, max: f64, refinements: u32) -> u64 {
+        ((max - start).max(0.0) / step).ceil() as u64 + 1 + refinements as u64
+    }
+}
+
+// One step of the search as reported in the results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoscaleStep {
+    pub load: f64,
+    // Achieved requests per second
+    pub rps: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+    // Percent of failed requests
+    pub error_rate: f64,
+    pub passed: bool,
+    // Objectives the step violated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoscaleReport {
+    pub unit: LoadUnit,
+    pub slo: Vec<String>,
+    pub step_duration_seconds: f64,
+    // Highest load that met the SLO, None if no step did
+    pub max_sustainable_load: Option<f64>,
+    // Throughput achieved at that load
+    pub max_sustainable_rps: Option<f64>,
+    pub steps: Vec<AutoscaleStep>,
+}
+
+impl AutoscaleReport {
+    pub fn new(
+        unit: LoadUnit,
+        slo: &Slo,
+        step_duration: Duration,
+        steps: Vec<AutoscaleStep>,
+    ) -> Self {
+        let best = steps
+            .iter()
+            .filter(|step| step.passed)
+            .max_by(|a, b| a.load.total_cmp(&b.load));
+        Self {
+            unit,
+            slo: slo.0.iter().map(|t| t.expression.clone()).collect(),
+            step_duration_seconds: step_duration.as_secs_f64(),
+            max_sustainable_load: best.map(|step| step.load),
+            max_sustainable_rps: best.map(|step| step.rps),
+            steps,
+        }
+    }
+}
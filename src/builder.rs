@@ -0,0 +1,383 @@
+//! Builder API for running load tests from other Rust programs
+
+use crate::abort::AbortCondition;
+use crate::cookies::CookieMode;
+use crate::data::DataMode;
+use crate::load_shape::Profile;
+use crate::notify::WebhookFormat;
+use crate::otlp::OtlpProtocol;
+use crate::stages::Stage;
+use crate::think_time::ThinkTime;
+use crate::thresholds::Threshold;
+use crate::{
+    finish_influx, finish_otlp, finish_pushgateway, finish_remote_write, init_influx, init_otlp,
+    init_prometheus, init_pushgateway, init_remote_write, metrics_server, Args, LoadTester,
+    TestResults, REGISTRY,
+};
+use clap::Parser;
+use humantime::format_duration;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Configures a load test with the same defaults as the CLI, e.g.
+// `TestBuilder::new(url).vus(100).run().await`
+pub struct TestBuilder {
+    args: Args,
+}
+
+impl TestBuilder {
+    pub fn new(url: &str) -> Self {
+        // "--url=..." keeps URLs that start with a dash from being read as flags
+        let args = Args::parse_from(["forgy".to_string(), format!("--url={}", url)]);
+        Self { args }
+    }
+
+    // Start from fully parsed command line arguments
+    pub fn from_args(args: Args) -> Self {
+        Self { args }
+    }
+
+    // Add another target; "weight=N,URL" or "weight=N%,URL" sets its share of the traffic
+    pub fn url(mut self, url: &str) -> Self {
+        self.args.url.push(url.to_string());
+        self
+    }
+
+    pub fn vus(mut self, vus: usize) -> Self {
+        self.args.vus = vus;
+        self
+    }
+
+    pub fn ramp_up(mut self, duration: Duration) -> Self {
+        self.args.ramp_up = format_duration(duration).to_string();
+        self
+    }
+
+    pub fn hold(mut self, duration: Duration) -> Self {
+        self.args.hold = format_duration(duration).to_string();
+        self
+    }
+
+    pub fn ramp_down(mut self, duration: Duration) -> Self {
+        self.args.ramp_down = format_duration(duration).to_string();
+        self
+    }
+
+    // Stages replace the ramp-up/hold/ramp-down trio
+    pub fn stage(mut self, duration: Duration, target_vus: usize) -> Self {
+        self.args.stage.push(Stage {
+            duration,
+            target: target_vus,
+        });
+        self
+    }
+
+    // Iteration budgets replace the ramp-up/hold/ramp-down trio as well
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.args.profile = Some(profile);
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u64) -> Self {
+        self.args.iterations = Some(iterations);
+        self
+    }
+
+    pub fn iterations_per_vu(mut self, iterations: u64) -> Self {
+        self.args.iterations_per_vu = Some(iterations);
+        self
+    }
+
+    pub fn think_time(mut self, think_time: ThinkTime) -> Self {
+        self.args.think_time = Some(think_time);
+        self
+    }
+
+    pub fn rps(mut self, rps: f64) -> Self {
+        self.args.rps = Some(rps);
+        self
+    }
+
+    pub fn max_rps(mut self, max_rps: f64) -> Self {
+        self.args.max_rps = Some(max_rps);
+        self
+    }
+
+    pub fn cookie_jar(mut self, mode: CookieMode) -> Self {
+        self.args.cookie_jar = Some(mode);
+        self
+    }
+
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.args.proxy = Some(url.to_string());
+        self
+    }
+
+    pub fn proxy_auth(mut self, username: &str, password: &str) -> Self {
+        self.args.proxy_auth = Some(format!("{}:{}", username, password));
+        self
+    }
+
+    // Connect to `addr` for `host` on `port`, as with curl's --resolve
+    pub fn resolve(mut self, host: &str, port: u16, addr: IpAddr) -> Self {
+        let addr = match addr {
+            IpAddr::V4(addr) => addr.to_string(),
+            IpAddr::V6(addr) => format!("[{}]", addr),
+        };
+        self.args
+            .resolve
+            .push(format!("{}:{}:{}", host, port, addr));
+        self
+    }
+
+    pub fn dns_server(mut self, server: SocketAddr) -> Self {
+        self.args.dns_server = Some(server.to_string());
+        self
+    }
+
+    pub fn ipv4(mut self) -> Self {
+        self.args.ipv4 = true;
+        self.args.ipv6 = false;
+        self
+    }
+
+    pub fn ipv6(mut self) -> Self {
+        self.args.ipv6 = true;
+        self.args.ipv4 = false;
+        self
+    }
+
+    // Add a source address for connections; several are used in turn
+    pub fn local_address(mut self, addr: IpAddr) -> Self {
+        self.args.local_address.push(addr);
+        self
+    }
+
+    pub fn client_per_vu(mut self) -> Self {
+        self.args.client_per_vu = true;
+        self
+    }
+
+    pub fn disable_keepalive(mut self) -> Self {
+        self.args.disable_keepalive = true;
+        self
+    }
+
+    pub fn max_connection_lifetime(mut self, lifetime: Duration) -> Self {
+        self.args.max_connection_lifetime = Some(lifetime);
+        self
+    }
+
+    pub fn max_duration(mut self, duration: Duration) -> Self {
+        self.args.max_duration = Some(format_duration(duration).to_string());
+        self
+    }
+
+    // Stop early once the condition holds, as with --abort-on
+    pub fn abort_on(mut self, condition: AbortCondition) -> Self {
+        self.args.abort_on.push(condition);
+        self
+    }
+
+    pub fn method(mut self, method: &str) -> Self {
+        self.args.method = method.to_string();
+        self
+    }
+
+    // "Key:Value", as with --header
+    pub fn header(mut self, header: &str) -> Self {
+        self.args.header.push(header.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.args.body = Some(body.to_string());
+        self
+    }
+
+    // CSV file for ${csv.column} placeholders
+    pub fn data(mut self, path: &str, mode: DataMode) -> Self {
+        self.args.data = Some(path.to_string());
+        self.args.data_mode = mode;
+        self
+    }
+
+    // Client-credentials token sent as a Bearer Authorization header
+    pub fn oauth2(mut self, token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        self.args.oauth2_token_url = Some(token_url.to_string());
+        self.args.client_id = Some(client_id.to_string());
+        self.args.client_secret = Some(client_secret.to_string());
+        self
+    }
+
+    // The whole-request timeout, --request-timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.args.request_timeout = timeout;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.args.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.args.retries = retries;
+        self
+    }
+
+    pub fn retry_backoff(mut self, backoff: Duration) -> Self {
+        self.args.retry_backoff = backoff;
+        self
+    }
+
+    // "502,503,timeout": status codes and error types, as with --retry-on
+    pub fn retry_on(mut self, conditions: &str) -> Self {
+        self.args.retry_on = conditions.to_string();
+        self
+    }
+
+    pub fn retry_non_idempotent(mut self) -> Self {
+        self.args.retry_non_idempotent = true;
+        self
+    }
+
+    // "200" or "200,201", as with --check-status
+    pub fn check_status(mut self, codes: &str) -> Self {
+        self.args.check_status = Some(codes.to_string());
+        self
+    }
+
+    // "200,201", "2xx,404" or "4xx-ok": statuses that count as success, as with --expect-status
+    pub fn expect_status(mut self, statuses: &str) -> Self {
+        self.args.expect_status = Some(statuses.to_string());
+        self
+    }
+
+    // "p95<250ms", "error_rate<1%", ... as with --threshold
+    pub fn threshold(mut self, threshold: &str) -> Self {
+        self.args.threshold.push(threshold.to_string());
+        self
+    }
+
+    // "latency:p99<500ms" or "availability:99.9", as with --slo
+    pub fn slo(mut self, slo: &str) -> Self {
+        self.args.slo.push(slo.to_string());
+        self
+    }
+
+    // Keep a uniform random sample of individual requests in TestResults::samples
+    pub fn sample_size(mut self, size: usize) -> Self {
+        self.args.sample_size = Some(size);
+        self
+    }
+
+    // NDJSON log of every request; "-" writes to stdout
+    pub fn request_log(mut self, path: &str) -> Self {
+        self.args.request_log = Some(path.to_string());
+        self
+    }
+
+    pub fn prometheus_url(mut self, url: &str) -> Self {
+        self.args.prometheus_url = Some(url.to_string());
+        self
+    }
+
+    // OpenTelemetry collector URL, as with --otlp-endpoint
+    pub fn otlp_endpoint(mut self, endpoint: &str, protocol: OtlpProtocol) -> Self {
+        self.args.otlp_endpoint = Some(endpoint.to_string());
+        self.args.otlp_protocol = protocol;
+        self
+    }
+
+    // InfluxDB URL and bucket, as with --influx-url and --influx-bucket
+    pub fn influx(mut self, url: &str, bucket: &str) -> Self {
+        self.args.influx_url = Some(url.to_string());
+        self.args.influx_bucket = Some(bucket.to_string());
+        self
+    }
+
+    // Pushgateway URL, as with --pushgateway-url
+    pub fn pushgateway_url(mut self, url: &str) -> Self {
+        self.args.pushgateway_url = Some(url.to_string());
+        self
+    }
+
+    // Webhook notified of breached thresholds, aborts and the results, as with --notify-webhook
+    pub fn notify_webhook(mut self, url: &str, format: WebhookFormat) -> Self {
+        self.args.notify_webhook = Some(url.to_string());
+        self.args.notify_format = format;
+        self
+    }
+
+    // Send traceparent headers, marking `sample_rate` of the traces as sampled
+    pub fn trace_propagation(mut self, sample_rate: f64) -> Self {
+        self.args.trace_propagation = true;
+        self.args.trace_sample_rate = sample_rate;
+        self
+    }
+
+    pub fn metrics_listen(mut self, addr: SocketAddr) -> Self {
+        self.args.metrics_listen = Some(addr);
+        self
+    }
+
+    pub fn app(mut self, app: &str) -> Self {
+        self.args.app = app.to_string();
+        self
+    }
+
+    // Static label on every exported series, as with --label
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.args.label.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    // Run the test to completion and evaluate thresholds; the caller decides what a
+    // failed threshold means
+    pub async fn run(self) -> Result<TestResults, BoxError> {
+        let args = self.args;
+        let thresholds = args
+            .threshold
+            .iter()
+            .map(|t| Threshold::parse(t))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if args.metrics_enabled() {
+            init_prometheus();
+            init_remote_write(&args)?;
+            init_otlp(&args)?;
+            init_influx(&args)?;
+            init_pushgateway(&args)?;
+        }
+        if let Some(addr) = args.metrics_listen {
+            metrics_server::spawn(addr, &REGISTRY)?;
+        }
+
+        let tester = LoadTester::new(&args).await?;
+        let mut results = tester.run_load_test(&args).await;
+        results.thresholds = thresholds.iter().map(|t| t.evaluate(&results)).collect();
+        tester.notify_finished(&results).await;
+
+        if args.prometheus_url.is_some() {
+            finish_remote_write(&args.app).await?;
+        }
+        if args.otlp_endpoint.is_some() {
+            finish_otlp().await?;
+        }
+        if args.influx_url.is_some() {
+            finish_influx().await?;
+        }
+        if args.pushgateway_url.is_some() {
+            finish_pushgateway().await?;
+        }
+
+        Ok(results)
+    }
+}
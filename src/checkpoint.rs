@@ -0,0 +1,181 @@
+//! Checkpoints of a running test, for resuming long soaks after a crash or restart
+//
+// With --checkpoint, the aggregated statistics, the time series and the elapsed time are
+// written to a file every --checkpoint-interval (and when the test is interrupted). Writes go
+// to a temporary file that is then renamed, so a crash mid-write leaves the previous
+// checkpoint intact. --resume loads one: the statistics continue from it and the load
+// profile picks up at its elapsed time. Individual requests (--sample-size) are not kept.
+
+use crate::checks::CheckSummary;
+use crate::errors::ErrorType;
+use crate::merge::{self, EncodedHistograms};
+use crate::stats::{Counters, EndpointStats, ShardStats, Stats, TargetStats, STATUS_CLASSES};
+use crate::timeseries::TimeseriesPoint;
+use crate::timing::PHASES;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Bumped when the layout changes incompatibly
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub error_types: BTreeMap<ErrorType, usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub saved_at: DateTime<Utc>,
+    // Time into the test, including the runs it was resumed from
+    pub elapsed_seconds: f64,
+    pub counters: Counters,
+    pub histograms: EncodedHistograms,
+    pub status_codes: HashMap<u16, usize>,
+    pub error_types: BTreeMap<ErrorType, usize>,
+    pub targets: BTreeMap<String, RequestCounts>,
+    pub endpoints: BTreeMap<String, RequestCounts>,
+    pub checks: Vec<CheckSummary>,
+    pub timeseries: Vec<TimeseriesPoint>,
+}
+
+impl Checkpoint {
+    pub fn capture(
+        stats: &Stats,
+        timeseries: &[TimeseriesPoint],
+        elapsed: Duration,
+    ) -> Result<Self, BoxError> {
+        // Counters first: requests finishing meanwhile land in the shards too, so the
+        // histograms can only be ahead of them, never behind
+        let counters = stats.counters();
+        let merged = stats.merged();
+        Ok(Self {
+            version: VERSION,
+            saved_at: Utc::now(),
+            elapsed_seconds: elapsed.as_secs_f64(),
+            counters,
+            histograms: EncodedHistograms::from_stats(&merged)?,
+            status_codes: merged.status_codes.clone(),
+            error_types: merged.error_types.clone(),
+            targets: merged
+                .targets
+                .iter()
+                .map(|(name, target)| {
+                    let counts = RequestCounts {
+                        total_requests: target.total_requests,
+                        successful_requests: target.successful_requests,
+                        error_types: BTreeMap::new(),
+                    };
+                    (name.clone(), counts)
+                })
+                .collect(),
+            endpoints: merged
+                .endpoints
+                .iter()
+                .map(|(name, endpoint)| {
+                    let counts = RequestCounts {
+                        total_requests: endpoint.total_requests,
+                        successful_requests: endpoint.successful_requests,
+                        error_types: endpoint.error_types.clone(),
+                    };
+                    (name.clone(), counts)
+                })
+                .collect(),
+            checks: merged.checks.values().cloned().collect(),
+            timeseries: timeseries.to_vec(),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, BoxError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read checkpoint {}: {}", path, e))?;
+        let checkpoint: Self = serde_json::from_str(&json)
+            .map_err(|e| format!("invalid checkpoint {}: {}", path, e))?;
+        if checkpoint.version != VERSION {
+            return Err(format!(
+                "checkpoint {} has version {}, this forgy reads version {}",
+                path, checkpoint.version, VERSION
+            )
+            .into());
+        }
+        Ok(checkpoint)
+    }
+
+    pub async fn save(&self, path: &str) -> Result<(), BoxError> {
+        let json = serde_json::to_vec(self)?;
+        let temporary = format!("{}.tmp", path);
+        tokio::fs::write(&temporary, json)
+            .await
+            .map_err(|e| format!("failed to write checkpoint {}: {}", temporary, e))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .map_err(|e| format!("failed to write checkpoint {}: {}", path, e))?;
+        Ok(())
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.elapsed_seconds)
+    }
+
+    // Add the checkpoint's statistics to fresh ones
+    pub fn restore(&self, stats: &Stats) -> Result<(), BoxError> {
+        let mut shard = ShardStats::new();
+        shard.histogram = merge::decode(&self.histograms.response_time)?;
+        shard.corrected_histogram = merge::decode(&self.histograms.corrected)?;
+        shard.status_codes = self.status_codes.clone();
+        shard.error_types = self.error_types.clone();
+        for (name, counts) in &self.targets {
+            let histogram = match self.histograms.targets.get(name) {
+                Some(encoded) => merge::decode(encoded)?,
+                None => continue,
+            };
+            shard.targets.insert(
+                name.clone(),
+                TargetStats {
+                    histogram,
+                    total_requests: counts.total_requests,
+                    successful_requests: counts.successful_requests,
+                },
+            );
+        }
+        for (name, counts) in &self.endpoints {
+            let histogram = match self.histograms.endpoints.get(name) {
+                Some(encoded) => merge::decode(encoded)?,
+                None => continue,
+            };
+            shard.endpoints.insert(
+                name.clone(),
+                EndpointStats {
+                    histogram,
+                    total_requests: counts.total_requests,
+                    successful_requests: counts.successful_requests,
+                    error_types: counts.error_types.clone(),
+                },
+            );
+        }
+        for (name, encoded) in &self.histograms.phases {
+            if let Some(phase) = PHASES.iter().find(|phase| **phase == name.as_str()) {
+                shard.phases.insert(*phase, merge::decode(encoded)?);
+            }
+        }
+        for (name, encoded) in &self.histograms.status_classes {
+            if let Some(class) = STATUS_CLASSES.iter().find(|class| **class == name.as_str()) {
+                shard.status_classes.insert(*class, merge::decode(encoded)?);
+            }
+        }
+        shard.checks = self
+            .checks
+            .iter()
+            .map(|check| (check.name.clone(), check.clone()))
+            .collect();
+        stats.restore(&self.counters, &shard);
+        Ok(())
+    }
+}
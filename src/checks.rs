@@ -0,0 +1,162 @@
+//! Response assertions evaluated after every request
+
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json_path::JsonPath;
+use std::fmt;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub enum Check {
+    // Status code must be one of the listed codes
+    Status(Vec<u16>),
+    // Header must be present and its value must contain the expected text
+    Header {
+        name: String,
+        value: String,
+    },
+    BodyContains(String),
+    // JSONPath must match at least one node, optionally equal to the expected value
+    JsonPath {
+        expression: String,
+        path: JsonPath,
+        expected: Option<serde_json::Value>,
+    },
+}
+
+// Outcome counters of a single check, as reported in TestResults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckSummary {
+    pub name: String,
+    pub passed: u64,
+    pub failed: u64,
+}
+
+impl Check {
+    // "200" or "200,201,204"
+    pub fn parse_status(spec: &str) -> Result<Self, BoxError> {
+        let codes = spec
+            .split(',')
+            .map(|code| {
+                code.trim()
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid status code in check: {}", code))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Check::Status(codes))
+    }
+
+    // "Content-Type:application/json"
+    pub fn parse_header(spec: &str) -> Result<Self, BoxError> {
+        let (name, value) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("header check must be \"Name:Value\": {}", spec))?;
+        Ok(Check::Header {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    pub fn parse_body(spec: &str) -> Result<Self, BoxError> {
+        Ok(Check::BodyContains(spec.to_string()))
+    }
+
+    // "$.data.id" (must exist) or "$.status==ok" (must equal; JSON literals are compared as JSON)
+    pub fn parse_jsonpath(spec: &str) -> Result<Self, BoxError> {
+        let (path, expected) = match spec.split_once("==") {
+            Some((path, expected)) => {
+                let expected = expected.trim();
+                let value = serde_json::from_str(expected)
+                    .unwrap_or_else(|_| serde_json::Value::String(expected.to_string()));
+                (path.trim(), Some(value))
+            }
+            None => (spec.trim(), None),
+        };
+
+        Ok(Check::JsonPath {
+            expression: spec.to_string(),
+            path: JsonPath::parse(path).map_err(|e| format!("invalid JSONPath {}: {}", path, e))?,
+            expected,
+        })
+    }
+
+    // Whether the check reads the response body, which then has to be buffered
+    pub fn needs_body(&self) -> bool {
+        matches!(self, Check::BodyContains(_) | Check::JsonPath { .. })
+    }
+
+    pub fn evaluate(&self, status_code: u16, headers: &HeaderMap, body: &str) -> bool {
+        match self {
+            Check::Status(codes) => codes.contains(&status_code),
+            Check::Header { name, value } => headers
+                .get_all(name.as_str())
+                .iter()
+                .any(|v| v.to_str().is_ok_and(|v| v.contains(value.as_str()))),
+            Check::BodyContains(needle) => body.contains(needle.as_str()),
+            Check::JsonPath { path, expected, .. } => {
+                let Ok(json) = serde_json::from_str::<serde_json::Value>(body) else {
+                    return false;
+                };
+                let nodes = path.query(&json);
+                match expected {
+                    Some(expected) => nodes.iter().any(|node| *node == expected),
+                    None => !nodes.is_empty(),
+                }
+            }
+        }
+    }
+}
+
+// Used as the `check` label and in reports
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Status(codes) => {
+                let codes: Vec<String> = codes.iter().map(|c| c.to_string()).collect();
+                write!(f, "status in {}", codes.join(","))
+            }
+            Check::Header { name, value } => write!(f, "header {} contains {}", name, value),
+            Check::BodyContains(needle) => write!(f, "body contains {}", needle),
+            Check::JsonPath { expression, .. } => write!(f, "jsonpath {}", expression),
+        }
+    }
+}
+
+// Check as written in a scenario file, e.g. `- status: [200, 201]` or `- jsonpath: "$.ok==true"`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckConfig {
+    status: Option<StatusList>,
+    header: Option<String>,
+    body_contains: Option<String>,
+    jsonpath: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum StatusList {
+    One(u16),
+    Many(Vec<u16>),
+}
+
+impl CheckConfig {
+    pub fn into_checks(self) -> Result<Vec<Check>, BoxError> {
+        let mut checks = Vec::new();
+        match self.status {
+            Some(StatusList::One(code)) => checks.push(Check::Status(vec![code])),
+            Some(StatusList::Many(codes)) => checks.push(Check::Status(codes)),
+            None => {}
+        }
+        if let Some(spec) = self.header {
+            checks.push(Check::parse_header(&spec)?);
+        }
+        if let Some(spec) = self.body_contains {
+            checks.push(Check::parse_body(&spec)?);
+        }
+        if let Some(spec) = self.jsonpath {
+            checks.push(Check::parse_jsonpath(&spec)?);
+        }
+        Ok(checks)
+    }
+}
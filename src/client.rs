@@ -0,0 +1,446 @@
+//! HTTP clients: how they are configured and which one a VU sends its requests with
+//
+// Certificates are read once; clients are built from the parsed options, so that clients can
+// be created for every VU (--client-per-vu) and replaced once their connections reach
+// --max-connection-lifetime. Replacing a client drops its pool: idle connections are closed
+// and in-flight requests finish on theirs, which are closed afterwards. With several
+// --local-address values, slots bind to them in turn, so that there is at least one slot
+// (and pool) per source address.
+
+use crate::dns::{AddressFamily, Resolver};
+use crate::proxy::Proxies;
+use crate::rng::Rngs;
+#[cfg(feature = "http3")]
+use crate::timing::TimedResolver;
+use crate::transport::{SendError, TimedConnector, TlsOptions, Transport};
+use crate::{Args, HttpVersion};
+use parking_lot::RwLock;
+use rand::Rng;
+use reqwest::{IntoUrl, Method, Request, RequestBuilder, Response};
+use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore};
+use rustls_pemfile::Item;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub struct ClientOptions {
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
+    http_version: HttpVersion,
+    tls: Arc<ClientConfig>,
+    // HTTPS proxies are spoken to in HTTP/1.1
+    proxy_tls: Arc<ClientConfig>,
+    pub keepalive: bool,
+    proxies: Arc<Proxies>,
+    resolver: Arc<Resolver>,
+    // Source addresses of connections, used round-robin by slot
+    pub local_addresses: Vec<IpAddr>,
+    // Builds the requests of every client; it sends none itself, except over HTTP/3
+    requests: reqwest::Client,
+    #[cfg(feature = "http3")]
+    http3: Http3Options,
+}
+
+// reqwest sends HTTP/3 itself, so its connections are not timed
+#[cfg(feature = "http3")]
+struct Http3Options {
+    identity: Option<reqwest::Identity>,
+    ca_certificate: Option<reqwest::Certificate>,
+    insecure: bool,
+    proxy: Option<reqwest::Proxy>,
+}
+
+impl ClientOptions {
+    pub fn from_args(args: &Args, insecure: bool) -> Result<Self, BoxError> {
+        #[cfg(not(feature = "http3"))]
+        if args.http_version == HttpVersion::Http3 {
+            return Err("HTTP/3 requires forgy to be built with --features http3".into());
+        }
+
+        // Client certificate for mutual TLS; the key may be in the certificate's file
+        let identity = match &args.cert {
+            Some(cert_path) => {
+                let (certs, key) = read_pem(cert_path)?;
+                let key = match &args.key {
+                    Some(key_path) => read_pem(key_path)?.1,
+                    None => key,
+                };
+                let key = key.ok_or_else(|| format!("no private key in {}", cert_path))?;
+                if certs.is_empty() {
+                    return Err(format!("no certificate in {}", cert_path).into());
+                }
+                Some((certs, key))
+            }
+            None => None,
+        };
+
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        if let Some(ca_path) = &args.cacert {
+            let (certs, _) = read_pem(ca_path)?;
+            if certs.is_empty() {
+                return Err(format!("no CA certificate in {}", ca_path).into());
+            }
+            for cert in &certs {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("invalid CA certificate {}: {}", ca_path, e))?;
+            }
+        }
+
+        let tls = TlsOptions {
+            roots,
+            identity,
+            insecure,
+        };
+        // ALPN as reqwest offers it for each version
+        let alpn: &[&[u8]] = match args.http_version {
+            HttpVersion::Http1 => &[b"http/1.1"],
+            HttpVersion::Http2PriorKnowledge => &[b"h2"],
+            HttpVersion::Http2 | HttpVersion::Http3 => &[b"h2", b"http/1.1"],
+        };
+
+        let proxies = Proxies::new(args.proxy.as_deref(), args.proxy_auth.as_deref())?;
+
+        let family = if args.ipv4 {
+            Some(AddressFamily::V4)
+        } else if args.ipv6 {
+            Some(AddressFamily::V6)
+        } else {
+            None
+        };
+        if let Some(family) = family {
+            if let Some(ip) = args.local_address.iter().find(|ip| !family.contains(ip)) {
+                return Err(format!("--local-address {} is not an {} address", ip, family).into());
+            }
+        }
+        let resolver = Resolver::new(&args.resolve, args.dns_server.as_deref(), family)?;
+
+        Ok(Self {
+            timeout: args.request_timeout,
+            connect_timeout: args.connect_timeout,
+            http_version: args.http_version,
+            tls: tls.config(alpn)?,
+            proxy_tls: tls.config(&[b"http/1.1"])?,
+            keepalive: !args.disable_keepalive,
+            proxies: Arc::new(proxies),
+            resolver: Arc::new(resolver),
+            local_addresses: args.local_address.clone(),
+            requests: reqwest::Client::builder()
+                .build()
+                .map_err(|e| format!("failed to create HTTP client: {}", e))?,
+            #[cfg(feature = "http3")]
+            http3: Http3Options::from_args(args, insecure)?,
+        })
+    }
+
+    // The resolver of the clients, for connections made without them
+    pub fn resolver(&self) -> Arc<Resolver> {
+        self.resolver.clone()
+    }
+
+    // A client keeping up to `pool_size` idle connections per host, connecting from
+    // `local_address` if given
+    pub fn build(
+        &self,
+        pool_size: usize,
+        local_address: Option<IpAddr>,
+    ) -> Result<Client, BoxError> {
+        let pool_size = if self.keepalive { pool_size } else { 0 };
+        #[cfg(feature = "http3")]
+        if self.http_version == HttpVersion::Http3 {
+            return Ok(Client {
+                requests: self.http3.build(self, pool_size, local_address)?,
+                sender: Sender::Http3,
+                timeout: self.timeout,
+            });
+        }
+
+        let connector = TimedConnector::new(
+            self.resolver.clone(),
+            self.tls.clone(),
+            self.proxy_tls.clone(),
+            self.proxies.clone(),
+            self.connect_timeout,
+            local_address,
+        );
+        let http2_only = self.http_version == HttpVersion::Http2PriorKnowledge;
+        Ok(Client {
+            requests: self.requests.clone(),
+            sender: Sender::Timed(Transport::new(
+                connector,
+                pool_size,
+                http2_only,
+                self.timeout,
+            )),
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[cfg(feature = "http3")]
+impl Http3Options {
+    fn from_args(args: &Args, insecure: bool) -> Result<Self, BoxError> {
+        let identity = match &args.cert {
+            Some(cert_path) => {
+                let mut pem = std::fs::read(cert_path)
+                    .map_err(|e| format!("failed to read {}: {}", cert_path, e))?;
+                if let Some(key_path) = &args.key {
+                    pem.push(b'\n');
+                    pem.extend(
+                        std::fs::read(key_path)
+                            .map_err(|e| format!("failed to read {}: {}", key_path, e))?,
+                    );
+                }
+                Some(
+                    reqwest::Identity::from_pem(&pem)
+                        .map_err(|e| format!("invalid client certificate or key: {}", e))?,
+                )
+            }
+            None => None,
+        };
+        let ca_certificate = match &args.cacert {
+            Some(ca_path) => {
+                let pem = std::fs::read(ca_path)
+                    .map_err(|e| format!("failed to read {}: {}", ca_path, e))?;
+                Some(
+                    reqwest::Certificate::from_pem(&pem)
+                        .map_err(|e| format!("invalid CA certificate {}: {}", ca_path, e))?,
+                )
+            }
+            None => None,
+        };
+        let proxy = match &args.proxy {
+            Some(url) => {
+                let mut proxy = reqwest::Proxy::all(url)
+                    .map_err(|e| format!("invalid proxy {}: {}", url, e))?;
+                if let Some((username, password)) = args
+                    .proxy_auth
+                    .as_deref()
+                    .and_then(|auth| auth.split_once(':'))
+                {
+                    proxy = proxy.basic_auth(username, password);
+                }
+                Some(proxy.no_proxy(reqwest::NoProxy::from_env()))
+            }
+            None => None,
+        };
+        Ok(Self {
+            identity,
+            ca_certificate,
+            insecure,
+            proxy,
+        })
+    }
+
+    fn build(
+        &self,
+        options: &ClientOptions,
+        pool_size: usize,
+        local_address: Option<IpAddr>,
+    ) -> Result<reqwest::Client, BoxError> {
+        let mut builder = reqwest::Client::builder()
+            .dns_resolver(Arc::new(TimedResolver::new(options.resolver.clone())))
+            .timeout(options.timeout)
+            .pool_max_idle_per_host(pool_size)
+            .local_address(local_address)
+            .http3_prior_knowledge()
+            .danger_accept_invalid_certs(self.insecure);
+        if let Some(connect_timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        if let Some(certificate) = &self.ca_certificate {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        Ok(builder
+            .build()
+            .map_err(|e| format!("failed to create HTTP client: {}", e))?)
+    }
+}
+
+// Certificates and the private key in a PEM file, as reqwest reads them
+fn read_pem(
+    path: &str,
+) -> Result<(Vec<rustls::Certificate>, Option<rustls::PrivateKey>), BoxError> {
+    let pem = std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let mut reader = pem.as_slice();
+    let (mut certs, mut key) = (Vec::new(), None);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| format!("invalid PEM in {}: {}", path, e))?
+        {
+            Some(Item::X509Certificate(cert)) => certs.push(rustls::Certificate(cert)),
+            Some(Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der)) => {
+                key.get_or_insert(rustls::PrivateKey(der));
+            }
+            Some(_) => {}
+            None => return Ok((certs, key)),
+        }
+    }
+}
+
+// The client of a VU: requests are built with reqwest and sent by the timed transport
+#[derive(Clone)]
+pub struct Client {
+    requests: reqwest::Client,
+    sender: Sender,
+    timeout: Duration,
+}
+
+#[derive(Clone)]
+enum Sender {
+    Timed(Transport),
+    #[cfg(feature = "http3")]
+    Http3,
+}
+
+impl Client {
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.requests.request(method, url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.requests.post(url)
+    }
+
+    pub async fn execute(&self, request: Request) -> Result<Response, SendError> {
+        self.send(request, None).await
+    }
+
+    // Send `request`, with `stream` as its body in place of the request's own when given
+    pub async fn send(
+        &self,
+        request: Request,
+        stream: Option<hyper::Body>,
+    ) -> Result<Response, SendError> {
+        match &self.sender {
+            Sender::Timed(transport) => transport.send(request, stream).await,
+            #[cfg(feature = "http3")]
+            Sender::Http3 => {
+                let mut request = request;
+                if let Some(stream) = stream {
+                    *request.body_mut() = Some(reqwest::Body::from(stream));
+                }
+                Ok(self.requests.execute(request).await?)
+            }
+        }
+    }
+
+    // The timeout of requests without one of their own
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+// A timeout in seconds ("30") or as a duration ("500ms", "1m")
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let timeout = match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Duration::from_secs_f64(seconds),
+        Ok(_) => return Err(format!("invalid timeout '{}'", value)),
+        Err(_) => humantime::parse_duration(value).map_err(|e| e.to_string())?,
+    };
+    if timeout.is_zero() {
+        return Err("timeout must be greater than zero".to_string());
+    }
+    Ok(timeout)
+}
+
+struct Slot {
+    client: Client,
+    // When the client is replaced, with --max-connection-lifetime
+    expires: Option<Instant>,
+}
+
+// One shared client, or one per VU number with --client-per-vu
+pub struct Clients {
+    options: ClientOptions,
+    pool_size: usize,
+    lifetime: Option<Duration>,
+    // For the lifetime jitter, drawn from the generator of the VU that makes the client
+    rngs: Arc<Rngs>,
+    slots: Box<[RwLock<Slot>]>,
+}
+
+impl Clients {
+    pub fn new(
+        options: ClientOptions,
+        slots: usize,
+        pool_size: usize,
+        lifetime: Option<Duration>,
+        rngs: Arc<Rngs>,
+    ) -> Result<Self, BoxError> {
+        let mut clients = Self {
+            options,
+            pool_size,
+            lifetime,
+            rngs,
+            slots: Box::new([]),
+        };
+        clients.slots = (0..slots.max(1))
+            .map(|index| Ok(RwLock::new(clients.new_slot(index, index + 1)?)))
+            .collect::<Result<_, BoxError>>()?;
+        Ok(clients)
+    }
+
+    pub fn keepalive(&self) -> bool {
+        self.options.keepalive
+    }
+
+    // The client of a 1-based VU number; arrival workers use the ones of their slot
+    pub fn get(&self, vu: usize) -> Client {
+        let index = vu.saturating_sub(1) % self.slots.len();
+        let slot = &self.slots[index];
+        {
+            let current = slot.read();
+            if current
+                .expires
+                .is_none_or(|expires| Instant::now() < expires)
+            {
+                return current.client.clone();
+            }
+        }
+        let mut current = slot.write();
+        // Another VU of a shared slot may have replaced it meanwhile
+        if current
+            .expires
+            .is_some_and(|expires| Instant::now() >= expires)
+        {
+            match self.new_slot(index, vu) {
+                Ok(replacement) => *current = replacement,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to replace HTTP client");
+                    current.expires = None;
+                }
+            }
+        }
+        current.client.clone()
+    }
+
+    // Lifetimes are jittered by ±10%, from the generator of `vu`, so that per-VU clients do
+    // not all reconnect at once
+    fn new_slot(&self, index: usize, vu: usize) -> Result<Slot, BoxError> {
+        let addresses = &self.options.local_addresses;
+        let local_address = (!addresses.is_empty()).then(|| addresses[index % addresses.len()]);
+        Ok(Slot {
+            client: self.options.build(self.pool_size, local_address)?,
+            expires: self.lifetime.map(|lifetime| {
+                let jitter = self.rngs.vu(vu).lock().gen_range(0.9..1.1);
+                Instant::now() + lifetime.mul_f64(jitter)
+            }),
+        })
+    }
+}
@@ -0,0 +1,181 @@
+//! Comparison of a run against a baseline, for `forgy compare`
+
+use crate::TestResults;
+
+// Process exit code when the current run regressed beyond the tolerances
+pub const REGRESSION_EXIT_CODE: i32 = 98;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerances {
+    // Allowed increase of the average and each percentile, in percent
+    pub latency_percent: f64,
+    // Allowed decrease of requests per second, in percent
+    pub rps_percent: f64,
+    // Allowed increase of the error rate, in percentage points
+    pub error_rate_points: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    // Relative change in percent; a decrease is the regression
+    LowerIsWorse,
+    // Relative change in percent; an increase is the regression
+    HigherIsWorse,
+    // Absolute change in percentage points; an increase is the regression
+    HigherIsWorsePoints,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonRow {
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    // Percent for throughput and latency, percentage points for the error rate
+    pub change: f64,
+    pub regressed: bool,
+    kind: Change,
+}
+
+pub fn compare(
+    baseline: &TestResults,
+    current: &TestResults,
+    tolerances: &Tolerances,
+) -> Vec<ComparisonRow> {
+    let error_rate = |results: &TestResults| {
+        results.failed_requests as f64 / results.total_requests.max(1) as f64 * 100.0
+    };
+
+    let mut rows = vec![
+        row(
+            "RPS",
+            baseline.requests_per_second,
+            current.requests_per_second,
+            Change::LowerIsWorse,
+            tolerances.rps_percent,
+        ),
+        row(
+            "Error rate",
+            error_rate(baseline),
+            error_rate(current),
+            Change::HigherIsWorsePoints,
+            tolerances.error_rate_points,
+        ),
+    ];
+    for (metric, baseline, current) in [
+        (
+            "Average",
+            baseline.avg_response_time_ms,
+            current.avg_response_time_ms,
+        ),
+        (
+            "P50",
+            baseline.p50_response_time_ms,
+            current.p50_response_time_ms,
+        ),
+        (
+            "P90",
+            baseline.p90_response_time_ms,
+            current.p90_response_time_ms,
+        ),
+        (
+            "P95",
+            baseline.p95_response_time_ms,
+            current.p95_response_time_ms,
+        ),
+        (
+            "P99",
+            baseline.p99_response_time_ms,
+            current.p99_response_time_ms,
+        ),
+    ] {
+        rows.push(row(
+            metric,
+            baseline,
+            current,
+            Change::HigherIsWorse,
+            tolerances.latency_percent,
+        ));
+    }
+    rows
+}
+
+fn row(
+    metric: &'static str,
+    baseline: f64,
+    current: f64,
+    kind: Change,
+    tolerance: f64,
+) -> ComparisonRow {
+    let change = match kind {
+        Change::HigherIsWorsePoints => current - baseline,
+        _ if baseline == current => 0.0,
+        // Anything from nothing is an unbounded increase
+        _ if baseline == 0.0 => f64::INFINITY,
+        _ => (current - baseline) / baseline * 100.0,
+    };
+    let regressed = match kind {
+        Change::LowerIsWorse => -change > tolerance,
+        Change::HigherIsWorse | Change::HigherIsWorsePoints => change > tolerance,
+    };
+    ComparisonRow {
+        metric,
+        baseline,
+        current,
+        change,
+        regressed,
+        kind,
+    }
+}
+
+pub fn print_comparison(rows: &[ComparisonRow]) {
+    println!("\nComparison with Baseline");
+    println!("═══════════════════════════════════════════════════════════");
+    println!(
+        "{:<12} {:>12} {:>12} {:>12}",
+        "Metric", "Baseline", "Current", "Change"
+    );
+    println!("───────────────────────────────────────────────────────────");
+    for row in rows {
+        let (baseline, current, change) = match row.kind {
+            Change::HigherIsWorsePoints => (
+                format!("{:.2}%", row.baseline),
+                format!("{:.2}%", row.current),
+                format!("{:+.2} pp", row.change),
+            ),
+            Change::LowerIsWorse => (
+                format!("{:.2}", row.baseline),
+                format!("{:.2}", row.current),
+                format_percent(row.change),
+            ),
+            Change::HigherIsWorse => (
+                format!("{:.2} ms", row.baseline),
+                format!("{:.2} ms", row.current),
+                format_percent(row.change),
+            ),
+        };
+        println!(
+            "{:<12} {:>12} {:>12} {:>12}  {}",
+            row.metric,
+            baseline,
+            current,
+            change,
+            if row.regressed { "REGRESSED" } else { "ok" }
+        );
+    }
+    println!("═══════════════════════════════════════════════════════════");
+
+    let regressions = rows.iter().filter(|row| row.regressed).count();
+    if regressions == 0 {
+        println!("No regressions");
+    } else {
+        println!("{} metric(s) regressed beyond the tolerances", regressions);
+    }
+}
+
+fn format_percent(change: f64) -> String {
+    if change.is_infinite() {
+        "new".to_string()
+    } else {
+        format!("{:+.1}%", change)
+    }
+}
@@ -0,0 +1,121 @@
+//! `--compression` and `--gzip-body`: content encoding of responses and request bodies
+//
+// reqwest is built without its decompression features, so responses are read as they came
+// over the wire and the bytes received are the compressed ones. --compression only sets the
+// Accept-Encoding that is asked for; gzip bodies, whoever asked for them, are decoded here as
+// they are read, so checks see the decoded body and its size can be reported next to the
+// wire size. There is no brotli decoder: br bodies are counted as they arrived and checks
+// see them encoded.
+
+use flate2::write::{GzDecoder, GzEncoder};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_ENCODING};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Br,
+    Identity,
+}
+
+impl Compression {
+    pub fn accept_encoding(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Self::Gzip => "gzip",
+            Self::Br => "br",
+            Self::Identity => "identity",
+        })
+    }
+}
+
+pub fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(body)
+        .expect("writing to a Vec does not fail");
+    encoder.finish().expect("writing to a Vec does not fail")
+}
+
+pub fn content_encoding_gzip() -> HeaderValue {
+    HeaderValue::from_static("gzip")
+}
+
+// Counts what is written to it, keeping it only when asked to
+struct Sink {
+    bytes: u64,
+    kept: Option<Vec<u8>>,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.bytes += buffer.len() as u64;
+        if let Some(kept) = &mut self.kept {
+            kept.extend_from_slice(buffer);
+        }
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Decodes a response body chunk by chunk, as it is read
+pub struct Decoder(Decoding);
+
+enum Decoding {
+    Identity(Sink),
+    Gzip(GzDecoder<Sink>),
+}
+
+impl Decoder {
+    // `keep` holds on to the decoded body, for checks and the script
+    pub fn new(headers: &HeaderMap, keep: bool) -> Self {
+        let sink = Sink {
+            bytes: 0,
+            kept: keep.then(Vec::new),
+        };
+        let gzip = headers
+            .get(CONTENT_ENCODING)
+            .and_then(|encoding| encoding.to_str().ok())
+            .is_some_and(|encoding| {
+                let encoding = encoding.trim();
+                encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip")
+            });
+        if gzip {
+            Self(Decoding::Gzip(GzDecoder::new(sink)))
+        } else {
+            Self(Decoding::Identity(sink))
+        }
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match &mut self.0 {
+            Decoding::Identity(sink) => sink.write_all(chunk),
+            Decoding::Gzip(decoder) => decoder.write_all(chunk),
+        }
+    }
+
+    // Fails if the body ended in the middle of a gzip stream
+    pub fn finish(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Decoding::Identity(_) => Ok(()),
+            Decoding::Gzip(decoder) => decoder.try_finish(),
+        }
+    }
+
+    pub fn decoded_bytes(&self) -> u64 {
+        match &self.0 {
+            Decoding::Identity(sink) => sink.bytes,
+            Decoding::Gzip(decoder) => decoder.get_ref().bytes,
+        }
+    }
+
+    pub fn into_body(mut self) -> Vec<u8> {
+        let sink = match &mut self.0 {
+            Decoding::Identity(sink) => sink,
+            Decoding::Gzip(decoder) => decoder.get_mut(),
+        };
+        sink.kept.take().unwrap_or_default()
+    }
+}
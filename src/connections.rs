@@ -0,0 +1,117 @@
+//! Connections of the load generator: established TCP connections and connection churn
+//
+// reqwest does not expose its connection pools, so connections are observed from the outside.
+// Each response carries the local address of the connection it arrived on; a local address
+// not seen before is a newly opened connection. The kernel's TCP tables, matched against the
+// process's socket file descriptors, tell which connections are still established and
+// which have closed. Only Linux has these tables; elsewhere closed connections and the
+// established count are not available. Connections of the metrics exporters (Remote Write,
+// OTLP, ...) are included in the established count only.
+
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// State column value of an established connection in /proc/net/tcp
+const TCP_ESTABLISHED: &str = "01";
+
+#[derive(Default)]
+pub struct ConnectionTracker {
+    // Local addresses of connections requests were sent on that have not been seen closing
+    open: Mutex<HashSet<SocketAddr>>,
+    opened: AtomicU64,
+    closed: AtomicU64,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called with the local address of every response's connection
+    pub fn observe(&self, local_addr: SocketAddr) {
+        if self.open.lock().insert(local_addr) {
+            self.opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Count the connections that are no longer established as closed, and return the
+    // number of established connections of the process
+    pub fn sweep(&self) -> Option<usize> {
+        let established = established_sockets()?;
+        let mut open = self.open.lock();
+        let before = open.len();
+        open.retain(|local_addr| established.contains(local_addr));
+        self.closed
+            .fetch_add((before - open.len()) as u64, Ordering::Relaxed);
+        Some(established.len())
+    }
+
+    pub fn opened(&self) -> u64 {
+        self.opened.load(Ordering::Relaxed)
+    }
+
+    pub fn closed(&self) -> u64 {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+// Local addresses of the process's established TCP connections
+fn established_sockets() -> Option<HashSet<SocketAddr>> {
+    let mut inodes = HashSet::new();
+    for entry in std::fs::read_dir("/proc/self/fd").ok()?.flatten() {
+        let Ok(target) = std::fs::read_link(entry.path()) else {
+            continue;
+        };
+        // Socket descriptors link to "socket:[INODE]"
+        if let Some(inode) = target
+            .to_str()
+            .and_then(|target| target.strip_prefix("socket:["))
+            .and_then(|target| target.strip_suffix(']'))
+        {
+            inodes.insert(inode.to_string());
+        }
+    }
+
+    let mut sockets = HashSet::new();
+    for table in ["/proc/self/net/tcp", "/proc/self/net/tcp6"] {
+        let Ok(contents) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+        for line in contents.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() > 9 && fields[3] == TCP_ESTABLISHED && inodes.contains(fields[9]) {
+                if let Some(local_addr) = parse_address(fields[1]) {
+                    sockets.insert(local_addr);
+                }
+            }
+        }
+    }
+    Some(sockets)
+}
+
+// "0100007F:1F90": the address as 32-bit words in host byte order, then the port
+fn parse_address(field: &str) -> Option<SocketAddr> {
+    let (address, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for word in 0..address.len() / 8 {
+        let word = u32::from_str_radix(address.get(word * 8..word * 8 + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?);
+            // Connections to IPv4 hosts on dual-stack sockets are reported as mapped addresses
+            match ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip),
+                None => IpAddr::V6(ip),
+            }
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,210 @@
+//! Cookie jars: cookies set by the server are sent back on later requests, per VU or shared
+//
+// reqwest's cookie store is a crate feature that is not enabled, so the jar is kept here and
+// applied to each request before it is sent. Set-Cookie headers of responses to redirects
+// that reqwest follows itself are not seen; only the final response's cookies are stored.
+
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::{Mutex, MutexGuard};
+use reqwest::header::{HeaderMap, SET_COOKIE};
+use reqwest::Url;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CookieMode {
+    // Every VU keeps its own cookies, like separate browser sessions
+    PerVu,
+    // One jar for all VUs, e.g. a session established once and reused by everyone
+    Shared,
+}
+
+impl fmt::Display for CookieMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PerVu => write!(f, "per VU"),
+            Self::Shared => write!(f, "shared"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cookie {
+    name: String,
+    value: String,
+    // Lowercase, without a leading dot
+    domain: String,
+    // Set without a Domain attribute: only sent to the exact host that set it
+    host_only: bool,
+    path: String,
+    secure: bool,
+    // Session cookies never expire during a test
+    expires: Option<DateTime<Utc>>,
+}
+
+impl Cookie {
+    // RFC 6265 section 4.1; attributes that do not affect matching are ignored
+    fn parse(header: &str, url: &Url, now: DateTime<Utc>) -> Option<Self> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let host = url.host_str()?.to_ascii_lowercase();
+
+        let mut cookie = Self {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    // A server may only set cookies for its own domain or a parent of it
+                    if !domain_matches(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => {
+                    if let Ok(expires) = DateTime::parse_from_rfc2822(value) {
+                        cookie.expires = Some(expires.with_timezone(&Utc));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Max-Age takes precedence over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(now + Duration::seconds(max_age.max(0)));
+        }
+        Some(cookie)
+    }
+
+    fn matches(&self, url: &Url, now: DateTime<Utc>) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    // Store the cookies of a response from `url`; expired ones delete earlier cookies
+    pub fn store(&mut self, url: &Url, headers: &HeaderMap) {
+        let now = Utc::now();
+        for header in headers.get_all(SET_COOKIE) {
+            let Some(cookie) = header
+                .to_str()
+                .ok()
+                .and_then(|header| Cookie::parse(header, url, now))
+            else {
+                continue;
+            };
+            self.cookies.retain(|existing| {
+                existing.name != cookie.name
+                    || existing.domain != cookie.domain
+                    || existing.path != cookie.path
+            });
+            if cookie.expires.is_none_or(|expires| expires > now) {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+
+    // Cookie header value for a request to `url`, cookies with longer paths first
+    pub fn header(&mut self, url: &Url) -> Option<String> {
+        let now = Utc::now();
+        self.cookies
+            .retain(|cookie| cookie.expires.is_none_or(|expires| expires > now));
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+}
+
+// One jar per VU, or a single jar shared by all of them
+pub struct CookieJars {
+    jars: Box<[Mutex<CookieJar>]>,
+}
+
+impl CookieJars {
+    // `vus` is the highest number of VUs (or arrival workers) that can run at once
+    pub fn new(mode: CookieMode, vus: usize) -> Self {
+        let jars = match mode {
+            CookieMode::PerVu => vus.max(1),
+            CookieMode::Shared => 1,
+        };
+        Self {
+            jars: (0..jars)
+                .map(|_| Mutex::new(CookieJar::default()))
+                .collect(),
+        }
+    }
+
+    // The jar of a 1-based VU number
+    pub fn jar(&self, vu: usize) -> MutexGuard<'_, CookieJar> {
+        self.jars[vu.saturating_sub(1) % self.jars.len()].lock()
+    }
+}
+
+// The directory of the request path, as the default cookie path
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => path[..end].to_string(),
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
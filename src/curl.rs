@@ -0,0 +1,268 @@
+//! `--from-curl`: a request copied as curl from browser devtools or API docs
+//
+// Understands the options such commands use: method, headers, data, basic auth, cookies and
+// user agent. Options that only change curl's own output (-s, -v, -i, --compressed, ...)
+// are ignored, as is any other option without an argument.
+
+use crate::scenario::{RequestSpec, Scenario};
+use crate::template::Template;
+use base64::Engine;
+use reqwest::header::{HeaderName, CONTENT_TYPE};
+use reqwest::Method;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Short options handled below that take a value, which may be attached as in -XPOST
+const SHORT_OPTIONS_WITH_VALUE: [&str; 7] = ["-X", "-H", "-d", "-u", "-b", "-A", "-e"];
+
+// Options whose value is the next word, other than the ones handled below
+const OPTIONS_WITH_VALUE: [&str; 12] = [
+    "-o",
+    "--output",
+    "-w",
+    "--write-out",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "--retry",
+    "-x",
+    "--proxy",
+    "--cacert",
+    "--resolve",
+];
+
+#[derive(Debug, Clone)]
+pub struct CurlRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(HeaderName, String)>,
+    pub body: Option<String>,
+    // -k/--insecure
+    pub insecure: bool,
+}
+
+impl CurlRequest {
+    pub fn parse(command: &str) -> Result<Self, BoxError> {
+        let words = split_words(command)?;
+        let mut words = words.iter().map(String::as_str).peekable();
+        if words.peek() == Some(&"curl") {
+            words.next();
+        }
+
+        let mut method = None;
+        let mut url = None;
+        let mut headers = Vec::new();
+        let mut data: Vec<String> = Vec::new();
+        let mut get = false;
+        let mut insecure = false;
+        while let Some(word) = words.next() {
+            // Values may be attached: --header=..., -HAccept:...
+            let (option, inline_value) = match word.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option, Some(value)),
+                _ if word.len() > 2
+                    && word.is_char_boundary(2)
+                    && SHORT_OPTIONS_WITH_VALUE.contains(&&word[..2]) =>
+                {
+                    (&word[..2], Some(&word[2..]))
+                }
+                _ => (word, None),
+            };
+            let mut value = || -> Result<String, BoxError> {
+                match inline_value {
+                    Some(value) => Ok(value.to_string()),
+                    None => words
+                        .next()
+                        .map(str::to_string)
+                        .ok_or_else(|| format!("curl option {} needs a value", option).into()),
+                }
+            };
+            match option {
+                "-X" | "--request" => method = Some(value()?),
+                "-H" | "--header" => {
+                    let header = value()?;
+                    let (name, value) = header
+                        .split_once(':')
+                        .ok_or_else(|| format!("invalid curl header: {}", header))?;
+                    headers.push((header_name(name)?, value.trim().to_string()));
+                }
+                "--data-raw" => data.push(value()?),
+                "-d" | "--data" | "--data-binary" | "--data-ascii" | "--data-urlencode" => {
+                    // "@file" reads the data from a file, as in curl
+                    let value = value()?;
+                    match value.strip_prefix('@') {
+                        Some(path) => data.push(
+                            std::fs::read_to_string(path)
+                                .map_err(|e| format!("failed to read {}: {}", path, e))?,
+                        ),
+                        None => data.push(value),
+                    }
+                }
+                "--json" => {
+                    data.push(value()?);
+                    headers.push((CONTENT_TYPE, "application/json".to_string()));
+                    headers.push((header_name("accept")?, "application/json".to_string()));
+                }
+                "-u" | "--user" => {
+                    let credentials = base64::engine::general_purpose::STANDARD.encode(value()?);
+                    headers.push((
+                        header_name("authorization")?,
+                        format!("Basic {}", credentials),
+                    ));
+                }
+                "-b" | "--cookie" => headers.push((header_name("cookie")?, value()?)),
+                "-A" | "--user-agent" => headers.push((header_name("user-agent")?, value()?)),
+                "-e" | "--referer" => headers.push((header_name("referer")?, value()?)),
+                "--url" => url = Some(value()?),
+                "-G" | "--get" => get = true,
+                "-k" | "--insecure" => insecure = true,
+                _ if OPTIONS_WITH_VALUE.contains(&option) => {
+                    value()?;
+                }
+                _ if option.starts_with('-') && option.len() > 1 => {}
+                _ => url = Some(word.to_string()),
+            }
+        }
+
+        let mut url = url.ok_or("curl command has no URL")?;
+        // curl joins several -d values with "&"
+        let mut body = (!data.is_empty()).then(|| data.join("&"));
+        if get {
+            if let Some(query) = body.take() {
+                url.push(if url.contains('?') { '&' } else { '?' });
+                url.push_str(&query);
+            }
+        }
+        let method = match method {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| format!("invalid HTTP method: {}", method))?,
+            None if body.is_some() => Method::POST,
+            None => Method::GET,
+        };
+        // As curl does for -d without an explicit content type
+        if body.is_some() && !headers.iter().any(|(name, _)| name == CONTENT_TYPE) {
+            headers.push((
+                CONTENT_TYPE,
+                "application/x-www-form-urlencoded".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            method,
+            url,
+            headers,
+            body,
+            insecure,
+        })
+    }
+
+    // A single-request scenario; the copied values are sent as is, without placeholders
+    pub fn scenario(&self) -> Result<Scenario, BoxError> {
+        Scenario::new(vec![RequestSpec {
+            target: self.url.clone(),
+            name: None,
+            method: self.method.clone(),
+            url: Template::literal(&self.url),
+            headers: self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), Template::literal(value)))
+                .collect(),
+            body: self.body.as_deref().map(Template::literal),
+            weight: 1,
+            checks: Vec::new(),
+            graphql: false,
+            timeout: None,
+        }])
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName, BoxError> {
+    HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid curl header name: {}", name).into())
+}
+
+// POSIX shell word splitting as far as copied curl commands need it: single and double
+// quotes, backslash escapes, line continuations and bash's $'...' strings
+fn split_words(command: &str) -> Result<Vec<String>, BoxError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated ' in curl command".into()),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated $' in curl command".into()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated $' in curl command".into()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated \" in curl command".into()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated \" in curl command".into()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                // Line continuation, with Windows line endings too
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some('\n') => {}
+                Some(c) => {
+                    in_word = true;
+                    word.push(c);
+                }
+                None => {}
+            },
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
@@ -0,0 +1,97 @@
+//! CSV data feeds whose columns are injected into request templates as `${csv.column}`
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// How rows are handed out to VUs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DataMode {
+    // Every request takes the next row, shared across all VUs
+    RoundRobin,
+    // Every VU cycles through its own disjoint share of the rows
+    Partition,
+}
+
+// The row a request is rendered with
+#[derive(Debug, Clone, Copy)]
+pub struct DataRow<'a> {
+    columns: &'a [String],
+    values: &'a [String],
+}
+
+impl<'a> DataRow<'a> {
+    pub fn get(&self, column: &str) -> Option<&'a str> {
+        self.columns
+            .iter()
+            .position(|c| c == column)
+            .and_then(|index| self.values.get(index))
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug)]
+pub struct DataFeed {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    mode: DataMode,
+    // Number of VU partitions in partition mode
+    partitions: usize,
+    next_row: AtomicUsize,
+}
+
+impl DataFeed {
+    // The first line of the file names the columns
+    pub fn from_csv(path: &str, mode: DataMode, partitions: usize) -> Result<Self, BoxError> {
+        let mut reader =
+            csv::Reader::from_path(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let columns = reader
+            .headers()
+            .map_err(|e| format!("failed to read header of {}: {}", path, e))?
+            .iter()
+            .map(|c| c.trim().to_string())
+            .collect();
+        let rows = reader
+            .records()
+            .map(|record| record.map(|r| r.iter().map(String::from).collect()))
+            .collect::<Result<Vec<Vec<String>>, _>>()
+            .map_err(|e| format!("invalid CSV in {}: {}", path, e))?;
+        if rows.is_empty() {
+            return Err(format!("{} has no data rows", path).into());
+        }
+
+        Ok(Self {
+            columns,
+            rows,
+            mode,
+            partitions: partitions.max(1),
+            next_row: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn has_column(&self, column: &str) -> bool {
+        self.columns.iter().any(|c| c == column)
+    }
+
+    // `vu_index` is 0-based; VUs beyond the number of rows share rows with lower VUs
+    pub fn row(&self, vu_index: usize, iteration: u64) -> DataRow<'_> {
+        let index = match self.mode {
+            DataMode::RoundRobin => self.next_row.fetch_add(1, Ordering::Relaxed) % self.rows.len(),
+            DataMode::Partition => {
+                let partition = vu_index % self.partitions;
+                if partition >= self.rows.len() {
+                    partition % self.rows.len()
+                } else {
+                    // Rows partition, partition + N, partition + 2N, ...
+                    let owned = (self.rows.len() - partition).div_ceil(self.partitions);
+                    partition + self.partitions * (iteration as usize % owned)
+                }
+            }
+        };
+
+        DataRow {
+            columns: &self.columns,
+            values: &self.rows[index],
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! `--debug-sample`: full request/response pairs of the first requests and failures
+//
+// The first N requests and the first N failed ones are logged with their request line,
+// headers and body, and the status line, headers and body of the response, so a wrong header
+// or credential shows without capturing traffic. Bodies are cut after MAX_BODY_BYTES and shown
+// as decoded text. Requests are only captured while one of the two counts has room left, so
+// a long run pays nothing for it once the samples are taken. A dry run prints every exchange
+// to stdout instead.
+
+use reqwest::header::HeaderMap;
+use reqwest::{Request, StatusCode, Version};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MAX_BODY_BYTES: usize = 2048;
+
+pub struct DebugSample {
+    limit: usize,
+    requests: AtomicUsize,
+    failures: AtomicUsize,
+    print: bool,
+}
+
+impl DebugSample {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            requests: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            print: false,
+        }
+    }
+
+    // Every exchange, printed; for --dry-run
+    pub fn printing() -> Self {
+        Self {
+            limit: usize::MAX,
+            print: true,
+            ..Self::new(0)
+        }
+    }
+
+    // Whether the next request may still be logged
+    pub fn active(&self) -> bool {
+        self.requests.load(Ordering::Relaxed) < self.limit
+            || self.failures.load(Ordering::Relaxed) < self.limit
+    }
+
+    // Logs a captured exchange if it is among the first requests or failures
+    pub fn offer(&self, endpoint: &str, success: bool, request: &str, response: &str) {
+        let number = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let failure = if success {
+            None
+        } else {
+            Some(self.failures.fetch_add(1, Ordering::Relaxed) + 1)
+        };
+        let label = match failure.filter(|failure| *failure <= self.limit) {
+            Some(failure) => format!("request {} (failure {}): {}", number, failure, endpoint),
+            None if number <= self.limit => format!("request {}: {}", number, endpoint),
+            None => return,
+        };
+        if self.print {
+            println!("\n{}\n{}\n{}", label, request, response);
+        } else {
+            tracing::info!("{}\n{}\n{}", label, request, response);
+        }
+    }
+}
+
+// "> GET https://..." and "> name: value" lines, then the body
+pub fn describe_request(request: &Request) -> String {
+    let mut text = format!(
+        "> {} {} {:?}",
+        request.method(),
+        request.url(),
+        request.version()
+    );
+    write_headers(&mut text, '>', request.headers());
+    if let Some(body) = request.body() {
+        text.push_str("\n>");
+        match body.as_bytes() {
+            Some(bytes) => write_body(&mut text, bytes),
+            None => text.push_str("\n(streamed body)"),
+        }
+    }
+    text
+}
+
+// "< HTTP/1.1 200 OK" and "< name: value" lines; the body is added with `add_body`
+pub fn describe_response(version: Version, status: StatusCode, headers: &HeaderMap) -> String {
+    let mut text = format!("< {:?} {}", version, status);
+    write_headers(&mut text, '<', headers);
+    text
+}
+
+pub fn add_body(response: &mut String, body: Option<&str>) {
+    response.push_str("\n<");
+    match body {
+        Some(body) => write_body(response, body.as_bytes()),
+        None => response.push_str("\n(body not read, --discard-body)"),
+    }
+}
+
+fn write_headers(text: &mut String, prefix: char, headers: &HeaderMap) {
+    for (name, value) in headers {
+        let _ = write!(
+            text,
+            "\n{} {}: {}",
+            prefix,
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+}
+
+fn write_body(text: &mut String, body: &[u8]) {
+    let shown = &body[..body.len().min(MAX_BODY_BYTES)];
+    let _ = write!(text, "\n{}", String::from_utf8_lossy(shown));
+    if shown.len() < body.len() {
+        let _ = write!(text, "\n... ({} more bytes)", body.len() - shown.len());
+    }
+}
@@ -0,0 +1,347 @@
+//! DNS resolution: `--resolve` overrides and queries to a specific `--dns-server`
+//
+// Overrides are curl-style HOST:PORT:ADDR entries. The connector of the transport looks them
+// up with the port it connects to; reqwest's resolver (HTTP/3) only gets the host name, so
+// the port of the request being sent is kept in a task-local around it, like the connection
+// timing slot.
+// `--dns-server` sends A and AAAA queries over UDP to the given server instead of using the
+// system resolver (/etc/hosts and search domains do not apply then). With --ipv4 or --ipv6,
+// addresses of the other family are dropped from every answer and override.
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+tokio::task_local! {
+    static PORT: Option<u16>;
+}
+
+// Host of an override that applies to every host, as in curl
+const ANY_HOST: &str = "*";
+
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const QUERY_ATTEMPTS: usize = 2;
+// Advertised with EDNS so that larger answers are not truncated
+const UDP_PAYLOAD_SIZE: u16 = 4096;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_OPT: u16 = 41;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn contains(self, ip: &IpAddr) -> bool {
+        match self {
+            Self::V4 => ip.is_ipv4(),
+            Self::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4 => write!(f, "IPv4"),
+            Self::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Resolver {
+    // By lowercase host and port
+    overrides: HashMap<(String, u16), Vec<IpAddr>>,
+    server: Option<SocketAddr>,
+    // Only addresses of this family are connected to, with --ipv4/--ipv6
+    family: Option<AddressFamily>,
+}
+
+impl Resolver {
+    pub fn new(
+        resolve: &[String],
+        dns_server: Option<&str>,
+        family: Option<AddressFamily>,
+    ) -> Result<Self, BoxError> {
+        let mut overrides = HashMap::new();
+        for entry in resolve {
+            let (host, port, addrs) = parse_override(entry)?;
+            overrides.insert((host, port), addrs);
+        }
+        let server = dns_server.map(parse_server).transpose()?;
+        Ok(Self {
+            overrides,
+            server,
+            family,
+        })
+    }
+
+    // The addresses `--resolve` gives for `host` on the port of the current request
+    pub fn override_for(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let port = PORT.try_with(|port| *port).ok().flatten()?;
+        self.override_for_port(host, port)
+    }
+
+    pub fn override_for_port(&self, host: &str, port: u16) -> Option<Vec<IpAddr>> {
+        let host = host.to_ascii_lowercase();
+        self.overrides
+            .get(&(host, port))
+            .or_else(|| self.overrides.get(&(ANY_HOST.to_string(), port)))
+            .map(|addrs| self.of_family(addrs.iter().copied()))
+    }
+
+    pub async fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let addrs = match self.server {
+            Some(server) => lookup_with(server, host, self.family).await?,
+            None => self.of_family(
+                tokio::net::lookup_host((host, 0))
+                    .await?
+                    .map(|addr| addr.ip()),
+            ),
+        };
+        match self.family {
+            Some(family) if addrs.is_empty() => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no {} addresses", host, family),
+            )),
+            _ => Ok(addrs),
+        }
+    }
+
+    fn of_family(&self, addrs: impl Iterator<Item = IpAddr>) -> Vec<IpAddr> {
+        addrs
+            .filter(|ip| self.family.is_none_or(|family| family.contains(ip)))
+            .collect()
+    }
+}
+
+// Run a request future with the port its URL connects to, for port-specific overrides
+pub async fn with_port<F: std::future::Future>(port: Option<u16>, future: F) -> F::Output {
+    PORT.scope(port, future).await
+}
+
+// "HOST:PORT:ADDR[,ADDR...]", IPv6 addresses optionally in brackets
+fn parse_override(entry: &str) -> Result<(String, u16, Vec<IpAddr>), BoxError> {
+    let invalid = || format!("--resolve must be HOST:PORT:ADDR[,ADDR...]: {}", entry);
+    let mut parts = entry.splitn(3, ':');
+    let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid().into());
+    };
+    if host.is_empty() {
+        return Err(invalid().into());
+    }
+    let port = port.parse::<u16>().map_err(|_| invalid())?;
+    let addrs = addrs
+        .split(',')
+        .map(|addr| {
+            let addr = addr.trim();
+            let addr = addr
+                .strip_prefix('[')
+                .and_then(|addr| addr.strip_suffix(']'))
+                .unwrap_or(addr);
+            addr.parse::<IpAddr>()
+                .map_err(|_| format!("invalid address in --resolve {}: {}", entry, addr))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((host.to_ascii_lowercase(), port, addrs))
+}
+
+// "ADDR" or "ADDR:PORT" ("[ADDR]:PORT" for IPv6)
+fn parse_server(server: &str) -> Result<SocketAddr, BoxError> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = server
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|_| {
+            format!(
+                "--dns-server must be an IP address, optionally with a port: {}",
+                server
+            )
+        })?;
+    Ok(SocketAddr::new(ip, DNS_PORT))
+}
+
+// IPv4 addresses first, as the system resolver usually orders them for dual-stack hosts;
+// only the records of `family` are queried when it is set
+async fn lookup_with(
+    server: SocketAddr,
+    host: &str,
+    family: Option<AddressFamily>,
+) -> io::Result<Vec<IpAddr>> {
+    let queries = |record_type, wanted: AddressFamily| async move {
+        match family {
+            Some(family) if family != wanted => Ok(Vec::new()),
+            _ => query(server, host, record_type).await,
+        }
+    };
+    let (v4, v6) = tokio::join!(
+        queries(TYPE_A, AddressFamily::V4),
+        queries(TYPE_AAAA, AddressFamily::V6)
+    );
+    let addrs: Vec<IpAddr> = match (v4, v6) {
+        (Err(e), Err(_)) => return Err(e),
+        (v4, v6) => v4
+            .unwrap_or_default()
+            .into_iter()
+            .chain(v6.unwrap_or_default())
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no addresses at DNS server {}", host, server),
+        ));
+    }
+    Ok(addrs)
+}
+
+async fn query(server: SocketAddr, host: &str, record_type: u16) -> io::Result<Vec<IpAddr>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+
+    let id: u16 = rand::thread_rng().gen();
+    let message = encode_query(id, host, record_type)?;
+    let mut buffer = vec![0u8; UDP_PAYLOAD_SIZE as usize];
+    for _ in 0..QUERY_ATTEMPTS {
+        socket.send(&message).await?;
+        // Responses to other queries (or late ones to an earlier attempt) are skipped
+        let response = tokio::time::timeout(QUERY_TIMEOUT, async {
+            loop {
+                let len = socket.recv(&mut buffer).await?;
+                if len >= 2 && u16::from_be_bytes([buffer[0], buffer[1]]) == id {
+                    return Ok::<_, io::Error>(len);
+                }
+            }
+        })
+        .await;
+        match response {
+            Ok(len) => return decode_response(&buffer[..len?], record_type),
+            Err(_) => continue,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("DNS server {} did not answer for {}", server, host),
+    ))
+}
+
+// Header with recursion desired, the question and an EDNS OPT record
+fn encode_query(id: u16, host: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(64);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&[0x01, 0x00]);
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 1]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid host name: {}", host),
+            ));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&record_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    // OPT: root name, type, UDP payload size as class, no extended flags or options
+    message.push(0);
+    message.extend_from_slice(&TYPE_OPT.to_be_bytes());
+    message.extend_from_slice(&UDP_PAYLOAD_SIZE.to_be_bytes());
+    message.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    Ok(message)
+}
+
+// Addresses of the answer section; CNAME records are skipped, their targets' records
+// follow them in the answer of a recursive server
+fn decode_response(message: &[u8], record_type: u16) -> io::Result<Vec<IpAddr>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    if message.len() < 12 {
+        return Err(malformed());
+    }
+    if message[2] & 0x02 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated DNS response",
+        ));
+    }
+    match message[3] & 0x0f {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Vec::new()),
+        rcode => {
+            return Err(io::Error::other(format!(
+                "DNS server answered with error code {}",
+                rcode
+            )))
+        }
+    }
+    let questions = u16::from_be_bytes([message[4], message[5]]);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(message, offset).ok_or_else(malformed)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(message, offset).ok_or_else(malformed)?;
+        let field = |at: usize| {
+            message
+                .get(at..at + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+        let kind = field(offset).ok_or_else(malformed)?;
+        let class = field(offset + 2).ok_or_else(malformed)?;
+        let len = field(offset + 8).ok_or_else(malformed)? as usize;
+        let data = message
+            .get(offset + 10..offset + 10 + len)
+            .ok_or_else(malformed)?;
+        offset += 10 + len;
+        if class != CLASS_IN || kind != record_type {
+            continue;
+        }
+        match (kind, data.len()) {
+            (TYPE_A, 4) => addrs.push(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (TYPE_AAAA, 16) => addrs.push(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(data).map_err(|_| malformed())?,
+            ))),
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(addrs)
+}
+
+// Offset after the (possibly compressed) name at `offset`
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)?;
+        match len {
+            0 => return Some(offset + 1),
+            // A pointer ends the name
+            len if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
+}
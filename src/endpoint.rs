@@ -0,0 +1,96 @@
+//! Endpoints: requests grouped by name or normalized path for the per-endpoint breakdown
+//
+// A request is reported under its `--name` (or scenario file name) when it has one, and
+// otherwise under its method and path with IDs replaced, e.g. "GET /users/{id}", so that
+// templated URLs and replayed logs do not produce one endpoint per ID. The number of
+// distinct endpoints is capped; requests beyond the cap are reported as "other" to keep
+// the `endpoint` label's cardinality bounded.
+
+use crate::scenario::RequestSpec;
+use parking_lot::RwLock;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+// Distinct endpoints reported before the rest is grouped into OTHER
+pub const MAX_ENDPOINTS: usize = 100;
+pub const OTHER: &str = "other";
+
+// Placeholder for path segments that look like IDs
+const ID: &str = "{id}";
+
+#[derive(Default)]
+pub struct Endpoints {
+    seen: RwLock<HashSet<String>>,
+}
+
+impl Endpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The endpoint a request for `url` (the rendered URL of `spec`) is reported under
+    pub fn resolve<'a>(&self, spec: &'a RequestSpec, url: &str) -> Cow<'a, str> {
+        let endpoint = match &spec.name {
+            Some(name) => Cow::Borrowed(name.as_str()),
+            None => match url.strip_prefix("tcp://") {
+                Some(address) => Cow::Owned(format!("TCP {}", address)),
+                None => Cow::Owned(format!("{} {}", spec.method, normalize_path(url))),
+            },
+        };
+        if self.seen.read().contains(endpoint.as_ref()) {
+            return endpoint;
+        }
+        let mut seen = self.seen.write();
+        if seen.len() >= MAX_ENDPOINTS && !seen.contains(endpoint.as_ref()) {
+            return Cow::Borrowed(OTHER);
+        }
+        seen.insert(endpoint.to_string());
+        endpoint
+    }
+}
+
+// The path of `url` without its query string, with ID-like segments replaced by {id}
+pub fn normalize_path(url: &str) -> String {
+    let path = match url.find("://") {
+        Some(scheme_end) => {
+            let authority = &url[scheme_end + 3..];
+            &authority[authority.find('/').unwrap_or(authority.len())..]
+        }
+        None => url,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| if is_id(segment) { ID } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Numbers, UUIDs, long hex strings (hashes, object IDs) and long tokens containing digits
+fn is_id(segment: &str) -> bool {
+    if segment.is_empty() {
+        return false;
+    }
+    if segment.bytes().all(|b| b.is_ascii_digit()) || is_uuid(segment) {
+        return true;
+    }
+    if segment.len() >= 16 && segment.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return true;
+    }
+    segment.len() >= 20
+        && segment.bytes().any(|b| b.is_ascii_digit())
+        && segment
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn is_uuid(segment: &str) -> bool {
+    let groups: Vec<&str> = segment.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
@@ -0,0 +1,196 @@
+//! Classification of failed requests
+
+use crate::transport::ConnectError;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::io;
+
+// Failure modes exported as the `error_type` label of forgy_errors_total
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ErrorType {
+    // The whole request took longer than --request-timeout (or the step's timeout)
+    #[serde(rename = "timeout")]
+    Timeout,
+    // No connection could be opened within --connect-timeout
+    #[serde(rename = "connect_timeout")]
+    ConnectTimeout,
+    #[serde(rename = "dns")]
+    Dns,
+    #[serde(rename = "connection_refused")]
+    ConnectionRefused,
+    #[serde(rename = "connection_reset")]
+    ConnectionReset,
+    #[serde(rename = "connect")]
+    Connect,
+    #[serde(rename = "tls")]
+    Tls,
+    #[serde(rename = "body")]
+    Body,
+    #[serde(rename = "5xx")]
+    ServerError,
+    #[serde(rename = "check_failed")]
+    CheckFailed,
+    #[serde(rename = "graphql")]
+    GraphQl,
+    // The --script failed or exited, so the request was not sent or not judged
+    #[serde(rename = "script")]
+    Script,
+    #[serde(rename = "other")]
+    Other,
+}
+
+impl ErrorType {
+    pub const ALL: [ErrorType; 13] = [
+        ErrorType::Timeout,
+        ErrorType::ConnectTimeout,
+        ErrorType::Dns,
+        ErrorType::ConnectionRefused,
+        ErrorType::ConnectionReset,
+        ErrorType::Connect,
+        ErrorType::Tls,
+        ErrorType::Body,
+        ErrorType::ServerError,
+        ErrorType::CheckFailed,
+        ErrorType::GraphQl,
+        ErrorType::Script,
+        ErrorType::Other,
+    ];
+
+    // The error type of an `error_type` label value
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|error_type| error_type.as_str() == name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorType::Timeout => "timeout",
+            ErrorType::ConnectTimeout => "connect_timeout",
+            ErrorType::Dns => "dns",
+            ErrorType::ConnectionRefused => "connection_refused",
+            ErrorType::ConnectionReset => "connection_reset",
+            ErrorType::Connect => "connect",
+            ErrorType::Tls => "tls",
+            ErrorType::Body => "body",
+            ErrorType::ServerError => "5xx",
+            ErrorType::CheckFailed => "check_failed",
+            ErrorType::GraphQl => "graphql",
+            ErrorType::Script => "script",
+            ErrorType::Other => "other",
+        }
+    }
+
+    // Classify a transport-level failure reported by reqwest, including failures while
+    // reading the response body
+    pub fn from_reqwest(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            // Connect timeouts fail the connector; the request timeout fails the request
+            return if err.is_connect() {
+                ErrorType::ConnectTimeout
+            } else {
+                ErrorType::Timeout
+            };
+        }
+        if Self::source_contains(err, &["certificate", "tls", "handshake"]) {
+            return ErrorType::Tls;
+        }
+        match Self::io_error_kind(err) {
+            Some(io::ErrorKind::ConnectionRefused) => return ErrorType::ConnectionRefused,
+            Some(
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof,
+            ) => return ErrorType::ConnectionReset,
+            _ => {}
+        }
+        if err.is_connect() {
+            // Resolver failures surface as connect errors
+            if Self::source_contains(err, &["lookup address", "dns", "name or service"]) {
+                ErrorType::Dns
+            } else {
+                ErrorType::Connect
+            }
+        } else if err.is_body() || err.is_decode() {
+            ErrorType::Body
+        } else if Self::source_contains(err, &["connection closed", "connection reset"]) {
+            ErrorType::ConnectionReset
+        } else {
+            ErrorType::Other
+        }
+    }
+
+    // Classify a failure of the transport (transport.rs) before the response headers arrived
+    pub fn from_hyper(err: &hyper::Error) -> Self {
+        // The connector classifies its own failures
+        let mut source = err.source();
+        while let Some(cause) = source {
+            if let Some(connect) = cause.downcast_ref::<ConnectError>() {
+                return connect.error_type();
+            }
+            source = cause.source();
+        }
+        match Self::io_error_kind(err) {
+            Some(io::ErrorKind::ConnectionRefused) => ErrorType::ConnectionRefused,
+            Some(
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof,
+            ) => ErrorType::ConnectionReset,
+            _ if err.is_incomplete_message()
+                || Self::source_contains(err, &["connection closed", "connection reset"]) =>
+            {
+                ErrorType::ConnectionReset
+            }
+            _ => ErrorType::Other,
+        }
+    }
+
+    // Classify a failure of a raw TCP connection (--protocol tcp)
+    pub fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => ErrorType::ConnectionRefused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => ErrorType::ConnectionReset,
+            io::ErrorKind::TimedOut => ErrorType::ConnectTimeout,
+            _ => ErrorType::Connect,
+        }
+    }
+
+    // Classify a completed response by its status code
+    pub fn from_status(status_code: u16) -> Option<Self> {
+        match status_code {
+            500..=599 => Some(ErrorType::ServerError),
+            _ => None,
+        }
+    }
+
+    // reqwest and hyper do not expose most failure causes directly, so look through the
+    // source chain
+    fn source_contains(err: &(dyn StdError + 'static), needles: &[&str]) -> bool {
+        let mut source = err.source();
+        while let Some(cause) = source {
+            let message = cause.to_string().to_lowercase();
+            if needles.iter().any(|needle| message.contains(needle)) {
+                return true;
+            }
+            source = cause.source();
+        }
+        false
+    }
+
+    fn io_error_kind(err: &(dyn StdError + 'static)) -> Option<io::ErrorKind> {
+        let mut source = err.source();
+        while let Some(cause) = source {
+            if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+                return Some(io_error.kind());
+            }
+            source = cause.source();
+        }
+        None
+    }
+}
@@ -0,0 +1,77 @@
+//! `--expect-status`: which response statuses count as success
+//
+// Without it, 2xx responses succeed. A list may name codes ("200,201,204"), ranges
+// ("200-299") and classes ("2xx"); "Nxx-ok" adds a class to the default 2xx, e.g. "4xx-ok" for
+// negative tests that probe missing resources. An expected 5xx is not counted as a server
+// error.
+
+use std::ops::RangeInclusive;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedStatus {
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl Default for ExpectedStatus {
+    fn default() -> Self {
+        Self {
+            ranges: vec![200..=299],
+        }
+    }
+}
+
+impl ExpectedStatus {
+    pub fn parse(spec: &str) -> Result<Self, BoxError> {
+        let mut ranges = Vec::new();
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let item = item.to_ascii_lowercase();
+            if let Some(class) = item.strip_suffix("-ok") {
+                ranges.push(200..=299);
+                ranges.push(parse_class(class)?);
+            } else if item.ends_with("xx") {
+                ranges.push(parse_class(&item)?);
+            } else if let Some((from, to)) = item.split_once('-') {
+                let (from, to) = (parse_code(from)?, parse_code(to)?);
+                if from > to {
+                    return Err(format!("invalid status range '{}'", item).into());
+                }
+                ranges.push(from..=to);
+            } else {
+                let code = parse_code(&item)?;
+                ranges.push(code..=code);
+            }
+        }
+        if ranges.is_empty() {
+            return Err("--expect-status needs at least one status".into());
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn contains(&self, status_code: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(&status_code))
+    }
+}
+
+// "4xx"
+fn parse_class(class: &str) -> Result<RangeInclusive<u16>, BoxError> {
+    match class.as_bytes() {
+        [digit @ b'1'..=b'5', b'x', b'x'] => {
+            let from = u16::from(digit - b'0') * 100;
+            Ok(from..=from + 99)
+        }
+        _ => Err(format!("invalid status class '{}', expected 1xx to 5xx", class).into()),
+    }
+}
+
+fn parse_code(code: &str) -> Result<u16, BoxError> {
+    match code.trim().parse::<u16>() {
+        Ok(code @ 100..=599) => Ok(code),
+        _ => Err(format!("invalid status code '{}'", code.trim()).into()),
+    }
+}
@@ -0,0 +1,58 @@
+//! Per-request metric sinks: the Prometheus registry and StatsD
+//
+// Every finished request and evaluated check, and every event of a --sse stream, is handed
+// to each configured exporter. The Prometheus exporter updates the registry that Remote
+// Write, OTLP, InfluxDB and the scrape endpoint read from; the StatsD exporter sends each
+// event over UDP as it happens.
+
+mod prometheus;
+mod statsd;
+
+pub use self::prometheus::PrometheusExporter;
+pub use self::statsd::{StatsdConfig, StatsdExporter};
+
+use crate::errors::ErrorType;
+use crate::stats::{self, ByteCounts};
+use crate::timing::PhaseTimings;
+use crate::trace::TraceContext;
+use std::time::Duration;
+
+// A finished request
+pub struct RequestEvent<'a> {
+    pub method: &'a str,
+    pub target: &'a str,
+    // Name or normalized path the request is grouped under
+    pub endpoint: &'a str,
+    // Scenario of a scenario file's `scenarios` that sent the request, "default" without them
+    pub scenario: &'a str,
+    // 0 when no response was received
+    pub status_code: u16,
+    // Negotiated HTTP version, "none" without a response
+    pub protocol: &'a str,
+    pub duration: Duration,
+    pub bytes: ByteCounts,
+    pub error_type: Option<ErrorType>,
+    pub phases: &'a PhaseTimings,
+    pub trace: Option<&'a TraceContext>,
+    // A retry of a failed request rather than its first attempt
+    pub retry: bool,
+}
+
+impl RequestEvent<'_> {
+    pub fn status_class(&self) -> &'static str {
+        stats::status_class(self.status_code)
+    }
+}
+
+pub trait Exporter: Send + Sync {
+    fn record_request(&self, event: &RequestEvent);
+
+    fn record_check(&self, name: &str, passed: bool);
+
+    // A --sse stream opened (`open`) or ended
+    fn record_sse_stream(&self, open: bool);
+
+    // An event of a --sse stream; `latency` is the time to the stream's first event (`first`)
+    // or since the previous one
+    fn record_sse_event(&self, endpoint: &str, first: bool, latency: Duration);
+}
@@ -0,0 +1,134 @@
+//! Exporter that updates the Prometheus registry
+
+use super::{Exporter, RequestEvent};
+use crate::timing;
+use crate::trace;
+use crate::{
+    CHECKS_COUNTER, DATA_DECODED, DATA_RECEIVED, DATA_SENT, DURATION_BUCKETS, ERRORS_COUNTER,
+    REQUEST_COUNTER, REQUEST_DURATION, REQUEST_PHASE_DURATION, RETRIES_COUNTER, SSE_EVENTS,
+    SSE_EVENT_INTERVAL, SSE_STREAMS, SSE_TIME_TO_FIRST_EVENT,
+};
+use prometheus::HistogramVec;
+use std::time::Duration;
+
+pub struct PrometheusExporter;
+
+impl Exporter for PrometheusExporter {
+    fn record_request(&self, event: &RequestEvent) {
+        let status_class = event.status_class();
+        let duration_secs = event.duration.as_secs_f64();
+
+        REQUEST_COUNTER
+            .with_label_values(&[
+                &event.status_code.to_string(),
+                event.method,
+                event.target,
+                event.endpoint,
+                event.protocol,
+                event.scenario,
+            ])
+            .inc();
+
+        REQUEST_DURATION
+            .with_label_values(&[
+                event.method,
+                status_class,
+                event.target,
+                event.endpoint,
+                event.scenario,
+            ])
+            .observe(duration_secs);
+        if let Some(context) = event.trace.filter(|context| context.sampled) {
+            trace::record_exemplar(
+                "forgy_request_duration_seconds",
+                // Ordered by label name, as the gathered series are
+                &[
+                    event.endpoint,
+                    event.method,
+                    event.scenario,
+                    status_class,
+                    event.target,
+                ],
+                &DURATION_BUCKETS,
+                duration_secs,
+                context,
+            );
+        }
+
+        for (part, sent, received) in [
+            (
+                "header",
+                event.bytes.header_sent,
+                event.bytes.header_received,
+            ),
+            ("body", event.bytes.body_sent, event.bytes.body_received),
+        ] {
+            DATA_SENT
+                .with_label_values(&[event.method, part])
+                .inc_by(sent);
+            DATA_RECEIVED
+                .with_label_values(&[event.method, status_class, part])
+                .inc_by(received);
+        }
+        for (direction, bytes) in [
+            ("sent", event.bytes.decoded_body_sent),
+            ("received", event.bytes.decoded_body_received),
+        ] {
+            DATA_DECODED
+                .with_label_values(&[event.method, direction])
+                .inc_by(bytes);
+        }
+
+        if event.retry {
+            RETRIES_COUNTER
+                .with_label_values(&[event.method, event.target, event.endpoint, event.scenario])
+                .inc();
+        }
+
+        if let Some(error_type) = event.error_type {
+            ERRORS_COUNTER
+                .with_label_values(&[
+                    error_type.as_str(),
+                    event.target,
+                    event.endpoint,
+                    event.scenario,
+                ])
+                .inc();
+        }
+
+        // Only the connection phases can be timed for requests that never got a response
+        for (phase, ms) in event.phases.phases() {
+            if event.status_code == 0 && !timing::is_connection_phase(phase) {
+                continue;
+            }
+            REQUEST_PHASE_DURATION
+                .with_label_values(&[phase])
+                .observe(ms / 1000.0);
+        }
+    }
+
+    fn record_check(&self, name: &str, passed: bool) {
+        let result = if passed { "pass" } else { "fail" };
+        CHECKS_COUNTER.with_label_values(&[name, result]).inc();
+    }
+
+    fn record_sse_stream(&self, open: bool) {
+        if open {
+            SSE_STREAMS.inc();
+        } else {
+            SSE_STREAMS.dec();
+        }
+    }
+
+    fn record_sse_event(&self, endpoint: &str, first: bool, latency: Duration) {
+        SSE_EVENTS.with_label_values(&[endpoint]).inc();
+        let histogram: &HistogramVec = if first {
+            &SSE_TIME_TO_FIRST_EVENT
+        } else {
+            &SSE_EVENT_INTERVAL
+        };
+        histogram
+            .with_label_values(&[endpoint])
+            .observe(latency.as_secs_f64());
+    }
+}
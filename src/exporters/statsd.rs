@@ -0,0 +1,268 @@
+//! Exporter that sends each request as StatsD metrics with DogStatsD tags
+
+use super::{Exporter, RequestEvent};
+use crate::timing;
+use std::fmt::Write;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Keeps datagrams within a typical MTU; larger events are split across several
+const MAX_PACKET_SIZE: usize = 1432;
+
+#[derive(Debug, Clone)]
+pub struct StatsdConfig {
+    // host:port of the StatsD server or Datadog agent
+    pub address: String,
+    // Prepended to every metric name, separated by a dot
+    pub prefix: String,
+    // Sent with every metric, after the per-metric tags
+    pub tags: Vec<(String, String)>,
+}
+
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    // Rendered once, without the leading separator
+    common_tags: String,
+}
+
+impl StatsdExporter {
+    pub fn new(config: StatsdConfig) -> Result<Self, BoxError> {
+        let address = config
+            .address
+            .to_socket_addrs()
+            .map_err(|e| format!("invalid StatsD address {}: {}", config.address, e))?
+            .next()
+            .ok_or_else(|| format!("StatsD address {} did not resolve", config.address))?;
+        let bind = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(address)?;
+        // A VU must never wait on the metrics sink
+        socket.set_nonblocking(true)?;
+
+        let common_tags = config
+            .tags
+            .iter()
+            .map(|(name, value)| format!("{}:{}", sanitize(name), sanitize(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(Self {
+            socket,
+            prefix: config.prefix,
+            common_tags,
+        })
+    }
+
+    // One `name:value|type|#tags` line
+    fn line(&self, lines: &mut String, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) {
+        if !lines.is_empty() {
+            lines.push('\n');
+        }
+        let _ = write!(lines, "{}.{}:{}|{}", self.prefix, name, value, kind);
+        let mut separator = "|#";
+        for (tag, tag_value) in tags {
+            let _ = write!(lines, "{}{}:{}", separator, tag, sanitize(tag_value));
+            separator = ",";
+        }
+        if !self.common_tags.is_empty() {
+            lines.push_str(separator);
+            lines.push_str(&self.common_tags);
+        }
+    }
+
+    // Pack lines into as few datagrams as possible; drops are fine for StatsD, so send
+    // errors (e.g. a full socket buffer) are ignored
+    fn send(&self, lines: &str) {
+        let mut packet = String::new();
+        for line in lines.lines() {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+                self.socket.send(packet.as_bytes()).ok();
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(line);
+        }
+        if !packet.is_empty() {
+            self.socket.send(packet.as_bytes()).ok();
+        }
+    }
+}
+
+impl Exporter for StatsdExporter {
+    fn record_request(&self, event: &RequestEvent) {
+        let status = event.status_code.to_string();
+        let status_class = event.status_class();
+        let mut lines = String::new();
+
+        self.line(
+            &mut lines,
+            "requests",
+            "1",
+            "c",
+            &[
+                ("method", event.method),
+                ("status", &status),
+                ("target", event.target),
+                ("endpoint", event.endpoint),
+                ("protocol", event.protocol),
+                ("scenario", event.scenario),
+            ],
+        );
+        self.line(
+            &mut lines,
+            "request.duration",
+            &format!("{:.3}", event.duration.as_secs_f64() * 1000.0),
+            "ms",
+            &[
+                ("method", event.method),
+                ("status_class", status_class),
+                ("target", event.target),
+                ("endpoint", event.endpoint),
+                ("scenario", event.scenario),
+            ],
+        );
+        for (part, sent, received) in [
+            (
+                "header",
+                event.bytes.header_sent,
+                event.bytes.header_received,
+            ),
+            ("body", event.bytes.body_sent, event.bytes.body_received),
+        ] {
+            self.line(
+                &mut lines,
+                "data.sent",
+                &sent.to_string(),
+                "c",
+                &[("method", event.method), ("part", part)],
+            );
+            self.line(
+                &mut lines,
+                "data.received",
+                &received.to_string(),
+                "c",
+                &[
+                    ("method", event.method),
+                    ("status_class", status_class),
+                    ("part", part),
+                ],
+            );
+        }
+        for (direction, bytes) in [
+            ("sent", event.bytes.decoded_body_sent),
+            ("received", event.bytes.decoded_body_received),
+        ] {
+            self.line(
+                &mut lines,
+                "data.decoded",
+                &bytes.to_string(),
+                "c",
+                &[("method", event.method), ("direction", direction)],
+            );
+        }
+        if event.retry {
+            self.line(
+                &mut lines,
+                "retries",
+                "1",
+                "c",
+                &[
+                    ("method", event.method),
+                    ("target", event.target),
+                    ("endpoint", event.endpoint),
+                    ("scenario", event.scenario),
+                ],
+            );
+        }
+        if let Some(error_type) = event.error_type {
+            self.line(
+                &mut lines,
+                "errors",
+                "1",
+                "c",
+                &[
+                    ("error_type", error_type.as_str()),
+                    ("target", event.target),
+                    ("endpoint", event.endpoint),
+                    ("scenario", event.scenario),
+                ],
+            );
+        }
+        for (phase, ms) in event.phases.phases() {
+            if event.status_code == 0 && !timing::is_connection_phase(phase) {
+                continue;
+            }
+            self.line(
+                &mut lines,
+                "request.phase.duration",
+                &format!("{:.3}", ms),
+                "ms",
+                &[("phase", phase)],
+            );
+        }
+
+        self.send(&lines);
+    }
+
+    fn record_check(&self, name: &str, passed: bool) {
+        let result = if passed { "pass" } else { "fail" };
+        let mut lines = String::new();
+        self.line(
+            &mut lines,
+            "checks",
+            "1",
+            "c",
+            &[("check", name), ("result", result)],
+        );
+        self.send(&lines);
+    }
+
+    fn record_sse_stream(&self, open: bool) {
+        let mut lines = String::new();
+        // A gauge delta, so that every load generator's streams add up
+        self.line(
+            &mut lines,
+            "sse.streams",
+            if open { "+1" } else { "-1" },
+            "g",
+            &[],
+        );
+        self.send(&lines);
+    }
+
+    fn record_sse_event(&self, endpoint: &str, first: bool, latency: Duration) {
+        let mut lines = String::new();
+        self.line(
+            &mut lines,
+            "sse.events",
+            "1",
+            "c",
+            &[("endpoint", endpoint)],
+        );
+        self.line(
+            &mut lines,
+            if first {
+                "sse.time_to_first_event"
+            } else {
+                "sse.event_interval"
+            },
+            &format!("{:.3}", latency.as_secs_f64() * 1000.0),
+            "ms",
+            &[("endpoint", endpoint)],
+        );
+        self.send(&lines);
+    }
+}
+
+// DogStatsD uses these characters as separators
+fn sanitize(text: &str) -> String {
+    text.replace([',', '|', '#', '\n'], "_")
+}
@@ -0,0 +1,92 @@
+//! `--save-failures`: responses of failed requests saved for post-mortem analysis
+//
+// Requests that failed a check (or GraphQL's error check) or got an unexpected 5xx are
+// written to `<dir>/<timestamp>-<n>.json` with their status, headers and decoded body, up to
+// the given number of files. Failures are only captured while there is room left, so a run
+// that fails all the time stops paying for it once the cap is reached.
+
+use crate::errors::ErrorType;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DEFAULT_DIR: &str = "failures";
+
+pub struct FailureCapture {
+    dir: PathBuf,
+    limit: usize,
+    saved: AtomicUsize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedRequest {
+    pub timestamp: DateTime<Utc>,
+    pub vu: usize,
+    pub endpoint: String,
+    pub method: String,
+    pub url: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub status: u16,
+    pub error_type: Option<ErrorType>,
+    pub duration_ms: f64,
+    pub response_headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+impl FailureCapture {
+    pub fn new(dir: &str, limit: usize) -> Result<Self, BoxError> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("cannot create failure directory {}: {}", dir, e))?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            limit,
+            saved: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn dir(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    pub fn saved(&self) -> usize {
+        self.saved.load(Ordering::Relaxed).min(self.limit)
+    }
+
+    // Whether a failure of the next request could still be saved
+    pub fn active(&self) -> bool {
+        self.saved.load(Ordering::Relaxed) < self.limit
+    }
+
+    // Failed assertions and server errors; connection errors have no response to save
+    pub fn wants(error_type: Option<ErrorType>) -> bool {
+        matches!(
+            error_type,
+            Some(ErrorType::ServerError | ErrorType::CheckFailed | ErrorType::GraphQl)
+        )
+    }
+
+    pub async fn save(&self, failure: FailedRequest) {
+        let number = self.saved.fetch_add(1, Ordering::Relaxed) + 1;
+        if number > self.limit {
+            return;
+        }
+        let path = self.dir.join(format!(
+            "{}-{}.json",
+            failure.timestamp.format("%Y%m%dT%H%M%S%.3fZ"),
+            number
+        ));
+        let written = match serde_json::to_vec_pretty(&failure) {
+            Ok(json) => tokio::fs::write(&path, json)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = written {
+            tracing::warn!(error = %e, path = %path.display(), "failed to save failed response");
+        }
+    }
+}
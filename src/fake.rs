@@ -0,0 +1,219 @@
+//! `${fake.*()}` placeholders: realistic-looking names, emails and text for request bodies
+//
+// Values are drawn from small built-in word lists, so they look plausible but are not meant to
+// be statistically realistic. Emails and usernames end in a process-wide counter, so no two
+// requests of a run get the same one.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const FIRST_NAMES: &[&str] = &[
+    "James",
+    "Mary",
+    "Robert",
+    "Patricia",
+    "John",
+    "Jennifer",
+    "Michael",
+    "Linda",
+    "David",
+    "Elizabeth",
+    "William",
+    "Barbara",
+    "Richard",
+    "Susan",
+    "Joseph",
+    "Jessica",
+    "Thomas",
+    "Sarah",
+    "Carlos",
+    "Karen",
+    "Daniel",
+    "Lisa",
+    "Matthew",
+    "Nancy",
+    "Anthony",
+    "Sofia",
+    "Mark",
+    "Emma",
+    "Wei",
+    "Aisha",
+    "Hiroshi",
+    "Olga",
+    "Mateo",
+    "Priya",
+    "Lukas",
+    "Fatima",
+    "Noah",
+    "Chloe",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Smith",
+    "Johnson",
+    "Williams",
+    "Brown",
+    "Jones",
+    "Garcia",
+    "Miller",
+    "Davis",
+    "Rodriguez",
+    "Martinez",
+    "Hernandez",
+    "Lopez",
+    "Gonzalez",
+    "Wilson",
+    "Anderson",
+    "Thomas",
+    "Taylor",
+    "Moore",
+    "Jackson",
+    "Martin",
+    "Lee",
+    "Perez",
+    "Thompson",
+    "White",
+    "Harris",
+    "Clark",
+    "Lewis",
+    "Walker",
+    "Young",
+    "Allen",
+    "King",
+    "Wright",
+    "Scott",
+    "Nguyen",
+    "Kim",
+    "Meyer",
+    "Rossi",
+    "Novak",
+    "Sato",
+    "Kowalski",
+];
+
+const DOMAINS: &[&str] = &[
+    "example.com",
+    "example.org",
+    "example.net",
+    "mail.example.com",
+];
+
+const LOREM: &str = "lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+    tempor incididunt ut labore et dolore magna aliqua. ut enim ad minim veniam, quis nostrud \
+    exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. duis aute irure dolor \
+    in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. excepteur \
+    sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est \
+    laborum. ";
+
+// Suffix of emails and usernames, shared by every VU
+static UNIQUE: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fake {
+    Name,
+    FirstName,
+    LastName,
+    Email,
+    Username,
+    Phone,
+    Uuid,
+    // Text of exactly this many characters
+    Lorem(usize),
+}
+
+impl Fake {
+    // `function` is what follows "fake." in the placeholder, e.g. "email()" or "lorem(256)"
+    pub fn parse(function: &str) -> Result<Self, BoxError> {
+        let (name, arguments) = function
+            .strip_suffix(')')
+            .and_then(|call| call.split_once('('))
+            .ok_or_else(|| format!("fake.{} must be called, e.g. ${{fake.email()}}", function))?;
+        let arguments = arguments.trim();
+        let no_arguments = |fake: Self| {
+            if arguments.is_empty() {
+                Ok(fake)
+            } else {
+                Err(format!("fake.{}() takes no arguments", name).into())
+            }
+        };
+        match name.trim() {
+            "name" => no_arguments(Self::Name),
+            "first_name" => no_arguments(Self::FirstName),
+            "last_name" => no_arguments(Self::LastName),
+            "email" => no_arguments(Self::Email),
+            "username" => no_arguments(Self::Username),
+            "phone" => no_arguments(Self::Phone),
+            "uuid" => no_arguments(Self::Uuid),
+            "lorem" => arguments
+                .parse()
+                .map(Self::Lorem)
+                .map_err(|_| format!("fake.lorem() needs a length, got '{}'", arguments).into()),
+            other => Err(format!(
+                "unknown function fake.{}(), expected name, first_name, last_name, email, \
+                 username, phone, uuid or lorem",
+                other
+            )
+            .into()),
+        }
+    }
+
+    pub fn render<R: Rng>(&self, rng: &mut R, rendered: &mut String) {
+        match self {
+            Self::Name => {
+                rendered.push_str(FIRST_NAMES.choose(rng).unwrap());
+                rendered.push(' ');
+                rendered.push_str(LAST_NAMES.choose(rng).unwrap());
+            }
+            Self::FirstName => rendered.push_str(FIRST_NAMES.choose(rng).unwrap()),
+            Self::LastName => rendered.push_str(LAST_NAMES.choose(rng).unwrap()),
+            Self::Email => {
+                rendered.push_str(&format!(
+                    "{}.{}{}@{}",
+                    ascii_lowercase(FIRST_NAMES.choose(rng).unwrap()),
+                    ascii_lowercase(LAST_NAMES.choose(rng).unwrap()),
+                    UNIQUE.fetch_add(1, Ordering::Relaxed),
+                    DOMAINS.choose(rng).unwrap()
+                ));
+            }
+            Self::Username => rendered.push_str(&format!(
+                "{}{}",
+                ascii_lowercase(FIRST_NAMES.choose(rng).unwrap()),
+                UNIQUE.fetch_add(1, Ordering::Relaxed)
+            )),
+            Self::Phone => rendered.push_str(&format!(
+                "+1-555-{:03}-{:04}",
+                rng.gen_range(100..1000),
+                rng.gen_range(0..10000)
+            )),
+            Self::Uuid => rendered.push_str(&uuid(rng).to_string()),
+            Self::Lorem(length) => {
+                // Start at a random word so bodies do not all begin alike
+                let words: Vec<usize> = LOREM.match_indices(' ').map(|(i, _)| i + 1).collect();
+                let mut start = *words.choose(rng).unwrap() % LOREM.len();
+                let mut left = *length;
+                while left > 0 {
+                    let chunk = &LOREM[start..(start + left).min(LOREM.len())];
+                    rendered.push_str(chunk);
+                    left -= chunk.len();
+                    start = 0;
+                }
+            }
+        }
+    }
+}
+
+// A UUID v4 from `rng` rather than the OS, so --seed makes it reproducible
+pub fn uuid<R: Rng>(rng: &mut R) -> uuid::Uuid {
+    uuid::Builder::from_random_bytes(rng.gen()).into_uuid()
+}
+
+// Names as they would appear in an email address
+fn ascii_lowercase(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
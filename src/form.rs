@@ -0,0 +1,190 @@
+//! `--form`: multipart/form-data bodies with fields and file uploads
+//
+// Each --form is "name=value" or "name=@path", as in curl -F; a file may name its content
+// type with ";type=image/png", otherwise it is guessed from the extension. Values are
+// templates rendered for every request. Files are read from disk while the request is sent,
+// a chunk at a time, so large uploads do not sit in memory once per VU. The body's length is
+// known upfront (files are measured at startup and must not change during the run), so it
+// is sent with a Content-Length and counted in the bytes sent as encoded.
+
+use crate::payload::{self, Contents, PayloadBody};
+use crate::template::{Template, TemplateContext};
+use hyper::body::Bytes;
+use reqwest::header::HeaderValue;
+use std::path::{Path, PathBuf};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone)]
+enum Value {
+    Text(Template),
+    File {
+        path: PathBuf,
+        // Part header, from the content disposition to the blank line
+        head: String,
+        size: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    value: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct Form {
+    boundary: String,
+    fields: Vec<Field>,
+}
+
+impl Form {
+    pub fn parse(specs: &[String]) -> Result<Self, BoxError> {
+        let fields = specs
+            .iter()
+            .map(|spec| Field::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            boundary: format!("forgy-{}", uuid::Uuid::new_v4().simple()),
+            fields,
+        })
+    }
+
+    pub fn field_count(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn file_sizes(&self) -> Vec<u64> {
+        self.fields
+            .iter()
+            .filter_map(|field| match field.value {
+                Value::File { size, .. } => Some(size),
+                Value::Text(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn body(&self, context: &TemplateContext<'_>) -> PayloadBody {
+        // Text parts are rendered upfront; files are read by the task feeding the body
+        enum Chunk {
+            Bytes(Bytes),
+            File(PathBuf, u64),
+        }
+        let text = |text: String| Chunk::Bytes(Bytes::from(text));
+        let mut chunks = Vec::new();
+        for field in &self.fields {
+            match &field.value {
+                Value::Text(template) => chunks.push(text(format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    self.boundary,
+                    field.name,
+                    template.render(context)
+                ))),
+                Value::File { path, head, size } => {
+                    chunks.push(text(format!("--{}\r\n{}", self.boundary, head)));
+                    chunks.push(Chunk::File(path.clone(), *size));
+                    chunks.push(text("\r\n".to_string()));
+                }
+            }
+        }
+        chunks.push(text(format!("--{}--\r\n", self.boundary)));
+        let length = chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Chunk::Bytes(bytes) => bytes.len() as u64,
+                Chunk::File(_, size) => *size,
+            })
+            .sum();
+
+        let (mut sender, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                let sent = match chunk {
+                    Chunk::Bytes(bytes) => sender.send_data(bytes).await.is_ok(),
+                    Chunk::File(path, size) => payload::send_file(&mut sender, &path, size).await,
+                };
+                if !sent {
+                    // A truncated body fails the request rather than sending a wrong one
+                    sender.abort();
+                    return;
+                }
+            }
+        });
+
+        PayloadBody {
+            content_type: Some(
+                HeaderValue::from_str(&format!("multipart/form-data; boundary={}", self.boundary))
+                    .expect("boundary is a valid header value"),
+            ),
+            content_encoding: None,
+            length,
+            decoded_length: length,
+            body: Contents::Stream(body),
+        }
+    }
+}
+
+impl Field {
+    fn parse(spec: &str) -> Result<Self, BoxError> {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("--form must be NAME=VALUE or NAME=@FILE, got '{}'", spec))?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(['"', '\r', '\n']) {
+            return Err(format!("invalid --form field name '{}'", name).into());
+        }
+        let value = match value.strip_prefix('@') {
+            Some(file) => {
+                let (path, content_type) = match file.split_once(";type=") {
+                    Some((path, content_type)) => (path, content_type.trim().to_string()),
+                    None => (file, guess_content_type(Path::new(file)).to_string()),
+                };
+                let size = std::fs::metadata(path)
+                    .map_err(|e| format!("failed to read --form file {}: {}", path, e))?
+                    .len();
+                let filename = Path::new(path)
+                    .file_name()
+                    .map(|filename| filename.to_string_lossy().replace('"', "%22"))
+                    .unwrap_or_default();
+                Value::File {
+                    path: PathBuf::from(path),
+                    head: format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                         Content-Type: {}\r\n\r\n",
+                        name, filename, content_type
+                    ),
+                    size,
+                }
+            }
+            None => Value::Text(Template::parse(value)?),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            value,
+        })
+    }
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("csv") => "text/csv",
+        Some("html" | "htm") => "text/html",
+        Some("xml") => "application/xml",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
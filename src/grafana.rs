@@ -0,0 +1,264 @@
+//! `forgy grafana-dashboard`: a Grafana dashboard for the metrics forgy exports to Prometheus
+//
+// The dashboard queries forgy's metric names, filtered by `app` and `run_id` variables whose
+// values come from the Prometheus data source chosen in the `datasource` variable, so it works
+// with Remote Write, the scrape endpoint and the Pushgateway alike. It is written as JSON to
+// import by hand or uploaded through Grafana's HTTP API, under a fixed UID so uploading it again
+// updates it in place.
+
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DASHBOARD_UID: &str = "forgy";
+pub const DEFAULT_TITLE: &str = "forgy";
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+// Series of the selected app and runs
+const SELECTOR: &str = r#"app=~"$app", run_id=~"$run_id""#;
+
+// Panels are laid out two per row on Grafana's 24 column grid
+const PANEL_WIDTH: usize = 12;
+const PANEL_HEIGHT: usize = 8;
+
+pub fn dashboard(title: &str) -> Value {
+    let s = SELECTOR;
+    let rate = |metric: &str| format!("rate({}{{{}}}[$__rate_interval])", metric, s);
+    let quantile = |q: f64, by: &str, metric: &str| {
+        format!(
+            "histogram_quantile({}, sum by (le{}) ({}))",
+            q,
+            by,
+            rate(&format!("{}_bucket", metric))
+        )
+    };
+
+    let panels = vec![
+        panel(
+            "Requests per second",
+            "reqps",
+            &[
+                (format!("sum({})", rate("forgy_requests_total")), "total"),
+                (format!("sum({})", rate("forgy_errors_total")), "failed"),
+            ],
+        ),
+        panel(
+            "Virtual users",
+            "short",
+            &[
+                (format!("sum(forgy_active_vus{{{}}})", s), "active"),
+                (format!("sum(forgy_target_vus{{{}}})", s), "target"),
+            ],
+        ),
+        panel(
+            "Response time",
+            "s",
+            &[
+                (quantile(0.5, "", "forgy_request_duration_seconds"), "p50"),
+                (quantile(0.95, "", "forgy_request_duration_seconds"), "p95"),
+                (quantile(0.99, "", "forgy_request_duration_seconds"), "p99"),
+            ],
+        ),
+        panel(
+            "Response time p95 by endpoint",
+            "s",
+            &[(
+                quantile(0.95, ", endpoint", "forgy_request_duration_seconds"),
+                "{{endpoint}}",
+            )],
+        ),
+        panel(
+            "Requests by status",
+            "reqps",
+            &[(
+                format!("sum by (status) ({})", rate("forgy_requests_total")),
+                "{{status}}",
+            )],
+        ),
+        panel(
+            "Errors by type",
+            "reqps",
+            &[(
+                format!("sum by (error_type) ({})", rate("forgy_errors_total")),
+                "{{error_type}}",
+            )],
+        ),
+        panel(
+            "Error rate",
+            "percentunit",
+            &[(
+                format!(
+                    "sum({}) / sum({})",
+                    rate("forgy_errors_total"),
+                    rate("forgy_requests_total")
+                ),
+                "error rate",
+            )],
+        ),
+        panel(
+            "Request phases p95",
+            "s",
+            &[(
+                quantile(0.95, ", phase", "forgy_request_phase_duration_seconds"),
+                "{{phase}}",
+            )],
+        ),
+        panel(
+            "Throughput",
+            "Bps",
+            &[(
+                format!(
+                    "sum by (direction) (forgy_throughput_bytes_per_second{{{}}})",
+                    s
+                ),
+                "{{direction}}",
+            )],
+        ),
+        panel(
+            "Checks",
+            "reqps",
+            &[(
+                format!("sum by (check, result) ({})", rate("forgy_checks_total")),
+                "{{check}} {{result}}",
+            )],
+        ),
+        panel(
+            "Connections",
+            "short",
+            &[
+                (
+                    format!("sum(forgy_connections_established{{{}}})", s),
+                    "established",
+                ),
+                (
+                    format!("sum({}) * 60", rate("forgy_connections_opened_total")),
+                    "opened per minute",
+                ),
+            ],
+        ),
+        panel(
+            "Retries and dropped iterations",
+            "reqps",
+            &[
+                (format!("sum({})", rate("forgy_retries_total")), "retries"),
+                (
+                    format!("sum({})", rate("forgy_dropped_iterations_total")),
+                    "dropped iterations",
+                ),
+            ],
+        ),
+    ];
+
+    json!({
+        "uid": DASHBOARD_UID,
+        "title": title,
+        "tags": ["forgy", "load-testing"],
+        "timezone": "browser",
+        "refresh": "10s",
+        "time": { "from": "now-30m", "to": "now" },
+        "schemaVersion": 39,
+        "templating": {
+            "list": [
+                {
+                    "name": "datasource",
+                    "label": "Data source",
+                    "type": "datasource",
+                    "query": "prometheus",
+                },
+                variable("app", "App", "label_values(forgy_requests_total, app)", false),
+                variable(
+                    "run_id",
+                    "Run",
+                    r#"label_values(forgy_requests_total{app=~"$app"}, run_id)"#,
+                    true,
+                ),
+            ]
+        },
+        "panels": panels
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut panel)| {
+                panel["id"] = json!(index + 1);
+                panel["gridPos"] = json!({
+                    "x": index % 2 * PANEL_WIDTH,
+                    "y": index / 2 * PANEL_HEIGHT,
+                    "w": PANEL_WIDTH,
+                    "h": PANEL_HEIGHT,
+                });
+                panel
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn datasource() -> Value {
+    json!({ "type": "prometheus", "uid": "${datasource}" })
+}
+
+// A variable listing label values; `all` allows selecting several values, or all of them
+fn variable(name: &str, label: &str, query: &str, all: bool) -> Value {
+    json!({
+        "name": name,
+        "label": label,
+        "type": "query",
+        "datasource": datasource(),
+        "query": { "query": query, "refId": name },
+        "refresh": 2,
+        "sort": 1,
+        "multi": all,
+        "includeAll": all,
+        "allValue": if all { Value::from(".*") } else { Value::Null },
+    })
+}
+
+fn panel(title: &str, unit: &str, queries: &[(String, &str)]) -> Value {
+    let targets: Vec<Value> = queries
+        .iter()
+        .zip('A'..)
+        .map(|((expr, legend), ref_id)| {
+            json!({
+                "datasource": datasource(),
+                "expr": expr,
+                "legendFormat": legend,
+                "refId": ref_id.to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "title": title,
+        "type": "timeseries",
+        "datasource": datasource(),
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "options": { "legend": { "displayMode": "list", "placement": "bottom" } },
+        "targets": targets,
+    })
+}
+
+// Create or update the dashboard through Grafana's HTTP API; returns its URL
+pub async fn upload(
+    grafana_url: &str,
+    api_key: &str,
+    dashboard: &Value,
+) -> Result<String, BoxError> {
+    let base = grafana_url.trim_end_matches('/');
+    let response = Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()?
+        .post(format!("{}/api/dashboards/db", base))
+        .bearer_auth(api_key)
+        .json(&json!({ "dashboard": dashboard, "overwrite": true }))
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!("Grafana upload failed with status {}: {}", status, body).into());
+    }
+    let path = serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|saved| saved["url"].as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("/d/{}", DASHBOARD_UID));
+    Ok(format!("{}{}", base, path))
+}
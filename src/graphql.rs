@@ -0,0 +1,42 @@
+//! GraphQL requests: JSON POST bodies and `errors[]` detection in responses
+
+use serde_json::{json, Value};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Build the POST body from a query ("@file.graphql" reads it from a file) and optional
+// JSON variables
+pub fn request_body(query: &str, variables: Option<&str>) -> Result<String, BoxError> {
+    let query = match query.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read GraphQL query {}: {}", path, e))?,
+        None => query.to_string(),
+    };
+    let variables: Value = match variables {
+        Some(variables) => serde_json::from_str(variables)
+            .map_err(|e| format!("--graphql-variables must be a JSON object: {}", e))?,
+        None => Value::Null,
+    };
+    if !variables.is_null() && !variables.is_object() {
+        return Err("--graphql-variables must be a JSON object".into());
+    }
+
+    let mut body = json!({ "query": query });
+    if !variables.is_null() {
+        body["variables"] = variables;
+    }
+    Ok(body.to_string())
+}
+
+// GraphQL servers report failures in a top-level `errors` array, usually with status 200
+pub fn has_errors(body: &str) -> bool {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|response| {
+            response
+                .get("errors")
+                .and_then(Value::as_array)
+                .map(|errors| !errors.is_empty())
+        })
+        .unwrap_or(false)
+}
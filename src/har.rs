@@ -0,0 +1,107 @@
+//! HAR import: a browser-recorded session turned into a scenario each VU walks in order
+
+use crate::scenario::{RequestSpec, Scenario};
+use crate::template::Template;
+use reqwest::header::HeaderName;
+use reqwest::Method;
+use serde::Deserialize;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Headers the HTTP client sets itself, or that only make sense on the recorded connection
+const SKIPPED_HEADERS: [&str; 6] = [
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "accept-encoding",
+];
+
+#[derive(Debug, Deserialize)]
+struct HarFile {
+    log: HarLog,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarEntry {
+    request: HarRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: Vec<HarHeader>,
+    post_data: Option<HarPostData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarHeader {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarPostData {
+    text: Option<String>,
+}
+
+// Entries keep their recorded order; requests that are not HTTP(S), such as data: URLs
+// or browser extensions, are left out. Recorded values are sent as is, without
+// placeholder expansion.
+pub fn scenario(path: &str) -> Result<Scenario, BoxError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let har: HarFile =
+        serde_json::from_str(&contents).map_err(|e| format!("invalid HAR file {}: {}", path, e))?;
+
+    let mut requests = Vec::new();
+    for entry in har.log.entries {
+        let request = entry.request;
+        if !request.url.starts_with("http://") && !request.url.starts_with("https://") {
+            continue;
+        }
+        let method = Method::from_bytes(request.method.as_bytes())
+            .map_err(|_| format!("invalid HTTP method in {}: {}", path, request.method))?;
+
+        let mut headers = Vec::new();
+        for header in &request.headers {
+            // HTTP/2 recordings include pseudo-headers such as :authority
+            let name = header.name.to_ascii_lowercase();
+            if name.starts_with(':') || SKIPPED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                headers.push((name, Template::literal(&header.value)));
+            }
+        }
+
+        requests.push(RequestSpec {
+            target: request.url.clone(),
+            name: None,
+            method,
+            url: Template::literal(&request.url),
+            headers,
+            body: request
+                .post_data
+                .and_then(|post_data| post_data.text)
+                .map(|text| Template::literal(&text)),
+            weight: 1,
+            checks: Vec::new(),
+            graphql: false,
+            timeout: None,
+        });
+    }
+    if requests.is_empty() {
+        return Err(format!("no HTTP requests found in {}", path).into());
+    }
+    Scenario::sequence(requests)
+}
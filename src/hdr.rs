@@ -0,0 +1,94 @@
+//! `--hdr-output`: response time histograms for HdrHistogram tooling
+//
+// The log is in the interval log format of HdrHistogram's HistogramLogWriter, which
+// HistogramLogProcessor and the HdrHistogram plotters read. It holds one interval spanning the
+// whole test for all responses, and tagged ones for the latencies corrected for coordinated
+// omission ("corrected") and for each status class ("2xx", "5xx", ...). Values are in
+// microseconds, as recorded. The CSV is the percentile distribution of all responses in
+// milliseconds, in the layout of outputPercentileDistribution's CSV mode.
+
+use crate::merge;
+use crate::TestResults;
+use hdrhistogram::serialization::interval_log::{IntervalLogWriterBuilder, Tag};
+use hdrhistogram::serialization::V2DeflateSerializer;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Steps between reported percentiles per halving of the distance to 100%
+const TICKS_PER_HALF_DISTANCE: u32 = 5;
+
+pub fn render_log(results: &TestResults) -> Result<String, BoxError> {
+    let histograms = histograms(results)?;
+    let duration = Duration::from_secs_f64(results.test_duration_seconds);
+    let start = SystemTime::now()
+        .checked_sub(duration)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let mut log = Vec::new();
+    let mut serializer = V2DeflateSerializer::new();
+    let mut writer = IntervalLogWriterBuilder::new()
+        .add_comment("Response times recorded by forgy, in microseconds")
+        .add_comment("[Histogram log format version 1.3]")
+        .with_start_time(start)
+        .with_base_time(start)
+        // Shows the max of each interval in milliseconds
+        .with_max_value_divisor(1000.0)
+        .begin_log_with(&mut log, &mut serializer)?;
+    let mut intervals = vec![(None, &histograms.response_time)];
+    intervals.push((Some("corrected"), &histograms.corrected));
+    for (class, encoded) in &histograms.status_classes {
+        intervals.push((Some(class.as_str()), encoded));
+    }
+    for (tag, encoded) in intervals {
+        let histogram = merge::decode(encoded)?;
+        writer
+            .write_histogram(&histogram, Duration::ZERO, duration, tag.and_then(Tag::new))
+            .map_err(|e| format!("failed to write histogram log: {}", e))?;
+    }
+    drop(writer);
+    Ok(String::from_utf8(log)?)
+}
+
+pub fn render_csv(results: &TestResults) -> Result<String, BoxError> {
+    let histogram = merge::decode(&histograms(results)?.response_time)?;
+    let mut csv = String::from("\"Value\",\"Percentile\",\"TotalCount\",\"1/(1-Percentile)\"\n");
+    let mut total_count = 0;
+    for value in histogram.iter_quantiles(TICKS_PER_HALF_DISTANCE) {
+        total_count += value.count_since_last_iteration();
+        let quantile = value.quantile_iterated_to();
+        let inverse = if quantile < 1.0 {
+            format!("{:.2}", 1.0 / (1.0 - quantile))
+        } else {
+            "Infinity".to_string()
+        };
+        let _ = writeln!(
+            csv,
+            "{:.3},{:.12},{},{}",
+            value.value_iterated_to() as f64 / 1000.0,
+            quantile,
+            total_count,
+            inverse
+        );
+    }
+    Ok(csv)
+}
+
+fn histograms(results: &TestResults) -> Result<&merge::EncodedHistograms, BoxError> {
+    results
+        .histograms
+        .as_ref()
+        .ok_or_else(|| "the results have no histograms".into())
+}
+
+// Next to the log: "latencies.hgrm" gets "latencies.csv"
+pub fn csv_path(path: &Path) -> PathBuf {
+    let csv = path.with_extension("csv");
+    if csv == path {
+        path.with_extension("distribution.csv")
+    } else {
+        csv
+    }
+}
@@ -0,0 +1,475 @@
+//! `--history`: a SQLite database of past runs, and `forgy history` to read it back
+//
+// Every run appends a row to the `runs` table with its headline figures, the commit under
+// test (from the variables CI systems set) and a fingerprint of the workload, so runs of the
+// same test can be followed over time however their output files were named. The whole
+// `TestResults` JSON is kept in the `results` column for anything the headline columns miss.
+// SQLite is compiled in; values always go to it as bound parameters.
+
+use crate::autoscale::AutoscaleOptions;
+use crate::load_shape::Profile;
+use crate::think_time::ThinkTime;
+use crate::{Args, Command, TestResults};
+use chrono::Utc;
+use ring::digest::{Context, SHA256};
+use rusqlite::{params, Connection, OpenFlags};
+use std::io::Read;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Where CI systems put the commit being tested, in the order they are looked up
+const GIT_SHA_VARIABLES: [&str; 5] = [
+    "FORGY_GIT_SHA",
+    "GITHUB_SHA",
+    "CI_COMMIT_SHA",
+    "BUILDKITE_COMMIT",
+    "GIT_COMMIT",
+];
+
+// Hashed ahead of the workload; changing how any field is encoded below needs a new version,
+// which starts new trends rather than mixing two encodings under one fingerprint
+const FINGERPRINT_VERSION: &str = "forgy-workload-1";
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id TEXT NOT NULL,
+    app TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    git_sha TEXT,
+    stop_reason TEXT NOT NULL,
+    passed INTEGER NOT NULL,
+    total_requests INTEGER NOT NULL,
+    failed_requests INTEGER NOT NULL,
+    requests_per_second REAL,
+    p50_ms REAL,
+    p95_ms REAL,
+    p99_ms REAL,
+    duration_seconds REAL,
+    results TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_fingerprint ON runs (fingerprint, started_at);";
+
+// A row of the `runs` table, without the results JSON
+#[derive(Debug, Clone)]
+pub struct HistoryRun {
+    pub id: i64,
+    pub app: String,
+    // UTC, as "YYYY-MM-DD HH:MM:SS"
+    pub started_at: String,
+    pub fingerprint: String,
+    pub git_sha: Option<String>,
+    pub stop_reason: String,
+    pub passed: i64,
+    pub total_requests: i64,
+    pub failed_requests: i64,
+    pub requests_per_second: Option<f64>,
+    pub p95_ms: Option<f64>,
+}
+
+// Which runs `forgy history` shows
+#[derive(Debug, Clone)]
+pub struct HistoryFilter {
+    pub app: Option<String>,
+    pub fingerprint: Option<String>,
+    // The latest runs that match
+    pub limit: usize,
+}
+
+// Identifies the workload: targets, requests and load profile, but not the outputs, labels
+// or metric exporters, which don't change what the target is put through. Scenario and body
+// files count with their contents.
+pub fn fingerprint(args: &Args) -> String {
+    let mut workload = Workload::new();
+    command(&mut workload, args.command.as_ref());
+    workload.list("url", &args.url);
+    workload.list("name", &args.name);
+    workload.option("har", args.har.as_deref());
+    workload.option("from_curl", args.from_curl.as_deref());
+    workload.file("config", args.config.as_deref());
+    workload.text("method", &args.method);
+    workload.list("header", &args.header);
+    workload.option("body", args.body.as_deref());
+    workload.file("body_file", args.body_file.as_deref());
+    workload.list("form", &args.form);
+    workload.option(
+        "body_size",
+        args.body_size.map(|n| n.to_string()).as_deref(),
+    );
+    workload.text("body_pattern", &choice(args.body_pattern));
+    workload.option("graphql_query", args.graphql_query.as_deref());
+    workload.option("graphql_variables", args.graphql_variables.as_deref());
+    workload.text("vus", &args.vus.to_string());
+    workload.text("ramp_up", &args.ramp_up);
+    workload.text("hold", &args.hold);
+    workload.text("ramp_down", &args.ramp_down);
+    let stages: Vec<String> = args
+        .stage
+        .iter()
+        .map(|stage| format!("{}:{}", millis(stage.duration), stage.target))
+        .collect();
+    workload.list("stage", &stages);
+    workload.option("profile", args.profile.map(profile).as_deref());
+    workload.option("rps", args.rps.map(|rps| rps.to_string()).as_deref());
+    workload.option(
+        "max_rps",
+        args.max_rps.map(|rps| rps.to_string()).as_deref(),
+    );
+    workload.option(
+        "iterations",
+        args.iterations.map(|n| n.to_string()).as_deref(),
+    );
+    workload.option(
+        "iterations_per_vu",
+        args.iterations_per_vu.map(|n| n.to_string()).as_deref(),
+    );
+    workload.option("think_time", args.think_time.map(think_time).as_deref());
+    workload.option("seed", args.seed.map(|seed| seed.to_string()).as_deref());
+    workload.text("http_version", &choice(args.http_version));
+    workload.text("protocol", &choice(args.protocol));
+    workload.option("payload_hex", args.payload_hex.as_deref());
+    workload.option("tcp_read", args.tcp_read.map(|n| n.to_string()).as_deref());
+    workload.finish()
+}
+
+// The subcommand and the options of those that shape the load
+fn command(workload: &mut Workload, command: Option<&Command>) {
+    match command {
+        None => workload.text("command", "run"),
+        Some(Command::Validate { config }) => {
+            workload.text("command", "validate");
+            workload.file("command.config", config.as_deref());
+        }
+        Some(Command::Replay {
+            log,
+            format,
+            target,
+            speed,
+        }) => {
+            workload.text("command", "replay");
+            workload.text("replay.log", log);
+            workload.text("replay.format", &choice(*format));
+            workload.text("replay.target", target);
+            workload.text("replay.speed", &speed.to_string());
+        }
+        Some(Command::Openapi {
+            spec,
+            operations,
+            server,
+        }) => {
+            workload.text("command", "openapi");
+            workload.text("openapi.spec", spec);
+            workload.list("openapi.operations", operations);
+            workload.option("openapi.server", server.as_deref());
+        }
+        Some(Command::Autoscale(options)) => {
+            let AutoscaleOptions {
+                slo,
+                start,
+                step,
+                step_duration,
+                refine,
+            } = options;
+            workload.text("command", "autoscale");
+            let slo: Vec<String> = slo.0.iter().map(|t| t.expression.clone()).collect();
+            workload.list("autoscale.slo", &slo);
+            workload.text("autoscale.start", &start.to_string());
+            workload.text("autoscale.step", &step.to_string());
+            workload.text("autoscale.step_duration", &millis(*step_duration));
+            workload.text("autoscale.refine", &refine.to_string());
+        }
+        // The rest work on result files and are never recorded
+        Some(Command::Merge { .. }) => workload.text("command", "merge"),
+        Some(Command::Compare { .. }) => workload.text("command", "compare"),
+        Some(Command::GrafanaDashboard { .. }) => workload.text("command", "grafana-dashboard"),
+        Some(Command::History { .. }) => workload.text("command", "history"),
+    }
+}
+
+// SHA-256 over length-prefixed fields, so no value can run into the next
+struct Workload(Context);
+
+impl Workload {
+    fn new() -> Self {
+        let mut workload = Self(Context::new(&SHA256));
+        workload.bytes(FINGERPRINT_VERSION.as_bytes());
+        workload
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.update(&(bytes.len() as u64).to_le_bytes());
+        self.0.update(bytes);
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.bytes(name.as_bytes());
+        self.bytes(value.as_bytes());
+    }
+
+    fn option(&mut self, name: &str, value: Option<&str>) {
+        self.bytes(name.as_bytes());
+        match value {
+            Some(value) => {
+                self.0.update(&[1]);
+                self.bytes(value.as_bytes());
+            }
+            None => self.0.update(&[0]),
+        }
+    }
+
+    fn list(&mut self, name: &str, values: &[String]) {
+        self.bytes(name.as_bytes());
+        self.0.update(&(values.len() as u64).to_le_bytes());
+        for value in values {
+            self.bytes(value.as_bytes());
+        }
+    }
+
+    // A file by its contents, read in chunks; one that cannot be read counts as empty
+    fn file(&mut self, name: &str, path: Option<&str>) {
+        let digest = path.map(|path| {
+            let mut file = Context::new(&SHA256);
+            if let Ok(mut reader) = std::fs::File::open(path) {
+                let mut buffer = [0u8; 64 * 1024];
+                while let Ok(read @ 1..) = reader.read(&mut buffer) {
+                    file.update(&buffer[..read]);
+                }
+            }
+            hex(file.finish().as_ref())
+        });
+        self.option(name, digest.as_deref());
+    }
+
+    fn finish(self) -> String {
+        hex(&self.0.finish().as_ref()[..6])
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// A --http-version, --protocol or similar choice as spelled on the command line
+fn choice(value: impl clap::ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
+
+fn millis(duration: Duration) -> String {
+    duration.as_millis().to_string()
+}
+
+fn profile(profile: Profile) -> String {
+    match profile {
+        Profile::Spike { base, peak, length } => {
+            format!("spike:{}:{}:{}", base, peak, millis(length))
+        }
+        Profile::Steps { size, length } => format!("steps:{}x{}", size, millis(length)),
+        Profile::Sine { period, min, max } => {
+            format!("sine:{}:{}:{}", millis(period), min, max)
+        }
+    }
+}
+
+fn think_time(think_time: ThinkTime) -> String {
+    match think_time {
+        ThinkTime::None => "none".to_string(),
+        ThinkTime::Constant(duration) => format!("constant:{}", millis(duration)),
+        ThinkTime::Uniform(min, max) => format!("uniform:{}-{}", millis(min), millis(max)),
+        ThinkTime::Exponential(mean) => format!("exponential:{}", millis(mean)),
+    }
+}
+
+pub fn git_sha() -> Option<String> {
+    GIT_SHA_VARIABLES
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|sha| !sha.is_empty()))
+}
+
+// Append the run; returns its id in the database
+pub fn record(path: &str, args: &Args, results: &TestResults) -> Result<i64, BoxError> {
+    let run_id = args
+        .static_labels()
+        .into_iter()
+        .find(|(name, _)| name == "run_id")
+        .map_or_else(|| args.run_id.clone(), |(_, value)| value);
+    let started_at = Utc::now()
+        - chrono::Duration::milliseconds((results.test_duration_seconds * 1000.0) as i64);
+    let stop_reason = serde_json::to_value(results.stop_reason)?;
+
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    connection.execute(
+        "INSERT INTO runs (run_id, app, started_at, fingerprint, git_sha, stop_reason, passed, \
+         total_requests, failed_requests, requests_per_second, p50_ms, p95_ms, p99_ms, \
+         duration_seconds, results) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            run_id,
+            args.app,
+            started_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            fingerprint(args),
+            git_sha(),
+            stop_reason.as_str().unwrap_or_default(),
+            results.passed(),
+            results.total_requests as i64,
+            results.failed_requests as i64,
+            real(results.requests_per_second),
+            real(results.p50_response_time_ms),
+            real(results.p95_response_time_ms),
+            real(results.p99_response_time_ms),
+            real(results.test_duration_seconds),
+            serde_json::to_string(results)?,
+        ],
+    )?;
+    Ok(connection.last_insert_rowid())
+}
+
+// The latest runs that match the filter, oldest first
+pub fn list(path: &str, filter: &HistoryFilter) -> Result<Vec<HistoryRun>, BoxError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(format!("no history database at {}", path).into());
+    }
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare(
+        "SELECT id, app, started_at, fingerprint, git_sha, stop_reason, passed, total_requests, \
+         failed_requests, requests_per_second, p95_ms FROM runs \
+         WHERE (?1 IS NULL OR app = ?1) AND (?2 IS NULL OR fingerprint = ?2) \
+         ORDER BY id DESC LIMIT ?3",
+    )?;
+    let rows = statement.query_map(
+        params![
+            filter.app,
+            filter.fingerprint,
+            i64::try_from(filter.limit).unwrap_or(i64::MAX)
+        ],
+        |row| {
+            Ok(HistoryRun {
+                id: row.get(0)?,
+                app: row.get(1)?,
+                started_at: row.get(2)?,
+                fingerprint: row.get(3)?,
+                git_sha: row.get(4)?,
+                stop_reason: row.get(5)?,
+                passed: row.get(6)?,
+                total_requests: row.get(7)?,
+                failed_requests: row.get(8)?,
+                requests_per_second: row.get(9)?,
+                p95_ms: row.get(10)?,
+            })
+        },
+    )?;
+    let mut runs = rows.collect::<Result<Vec<_>, _>>()?;
+    runs.reverse();
+    Ok(runs)
+}
+
+// NaN and infinities are stored as NULL
+fn real(value: f64) -> Option<f64> {
+    value.is_finite().then_some(value)
+}
+
+fn error_rate(run: &HistoryRun) -> f64 {
+    run.failed_requests as f64 / run.total_requests.max(1) as f64 * 100.0
+}
+
+fn short_sha(run: &HistoryRun) -> &str {
+    run.git_sha.as_deref().map_or("-", |sha| {
+        sha.char_indices().nth(8).map_or(sha, |(i, _)| &sha[..i])
+    })
+}
+
+fn number(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| format!("{:.2}", value))
+}
+
+// Relative change from the previous run of the same workload, in percent
+fn change(previous: Option<f64>, current: Option<f64>) -> String {
+    match (previous, current) {
+        (Some(previous), Some(current)) if previous != 0.0 => {
+            format!("{:+.1}%", (current - previous) / previous * 100.0)
+        }
+        _ => "-".to_string(),
+    }
+}
+
+pub fn print_runs(runs: &[HistoryRun]) {
+    println!("\nRun History");
+    println!("════════════════════════════════════════════════════════════════════════════════════════════════");
+    println!(
+        "{:>5}  {:<19}  {:<12}  {:<12}  {:<8}  {:>9}  {:>9}  {:>9}  {:>7}  Result",
+        "ID", "Started (UTC)", "App", "Workload", "Commit", "Requests", "RPS", "P95 ms", "Errors"
+    );
+    println!("────────────────────────────────────────────────────────────────────────────────────────────────");
+    for run in runs {
+        let result = match (run.passed != 0, run.stop_reason.as_str()) {
+            (true, "completed") => "passed".to_string(),
+            (true, reason) => format!("passed ({})", reason),
+            (false, "completed") => "failed".to_string(),
+            (false, reason) => format!("failed ({})", reason),
+        };
+        println!(
+            "{:>5}  {:<19}  {:<12}  {:<12}  {:<8}  {:>9}  {:>9}  {:>9}  {:>6.2}%  {}",
+            run.id,
+            run.started_at,
+            run.app,
+            run.fingerprint,
+            short_sha(run),
+            run.total_requests,
+            number(run.requests_per_second),
+            number(run.p95_ms),
+            error_rate(run),
+            result
+        );
+    }
+    println!("════════════════════════════════════════════════════════════════════════════════════════════════");
+    println!("{} runs", runs.len());
+}
+
+// One table per workload, with each run's p95 and RPS against the run before it
+pub fn print_trends(runs: &[HistoryRun]) {
+    let mut fingerprints: Vec<&str> = Vec::new();
+    for run in runs {
+        if !fingerprints.contains(&run.fingerprint.as_str()) {
+            fingerprints.push(&run.fingerprint);
+        }
+    }
+    for fingerprint in fingerprints {
+        let workload: Vec<&HistoryRun> = runs
+            .iter()
+            .filter(|run| run.fingerprint == fingerprint)
+            .collect();
+        println!(
+            "\nTrend of workload {} ({}, {} runs)",
+            fingerprint,
+            workload[0].app,
+            workload.len()
+        );
+        println!("═══════════════════════════════════════════════════════════════════════════");
+        println!(
+            "{:>5}  {:<19}  {:<8}  {:>9}  {:>8}  {:>9}  {:>8}",
+            "ID", "Started (UTC)", "Commit", "P95 ms", "Change", "RPS", "Change"
+        );
+        println!("───────────────────────────────────────────────────────────────────────────");
+        let mut previous: Option<&HistoryRun> = None;
+        for run in workload {
+            println!(
+                "{:>5}  {:<19}  {:<8}  {:>9}  {:>8}  {:>9}  {:>8}",
+                run.id,
+                run.started_at,
+                short_sha(run),
+                number(run.p95_ms),
+                change(previous.and_then(|p| p.p95_ms), run.p95_ms),
+                number(run.requests_per_second),
+                change(
+                    previous.and_then(|p| p.requests_per_second),
+                    run.requests_per_second
+                ),
+            );
+            previous = Some(run);
+        }
+        println!("═══════════════════════════════════════════════════════════════════════════");
+    }
+}
@@ -0,0 +1,348 @@
+//! `--user` and `--digest`: HTTP Basic and Digest authentication
+//
+// Basic credentials are sent on every request. Digest (RFC 7616) needs a challenge first: each
+// VU sends its first request without credentials, and on a 401 with a Digest challenge sends
+// it again with the response computed from the nonce. Later requests of the VU answer the same
+// challenge up front with an increasing nonce count, and when the server declares the nonce
+// stale the VU takes the new one and retries, as browsers do. A 401 for a nonce that was just
+// answered means wrong credentials and is not retried. MD5 and SHA-256, with or without -sess,
+// and qop=auth are supported; qop=auth-int challenges are not answered.
+
+use crate::client::Client;
+use crate::transport::SendError;
+use base64::Engine;
+use md5::{Digest, Md5};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Request, Response, StatusCode};
+use std::fmt::Write;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    // "name:password"; the password may contain colons
+    pub fn parse(user: &str) -> Result<Self, BoxError> {
+        let (username, password) = user
+            .split_once(':')
+            .ok_or_else(|| format!("--user must be name:password: {}", user))?;
+        Ok(Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    pub fn basic_authorization(&self) -> Result<HeaderValue, BoxError> {
+        let encoded = base64::engine::general_purpose::STANDARD
+            .encode(format!("{}:{}", self.username, self.password));
+        Ok(HeaderValue::from_str(&format!("Basic {}", encoded))?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Self::Md5),
+            "MD5-SESS" => Some(Self::Md5Sess),
+            "SHA-256" => Some(Self::Sha256),
+            "SHA-256-SESS" => Some(Self::Sha256Sess),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Md5Sess => "MD5-sess",
+            Self::Sha256 => "SHA-256",
+            Self::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn session(self) -> bool {
+        matches!(self, Self::Md5Sess | Self::Sha256Sess)
+    }
+
+    fn hash(self, data: &str) -> String {
+        match self {
+            Self::Md5 | Self::Md5Sess => hex(&Md5::digest(data.as_bytes())),
+            Self::Sha256 | Self::Sha256Sess => {
+                hex(ring::digest::digest(&ring::digest::SHA256, data.as_bytes()).as_ref())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    // Whether the server asked for qop=auth; without qop the RFC 2069 response is sent
+    qop_auth: bool,
+    stale: bool,
+}
+
+impl Challenge {
+    // The Digest challenges of a 401, strongest algorithm first
+    fn from_headers(headers: &HeaderMap) -> Vec<Self> {
+        let mut challenges: Vec<Self> = headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_challenges)
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| Self::from_params(&params))
+            .collect();
+        challenges.sort_by_key(|challenge| match challenge.algorithm {
+            Algorithm::Sha256 | Algorithm::Sha256Sess => 0,
+            Algorithm::Md5 | Algorithm::Md5Sess => 1,
+        });
+        challenges
+    }
+
+    fn from_params(params: &[(String, String)]) -> Option<Self> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        let algorithm = match param("algorithm") {
+            Some(name) => Algorithm::parse(&name)?,
+            None => Algorithm::Md5,
+        };
+        let qop_auth = match param("qop") {
+            Some(qop) => {
+                if !qop
+                    .split(',')
+                    .any(|qop| qop.trim().eq_ignore_ascii_case("auth"))
+                {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+        Some(Self {
+            realm: param("realm").unwrap_or_default(),
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm,
+            qop_auth,
+            stale: param("stale").is_some_and(|stale| stale.eq_ignore_ascii_case("true")),
+        })
+    }
+}
+
+// The challenge a VU answers and how often it has used its nonce
+struct Session {
+    challenge: Challenge,
+    nonce_count: u32,
+}
+
+pub struct DigestAuth {
+    credentials: Credentials,
+    sessions: Vec<Mutex<Option<Session>>>,
+}
+
+impl DigestAuth {
+    pub fn new(credentials: Credentials, vus: usize) -> Self {
+        Self {
+            credentials,
+            sessions: (0..vus.max(1)).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    fn session(&self, vu: usize) -> &Mutex<Option<Session>> {
+        &self.sessions[vu.saturating_sub(1) % self.sessions.len()]
+    }
+
+    // Answers the VU's current challenge on the request, with a cnonce from the VU's generator;
+    // returns whether the request is left to Digest, i.e. carries no Authorization header of
+    // its own
+    pub fn authorize(&self, vu: usize, request: &mut Request, rng: &mut impl Rng) -> bool {
+        if request.headers().contains_key(AUTHORIZATION) {
+            return false;
+        }
+        if let Some(session) = self.session(vu).lock().as_mut() {
+            session.nonce_count += 1;
+            if let Ok(value) = self.authorization(session, request, rng) {
+                request.headers_mut().insert(AUTHORIZATION, value);
+            }
+        }
+        true
+    }
+
+    // Sends the request, and once more with credentials when the server answers with a new
+    // Digest challenge. The handshake is part of the request's response time
+    pub async fn execute(
+        &self,
+        client: &Client,
+        vu: usize,
+        rng: &Mutex<StdRng>,
+        request: Request,
+        stream: Option<hyper::Body>,
+    ) -> Result<Response, SendError> {
+        // Streamed bodies cannot be sent twice; their 401 is the response
+        let retry = request.try_clone().filter(|_| stream.is_none());
+        let answered = request.headers().contains_key(AUTHORIZATION);
+        let response = client.send(request, stream).await?;
+        match retry {
+            Some(mut retry)
+                if response.status() == StatusCode::UNAUTHORIZED
+                    && self.challenge(vu, response.headers(), answered) =>
+            {
+                retry.headers_mut().remove(AUTHORIZATION);
+                self.authorize(vu, &mut retry, &mut *rng.lock());
+                client.execute(retry).await
+            }
+            _ => Ok(response),
+        }
+    }
+
+    // Takes the challenge of a 401; returns whether it is worth answering
+    fn challenge(&self, vu: usize, headers: &HeaderMap, answered: bool) -> bool {
+        let Some(challenge) = Challenge::from_headers(headers).into_iter().next() else {
+            return false;
+        };
+        let mut session = self.session(vu).lock();
+        let new_nonce = session
+            .as_ref()
+            .is_none_or(|current| current.challenge.nonce != challenge.nonce);
+        // The nonce just answered, rejected again without being stale: wrong credentials
+        let retry = !answered || challenge.stale || new_nonce;
+        if new_nonce {
+            *session = Some(Session {
+                challenge,
+                nonce_count: 0,
+            });
+        }
+        retry
+    }
+
+    fn authorization(
+        &self,
+        session: &Session,
+        request: &Request,
+        rng: &mut impl Rng,
+    ) -> Result<HeaderValue, BoxError> {
+        let challenge = &session.challenge;
+        let algorithm = challenge.algorithm;
+        let url = request.url();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let cnonce = format!("{:016x}", rng.gen::<u64>());
+        let nonce_count = format!("{:08x}", session.nonce_count);
+        let Credentials { username, password } = &self.credentials;
+
+        let mut ha1 = algorithm.hash(&format!("{}:{}:{}", username, challenge.realm, password));
+        if algorithm.session() {
+            ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, cnonce));
+        }
+        let ha2 = algorithm.hash(&format!("{}:{}", request.method(), uri));
+        let response = if challenge.qop_auth {
+            algorithm.hash(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, challenge.nonce, nonce_count, cnonce, ha2
+            ))
+        } else {
+            algorithm.hash(&format!("{}:{}:{}", ha1, challenge.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(username),
+            quote(&challenge.realm),
+            quote(&challenge.nonce),
+            quote(&uri),
+            algorithm.name(),
+            response
+        );
+        if challenge.qop_auth {
+            let _ = write!(
+                header,
+                ", qop=auth, nc={}, cnonce=\"{}\"",
+                nonce_count, cnonce
+            );
+        }
+        if let Some(opaque) = &challenge.opaque {
+            let _ = write!(header, ", opaque=\"{}\"", quote(opaque));
+        }
+        Ok(HeaderValue::from_str(&header)?)
+    }
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Challenges of a WWW-Authenticate value as (scheme, params); a value may hold several, as in
+// `Basic realm="a", Digest realm="b", nonce="..."`
+fn parse_challenges(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let mut token = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',' && *c != '=') {
+            token.push(c);
+        }
+        if token.is_empty() {
+            // A stray '=' of a token68, or the end
+            if chars.next().is_none() {
+                break;
+            }
+            continue;
+        }
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        if chars.next_if_eq(&'=').is_none() {
+            challenges.push((token, Vec::new()));
+            continue;
+        }
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        let mut param = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => param.extend(chars.next()),
+                    c => param.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',') {
+                param.push(c);
+            }
+        }
+        if let Some((_, params)) = challenges.last_mut() {
+            params.push((token, param));
+        }
+    }
+    challenges
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut text, byte| {
+        let _ = write!(text, "{:02x}", byte);
+        text
+    })
+}
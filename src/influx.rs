@@ -0,0 +1,184 @@
+//! InfluxDB line-protocol output, for stacks built on Influx or Telegraf instead of Prometheus
+//
+// Writes the same registry as Remote Write on every push. Each series becomes one line
+// whose measurement is the metric name and whose tags are the app, --label values and the
+// metric's own labels. Counters and gauges have a single `value` field; histograms have
+// `count`, `sum` and one field per cumulative bucket, keyed by its upper bound, as
+// Telegraf's Prometheus input does.
+
+use prometheus::proto::{Metric, MetricFamily};
+use reqwest::Client;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    // Server base URL, e.g. http://localhost:8086
+    pub url: String,
+    pub bucket: String,
+    pub org: Option<String>,
+    pub token: Option<String>,
+    pub app: String,
+    pub labels: Vec<(String, String)>,
+}
+
+pub struct InfluxWriter {
+    client: Client,
+    config: InfluxConfig,
+}
+
+impl InfluxWriter {
+    pub fn new(config: InfluxConfig) -> Result<Self, BoxError> {
+        Ok(Self {
+            client: Client::builder().timeout(WRITE_TIMEOUT).build()?,
+            config,
+        })
+    }
+
+    // Write the current value of every series via the v2 write API, which InfluxDB 1.8+
+    // also serves
+    pub async fn write(&self, registry: &prometheus::Registry) -> Result<(), BoxError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let body = self.render_lines(&registry.gather(), timestamp);
+
+        let mut query = vec![("bucket", self.config.bucket.as_str()), ("precision", "ms")];
+        if let Some(org) = &self.config.org {
+            query.push(("org", org));
+        }
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/api/v2/write",
+                self.config.url.trim_end_matches('/')
+            ))
+            .query(&query)
+            .header("content-type", "text/plain; charset=utf-8")
+            .body(body);
+        if let Some(token) = &self.config.token {
+            request = request.header("authorization", format!("Token {}", token));
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("InfluxDB write failed with status {}: {}", status, body).into());
+        }
+        Ok(())
+    }
+
+    fn render_lines(&self, families: &[MetricFamily], timestamp: u64) -> String {
+        let mut lines = String::new();
+        for family in families {
+            for metric in family.get_metric() {
+                let fields = if metric.has_counter() {
+                    float_field("value", metric.get_counter().get_value())
+                } else if metric.has_gauge() {
+                    float_field("value", metric.get_gauge().get_value())
+                } else if metric.has_histogram() {
+                    histogram_fields(metric)
+                } else {
+                    continue;
+                };
+                // A line needs at least one field, and non-finite values have none
+                if fields.is_empty() {
+                    continue;
+                }
+                let _ = writeln!(
+                    lines,
+                    "{}{} {} {}",
+                    escape_measurement(family.get_name()),
+                    self.tags(metric),
+                    fields.join(","),
+                    timestamp
+                );
+            }
+        }
+        lines
+    }
+
+    // ",key=value" pairs; the metric's own labels win over static labels of the same name
+    fn tags(&self, metric: &Metric) -> String {
+        let mut tags = vec![("app", self.config.app.as_str())];
+        for (name, value) in &self.config.labels {
+            if metric
+                .get_label()
+                .iter()
+                .all(|pair| pair.get_name() != name)
+            {
+                tags.push((name, value));
+            }
+        }
+        for pair in metric.get_label() {
+            tags.push((pair.get_name(), pair.get_value()));
+        }
+        // Influx rejects empty tag values, and prefers tags sorted by key
+        tags.retain(|(_, value)| !value.is_empty());
+        tags.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut rendered = String::new();
+        for (name, value) in tags {
+            let _ = write!(rendered, ",{}={}", escape_tag(name), escape_tag(value));
+        }
+        rendered
+    }
+}
+
+fn histogram_fields(metric: &Metric) -> Vec<String> {
+    let histogram = metric.get_histogram();
+    let mut fields = vec![format!("count={}i", histogram.get_sample_count())];
+    fields.extend(float_field("sum", histogram.get_sample_sum()));
+    let mut has_inf_bucket = false;
+    for bucket in histogram.get_bucket() {
+        let upper_bound = bucket.get_upper_bound();
+        has_inf_bucket |= upper_bound == f64::INFINITY;
+        fields.push(format!(
+            "{}={}i",
+            bucket_key(upper_bound),
+            bucket.get_cumulative_count()
+        ));
+    }
+    if !has_inf_bucket {
+        fields.push(format!(
+            "{}={}i",
+            bucket_key(f64::INFINITY),
+            histogram.get_sample_count()
+        ));
+    }
+    fields
+}
+
+fn float_field(name: &str, value: f64) -> Vec<String> {
+    if value.is_finite() {
+        vec![format!("{}={}", name, value)]
+    } else {
+        Vec::new()
+    }
+}
+
+fn bucket_key(upper_bound: f64) -> String {
+    if upper_bound == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        upper_bound.to_string()
+    }
+}
+
+fn escape_measurement(name: &str) -> String {
+    name.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+fn escape_tag(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,81 @@
+//! JUnit XML output, so CI systems can show thresholds and checks like unit tests
+
+use crate::TestResults;
+use std::fmt::Write;
+
+// One <testsuite> for thresholds and one for checks; a check fails if any request failed it
+pub fn render_xml(results: &TestResults) -> String {
+    let threshold_failures = results.thresholds.iter().filter(|t| !t.passed).count();
+    let check_failures = results.checks.iter().filter(|c| c.failed > 0).count();
+    let tests = results.thresholds.len() + results.checks.len();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"forgy\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        tests,
+        threshold_failures + check_failures,
+        results.test_duration_seconds
+    );
+
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"thresholds\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        results.thresholds.len(),
+        threshold_failures,
+        results.test_duration_seconds
+    );
+    for threshold in &results.thresholds {
+        let name = escape(&threshold.threshold);
+        if threshold.passed {
+            let _ = writeln!(
+                xml,
+                "    <testcase classname=\"forgy.thresholds\" name=\"{}\"/>",
+                name
+            );
+        } else {
+            let _ = writeln!(
+                xml,
+                "    <testcase classname=\"forgy.thresholds\" name=\"{0}\">\n      <failure message=\"{0} (actual: {1:.2})\"/>\n    </testcase>",
+                name, threshold.actual
+            );
+        }
+    }
+    xml.push_str("  </testsuite>\n");
+
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"checks\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        results.checks.len(),
+        check_failures,
+        results.test_duration_seconds
+    );
+    for check in &results.checks {
+        let name = escape(&check.name);
+        if check.failed == 0 {
+            let _ = writeln!(
+                xml,
+                "    <testcase classname=\"forgy.checks\" name=\"{}\"/>",
+                name
+            );
+        } else {
+            let _ = writeln!(
+                xml,
+                "    <testcase classname=\"forgy.checks\" name=\"{}\">\n      <failure message=\"{} of {} requests failed\"/>\n    </testcase>",
+                name,
+                check.failed,
+                check.passed + check.failed
+            );
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,224 @@
+//! `--jwt-signing-key`: JWTs signed by forgy itself, one per VU, sent as Bearer tokens
+//
+// APIs that authenticate with JWTs are tested without a token service: forgy signs the tokens
+// with the given key. The claims are a template, so `{"sub":"user-${VU}"}` gives every VU its
+// own principal; `iat` and `exp` are added unless the template sets them. A VU keeps its token
+// until 80% of `--jwt-ttl` has passed and then signs a new one, as a client refreshing ahead of
+// expiry would. The algorithm follows from the key: RS256 for RSA keys (PKCS#1 or PKCS#8 PEM),
+// ES256 for P-256 keys and EdDSA for Ed25519 keys (PKCS#8 PEM), HS256 for any other file,
+// whose contents are the shared secret.
+
+use crate::template::{Template, TemplateContext};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use parking_lot::Mutex;
+use reqwest::header::HeaderValue;
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
+use serde_json::{Map, Value};
+use std::time::{Duration, Instant};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DEFAULT_TTL: &str = "5m";
+
+enum SigningKey {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+    Hmac(ring::hmac::Key),
+}
+
+impl SigningKey {
+    fn load(path: &str) -> Result<Self, BoxError> {
+        let contents = std::fs::read(path)
+            .map_err(|e| format!("cannot read JWT signing key {}: {}", path, e))?;
+        let Some((label, der)) = pem(&contents) else {
+            // Not PEM: an HMAC secret, without the newline editors add
+            let secret = contents.trim_ascii_end();
+            if secret.is_empty() {
+                return Err(format!("JWT signing key {} is empty", path).into());
+            }
+            return Ok(Self::Hmac(ring::hmac::Key::new(
+                ring::hmac::HMAC_SHA256,
+                secret,
+            )));
+        };
+        let rejected = |e: ring::error::KeyRejected| -> BoxError {
+            format!("unusable JWT signing key {}: {}", path, e).into()
+        };
+        match label.as_str() {
+            "RSA PRIVATE KEY" => Ok(Self::Rsa(RsaKeyPair::from_der(&der).map_err(rejected)?)),
+            "PRIVATE KEY" => {
+                if let Ok(key) = RsaKeyPair::from_pkcs8(&der) {
+                    return Ok(Self::Rsa(key));
+                }
+                if let Ok(key) = EcdsaKeyPair::from_pkcs8(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &der,
+                    &SystemRandom::new(),
+                ) {
+                    return Ok(Self::Ecdsa(key));
+                }
+                Ok(Self::Ed25519(
+                    Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(|_| {
+                        format!(
+                            "JWT signing key {} is not an RSA, P-256 or Ed25519 key",
+                            path
+                        )
+                    })?,
+                ))
+            }
+            "EC PRIVATE KEY" => Err(format!(
+                "JWT signing key {} is a SEC1 EC key; convert it to PKCS#8 with \
+                 `openssl pkcs8 -topk8 -nocrypt -in {} -out key.pem`",
+                path, path
+            )
+            .into()),
+            other => Err(format!(
+                "JWT signing key {} holds a {}, not a private key",
+                path, other
+            )
+            .into()),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Rsa(_) => "RS256",
+            Self::Ecdsa(_) => "ES256",
+            Self::Ed25519(_) => "EdDSA",
+            Self::Hmac(_) => "HS256",
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BoxError> {
+        let failed = |_| -> BoxError { "signing the JWT failed".into() };
+        match self {
+            Self::Rsa(key) => {
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(
+                    &signature::RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    message,
+                    &mut signature,
+                )
+                .map_err(failed)?;
+                Ok(signature)
+            }
+            Self::Ecdsa(key) => Ok(key
+                .sign(&SystemRandom::new(), message)
+                .map_err(failed)?
+                .as_ref()
+                .to_vec()),
+            Self::Ed25519(key) => Ok(key.sign(message).as_ref().to_vec()),
+            Self::Hmac(key) => Ok(ring::hmac::sign(key, message).as_ref().to_vec()),
+        }
+    }
+}
+
+// The label and DER contents of the first PEM block
+fn pem(contents: &[u8]) -> Option<(String, Vec<u8>)> {
+    let text = std::str::from_utf8(contents).ok()?;
+    let start = text.find("-----BEGIN ")?;
+    let rest = &text[start + "-----BEGIN ".len()..];
+    let (label, rest) = rest.split_once("-----")?;
+    let end = rest.find("-----END ")?;
+    let body: String = rest[..end].chars().filter(|c| !c.is_whitespace()).collect();
+    Some((label.to_string(), STANDARD.decode(body).ok()?))
+}
+
+// A VU's current token and when it was signed
+struct Token {
+    authorization: HeaderValue,
+    signed_at: Instant,
+}
+
+pub struct JwtMinter {
+    key: SigningKey,
+    claims: Template,
+    ttl: Duration,
+    tokens: Vec<Mutex<Option<Token>>>,
+}
+
+impl JwtMinter {
+    pub fn new(key_path: &str, claims: &str, ttl: Duration, vus: usize) -> Result<Self, BoxError> {
+        if ttl.is_zero() {
+            return Err("--jwt-ttl must be longer than zero".into());
+        }
+        let minter = Self {
+            key: SigningKey::load(key_path)?,
+            claims: Template::parse(claims)?,
+            ttl,
+            tokens: (0..vus.max(1)).map(|_| Mutex::new(None)).collect(),
+        };
+        // Claims built from data rows or extracted values are only known per request
+        if minter.claims.csv_columns().next().is_none()
+            && minter.claims.variables().next().is_none()
+        {
+            let context = TemplateContext {
+                vu: 1,
+                iteration: 0,
+                row: None,
+                vars: None,
+                rng: None,
+            };
+            minter.claims(&context)?;
+        }
+        Ok(minter)
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        self.key.algorithm()
+    }
+
+    // The VU's Bearer Authorization header, signed anew when the current one is due for rotation
+    pub fn authorization(&self, context: &TemplateContext<'_>) -> Option<HeaderValue> {
+        let mut token = self.tokens[context.vu.saturating_sub(1) % self.tokens.len()].lock();
+        let rotate = self.ttl.mul_f64(0.8);
+        if let Some(token) = token.as_ref().filter(|t| t.signed_at.elapsed() < rotate) {
+            return Some(token.authorization.clone());
+        }
+        match self.mint(context) {
+            Ok(authorization) => {
+                *token = Some(Token {
+                    authorization: authorization.clone(),
+                    signed_at: Instant::now(),
+                });
+                Some(authorization)
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, vu = context.vu, "failed to mint JWT");
+                None
+            }
+        }
+    }
+
+    fn claims(&self, context: &TemplateContext<'_>) -> Result<Map<String, Value>, BoxError> {
+        let rendered = self.claims.render(context);
+        match serde_json::from_str(&rendered) {
+            Ok(Value::Object(claims)) => Ok(claims),
+            Ok(_) => Err(format!("--jwt-claims must be a JSON object: {}", rendered).into()),
+            Err(e) => Err(format!("invalid --jwt-claims JSON ({}): {}", e, rendered).into()),
+        }
+    }
+
+    fn mint(&self, context: &TemplateContext<'_>) -> Result<HeaderValue, BoxError> {
+        let mut claims = self.claims(context)?;
+        let now = chrono::Utc::now().timestamp();
+        claims.entry("iat").or_insert(now.into());
+        claims
+            .entry("exp")
+            .or_insert((now + self.ttl.as_secs() as i64).into());
+
+        let header = serde_json::json!({ "alg": self.key.algorithm(), "typ": "JWT" });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(Value::Object(claims).to_string())
+        );
+        let signature = self.key.sign(signing_input.as_bytes())?;
+        let token = format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature));
+        Ok(HeaderValue::from_str(&format!("Bearer {}", token))?)
+    }
+}
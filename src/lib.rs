@@ -0,0 +1,5986 @@
+//! forgy: HTTP load testing with Prometheus metrics
+//!
+//! The `forgy` binary is a thin wrapper around this crate. Other programs can embed a
+//! load test with [`TestBuilder`]:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let results = forgy::TestBuilder::new("http://localhost:3000/api")
+//!     .vus(100)
+//!     .hold(std::time::Duration::from_secs(60))
+//!     .threshold("p95<250ms")
+//!     .run()
+//!     .await?;
+//! assert!(results.thresholds.iter().all(|t| t.passed));
+//! # Ok(())
+//! # }
+//! ```
+
+// Standard library imports
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// External crate imports
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use hdrhistogram::Histogram;
+use humantime::parse_duration;
+use hyper::client::connect::HttpInfo;
+use indicatif::{ProgressBar, ProgressStyle};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use prometheus::{
+    Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry,
+};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CACHE_CONTROL,
+    CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE,
+};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, sleep};
+use tracing::Instrument;
+
+// Error classification module
+pub mod errors;
+use errors::ErrorType;
+
+// Response checks module
+pub mod checks;
+
+// Expected response status module
+pub mod expect_status;
+use checks::{Check, CheckSummary};
+use expect_status::ExpectedStatus;
+
+// Thresholds module
+pub mod thresholds;
+use thresholds::{Measurements, Threshold, ThresholdResult};
+
+// Logging module
+pub mod logging;
+use logging::LogLevel;
+
+// Request/response sampling module
+pub mod debug_sample;
+use debug_sample::DebugSample;
+
+// Failure artifacts module
+pub mod failures;
+use failures::{FailedRequest, FailureCapture};
+
+// Service level objectives module
+pub mod slo;
+use slo::{Slo, SloSummary};
+
+// Early abort module
+pub mod abort;
+use abort::AbortCondition;
+
+// Script hook module
+pub mod script;
+
+// WASM plugin module
+#[cfg(feature = "wasm")]
+pub mod plugin;
+use script::{HookContext, Script, ScriptRequest, ScriptResponse};
+
+// Checkpoint module
+pub mod checkpoint;
+use checkpoint::Checkpoint;
+
+// Capacity search module
+pub mod autoscale;
+use autoscale::{AutoscaleOptions, AutoscaleReport, AutoscaleStep, LoadUnit};
+
+// Scenario module
+pub mod scenario;
+use scenario::{ParallelScenario, RequestSpec, Scenario};
+
+// Setup and teardown module
+pub mod setup;
+use setup::Variables;
+
+// Stage-based load profile module
+pub mod stages;
+use stages::Stage;
+
+// Load shapes: --stage lists and --profile generators
+pub mod load_shape;
+use load_shape::{LoadShape, Profile, Stages};
+
+// curl command import
+pub mod curl;
+use curl::CurlRequest;
+
+// HAR import
+pub mod har;
+
+// Scenarios from OpenAPI documents
+pub mod openapi;
+
+// Access-log replay
+pub mod replay;
+use replay::{LogFormat, Replay};
+
+// Think time distributions
+pub mod think_time;
+use think_time::ThinkTime;
+
+// Seeded random number generators module
+pub mod rng;
+use rng::Rngs;
+
+// Multipart form module
+pub mod form;
+use form::Form;
+
+// Request bodies from files and forms module
+pub mod payload;
+use payload::{BodyPattern, Contents, Payload};
+
+// Content encoding module
+pub mod compression;
+use compression::{Compression, Decoder};
+
+// Request templating module
+pub mod template;
+
+// Fake data module
+pub mod fake;
+
+// OAuth2 client-credentials module
+pub mod oauth2;
+use oauth2::{OAuth2Config, TokenSource};
+
+// Basic and Digest authentication module
+pub mod http_auth;
+use http_auth::{Credentials, DigestAuth};
+
+// Locally signed JWT module
+pub mod jwt;
+use jwt::JwtMinter;
+
+// HMAC request signing module
+pub mod signing;
+use signing::{HmacAlgorithm, RequestSigner, SignatureEncoding, SigningConfig};
+
+// Server-sent events module
+pub mod sse;
+use sse::{SseMonitor, SseSummary};
+
+// Raw TCP module
+pub mod tcp;
+use tcp::{TcpConfig, TcpProber, TcpSummary};
+
+// DNS resolution module
+pub mod dns;
+
+// Request phase timing module
+pub mod timing;
+use timing::{ConnectionPhases, PhaseTimings, PHASES};
+
+// HTTP client module
+pub mod client;
+use client::{ClientOptions, Clients};
+
+// Timed transport module
+pub mod transport;
+
+// Forward proxy module
+pub mod proxy;
+
+// Connection counting module
+pub mod connections;
+use connections::ConnectionTracker;
+
+// Cookie jar module
+pub mod cookies;
+use cookies::{CookieJars, CookieMode};
+
+// Endpoint grouping module
+pub mod endpoint;
+use endpoint::Endpoints;
+
+// Header size accounting module
+pub mod wire_size;
+
+// Sharded request statistics module
+pub mod stats;
+use stats::{ByteCounts, RequestRecord, Reservoir, ShardStats, Stats};
+
+// Per-second time series module
+pub mod timeseries;
+use timeseries::{HeatmapRow, Timeseries, TimeseriesPoint};
+
+// HTML report module
+pub mod report;
+
+// JUnit XML output module
+pub mod junit;
+
+// HdrHistogram log output module
+pub mod hdr;
+
+// Per-request NDJSON log module
+pub mod request_log;
+use request_log::{RequestLog, RequestLogEntry};
+
+// GraphQL helper module
+pub mod graphql;
+
+// CSV data feed module
+pub mod data;
+use data::{DataFeed, DataMode};
+use template::{Template, TemplateContext};
+
+// Metrics scrape endpoint module
+pub mod metrics_server;
+
+// Embedding API
+mod builder;
+pub use builder::TestBuilder;
+
+// Remote write module
+pub mod remote_write;
+use remote_write::{
+    QueuePolicy, RemoteWriteClient, RemoteWriteConfig, RemoteWriteVersion,
+    REMOTE_WRITE_DROPPED_BATCHES, REMOTE_WRITE_QUEUE_DEPTH, REMOTE_WRITE_RETRIES,
+    REMOTE_WRITE_SAMPLES_FAILED, REMOTE_WRITE_SAMPLES_SENT,
+};
+
+// OpenTelemetry metrics exporter module
+pub mod otlp;
+use otlp::{OtlpConfig, OtlpExporter, OtlpProtocol};
+
+// Global request rate ceiling
+pub mod rate_limit;
+use rate_limit::RateLimiter;
+
+// Retry policy module
+pub mod retry;
+use retry::RetryPolicy;
+
+// InfluxDB line-protocol output module
+pub mod influx;
+use influx::{InfluxConfig, InfluxWriter};
+
+// Grafana dashboard module
+pub mod grafana;
+
+// Prometheus Pushgateway output module
+pub mod pushgateway;
+use pushgateway::{PushgatewayConfig, PushgatewayWriter};
+
+// Webhook notifications module
+pub mod notify;
+use notify::{Event, Notifier, NotifyConfig, WebhookFormat};
+
+// Run history database module
+pub mod history;
+
+// Per-request metric sinks
+pub mod exporters;
+use exporters::{Exporter, PrometheusExporter, RequestEvent, StatsdConfig, StatsdExporter};
+
+// Combining and comparing result files
+pub mod compare;
+pub mod merge;
+use merge::EncodedHistograms;
+
+// Trace-context propagation and client spans
+pub mod trace;
+use trace::{ClientSpan, TraceContext, Tracer};
+
+// =============================================================================
+// PROMETHEUS METRICS
+// =============================================================================
+
+// Buckets of the request duration histograms, in seconds
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+    static ref REMOTE_WRITE_CLIENT: parking_lot::Mutex<Option<RemoteWriteClient>> = parking_lot::Mutex::new(None);
+    static ref OTLP_EXPORTER: parking_lot::Mutex<Option<Arc<OtlpExporter>>> = parking_lot::Mutex::new(None);
+    static ref INFLUX_WRITER: parking_lot::Mutex<Option<Arc<InfluxWriter>>> = parking_lot::Mutex::new(None);
+    static ref PUSHGATEWAY_WRITER: parking_lot::Mutex<Option<Arc<PushgatewayWriter>>> = parking_lot::Mutex::new(None);
+
+    // Request metrics
+    static ref REQUEST_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_requests_total", "Total number of requests made"),
+        &["status", "method", "target", "endpoint", "protocol", "scenario"]
+    ).unwrap();
+
+    static ref ERRORS_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_errors_total", "Total number of failed requests by error type"),
+        &["error_type", "target", "endpoint", "scenario"]
+    ).unwrap();
+
+    static ref REQUEST_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("forgy_request_duration_seconds", "Request duration in seconds")
+            .buckets(DURATION_BUCKETS.to_vec()),
+        &["method", "status_class", "target", "endpoint", "scenario"]
+    ).unwrap();
+
+    static ref REQUEST_PHASE_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("forgy_request_phase_duration_seconds", "Request duration by phase (dns, connect, tls, ttfb, download) in seconds")
+            .buckets(DURATION_BUCKETS.to_vec()),
+        &["phase"]
+    ).unwrap();
+
+    static ref CHECKS_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_checks_total", "Total number of response checks by outcome"),
+        &["check", "result"]
+    ).unwrap();
+
+    static ref ACTIVE_VUS: IntGauge = IntGauge::new(
+        "forgy_active_vus", "Number of active virtual users"
+    ).unwrap();
+
+    static ref TARGET_VUS: IntGauge = IntGauge::new(
+        "forgy_target_vus", "Target number of virtual users"
+    ).unwrap();
+
+    static ref SUCCESS_RATE: Gauge = Gauge::new(
+        "forgy_success_rate", "Current success rate (percentage)"
+    ).unwrap();
+
+    static ref REQUESTS_PER_SECOND: Gauge = Gauge::new(
+        "forgy_requests_per_second", "Current requests per second"
+    ).unwrap();
+
+    // Response time percentiles
+    static ref RESPONSE_TIME_P50: Gauge = Gauge::new(
+        "forgy_response_time_p50_ms", "50th percentile response time in milliseconds"
+    ).unwrap();
+
+    static ref RESPONSE_TIME_P90: Gauge = Gauge::new(
+        "forgy_response_time_p90_ms", "90th percentile response time in milliseconds"
+    ).unwrap();
+
+    static ref RESPONSE_TIME_P95: Gauge = Gauge::new(
+        "forgy_response_time_p95_ms", "95th percentile response time in milliseconds"
+    ).unwrap();
+
+    static ref RESPONSE_TIME_P99: Gauge = Gauge::new(
+        "forgy_response_time_p99_ms", "99th percentile response time in milliseconds"
+    ).unwrap();
+
+    // Test phase indicator
+    static ref TEST_PHASE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("forgy_phase", "Current test phase (0=idle, 1=rampup, 2=hold, 3=rampdown)"),
+        &["phase"]
+    ).unwrap();
+
+    static ref SLO_BURN_RATE: GaugeVec = GaugeVec::new(
+        Opts::new("forgy_slo_burn_rate", "Rate at which each --slo spends its error budget (since last push; 1 spends exactly the budget)"),
+        &["slo"]
+    ).unwrap();
+
+    static ref SLO_ERROR_BUDGET_REMAINING: GaugeVec = GaugeVec::new(
+        Opts::new("forgy_slo_error_budget_remaining", "Share of each --slo's error budget left over the run so far (negative once exceeded)"),
+        &["slo"]
+    ).unwrap();
+
+    static ref STAGE: IntGauge = IntGauge::new(
+        "forgy_stage", "Current --stage or --profile segment (1-based index, 0 when none is running)"
+    ).unwrap();
+
+    static ref RETRIES_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_retries_total", "Retries of failed requests (also counted in forgy_requests_total)"),
+        &["method", "target", "endpoint", "scenario"]
+    ).unwrap();
+
+    static ref DROPPED_ITERATIONS: IntCounter = IntCounter::new(
+        "forgy_dropped_iterations_total", "Scheduled requests skipped because all VUs were busy (arrival-rate mode)"
+    ).unwrap();
+
+    // Rate limiter (--max-rps)
+    static ref RATE_LIMIT_CONFIGURED: Gauge = Gauge::new(
+        "forgy_rate_limit_configured_rps", "Request rate ceiling set with --max-rps"
+    ).unwrap();
+
+    static ref CONNECTIONS_ESTABLISHED: IntGauge = IntGauge::new(
+        "forgy_connections_established", "Established TCP connections of the load generator (Linux only)"
+    ).unwrap();
+
+    static ref CONNECTIONS_OPENED: IntCounter = IntCounter::new(
+        "forgy_connections_opened_total", "Connections opened to the targets"
+    ).unwrap();
+
+    static ref CONNECTIONS_CLOSED: IntCounter = IntCounter::new(
+        "forgy_connections_closed_total", "Connections to the targets that were closed (Linux only)"
+    ).unwrap();
+
+    static ref RATE_LIMIT_ACHIEVED: Gauge = Gauge::new(
+        "forgy_rate_limit_achieved_rps", "Requests per second let through by the --max-rps limiter (since last push)"
+    ).unwrap();
+
+    // Data transfer metrics
+    static ref DATA_SENT: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_data_sent", "Total number of bytes sent in HTTP requests, by part (header or body)"),
+        &["method", "part"]
+    ).unwrap();
+
+    static ref THROUGHPUT: GaugeVec = GaugeVec::new(
+        Opts::new("forgy_throughput_bytes_per_second", "Bytes per second sent (upload) and received (download), headers included (since last push)"),
+        &["direction"]
+    ).unwrap();
+
+    static ref DATA_RECEIVED: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_data_received", "Total number of bytes received in HTTP responses, by part (header or body)"),
+        &["method", "status_class", "part"]
+    ).unwrap();
+
+    // Server-sent events (--sse)
+    static ref SSE_STREAMS: IntGauge = IntGauge::new(
+        "forgy_sse_streams", "Server-sent event streams currently open"
+    ).unwrap();
+
+    static ref SSE_EVENTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_sse_events_total", "Server-sent events received"),
+        &["endpoint"]
+    ).unwrap();
+
+    static ref SSE_TIME_TO_FIRST_EVENT: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("forgy_sse_time_to_first_event_seconds", "Time from opening a server-sent event stream to its first event in seconds")
+            .buckets(DURATION_BUCKETS.to_vec()),
+        &["endpoint"]
+    ).unwrap();
+
+    static ref SSE_EVENT_INTERVAL: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("forgy_sse_event_interval_seconds", "Time between consecutive server-sent events of a stream in seconds")
+            .buckets(DURATION_BUCKETS.to_vec()),
+        &["endpoint"]
+    ).unwrap();
+
+    static ref DATA_DECODED: IntCounterVec = IntCounterVec::new(
+        Opts::new("forgy_data_decoded", "Total number of HTTP body bytes before content encoding (sent) and after decoding (received), by direction"),
+        &["method", "direction"]
+    ).unwrap();
+}
+
+// =============================================================================
+// DATA STRUCTURES
+// =============================================================================
+
+#[derive(Parser, Debug, Clone)]
+#[clap(name = "forgy")]
+#[clap(about = "High-performance REST endpoint load testing tool with Prometheus metrics", long_about = None)]
+#[clap(subcommand_negates_reqs = true)]
+pub struct Args {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// Target URL to test; repeat for several targets, optionally weighted as "weight=3,URL"
+    /// or "weight=70%,URL"
+    #[clap(long, value_parser, required_unless_present_any = ["config", "har", "from_curl"])]
+    pub url: Vec<String>,
+
+    /// Scenario file (YAML or TOML) with a weighted list of requests, used instead of --url
+    #[clap(long, value_name = "FILE", conflicts_with = "url")]
+    pub config: Option<String>,
+
+    /// Browser-recorded HAR file whose requests every VU sends in order, used instead of --url
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["url", "config", "graphql_query"])]
+    pub har: Option<String>,
+
+    /// Request copied as a curl command (e.g. from browser devtools), used instead of --url
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        conflicts_with_all = ["url", "config", "har", "graphql_query", "method", "body"]
+    )]
+    pub from_curl: Option<String>,
+
+    /// Endpoint name for the --url at the same position, reported instead of its normalized
+    /// path (e.g. "GET /users/{id}"); repeat for several URLs
+    #[clap(long, conflicts_with_all = ["config", "har", "from_curl"])]
+    pub name: Vec<String>,
+
+    /// Number of virtual users (concurrent connections)
+    #[clap(long, default_value = "10")]
+    pub vus: usize,
+
+    /// Ramp-up duration (e.g., 5m, 30s, 1h)
+    #[clap(long, default_value = "10s")]
+    pub ramp_up: String,
+
+    /// Hold duration at peak load (e.g., 1h, 30m, 60s)
+    #[clap(long, default_value = "30s")]
+    pub hold: String,
+
+    /// Ramp-down duration (e.g., 60s, 5m)
+    #[clap(long, default_value = "10s")]
+    pub ramp_down: String,
+
+    /// Load stage as DURATION:TARGET_VUS (e.g., 2m:50); repeat to build a custom ramp profile
+    #[clap(
+        long,
+        value_name = "DURATION:VUS",
+        value_parser = Stage::parse,
+        conflicts_with_all = ["ramp_up", "hold", "ramp_down", "rps"]
+    )]
+    pub stage: Vec<Stage>,
+
+    /// Generated load profile: spike:BASE:PEAK:LENGTH, steps:SIZExLENGTH or sine:period=10m,min=10,max=200
+    #[clap(
+        long,
+        value_name = "SHAPE",
+        value_parser = Profile::parse,
+        conflicts_with_all = ["ramp_up", "ramp_down", "rps", "stage"]
+    )]
+    pub profile: Option<Profile>,
+
+    /// End the test after N requests in total, shared by all VUs, instead of after a duration
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["ramp_up", "hold", "ramp_down", "rps", "stage", "profile", "iterations_per_vu"]
+    )]
+    pub iterations: Option<u64>,
+
+    /// End the test once every VU has made N requests, instead of after a duration
+    #[clap(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["ramp_up", "hold", "ramp_down", "rps", "stage", "profile"]
+    )]
+    pub iterations_per_vu: Option<u64>,
+
+    /// Pause between a VU's requests: none, constant:500ms, uniform:200ms-2s or exponential:1s (default: ~1s with jitter)
+    #[clap(long, value_name = "DIST", value_parser = ThinkTime::parse, conflicts_with = "rps")]
+    pub think_time: Option<ThinkTime>,
+
+    /// Seed the random values of every VU (placeholders, request choice, think time, retry backoff) for reproducible runs
+    #[clap(long, value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// HTTP protocol version: 1.1 (forced), 2 (offered via TLS ALPN), 2-prior-knowledge (also h2c) or 3 (QUIC)
+    #[clap(long, value_enum, default_value = "2")]
+    pub http_version: HttpVersion,
+
+    /// Client certificate (PEM) for mutual TLS; may also contain the private key
+    #[clap(long, value_name = "FILE")]
+    pub cert: Option<String>,
+
+    /// Private key (PEM) for --cert
+    #[clap(long, value_name = "FILE", requires = "cert")]
+    pub key: Option<String>,
+
+    /// Additional CA certificate (PEM) to trust, e.g. for a private CA
+    #[clap(long, value_name = "FILE")]
+    pub cacert: Option<String>,
+
+    /// Skip TLS certificate verification (self-signed staging environments only)
+    #[clap(long)]
+    pub insecure: bool,
+
+    /// Send all requests through this HTTP, HTTPS or SOCKS5 proxy (hosts in NO_PROXY are reached directly)
+    #[clap(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Credentials for --proxy as USER:PASSWORD (sent with Basic authentication)
+    #[clap(long, value_name = "USER:PASSWORD", requires = "proxy")]
+    pub proxy_auth: Option<String>,
+
+    /// Connect to these addresses for a host and port instead of resolving it, as in curl
+    /// ("*" as HOST matches any host; can be used multiple times)
+    #[clap(long, value_name = "HOST:PORT:ADDR[,ADDR...]")]
+    pub resolve: Vec<String>,
+
+    /// Resolve host names with this DNS server (ADDR or ADDR:PORT) instead of the system resolver
+    #[clap(long, value_name = "ADDR")]
+    pub dns_server: Option<String>,
+
+    /// Only connect to IPv4 addresses of the target hosts
+    #[clap(long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+
+    /// Only connect to IPv6 addresses of the target hosts
+    #[clap(long)]
+    pub ipv6: bool,
+
+    /// Source IP address for connections; with several (can be used multiple times), VUs use
+    /// them in turn, to spread connections over more ephemeral ports
+    #[clap(long, value_name = "ADDR")]
+    pub local_address: Vec<std::net::IpAddr>,
+
+    /// OAuth2 token endpoint; a client-credentials token is sent as a Bearer Authorization header
+    #[clap(long, value_name = "URL", requires_all = ["client_id", "client_secret"])]
+    pub oauth2_token_url: Option<String>,
+
+    /// OAuth2 client ID
+    #[clap(long, requires = "oauth2_token_url")]
+    pub client_id: Option<String>,
+
+    /// OAuth2 client secret
+    #[clap(long, requires = "oauth2_token_url")]
+    pub client_secret: Option<String>,
+
+    /// OAuth2 scope to request
+    #[clap(long, requires = "oauth2_token_url")]
+    pub oauth2_scope: Option<String>,
+
+    /// Credentials sent with HTTP Basic authentication, or Digest with --digest
+    #[clap(
+        long,
+        value_name = "NAME:PASSWORD",
+        conflicts_with = "oauth2_token_url"
+    )]
+    pub user: Option<String>,
+
+    /// Authenticate --user with HTTP Digest, answering the server's 401 challenge in each VU
+    #[clap(long, requires = "user")]
+    pub digest: bool,
+
+    /// Key that forgy signs a JWT per VU with, sent as a Bearer Authorization header: an RSA,
+    /// P-256 or Ed25519 private key in PEM, or any other file as an HMAC secret
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["oauth2_token_url", "user"]
+    )]
+    pub jwt_signing_key: Option<String>,
+
+    /// Claims of the JWTs as a JSON template, e.g. '{"sub":"user-${VU}"}'; iat and exp are
+    /// added unless set
+    #[clap(long, value_name = "JSON", requires = "jwt_signing_key")]
+    pub jwt_claims: Option<String>,
+
+    /// Lifetime of the JWTs; a VU signs a new one once 80% of it has passed
+    #[clap(long, value_name = "DURATION", default_value = jwt::DEFAULT_TTL, value_parser = parse_duration)]
+    pub jwt_ttl: Duration,
+
+    /// Secret that every request is signed with, in an HMAC signature header
+    #[clap(long, value_name = "SECRET")]
+    pub hmac_secret: Option<String>,
+
+    /// Hash function of the HMAC signature
+    #[clap(long, value_enum, default_value = "sha256")]
+    pub hmac_algorithm: HmacAlgorithm,
+
+    /// Header the HMAC signature is sent in
+    #[clap(long, value_name = "NAME", default_value = signing::DEFAULT_HEADER)]
+    pub hmac_header: String,
+
+    /// What the HMAC signs: text with {method}, {path}, {query}, {body}, {timestamp} and
+    /// {timestamp_ms} placeholders and \n escapes
+    #[clap(long, value_name = "FORMAT", default_value = signing::DEFAULT_PAYLOAD)]
+    pub hmac_payload: String,
+
+    /// Encoding of the HMAC signature
+    #[clap(long, value_enum, default_value = "hex")]
+    pub hmac_encoding: SignatureEncoding,
+
+    /// Text sent before the HMAC signature, e.g. "sha256="
+    #[clap(long, value_name = "TEXT", requires = "hmac_secret")]
+    pub hmac_prefix: Option<String>,
+
+    /// Header the timestamp of the HMAC signature is sent in
+    #[clap(long, value_name = "NAME", requires = "hmac_secret")]
+    pub hmac_timestamp_header: Option<String>,
+
+    /// CSV file whose columns are available in templates as ${csv.column}
+    #[clap(long, value_name = "FILE")]
+    pub data: Option<String>,
+
+    /// How --data rows are assigned: round-robin across all VUs or a partition per VU
+    #[clap(long, value_enum, default_value = "round-robin")]
+    pub data_mode: DataMode,
+
+    /// Constant arrival rate in requests per second (open model); --vus sets the max concurrent requests
+    #[clap(long)]
+    pub rps: Option<f64>,
+
+    /// Hard ceiling on requests per second across all VUs, whatever the load profile
+    #[clap(long, value_name = "RATE", value_parser = rate_limit::parse_max_rps)]
+    pub max_rps: Option<f64>,
+
+    /// HTTP method to use
+    #[clap(long, default_value = "GET")]
+    pub method: String,
+
+    /// Request body (for POST/PUT requests)
+    #[clap(long)]
+    pub body: Option<String>,
+
+    /// Multipart form field NAME=VALUE, or file upload NAME=@PATH[;type=MIME] streamed from disk (repeatable)
+    #[clap(long, value_name = "FIELD", conflicts_with_all = ["body", "config", "har", "from_curl", "graphql_query"])]
+    pub form: Vec<String>,
+
+    /// Send the contents of a file as the body of every request, streamed from disk; @- reads standard input
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["body", "form", "config", "har", "from_curl", "graphql_query"])]
+    pub body_file: Option<String>,
+
+    /// Send a generated body of this size with every request, e.g. 256KB or 10MB, for upload throughput tests
+    #[clap(long, value_name = "SIZE", value_parser = payload::parse_size, conflicts_with_all = ["body", "form", "body_file", "config", "har", "from_curl", "graphql_query"])]
+    pub body_size: Option<u64>,
+
+    /// Contents of the --body-size payload
+    #[clap(long, value_enum, default_value = "random", requires = "body_size")]
+    pub body_pattern: BodyPattern,
+
+    /// GraphQL query (or @file) sent as a JSON POST; responses with errors[] count as failures
+    #[clap(long, value_name = "QUERY", conflicts_with_all = ["body", "config"])]
+    pub graphql_query: Option<String>,
+
+    /// GraphQL variables as a JSON object
+    #[clap(long, value_name = "JSON", requires = "graphql_query")]
+    pub graphql_variables: Option<String>,
+
+    /// Headers in format "Key:Value" (can be used multiple times)
+    #[clap(long)]
+    pub header: Vec<String>,
+
+    /// Check that the response status is one of the given codes (e.g., 200,201)
+    #[clap(long, value_name = "CODES")]
+    pub check_status: Option<String>,
+
+    /// Response time percentiles to report, overall and per status class (e.g., 50,75,90,99,99.9,99.99)
+    #[clap(long, value_name = "LIST", value_delimiter = ',', default_value = "50,90,95,99", value_parser = parse_percentile)]
+    pub percentiles: Vec<f64>,
+
+    /// Statuses that count as success instead of 2xx: codes, ranges and classes (e.g., 200,201,204 or 2xx,404); 4xx-ok adds 4xx to 2xx
+    #[clap(long, value_name = "STATUSES")]
+    pub expect_status: Option<String>,
+
+    /// Check that a response header contains a value, "Name:Value" (can be used multiple times)
+    #[clap(long, value_name = "HEADER")]
+    pub check_header: Vec<String>,
+
+    /// Check that the response body contains a substring (can be used multiple times)
+    #[clap(long, value_name = "TEXT")]
+    pub check_body: Vec<String>,
+
+    /// Check a JSONPath expression, "$.path" (exists) or "$.path==value" (can be used multiple times)
+    #[clap(long, value_name = "EXPR")]
+    pub check_jsonpath: Vec<String>,
+
+    /// Rhai script whose request and response functions build requests and judge responses
+    #[clap(long, value_name = "FILE")]
+    pub script: Option<String>,
+
+    /// WASM module whose generate_request and validate_response exports build requests and
+    /// judge responses
+    #[clap(long, value_name = "FILE", conflicts_with = "script")]
+    pub plugin: Option<String>,
+
+    /// Time a --script function or --plugin export may run before the request fails with error
+    /// type script, in seconds or as a duration (default: --request-timeout)
+    #[clap(long, value_name = "DURATION", value_parser = client::parse_timeout)]
+    pub script_timeout: Option<Duration>,
+
+    /// Do not download response bodies, for tests that only measure latency (size from Content-Length)
+    #[clap(long, conflicts_with_all = ["check_body", "check_jsonpath", "graphql_query", "script", "plugin"])]
+    pub discard_body: bool,
+
+    /// Download throughput mode: report per-request and aggregate download rates (MB/s) of the fully streamed response bodies
+    #[clap(long, conflicts_with = "discard_body")]
+    pub throughput: bool,
+
+    /// Accept-Encoding to ask for (gzip, br or identity); gzip responses are decoded, and their sizes reported both on the wire and decoded
+    #[clap(long, value_enum, value_name = "ENCODING")]
+    pub compression: Option<Compression>,
+
+    /// Compress request bodies with gzip and send them with Content-Encoding: gzip
+    #[clap(long, conflicts_with = "form")]
+    pub gzip_body: bool,
+
+    /// Keep cookies set by the server and send them on later requests, with one jar per VU
+    /// (per-vu) or one jar for all VUs (shared)
+    #[clap(long, value_enum, value_name = "MODE")]
+    pub cookie_jar: Option<CookieMode>,
+
+    /// Close every connection after one request (Connection: close), so each request opens a
+    /// new connection and TLS handshake
+    #[clap(long)]
+    pub disable_keepalive: bool,
+
+    /// Reconnect once a client's connections are this old (e.g. 30s, 5m), for connection churn
+    #[clap(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub max_connection_lifetime: Option<Duration>,
+
+    /// Give every VU its own HTTP client and connection pool, so each VU opens its own
+    /// connections as separate users would, instead of sharing one pool
+    #[clap(long)]
+    pub client_per_vu: bool,
+
+    /// Pass/fail threshold, e.g. "p95<250ms" or "error_rate<1%" (can be used multiple times)
+    #[clap(long, value_name = "EXPR")]
+    pub threshold: Vec<String>,
+
+    /// Service level objective to report error budget consumption and burn rate for, e.g.
+    /// "latency:p99<500ms" or "availability:99.9" (can be used multiple times)
+    #[clap(long, value_name = "SLO")]
+    pub slo: Vec<String>,
+
+    /// Time allowed for a whole request, from sending it until the response body has been
+    /// read (only until the headers with --max-response-duration), in seconds or as a duration
+    /// (e.g. 500ms); --timeout is an alias
+    #[clap(long, alias = "timeout", value_name = "DURATION", default_value = "30", value_parser = client::parse_timeout)]
+    pub request_timeout: Duration,
+
+    /// Time allowed for opening a connection, including the TLS handshake (default: only
+    /// bounded by --request-timeout)
+    #[clap(long, value_name = "DURATION", value_parser = client::parse_timeout)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Longest a VU stays on one response, e.g. 60s for long polls or chunked streams; a body
+    /// still arriving then is cut off, and --request-timeout only bounds the wait for headers
+    #[clap(long, value_name = "DURATION", value_parser = client::parse_timeout, conflicts_with_all = ["sse", "discard_body"])]
+    pub max_response_duration: Option<Duration>,
+
+    /// Retry failed requests up to N times; every attempt is reported as a request of its own
+    #[clap(long, value_name = "N", default_value = "0")]
+    pub retries: u32,
+
+    /// Wait before the first retry, doubled for each further one (with jitter)
+    #[clap(long, value_name = "DURATION", default_value = "100ms", value_parser = parse_duration)]
+    pub retry_backoff: Duration,
+
+    /// Status codes and error types to retry, comma-separated (e.g. 502,503,timeout)
+    #[clap(long, value_name = "CONDITIONS", default_value = retry::DEFAULT_RETRY_ON)]
+    pub retry_on: String,
+
+    /// Also retry requests with non-idempotent methods such as POST and PATCH
+    #[clap(long)]
+    pub retry_non_idempotent: bool,
+
+    /// Hold server-sent event streams open instead of sending requests, measuring the time to
+    /// the first event, the time between events and events per second
+    #[clap(long, conflicts_with_all = ["rps", "compression", "discard_body"])]
+    pub sse: bool,
+
+    /// Protocol of the load: http, or tcp for raw connections to tcp://HOST:PORT URLs
+    #[clap(long, value_enum, default_value = "http")]
+    pub protocol: Protocol,
+
+    /// Bytes to send on every TCP connection, in hex (e.g. 50494e470a); without it
+    /// connections are only opened and closed
+    #[clap(long, value_name = "HEX")]
+    pub payload_hex: Option<String>,
+
+    /// Response bytes to wait for on every TCP connection, measuring the round trip
+    #[clap(long, value_name = "BYTES")]
+    pub tcp_read: Option<usize>,
+
+    /// Header that carries a fresh UUID for every request, kept across its retries (e.g.
+    /// Idempotency-Key); requests with it are retried whatever their method
+    #[clap(long, value_name = "NAME")]
+    pub idempotency_key_header: Option<String>,
+
+    /// Output results to JSON file
+    #[clap(long)]
+    pub output: Option<String>,
+
+    /// Write a self-contained HTML report with charts to this file
+    #[clap(long, value_name = "FILE")]
+    pub report: Option<String>,
+
+    /// Write thresholds and checks as JUnit XML test cases to this file
+    #[clap(long, value_name = "FILE")]
+    pub output_junit: Option<String>,
+
+    /// Write the response time percentiles of every second to this file, for latency heatmaps: CSV, or JSON if it ends in .json
+    #[clap(long, value_name = "FILE")]
+    pub heatmap: Option<String>,
+
+    /// Write the response time histograms as an HdrHistogram log to this file, and their percentile distribution as CSV next to it
+    #[clap(long, value_name = "FILE")]
+    pub hdr_output: Option<String>,
+
+    /// Append the results to this SQLite database of past runs, read back with `forgy history`
+    #[clap(long, value_name = "FILE")]
+    pub history: Option<String>,
+
+    /// Least severe diagnostics to log: error, warn, info, debug (also failed requests) or trace
+    #[clap(long, value_enum, value_name = "LEVEL", default_value = "warn")]
+    pub log_level: LogLevel,
+
+    /// Log diagnostics as text or as one JSON object per line
+    #[clap(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    pub log_format: logging::LogFormat,
+
+    /// Append diagnostics to this file instead of printing them to stderr
+    #[clap(long, value_name = "FILE")]
+    pub log_file: Option<String>,
+
+    /// Log the full request and response (headers, truncated bodies) of the first N requests and
+    /// the first N failed ones, at info level
+    #[clap(long, value_name = "N")]
+    pub debug_sample: Option<usize>,
+
+    /// Save the status, headers and body of up to N responses that failed a check or were an
+    /// unexpected 5xx, one JSON file each in --failures-dir
+    #[clap(long, value_name = "N")]
+    pub save_failures: Option<usize>,
+
+    /// Directory for --save-failures
+    #[clap(long, value_name = "DIR", default_value = failures::DEFAULT_DIR)]
+    pub failures_dir: String,
+
+    /// Stream one JSON line per request to this file ("-" for stdout) while the test runs
+    #[clap(long, value_name = "FILE")]
+    pub request_log: Option<String>,
+
+    /// Include a uniform random sample of N individual requests in the JSON output
+    #[clap(long, value_name = "N")]
+    pub sample_size: Option<usize>,
+
+    /// Prometheus Remote Write URL (e.g., http://localhost:9090/api/v1/write)
+    #[clap(long, value_name = "URL")]
+    pub prometheus_url: Option<String>,
+
+    /// Serve metrics for Prometheus to scrape at http://ADDR/metrics (e.g., 0.0.0.0:9095)
+    #[clap(long, value_name = "ADDR")]
+    pub metrics_listen: Option<std::net::SocketAddr>,
+
+    /// OpenTelemetry collector to export metrics to via OTLP (e.g., http://localhost:4317)
+    #[clap(long, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
+    /// OTLP transport: grpc or http (protobuf)
+    #[clap(long, value_enum, default_value = "grpc")]
+    pub otlp_protocol: OtlpProtocol,
+
+    /// InfluxDB URL to write metrics to in line protocol (e.g., http://localhost:8086)
+    #[clap(long, value_name = "URL", requires = "influx_bucket")]
+    pub influx_url: Option<String>,
+
+    /// InfluxDB bucket (or database/retention-policy on InfluxDB 1.x)
+    #[clap(long, value_name = "BUCKET")]
+    pub influx_bucket: Option<String>,
+
+    /// InfluxDB organization
+    #[clap(long, value_name = "ORG")]
+    pub influx_org: Option<String>,
+
+    /// InfluxDB API token
+    #[clap(long, value_name = "TOKEN")]
+    pub influx_token: Option<String>,
+
+    /// Prometheus Pushgateway to push metrics to (e.g., http://localhost:9091)
+    #[clap(long, value_name = "URL")]
+    pub pushgateway_url: Option<String>,
+
+    /// Pushgateway job label (default: the --app value)
+    #[clap(long, value_name = "JOB", requires = "pushgateway_url")]
+    pub pushgateway_job: Option<String>,
+
+    /// Pushgateway instance label (default: the host name)
+    #[clap(long, value_name = "INSTANCE", requires = "pushgateway_url")]
+    pub pushgateway_instance: Option<String>,
+
+    /// Leave the final values on the Pushgateway instead of deleting the run's group at the end
+    #[clap(long, requires = "pushgateway_url")]
+    pub pushgateway_keep: bool,
+
+    /// POST a summary to this URL when the test ends, and right away when a threshold or
+    /// --abort-on condition trips
+    #[clap(long, value_name = "URL")]
+    pub notify_webhook: Option<String>,
+
+    /// Payload of --notify-webhook: JSON fields, or a Slack-compatible {"text": ...} message
+    #[clap(long, value_enum, default_value = "json", requires = "notify_webhook")]
+    pub notify_format: WebhookFormat,
+
+    /// Send per-request timings and counters to a StatsD server or Datadog agent (HOST:PORT)
+    #[clap(long, value_name = "HOST:PORT")]
+    pub statsd: Option<String>,
+
+    /// Prefix of the StatsD metric names
+    #[clap(long, default_value = "forgy")]
+    pub statsd_prefix: String,
+
+    /// Send a W3C traceparent header with every request, starting a new trace per request
+    #[clap(long)]
+    pub trace_propagation: bool,
+
+    /// Fraction of traced requests marked as sampled; their client spans are exported via OTLP
+    #[clap(long, value_name = "RATE", default_value = "0.01", value_parser = parse_sample_rate)]
+    pub trace_sample_rate: f64,
+
+    /// Application label for grouping metrics in Prometheus (default: forgy)
+    #[clap(long, default_value = "forgy")]
+    pub app: String,
+
+    /// Static label added to every exported series (e.g., env=staging); repeatable
+    #[clap(long, value_name = "KEY=VALUE", value_parser = parse_label)]
+    pub label: Vec<(String, String)>,
+
+    // Unique per run; exported as the run_id label unless --label sets one
+    #[clap(skip = uuid::Uuid::new_v4().to_string())]
+    pub run_id: String,
+
+    /// Metrics push frequency in seconds (default: 10)
+    #[clap(long, default_value = "10")]
+    pub metrics_frequency: u64,
+
+    /// Maximum number of samples per Remote Write request
+    #[clap(long, default_value = "2000")]
+    pub remote_write_batch_size: usize,
+
+    /// Maximum size of a Remote Write request before compression, e.g. 512KB; larger snapshots are split
+    #[clap(long, value_name = "SIZE", value_parser = payload::parse_size)]
+    pub remote_write_max_bytes: Option<u64>,
+
+    /// Number of parallel Remote Write senders, and so of Remote Write requests in flight
+    #[clap(
+        long,
+        visible_alias = "remote-write-max-in-flight",
+        default_value = "1"
+    )]
+    pub remote_write_shards: usize,
+
+    /// Directory for an on-disk Remote Write buffer; undelivered samples are retried on later pushes and runs
+    #[clap(long, value_name = "DIR")]
+    pub remote_write_wal: Option<String>,
+
+    /// Maximum number of metric snapshots queued for Remote Write
+    #[clap(long, default_value = "100")]
+    pub remote_write_queue_capacity: usize,
+
+    /// Behaviour when the Remote Write queue is full
+    #[clap(long, value_enum, default_value = "drop")]
+    pub remote_write_queue_policy: QueuePolicy,
+
+    /// Retries of a Remote Write request that failed with a connection error, 429 or 5xx
+    #[clap(long, default_value = "3")]
+    pub remote_write_max_retries: u32,
+
+    /// Remote Write protocol version; 2.0 falls back to 1.0 when the endpoint does not accept it
+    #[clap(long, value_enum, default_value = "1.0")]
+    pub remote_write_version: RemoteWriteVersion,
+
+    /// Hard upper bound on total test time (e.g., 15m); VUs still running are cancelled when reached
+    #[clap(long)]
+    pub max_duration: Option<String>,
+
+    /// Check the options, send one request per scenario step and print it, then exit instead
+    /// of running the test
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Stop the run early once a condition holds, e.g. "error_rate>50% for 30s" (can be used
+    /// multiple times)
+    #[clap(long, value_name = "EXPR", value_parser = AbortCondition::parse)]
+    pub abort_on: Vec<AbortCondition>,
+
+    /// Save the aggregated statistics to this file periodically, so that --resume can continue the run
+    #[clap(long, value_name = "FILE")]
+    pub checkpoint: Option<String>,
+
+    /// Print a live stats line (RPS, active VUs, p95, error rate) this often; SIGUSR1 prints one
+    /// at any time
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub stats_interval: Option<Duration>,
+
+    /// How often the --checkpoint file is written
+    #[clap(long, value_name = "DURATION", default_value = "1m", value_parser = parse_duration)]
+    pub checkpoint_interval: Duration,
+
+    /// Continue a run from a --checkpoint file, keeping its statistics and elapsed time
+    #[clap(long, value_name = "FILE", conflicts_with = "iterations_per_vu")]
+    pub resume: Option<String>,
+}
+
+// Tools that work on result files instead of running a test
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Check the options and scenario file and send one request per step, as --dry-run does
+    Validate {
+        /// Scenario file to check, instead of the one given with --config
+        #[clap(long, value_name = "FILE")]
+        config: Option<String>,
+    },
+
+    /// Merge the --output files of runs made in parallel into one result
+    Merge {
+        /// Result files to merge
+        #[clap(required = true, value_name = "FILE")]
+        files: Vec<String>,
+
+        /// Where to write the merged results
+        #[clap(short, long, value_name = "FILE")]
+        output: String,
+    },
+
+    /// Compare a run with a baseline and fail if it regressed beyond the tolerances
+    Compare {
+        /// Results of the reference run
+        #[clap(value_name = "BASELINE")]
+        baseline: String,
+
+        /// Results of the run to check
+        #[clap(value_name = "CURRENT")]
+        current: String,
+
+        /// Allowed increase of the average and each percentile, in percent
+        #[clap(long, value_name = "PCT", default_value = "10")]
+        latency_tolerance: f64,
+
+        /// Allowed decrease of requests per second, in percent
+        #[clap(long, value_name = "PCT", default_value = "10")]
+        rps_tolerance: f64,
+
+        /// Allowed increase of the error rate, in percentage points
+        #[clap(long, value_name = "POINTS", default_value = "1")]
+        error_rate_tolerance: f64,
+    },
+
+    /// Replay the requests of an access log against a target on their recorded schedule
+    Replay {
+        /// Access log to replay
+        #[clap(long, value_name = "FILE")]
+        log: String,
+
+        /// Log line format
+        #[clap(long, value_enum, default_value = "combined")]
+        format: LogFormat,
+
+        /// Base URL the recorded paths are sent to
+        #[clap(long, value_name = "URL")]
+        target: String,
+
+        /// Replay speed relative to the recording, e.g. 2x for twice as fast
+        #[clap(long, value_name = "FACTOR", default_value = "1x", value_parser = replay::parse_speed)]
+        speed: f64,
+    },
+
+    /// Load test every operation of an OpenAPI 3 document, with generated parameters and bodies
+    Openapi {
+        /// OpenAPI document (YAML or JSON)
+        #[clap(long, value_name = "FILE")]
+        spec: String,
+
+        /// Comma-separated operationIds to test (default: all operations)
+        #[clap(long, value_name = "IDS", value_delimiter = ',')]
+        operations: Vec<String>,
+
+        /// Base URL to send requests to instead of the document's first server
+        #[clap(long, value_name = "URL")]
+        server: Option<String>,
+    },
+
+    /// Find the highest load that meets an SLO, stepping VUs (or --rps) up to --vus (or --rps)
+    Autoscale(AutoscaleOptions),
+
+    /// Generate a Grafana dashboard for the Prometheus metrics, as a file or uploaded to Grafana
+    GrafanaDashboard {
+        /// File to write the dashboard JSON to
+        #[clap(long, value_name = "FILE", required_unless_present = "grafana_url")]
+        out: Option<String>,
+
+        /// Grafana to create or update the dashboard in (e.g., http://localhost:3000)
+        #[clap(long, value_name = "URL", requires = "api_key")]
+        grafana_url: Option<String>,
+
+        /// Grafana API key or service account token
+        #[clap(long, value_name = "KEY", requires = "grafana_url")]
+        api_key: Option<String>,
+
+        /// Dashboard title
+        #[clap(long, default_value = grafana::DEFAULT_TITLE)]
+        title: String,
+    },
+
+    /// List the runs of a --history database, or the p95 and RPS trend of each workload
+    History {
+        /// History database written with --history
+        #[clap(value_name = "FILE")]
+        database: String,
+
+        /// Only runs with this --app
+        #[clap(long)]
+        app: Option<String>,
+
+        /// Only runs of this workload fingerprint
+        #[clap(long, value_name = "FINGERPRINT")]
+        workload: Option<String>,
+
+        /// Number of latest runs to show
+        #[clap(long, default_value = "20")]
+        limit: usize,
+
+        /// Show p95 and RPS over time per workload, with the change from the run before
+        #[clap(long)]
+        trend: bool,
+    },
+}
+
+impl Command {
+    // Subcommands that go through the load test (with a generated scenario, or as a dry run
+    // for `validate`), rather than working on result files
+    pub fn runs_load_test(&self) -> bool {
+        matches!(
+            self,
+            Self::Replay { .. } | Self::Openapi { .. } | Self::Autoscale(_) | Self::Validate { .. }
+        )
+    }
+}
+
+impl Args {
+    // Total request budget of an iteration-based run
+    pub fn total_iterations(&self) -> Option<u64> {
+        self.iterations.or_else(|| {
+            self.iterations_per_vu
+                .map(|per_vu| per_vu * self.vus as u64)
+        })
+    }
+
+    // The --profile or --stage list driving the VU count, if either is set
+    pub fn load_shape(&self) -> Option<Box<dyn LoadShape>> {
+        if let Some(profile) = &self.profile {
+            let hold = parse_duration(&self.hold).expect("Invalid hold duration");
+            Some(profile.shape(self.vus, hold))
+        } else if !self.stage.is_empty() {
+            Some(Box::new(Stages(self.stage.clone())))
+        } else {
+            None
+        }
+    }
+
+    // Highest number of VUs the test runs at once
+    pub fn autoscale(&self) -> Option<&AutoscaleOptions> {
+        match &self.command {
+            Some(Command::Autoscale(options)) => Some(options),
+            _ => None,
+        }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run || matches!(self.command, Some(Command::Validate { .. }))
+    }
+
+    pub fn peak_vus(&self) -> usize {
+        self.load_shape().map_or(self.vus, |shape| shape.peak_vus())
+    }
+
+    // --label values plus the generated run_id
+    pub fn static_labels(&self) -> Vec<(String, String)> {
+        let mut labels = self.label.clone();
+        if !labels.iter().any(|(name, _)| name == "run_id") {
+            labels.push(("run_id".to_string(), self.run_id.clone()));
+        }
+        labels
+    }
+
+    // Metrics are collected when they are pushed via remote write, OTLP, InfluxDB or a
+    // Pushgateway, or exposed for scraping
+    pub fn metrics_enabled(&self) -> bool {
+        self.prometheus_url.is_some()
+            || self.otlp_endpoint.is_some()
+            || self.influx_url.is_some()
+            || self.pushgateway_url.is_some()
+            || self.metrics_listen.is_some()
+    }
+}
+
+fn parse_percentile(percentile: &str) -> Result<f64, String> {
+    match percentile.trim().parse::<f64>() {
+        Ok(percentile) if percentile > 0.0 && percentile <= 100.0 => Ok(percentile),
+        _ => Err(format!(
+            "expected a percentile above 0 and up to 100, got '{}'",
+            percentile
+        )),
+    }
+}
+
+fn parse_sample_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("expected a number between 0 and 1, got '{}'", rate)),
+    }
+}
+
+// "key=value" for --label; names follow the Prometheus label name rules
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    let (name, value) = label
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", label))?;
+    let name = name.trim();
+    let valid = name
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if name.is_empty() || !valid {
+        return Err(format!("invalid label name '{}'", name));
+    }
+    if name.starts_with("__") || name == "app" || name == "le" {
+        return Err(format!("label name '{}' is reserved", name));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+// A single request, as kept in the --sample-size reservoir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestStats {
+    pub success: bool,
+    pub status_code: u16,
+    pub duration_ms: f64,
+    pub timestamp: DateTime<Utc>,
+    pub phases: PhaseTimings,
+    pub error_type: Option<ErrorType>,
+}
+
+// Download rates of --throughput, in megabytes (10^6 bytes) per second
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ThroughputSummary {
+    // Body bytes of all responses over the test duration
+    pub download_mb_per_sec: f64,
+    // Rates of single successful requests; the low percentiles are the slow downloads
+    pub avg_mb_per_sec: f64,
+    pub p5_mb_per_sec: f64,
+    pub p50_mb_per_sec: f64,
+    pub p95_mb_per_sec: f64,
+    pub max_mb_per_sec: f64,
+}
+
+impl ThroughputSummary {
+    // From a histogram of per-request rates in bytes per second
+    fn new(body_bytes_received: u64, duration_seconds: f64, rates: &Histogram<u64>) -> Self {
+        let mb = |bytes_per_second: f64| bytes_per_second / 1_000_000.0;
+        let percentile = |percentile: f64| mb(rates.value_at_percentile(percentile) as f64);
+        Self {
+            download_mb_per_sec: if duration_seconds > 0.0 {
+                mb(body_bytes_received as f64 / duration_seconds)
+            } else {
+                0.0
+            },
+            avg_mb_per_sec: mb(rates.mean()),
+            p5_mb_per_sec: percentile(5.0),
+            p50_mb_per_sec: percentile(50.0),
+            p95_mb_per_sec: percentile(95.0),
+            max_mb_per_sec: mb(rates.max() as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyPercentiles {
+    // From a histogram recorded in microseconds
+    pub(crate) fn from_histogram(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return Self::default();
+        }
+        let ms = |percentile: f64| histogram.value_at_percentile(percentile) as f64 / 1000.0;
+        Self {
+            p50_ms: ms(50.0),
+            p90_ms: ms(90.0),
+            p95_ms: ms(95.0),
+            p99_ms: ms(99.0),
+            max_ms: histogram.max() as f64 / 1000.0,
+        }
+    }
+}
+
+// One of the --percentiles, as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PercentileValue {
+    pub percentile: f64,
+    pub ms: f64,
+}
+
+// From a histogram recorded in microseconds
+pub(crate) fn percentile_values(
+    histogram: &Histogram<u64>,
+    percentiles: &[f64],
+) -> Vec<PercentileValue> {
+    percentiles
+        .iter()
+        .map(|&percentile| PercentileValue {
+            percentile,
+            ms: if histogram.is_empty() {
+                0.0
+            } else {
+                histogram.value_at_percentile(percentile) as f64 / 1000.0
+            },
+        })
+        .collect()
+}
+
+// Latency of the responses of one status class, as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusClassSummary {
+    pub status_class: String,
+    pub requests: u64,
+    pub avg_ms: f64,
+    pub percentiles: Vec<PercentileValue>,
+}
+
+impl StatusClassSummary {
+    pub(crate) fn new(status_class: &str, histogram: &Histogram<u64>, percentiles: &[f64]) -> Self {
+        Self {
+            status_class: status_class.to_string(),
+            requests: histogram.len(),
+            avg_ms: histogram.mean() / 1000.0,
+            percentiles: percentile_values(histogram, percentiles),
+        }
+    }
+}
+
+// Latency of a single request phase, as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseSummary {
+    pub phase: String,
+    pub count: u64,
+    pub avg_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+// Results of a single target, as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetSummary {
+    pub target: String,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    pub avg_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+}
+
+// Results of a single endpoint (request name or normalized path), as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointSummary {
+    pub endpoint: String,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    pub error_rate: f64,
+    pub avg_response_time_ms: f64,
+    pub p50_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+    pub error_types: BTreeMap<ErrorType, usize>,
+}
+
+// Results of one of a scenario file's parallel scenarios, as reported in TestResults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioSummary {
+    pub scenario: String,
+    pub vus: usize,
+    // Target arrival rate of a scenario with `rps`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rps: Option<f64>,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    pub requests_per_second: f64,
+    pub avg_response_time_ms: f64,
+    pub p50_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestResults {
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub failed_requests: usize,
+    pub vus: usize,
+    pub avg_response_time_ms: f64,
+    pub min_response_time_ms: f64,
+    pub max_response_time_ms: f64,
+    pub p50_response_time_ms: f64,
+    pub p90_response_time_ms: f64,
+    pub p95_response_time_ms: f64,
+    pub p99_response_time_ms: f64,
+    // The --percentiles, in the order given
+    #[serde(default)]
+    pub percentiles: Vec<PercentileValue>,
+    // Error responses are often fast, so they can hide how slow the successful ones were
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub status_classes: Vec<StatusClassSummary>,
+    // Percentiles as if every request had started on schedule (coordinated omission corrected)
+    pub corrected_percentiles: LatencyPercentiles,
+    pub requests_per_second: f64,
+    pub test_duration_seconds: f64,
+    pub status_code_distribution: HashMap<u16, usize>,
+    pub error_types: BTreeMap<ErrorType, usize>,
+    pub targets: Vec<TargetSummary>,
+    #[serde(default)]
+    pub endpoints: Vec<EndpointSummary>,
+    // The scenario file's parallel scenarios, each on its own; the totals above include them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scenarios: Vec<ScenarioSummary>,
+    pub phases: Vec<PhaseSummary>,
+    pub timeseries: Vec<TimeseriesPoint>,
+    // Response time percentiles of every interval, with --heatmap
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub heatmap: Vec<HeatmapRow>,
+    // Steps and outcome of `forgy autoscale`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autoscale: Option<AutoscaleReport>,
+    // Requests of the scenario's vu_init, which the totals above leave out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vu_init: Vec<EndpointSummary>,
+    pub total_bytes_sent: u64,
+    pub total_bytes_received: u64,
+    // Effective upload rate over the whole test, in megabits per second
+    #[serde(default)]
+    pub upload_mbps: f64,
+    // Download rates, with --throughput
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throughput: Option<ThroughputSummary>,
+    // Split of the totals into request/response heads and bodies
+    #[serde(default)]
+    pub header_bytes_sent: u64,
+    #[serde(default)]
+    pub body_bytes_sent: u64,
+    #[serde(default)]
+    pub header_bytes_received: u64,
+    #[serde(default)]
+    pub body_bytes_received: u64,
+    // Bodies before content encoding (sent) and after decoding (received); the body bytes
+    // above are the ones on the wire
+    #[serde(default)]
+    pub decoded_body_bytes_sent: u64,
+    #[serde(default)]
+    pub decoded_body_bytes_received: u64,
+    pub dropped_iterations: u64,
+    // Retry attempts, included in total_requests, and the requests they turned into successes
+    #[serde(default)]
+    pub retries: u64,
+    #[serde(default)]
+    pub recovered_requests: u64,
+    // Responses whose body was still arriving at --max-response-duration
+    #[serde(default)]
+    pub cut_off_responses: u64,
+    pub checks: Vec<CheckSummary>,
+    pub thresholds: Vec<ThresholdResult>,
+    // Error budget consumption of each --slo
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slos: Vec<SloSummary>,
+    // Event streams of --sse runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sse: Option<SseSummary>,
+    // Connections of --protocol tcp runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<TcpSummary>,
+    pub stop_reason: StopReason,
+    // The --abort-on condition that stopped the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborted_by: Option<String>,
+    // Uniform random sample of individual requests, only collected with --sample-size
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub samples: Vec<RequestStats>,
+    // Serialized histograms that let `forgy merge` recombine percentiles exactly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histograms: Option<EncodedHistograms>,
+}
+
+impl TestResults {
+    // Results previously written with --output
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let json =
+            std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        Ok(serde_json::from_str(&json)
+            .map_err(|e| format!("invalid results in {}: {}", path, e))?)
+    }
+
+    // Every threshold held and no --abort-on condition stopped the run
+    pub fn passed(&self) -> bool {
+        self.stop_reason != StopReason::Aborted && self.thresholds.iter().all(|t| t.passed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Protocol {
+    Http,
+    // Raw connections that send --payload-hex and read --tcp-read bytes
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HttpVersion {
+    #[value(name = "1.1")]
+    Http1,
+    // Offered via ALPN; plain-text connections stay on HTTP/1.1
+    #[value(name = "2")]
+    Http2,
+    // HTTP/2 without negotiation, including over plain text (h2c)
+    #[value(name = "2-prior-knowledge")]
+    Http2PriorKnowledge,
+    // QUIC; only available when built with the `http3` feature
+    #[value(name = "3")]
+    Http3,
+}
+
+// The Authorization header of the OAuth2 token, for requests sent outside of the VUs
+fn oauth2_headers(oauth2: &Option<Arc<TokenSource>>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(oauth2) = oauth2 {
+        headers.insert(AUTHORIZATION, oauth2.authorization());
+    }
+    headers
+}
+
+// "P50 (Median)", "P99.9"
+fn percentile_label(percentile: f64) -> String {
+    if percentile == 50.0 {
+        "P50 (Median)".to_string()
+    } else {
+        format!("P{}", percentile)
+    }
+}
+
+// Reads a response body to the end, counting its bytes as received and once decoded. The
+// decoded body is only kept when `keep` is set
+// A body still arriving at `cut_off_at` is cut off there; the last value is whether it was.
+// One still arriving at `timeout_at` failed with a timeout
+async fn read_body(
+    mut response: reqwest::Response,
+    keep: bool,
+    cut_off_at: Option<Instant>,
+    timeout_at: Option<Instant>,
+) -> (Vec<u8>, u64, u64, Option<ErrorType>, bool) {
+    let mut decoder = Decoder::new(response.headers(), keep);
+    let mut bytes = 0;
+    let mut cut_off = false;
+    let mut error = loop {
+        let chunk = match cut_off_at.or(timeout_at) {
+            Some(deadline) => {
+                let deadline = tokio::time::Instant::from_std(deadline);
+                match tokio::time::timeout_at(deadline, response.chunk()).await {
+                    Ok(chunk) => chunk,
+                    Err(_) if cut_off_at.is_some() => {
+                        cut_off = true;
+                        break None;
+                    }
+                    Err(_) => break Some(ErrorType::Timeout),
+                }
+            }
+            None => response.chunk().await,
+        };
+        match chunk {
+            Ok(Some(chunk)) => {
+                bytes += chunk.len() as u64;
+                // A body that does not decode is as broken as one cut short
+                if decoder.write(&chunk).is_err() {
+                    break Some(ErrorType::Body);
+                }
+            }
+            Ok(None) => break None,
+            Err(e) => break Some(ErrorType::from_reqwest(&e)),
+        }
+    };
+    // A compressed body cut off mid-stream cannot be finished
+    if error.is_none() && !cut_off && decoder.finish().is_err() {
+        error = Some(ErrorType::Body);
+    }
+    let decoded_bytes = decoder.decoded_bytes();
+    (decoder.into_body(), bytes, decoded_bytes, error, cut_off)
+}
+
+// Negotiated protocol, used as the `protocol` label
+fn protocol_label(version: reqwest::Version) -> &'static str {
+    match version {
+        reqwest::Version::HTTP_09 => "0.9",
+        reqwest::Version::HTTP_10 => "1.0",
+        reqwest::Version::HTTP_11 => "1.1",
+        reqwest::Version::HTTP_2 => "2",
+        reqwest::Version::HTTP_3 => "3",
+        _ => "unknown",
+    }
+}
+
+// Why the load test ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    Completed,
+    MaxDuration,
+    Interrupted,
+    // An --abort-on condition held
+    Aborted,
+}
+
+// One attempt, as it is reported once it is over
+struct AttemptRecord<'a> {
+    method: &'a str,
+    endpoint: &'a str,
+    url: String,
+    timestamp: DateTime<Utc>,
+    success: bool,
+    status_code: u16,
+    protocol: &'static str,
+    duration: Duration,
+    bytes: ByteCounts,
+    error_type: Option<ErrorType>,
+    phases: PhaseTimings,
+    trace_context: Option<TraceContext>,
+}
+
+// =============================================================================
+// LOAD TESTER
+// =============================================================================
+
+// Tasks of the VUs (or arrival workers) an executor started, and the stop signals of its VUs
+type VuHandles = (Vec<tokio::task::JoinHandle<()>>, Vec<Arc<Mutex<bool>>>);
+
+// Ramp-up, hold and ramp-down of a parallel scenario, from the command line's unless it sets
+// its own
+fn parallel_phases(
+    parallel: &ParallelScenario,
+    (ramp_up, hold, ramp_down): (Duration, Duration, Duration),
+) -> (Duration, Duration, Duration) {
+    (
+        parallel.ramp_up.unwrap_or(ramp_up),
+        parallel.hold.unwrap_or(hold),
+        parallel.ramp_down.unwrap_or(ramp_down),
+    )
+}
+
+pub struct LoadTester {
+    clients: Arc<Clients>,
+    connections: Arc<ConnectionTracker>,
+    scenario: Arc<Scenario>,
+    samples: Option<Arc<Mutex<Reservoir<RequestStats>>>>,
+    active_vus: Arc<AtomicUsize>,
+    // Counters, histograms and breakdowns, sharded by VU
+    aggregates: Arc<Stats>,
+    timeseries: Arc<Mutex<Timeseries>>,
+    data: Option<Arc<DataFeed>>,
+    oauth2: Option<Arc<TokenSource>>,
+    // Authorization header of --user without --digest
+    basic_auth: Option<HeaderValue>,
+    digest_auth: Option<Arc<DigestAuth>>,
+    jwt: Option<Arc<JwtMinter>>,
+    signer: Option<Arc<RequestSigner>>,
+    idempotency_key_header: Option<HeaderName>,
+    sse: Option<Arc<SseMonitor>>,
+    tcp: Option<Arc<TcpProber>>,
+    notifier: Option<Arc<Notifier>>,
+    // --max-response-duration, and the --request-timeout left to bound the headers
+    max_response_duration: Option<Duration>,
+    request_timeout: Duration,
+    request_log: Option<RequestLog>,
+    tracer: Option<Tracer>,
+    // Sinks for per-request metrics
+    exporters: Arc<Vec<Box<dyn Exporter>>>,
+    // Requests left of an --iterations budget, claimed by VUs before each request
+    iterations_left: Option<Arc<AtomicU64>>,
+    iterations_per_vu: Option<u64>,
+    think_time: Option<ThinkTime>,
+    rngs: Arc<Rngs>,
+    // Body of every request, with --form or --body-file
+    payload: Option<Arc<Payload>>,
+    discard_body: bool,
+    debug_sample: Option<Arc<DebugSample>>,
+    failures: Option<Arc<FailureCapture>>,
+    throughput: bool,
+    compression: Option<Compression>,
+    gzip_body: bool,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    // Statuses that count as success, 2xx unless --expect-status says otherwise
+    expected_status: Arc<ExpectedStatus>,
+    // Reported by calculate_results
+    percentiles: Arc<Vec<f64>>,
+    slos: Arc<Vec<Slo>>,
+    replay: Option<Arc<Replay>>,
+    endpoints: Arc<Endpoints>,
+    cookies: Option<Arc<CookieJars>>,
+    // Checkpoint the run continues from, with --resume
+    resumed: Option<Arc<Checkpoint>>,
+    script: Option<Arc<Script>>,
+    // Values extracted by the setup requests, for teardown
+    setup_variables: Arc<Variables>,
+    // Requests of the scenario's vu_init, kept apart from the steady-state statistics
+    init_stats: Arc<Stats>,
+    // The scenario file's parallel scenarios, and the one this tester's VUs run, if any
+    scenario_runs: Arc<Vec<Arc<ScenarioRun>>>,
+    scenario_run: Option<Arc<ScenarioRun>>,
+}
+
+// Statistics of one of the parallel scenarios, besides the totals every request adds to
+struct ScenarioRun {
+    name: String,
+    vus: usize,
+    rps: Option<f64>,
+    // Its VUs are numbered after those of the scenarios before it
+    vu_offset: usize,
+    stats: Stats,
+}
+
+impl ScenarioRun {
+    fn summary(&self, duration_seconds: f64) -> ScenarioSummary {
+        let histogram = self.stats.histogram();
+        let percentile = |percentile: f64| {
+            if histogram.is_empty() {
+                0.0
+            } else {
+                histogram.value_at_percentile(percentile) as f64 / 1000.0
+            }
+        };
+        let total_requests = self.stats.total_requests();
+        let successful_requests = self.stats.successful_requests();
+        ScenarioSummary {
+            scenario: self.name.clone(),
+            vus: self.vus,
+            rps: self.rps,
+            total_requests,
+            successful_requests,
+            failed_requests: total_requests - successful_requests,
+            requests_per_second: if duration_seconds > 0.0 {
+                total_requests as f64 / duration_seconds
+            } else {
+                0.0
+            },
+            avg_response_time_ms: self.stats.mean_duration_micros() / 1000.0,
+            p50_response_time_ms: percentile(50.0),
+            p95_response_time_ms: percentile(95.0),
+            p99_response_time_ms: percentile(99.0),
+        }
+    }
+}
+
+// A request the open-model executors hand to the next free worker
+struct Arrival {
+    // When the request was due; latency measured from here includes any wait for a worker
+    scheduled_at: Instant,
+    // Scenario request to send; a weighted pick when not set
+    request: Option<usize>,
+}
+
+impl LoadTester {
+    pub async fn new(args: &Args) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut headers = Vec::new();
+        for header in &args.header {
+            if let Some((key, value)) = header.split_once(':') {
+                if let Ok(name) = HeaderName::from_bytes(key.trim().as_bytes()) {
+                    headers.push((name, Template::parse(value.trim())?));
+                }
+            }
+        }
+
+        let curl = args
+            .from_curl
+            .as_deref()
+            .map(CurlRequest::parse)
+            .transpose()?;
+        // curl's -k carries over
+        let insecure = args.insecure || curl.as_ref().is_some_and(|curl| curl.insecure);
+
+        let replay = match &args.command {
+            Some(Command::Replay {
+                log,
+                format,
+                target,
+                speed,
+            }) => Some(Arc::new(Replay::load(log, *format, target, *speed)?)),
+            _ => None,
+        };
+
+        let mut scenario = if let Some(replay) = &replay {
+            replay.scenario()?
+        } else if let Some(Command::Openapi {
+            spec,
+            operations,
+            server,
+        }) = &args.command
+        {
+            openapi::scenario(spec, operations, server.as_deref())?
+        } else if let Some(path) = &args.config {
+            Scenario::from_file(path)?
+        } else if let Some(path) = &args.har {
+            har::scenario(path)?
+        } else if let Some(curl) = &curl {
+            curl.scenario()?
+        } else if args.url.is_empty() {
+            return Err("either --url, --config, --har or --from-curl is required".into());
+        } else if let Some(query) = &args.graphql_query {
+            let body = graphql::request_body(query, args.graphql_variables.as_deref())?;
+            let mut scenario =
+                Scenario::from_urls(&args.url, &args.name, Method::POST, Some(&body))?;
+            for request in &mut scenario.requests {
+                request.graphql = true;
+            }
+            if !headers.iter().any(|(name, _)| name == CONTENT_TYPE) {
+                headers.push((CONTENT_TYPE, Template::parse("application/json")?));
+            }
+            scenario
+        } else {
+            Scenario::from_urls(
+                &args.url,
+                &args.name,
+                Method::from_bytes(args.method.as_bytes()).unwrap_or(Method::GET),
+                args.body.as_deref(),
+            )?
+        };
+        scenario.add_headers(&headers);
+        scenario.add_checks(&Self::parse_cli_checks(args)?);
+        scenario.check_variables()?;
+
+        // Parallel scenarios bring their own executors and VUs
+        if !scenario.parallel.is_empty() {
+            let conflicting = [
+                (args.rps.is_some(), "--rps"),
+                (args.load_shape().is_some(), "--stage or --profile"),
+                (
+                    args.total_iterations().is_some(),
+                    "--iterations or --iterations-per-vu",
+                ),
+                (args.autoscale().is_some(), "autoscale"),
+                (args.resume.is_some(), "--resume"),
+            ];
+            if let Some((_, option)) = conflicting.iter().find(|(given, _)| *given) {
+                return Err(format!(
+                    "the scenarios of a scenario file cannot be combined with {}",
+                    option
+                )
+                .into());
+            }
+        }
+        // Every number a VU or arrival worker can run as
+        let vus = scenario
+            .parallel_vus()
+            .unwrap_or_else(|| args.peak_vus().max(args.vus));
+        let rngs = Arc::new(Rngs::new(args.seed, vus));
+        let client_options = ClientOptions::from_args(args, insecure)?;
+        let resolver = client_options.resolver();
+        // A VU has at most one request in flight, so its pool needs one connection per host
+        let clients = if args.client_per_vu {
+            Clients::new(
+                client_options,
+                vus,
+                1,
+                args.max_connection_lifetime,
+                rngs.clone(),
+            )?
+        } else {
+            // One shared client per source address, each pooling its share of the VUs
+            let sources = client_options.local_addresses.len().max(1);
+            Clients::new(
+                client_options,
+                sources,
+                vus.div_ceil(sources),
+                args.max_connection_lifetime,
+                rngs.clone(),
+            )?
+        };
+
+        let tcp = match args.protocol {
+            Protocol::Tcp => {
+                if args.sse || args.max_response_duration.is_some() {
+                    return Err(
+                        "--protocol tcp cannot be combined with --sse or --max-response-duration"
+                            .into(),
+                    );
+                }
+                // Templated addresses are checked as they are rendered
+                for request in scenario
+                    .requests
+                    .iter()
+                    .filter(|r| !r.target.contains("${"))
+                {
+                    tcp::parse_address(&request.target)?;
+                }
+                let config = TcpConfig {
+                    payload: args
+                        .payload_hex
+                        .as_deref()
+                        .map(tcp::parse_hex)
+                        .transpose()?
+                        .unwrap_or_default(),
+                    read_bytes: args.tcp_read.unwrap_or(0),
+                    connect_timeout: args.connect_timeout,
+                    timeout: args.request_timeout,
+                    local_addresses: args.local_address.clone(),
+                };
+                Some(Arc::new(TcpProber::new(config, resolver)?))
+            }
+            Protocol::Http if args.payload_hex.is_some() || args.tcp_read.is_some() => {
+                return Err("--payload-hex and --tcp-read need --protocol tcp".into());
+            }
+            Protocol::Http => None,
+        };
+
+        let data = args
+            .data
+            .as_deref()
+            .map(|path| DataFeed::from_csv(path, args.data_mode, vus))
+            .transpose()?;
+        for column in scenario.templates().flat_map(|t| t.csv_columns()) {
+            match &data {
+                Some(feed) if feed.has_column(column) => {}
+                Some(_) => return Err(format!("--data has no column named {}", column).into()),
+                None => return Err(format!("${{csv.{}}} requires --data", column).into()),
+            }
+        }
+
+        let oauth2 = match (&args.oauth2_token_url, &args.client_id, &args.client_secret) {
+            (Some(token_url), Some(client_id), Some(client_secret)) => {
+                let config = OAuth2Config {
+                    token_url: token_url.clone(),
+                    client_id: client_id.clone(),
+                    client_secret: client_secret.clone(),
+                    scope: args.oauth2_scope.clone(),
+                };
+                // Token requests go over the first VU's client
+                let source = TokenSource::fetch(clients.get(1), config)
+                    .await
+                    .map_err(|e| format!("failed to fetch OAuth2 token: {}", e))?;
+                Some(Arc::new(source))
+            }
+            _ => None,
+        };
+
+        let credentials = args.user.as_deref().map(Credentials::parse).transpose()?;
+        let (basic_auth, digest_auth) = match credentials {
+            // Each VU (in --rps mode, each VU number) answers its own challenges
+            Some(credentials) if args.digest => {
+                (None, Some(Arc::new(DigestAuth::new(credentials, vus))))
+            }
+            Some(credentials) => (Some(credentials.basic_authorization()?), None),
+            None => (None, None),
+        };
+        // One token per VU, like the Digest challenges
+        let jwt = match &args.jwt_signing_key {
+            Some(key) => Some(Arc::new(JwtMinter::new(
+                key,
+                args.jwt_claims.as_deref().unwrap_or("{}"),
+                args.jwt_ttl,
+                vus,
+            )?)),
+            None => None,
+        };
+        let signer = match &args.hmac_secret {
+            Some(secret) => Some(Arc::new(RequestSigner::new(&SigningConfig {
+                secret: secret.clone(),
+                algorithm: args.hmac_algorithm,
+                header: args.hmac_header.clone(),
+                payload: args.hmac_payload.clone(),
+                encoding: args.hmac_encoding,
+                prefix: args.hmac_prefix.clone().unwrap_or_default(),
+                timestamp_header: args.hmac_timestamp_header.clone(),
+            })?)),
+            None => None,
+        };
+
+        // Setup requests go over the first VU's client too, before any VU starts
+        let setup_variables = if scenario.setup.is_empty() {
+            Variables::new()
+        } else {
+            let variables =
+                setup::run_setup(&clients.get(1), &scenario.setup, &oauth2_headers(&oauth2))
+                    .await?;
+            scenario.bind(&variables);
+            variables
+        };
+
+        let mut payload = if !args.form.is_empty() {
+            Some(Payload::Form(Form::parse(&args.form)?))
+        } else if let Some(path) = &args.body_file {
+            Some(Payload::from_file(path)?)
+        } else {
+            args.body_size
+                .map(|size| Payload::generated(size, args.body_pattern, args.seed))
+        };
+        if args.gzip_body {
+            payload = payload.map(Payload::gzip).transpose()?;
+        }
+
+        let request_log = match &args.request_log {
+            Some(path) => Some(RequestLog::open(path).await?),
+            None => None,
+        };
+
+        let mut exporters: Vec<Box<dyn Exporter>> = Vec::new();
+        if args.metrics_enabled() {
+            exporters.push(Box::new(PrometheusExporter));
+        }
+        if let Some(address) = &args.statsd {
+            let mut tags = vec![("app".to_string(), args.app.clone())];
+            tags.extend(args.static_labels());
+            exporters.push(Box::new(StatsdExporter::new(StatsdConfig {
+                address: address.clone(),
+                prefix: args.statsd_prefix.clone(),
+                tags,
+            })?));
+        }
+
+        let script_timeout = args.script_timeout.unwrap_or(args.request_timeout);
+        let script = match (&args.script, &args.plugin) {
+            (Some(path), _) => Some(Arc::new(Script::load(path, script_timeout)?)),
+            (None, Some(path)) => Some(Arc::new(Script::load_plugin(path, script_timeout)?)),
+            (None, None) => None,
+        };
+
+        // The statistics of a resumed run start from those of its checkpoint
+        let aggregates = Stats::with_default_shards();
+        let resumed = match &args.resume {
+            Some(_) if args.autoscale().is_some() => {
+                return Err("--resume cannot continue an autoscale run".into())
+            }
+            Some(path) => {
+                let checkpoint = Checkpoint::load(path)?;
+                checkpoint.restore(&aggregates)?;
+                Some(Arc::new(checkpoint))
+            }
+            None => None,
+        };
+        // Requests made before the checkpoint count towards --iterations
+        let iterations_left = args.iterations.map(|iterations| {
+            let done = aggregates.total_requests() as u64;
+            Arc::new(AtomicU64::new(iterations.saturating_sub(done)))
+        });
+
+        Ok(Self {
+            clients: Arc::new(clients),
+            connections: Arc::new(ConnectionTracker::new()),
+            samples: args
+                .sample_size
+                .map(|size| Arc::new(Mutex::new(Reservoir::new(size)))),
+            active_vus: Arc::new(AtomicUsize::new(0)),
+            aggregates: Arc::new(aggregates),
+            timeseries: Arc::new(Mutex::new(Timeseries::new(Instant::now()))),
+            data: data.map(Arc::new),
+            oauth2,
+            basic_auth,
+            digest_auth,
+            jwt,
+            signer,
+            idempotency_key_header: args
+                .idempotency_key_header
+                .as_deref()
+                .map(|name| {
+                    HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| format!("invalid --idempotency-key-header: {}", name))
+                })
+                .transpose()?,
+            max_response_duration: args.max_response_duration,
+            request_timeout: args.request_timeout,
+            tcp,
+            notifier: match &args.notify_webhook {
+                Some(url) => Some(Arc::new(Notifier::new(NotifyConfig {
+                    url: url.clone(),
+                    format: args.notify_format,
+                    app: args.app.clone(),
+                    run_id: args
+                        .static_labels()
+                        .into_iter()
+                        .find(|(name, _)| name == "run_id")
+                        .map(|(_, value)| value)
+                        .unwrap_or_default(),
+                })?)),
+                None => None,
+            },
+            sse: match args.sse {
+                true => Some(Arc::new(SseMonitor::new(vus, args.request_timeout)?)),
+                false => None,
+            },
+            request_log,
+            tracer: args.trace_propagation.then_some(Tracer {
+                sample_rate: args.trace_sample_rate,
+                export_spans: args.otlp_endpoint.is_some(),
+            }),
+            exporters: Arc::new(exporters),
+            iterations_left,
+            iterations_per_vu: args.iterations_per_vu,
+            think_time: args.think_time,
+            rngs,
+            payload: payload.map(Arc::new),
+            discard_body: args.discard_body,
+            debug_sample: if args.dry_run() {
+                Some(Arc::new(DebugSample::printing()))
+            } else {
+                args.debug_sample
+                    .map(|limit| Arc::new(DebugSample::new(limit)))
+            },
+            failures: match args.save_failures {
+                Some(limit) => Some(Arc::new(FailureCapture::new(&args.failures_dir, limit)?)),
+                None => None,
+            },
+            throughput: args.throughput,
+            compression: args.compression,
+            gzip_body: args.gzip_body,
+            rate_limiter: args
+                .max_rps
+                .map(|max_rps| Arc::new(RateLimiter::new(max_rps))),
+            retry_policy: RetryPolicy::from_args(args)?.map(Arc::new),
+            expected_status: Arc::new(match &args.expect_status {
+                Some(spec) => ExpectedStatus::parse(spec)?,
+                None => ExpectedStatus::default(),
+            }),
+            percentiles: Arc::new(args.percentiles.clone()),
+            slos: Arc::new(
+                args.slo
+                    .iter()
+                    .map(|slo| Slo::parse(slo))
+                    .collect::<Result<_, _>>()?,
+            ),
+            replay,
+            endpoints: Arc::new(Endpoints::new()),
+            // Arrival workers use the jars of the VU numbers they run as
+            cookies: args
+                .cookie_jar
+                .map(|mode| Arc::new(CookieJars::new(mode, vus))),
+            resumed,
+            script,
+            setup_variables: Arc::new(setup_variables),
+            init_stats: Arc::new(Stats::with_default_shards()),
+            scenario_runs: Arc::new(
+                scenario
+                    .parallel
+                    .iter()
+                    .scan(0, |vu_offset, parallel| {
+                        let run = ScenarioRun {
+                            name: parallel.name.clone(),
+                            vus: parallel.vus,
+                            rps: parallel.rps,
+                            vu_offset: *vu_offset,
+                            stats: Stats::with_default_shards(),
+                        };
+                        *vu_offset += parallel.vus;
+                        Some(Arc::new(run))
+                    })
+                    .collect(),
+            ),
+            scenario_run: None,
+            scenario: Arc::new(scenario),
+        })
+    }
+
+    fn parse_cli_checks(
+        args: &Args,
+    ) -> Result<Vec<Check>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut checks = Vec::new();
+        if let Some(codes) = &args.check_status {
+            checks.push(Check::parse_status(codes)?);
+        }
+        for header in &args.check_header {
+            checks.push(Check::parse_header(header)?);
+        }
+        for body in &args.check_body {
+            checks.push(Check::parse_body(body)?);
+        }
+        for expression in &args.check_jsonpath {
+            checks.push(Check::parse_jsonpath(expression)?);
+        }
+        Ok(checks)
+    }
+
+    // Evaluate every check of the request; returns whether all of them passed
+    fn run_checks(
+        &self,
+        shard_key: usize,
+        spec: &RequestSpec,
+        status_code: u16,
+        headers: &HeaderMap,
+        body: &str,
+    ) -> bool {
+        let mut all_passed = true;
+        for check in &spec.checks {
+            let passed = check.evaluate(status_code, headers, body);
+            all_passed &= passed;
+            self.record_check(shard_key, &check.to_string(), passed);
+        }
+        all_passed
+    }
+
+    fn record_check(&self, shard_key: usize, name: &str, passed: bool) {
+        for exporter in self.exporters.iter() {
+            exporter.record_check(name, passed);
+        }
+        self.aggregates.shard(shard_key).record_check(name, passed);
+    }
+
+    // Send a request, and send it again while the retry policy asks for it; returns the
+    // stats of the last attempt
+    async fn make_request(
+        &self,
+        spec: &RequestSpec,
+        context: &TemplateContext<'_>,
+    ) -> RequestStats {
+        let mut attempt = 0;
+        // One key for all attempts, so the server sees retries as the same operation
+        let idempotency_key = self.idempotency_key(context.vu);
+        loop {
+            let stat = self
+                .send_attempt(spec, context, attempt, idempotency_key.as_ref())
+                .await;
+            let retry_policy = self.retry_policy.as_deref().filter(|policy| {
+                !stat.success
+                    && policy.should_retry(&spec.method, attempt, stat.status_code, stat.error_type)
+            });
+            let Some(retry_policy) = retry_policy else {
+                if attempt > 0 && stat.success {
+                    self.aggregates.record_recovered();
+                }
+                return stat;
+            };
+            let backoff = retry_policy.backoff(attempt, &mut *self.rngs.vu(context.vu).lock());
+            sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    // A fresh --idempotency-key-header value, from the VU's generator so --seed repeats it
+    fn idempotency_key(&self, vu: usize) -> Option<HeaderValue> {
+        self.idempotency_key_header.as_ref()?;
+        let key = fake::uuid(&mut *self.rngs.vu(vu).lock());
+        HeaderValue::from_str(&key.to_string()).ok()
+    }
+
+    async fn send_attempt(
+        &self,
+        spec: &RequestSpec,
+        context: &TemplateContext<'_>,
+        attempt: u32,
+        idempotency_key: Option<&HeaderValue>,
+    ) -> RequestStats {
+        // Time spent waiting for the rate limiter is not part of the request's latency
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        if let Some(tcp) = &self.tcp {
+            return self.send_tcp(tcp, spec, context, attempt).await;
+        }
+
+        let mut url = spec.url.render(context);
+        let endpoint = self.endpoints.resolve(spec, &url);
+        let mut headers = HeaderMap::new();
+        if let Some(oauth2) = &self.oauth2 {
+            headers.insert(AUTHORIZATION, oauth2.authorization());
+        }
+        if let Some(basic_auth) = &self.basic_auth {
+            headers.insert(AUTHORIZATION, basic_auth.clone());
+        }
+        if let Some(token) = self.jwt.as_ref().and_then(|jwt| jwt.authorization(context)) {
+            headers.insert(AUTHORIZATION, token);
+        }
+        if let (Some(name), Some(key)) = (&self.idempotency_key_header, idempotency_key) {
+            headers.insert(name.clone(), key.clone());
+        }
+        // What an EventSource sends; the scenario's headers may still replace them
+        if let Some(sse) = &self.sse {
+            headers.insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            let last_event_id = sse.last_event_id(context.vu);
+            if let Some(Ok(id)) = last_event_id.as_deref().map(HeaderValue::from_str) {
+                headers.insert(HeaderName::from_static("last-event-id"), id);
+            }
+        }
+        for (name, value) in &spec.headers {
+            if let Ok(value) = HeaderValue::from_str(&value.render(context)) {
+                headers.insert(name.clone(), value);
+            }
+        }
+        let trace_context = self
+            .tracer
+            .map(|tracer| tracer.start(&mut rand::thread_rng()));
+        if let Some(trace_context) = &trace_context {
+            if let Ok(value) = HeaderValue::from_str(&trace_context.traceparent()) {
+                headers.insert(HeaderName::from_static("traceparent"), value);
+            }
+        }
+        // reqwest's default Accept, set here so that it is counted in the header bytes
+        headers
+            .entry(ACCEPT)
+            .or_insert(HeaderValue::from_static("*/*"));
+        if let Some(compression) = self.compression {
+            headers
+                .entry(ACCEPT_ENCODING)
+                .or_insert(compression.accept_encoding());
+        }
+        if !self.clients.keepalive() {
+            headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        let mut method = spec.method.clone();
+        let mut body = spec
+            .body
+            .as_ref()
+            .map(|body| body.render(context).into_bytes());
+
+        // The script sees the request as it would be sent and may replace any part of it
+        let hook_context = HookContext {
+            vu: context.vu,
+            iteration: context.iteration,
+            name: &endpoint,
+        };
+        let mut script_request = None;
+        let mut script_failed = false;
+        if let Some(script) = &self.script {
+            let mut request = ScriptRequest::new(&method, &url, &headers, body.as_deref());
+            if script.request_hook {
+                let changed = match script.on_request(hook_context, &request) {
+                    Ok(Some(changed)) => changed.method().and_then(|method| {
+                        Ok((method, changed.header_map()?, changed.body.clone(), changed))
+                    }),
+                    Ok(None) => Ok((
+                        method.clone(),
+                        headers.clone(),
+                        body.clone(),
+                        request.clone(),
+                    )),
+                    Err(e) => Err(e),
+                };
+                match changed {
+                    Ok((changed_method, changed_headers, changed_body, changed)) => {
+                        method = changed_method;
+                        headers = changed_headers;
+                        url = changed.url.clone();
+                        body = changed_body;
+                        request = changed;
+                    }
+                    Err(e) => {
+                        script.report_error(&e);
+                        script_failed = true;
+                    }
+                }
+            }
+            script_request = Some(request);
+        }
+
+        // Time spent in the script is not part of the request's latency
+        let start = Instant::now();
+        let timestamp = Utc::now();
+
+        let client = self.clients.get(context.vu);
+        let mut request = client.request(method, &url).headers(headers);
+        // A streamed body has no bytes to count on the built request, so its length is kept
+        let mut payload_length = None;
+        let mut decoded_body_length = 0;
+        // Sent by the transport in place of the request's body
+        let mut stream = None;
+        match (body, &self.payload) {
+            (Some(body), _) => {
+                decoded_body_length = body.len() as u64;
+                request = if self.gzip_body {
+                    request
+                        .header(CONTENT_ENCODING, compression::content_encoding_gzip())
+                        .body(compression::gzip(&body))
+                } else {
+                    request.body(body)
+                };
+            }
+            (None, Some(payload)) => {
+                let payload = payload.body(context);
+                payload_length = Some(payload.length);
+                decoded_body_length = payload.decoded_length;
+                if let Some(content_type) = payload.content_type {
+                    request = request.header(CONTENT_TYPE, content_type);
+                }
+                if let Some(content_encoding) = payload.content_encoding {
+                    request = request.header(CONTENT_ENCODING, content_encoding);
+                }
+                request = request.header(CONTENT_LENGTH, payload.length);
+                request = match payload.body {
+                    Contents::Bytes(bytes) => request.body(bytes),
+                    // The request carries an empty stream in its place, so it is signed,
+                    // sampled and counted as a streamed body
+                    Contents::Stream(body) => {
+                        stream = Some(body);
+                        request.body(reqwest::Body::from(hyper::Body::empty()))
+                    }
+                };
+            }
+            (None, None) => {}
+        }
+        // A scenario step's own timeout replaces --request-timeout
+        if let Some(timeout) = spec.timeout {
+            request = request.timeout(timeout);
+        }
+        // A stream may stay open for the whole run, and a long response until
+        // --max-response-duration; only the wait for their headers is bounded by the timeout
+        let header_timeout = match (&self.sse, self.max_response_duration) {
+            (Some(sse), _) => {
+                request = request.timeout(sse::STREAM_TIMEOUT);
+                Some(spec.timeout.unwrap_or(sse.header_timeout()))
+            }
+            (None, Some(max_response_duration)) => {
+                request = request.timeout(sse::STREAM_TIMEOUT);
+                let timeout = spec.timeout.unwrap_or(self.request_timeout);
+                Some(timeout.min(max_response_duration))
+            }
+            (None, None) => None,
+        };
+
+        let mut request = request.build();
+        if let (Some(cookies), Ok(request)) = (&self.cookies, &mut request) {
+            if let Some(jar_cookies) = cookies.jar(context.vu).header(request.url()) {
+                // Cookies set on the request itself (--header, HAR, curl -b) are kept
+                let cookie = match request.headers().get(COOKIE).map(HeaderValue::to_str) {
+                    Some(Ok(existing)) => format!("{}; {}", existing, jar_cookies),
+                    _ => jar_cookies,
+                };
+                if let Ok(value) = HeaderValue::from_str(&cookie) {
+                    request.headers_mut().insert(COOKIE, value);
+                }
+            }
+        }
+        // Signed as sent, after the script and compression
+        if let (Some(signer), Ok(request)) = (&self.signer, &mut request) {
+            signer.sign(request);
+        }
+        // Added last, so an Authorization header set any other way is left alone
+        let digest_auth = match (&self.digest_auth, &mut request) {
+            (Some(digest_auth), Ok(request)) => digest_auth
+                .authorize(context.vu, request, &mut *self.rngs.vu(context.vu).lock())
+                .then_some(digest_auth),
+            _ => None,
+        };
+
+        // Sizes are taken from the built request, so they include every header sent
+        let (header_bytes_sent, body_bytes_sent, decoded_body_bytes_sent) = match &request {
+            Ok(request) if !script_failed => (
+                wire_size::request_header_bytes(request),
+                request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .map_or(payload_length.unwrap_or(0), |body| body.len() as u64),
+                decoded_body_length,
+            ),
+            _ => (0, 0, 0),
+        };
+        // Captured while --debug-sample may still log this request
+        let debug_sample = self.debug_sample.as_ref().filter(|sample| sample.active());
+        let sampled_request = match (debug_sample, &request) {
+            (Some(_), Ok(request)) => Some(debug_sample::describe_request(request)),
+            _ => None,
+        };
+        let mut sampled_response = None;
+        // Kept while --save-failures has room for another failed response
+        let capture_failure = self.failures.as_ref().is_some_and(|f| f.active());
+        let failed_request = match &request {
+            Ok(request) if capture_failure => Some((
+                request.method().to_string(),
+                request.url().to_string(),
+                script::header_strings(request.headers()),
+            )),
+            _ => None,
+        };
+        let mut failed_response = None;
+        // --resolve overrides are port-specific
+        let port = request
+            .as_ref()
+            .ok()
+            .and_then(|request| request.url().port_or_known_default());
+        let send = async {
+            match (request, digest_auth) {
+                (Ok(request), Some(digest_auth)) => {
+                    digest_auth
+                        .execute(
+                            &client,
+                            context.vu,
+                            self.rngs.vu(context.vu),
+                            request,
+                            stream,
+                        )
+                        .await
+                }
+                (Ok(request), None) => client.send(request, stream).await,
+                (Err(e), _) => Err(e.into()),
+            }
+        };
+        // None when the headers of a stream did not arrive in time
+        let send = async {
+            match header_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, send).await.ok(),
+                None => Some(send.await),
+            }
+        };
+
+        // Requests the script failed to build are not sent
+        let mut headers_timed_out = false;
+        let (result, connection_phases) = if script_failed {
+            (None, ConnectionPhases::default())
+        } else {
+            let (result, connection_phases) =
+                timing::with_connection_timing(dns::with_port(port, send)).await;
+            headers_timed_out = result.is_none();
+            (result, connection_phases)
+        };
+        let duration = start.elapsed();
+        let mut download_ms = 0.0;
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+
+        let (
+            success,
+            status_code,
+            header_bytes_received,
+            body_bytes_received,
+            decoded_body_bytes_received,
+            error_type,
+            protocol,
+        ) = match result {
+            None if headers_timed_out => {
+                if sampled_request.is_some() {
+                    sampled_response = Some("< no response headers in time".to_string());
+                }
+                (false, 0, 0, 0, 0, Some(ErrorType::Timeout), "none")
+            }
+            None => {
+                if sampled_request.is_some() {
+                    sampled_response = Some("< not sent, the script failed".to_string());
+                }
+                (false, 0, 0, 0, 0, Some(ErrorType::Script), "none")
+            }
+            Some(Ok(response)) => {
+                let code = response.status().as_u16();
+                let protocol = protocol_label(response.version());
+                let is_success = self.expected_status.contains(code);
+                let header_bytes = wire_size::response_header_bytes(&response);
+                if let Some(info) = response.extensions().get::<HttpInfo>() {
+                    self.connections.observe(info.local_addr());
+                }
+                if let Some(cookies) = &self.cookies {
+                    cookies
+                        .jar(context.vu)
+                        .store(response.url(), response.headers());
+                }
+
+                if sampled_request.is_some() {
+                    sampled_response = Some(debug_sample::describe_response(
+                        response.version(),
+                        response.status(),
+                        response.headers(),
+                    ));
+                }
+
+                // Headers are only kept around when checks or the script need them
+                let response_hook = self.script.as_ref().is_some_and(|s| s.response_hook);
+                let headers = if spec.checks.is_empty() && !response_hook && !capture_failure {
+                    HeaderMap::new()
+                } else {
+                    response.headers().clone()
+                };
+
+                // Bodies are only buffered for checks that inspect them; otherwise they are
+                // streamed and counted, or not downloaded at all with --discard-body
+                let download_start = Instant::now();
+                let (raw_body, body_bytes, decoded_body_bytes, body_error, cut_off) =
+                    if self.discard_body {
+                        let body_bytes = response.content_length().unwrap_or(0);
+                        (Vec::new(), body_bytes, body_bytes, None, false)
+                    } else if let Some(sse) = self
+                        .sse
+                        .as_ref()
+                        .filter(|_| sse::is_event_stream(response.headers()))
+                    {
+                        let on_event = |first, latency| {
+                            for exporter in self.exporters.iter() {
+                                exporter.record_sse_event(&endpoint, first, latency);
+                            }
+                        };
+                        for exporter in self.exporters.iter() {
+                            exporter.record_sse_stream(true);
+                        }
+                        let (bytes, error) =
+                            sse.read_stream(context.vu, response, start, on_event).await;
+                        for exporter in self.exporters.iter() {
+                            exporter.record_sse_stream(false);
+                        }
+                        (Vec::new(), bytes, bytes, error, false)
+                    } else {
+                        let keep = spec.needs_body()
+                            || response_hook
+                            || sampled_request.is_some()
+                            || capture_failure;
+                        let cut_off_at = self.max_response_duration.map(|max| start + max);
+                        // Streams are only bounded by the timeout until their headers arrive
+                        let timeout_at = header_timeout
+                            .is_none()
+                            .then(|| start + spec.timeout.unwrap_or(self.request_timeout));
+                        read_body(response, keep, cut_off_at, timeout_at).await
+                    };
+                download_ms = download_start.elapsed().as_secs_f64() * 1000.0;
+                // Checks see the body as text; a body that is not UTF-8 is kept as it came for
+                // the script
+                let (mut body, binary_body) = match String::from_utf8(raw_body) {
+                    Ok(body) => (body, None),
+                    Err(e) => (
+                        String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                        response_hook.then(|| e.into_bytes()),
+                    ),
+                };
+                if cut_off {
+                    self.aggregates.record_cut_off();
+                }
+                if let Some(response) = &mut sampled_response {
+                    let body = (!self.discard_body).then_some(body.as_str());
+                    debug_sample::add_body(response, body);
+                }
+
+                let checks_passed = self.run_checks(context.vu, spec, code, &headers, &body);
+                let graphql_failed = spec.graphql && graphql::has_errors(&body);
+                let (script_passed, script_error) = match (&self.script, &script_request) {
+                    (Some(script), Some(request)) if response_hook => {
+                        // A failure may still need the body
+                        let response_body = match binary_body {
+                            Some(binary_body) => binary_body,
+                            None if capture_failure => body.clone().into_bytes(),
+                            None => std::mem::take(&mut body).into_bytes(),
+                        };
+                        let response = ScriptResponse::new(code, &headers, response_body);
+                        match script.on_response(hook_context, request, &response) {
+                            Ok(verdict) => {
+                                let name = match &verdict.message {
+                                    Some(message) => format!("script: {}", message),
+                                    None => "script".to_string(),
+                                };
+                                self.record_check(context.vu, &name, verdict.pass);
+                                (verdict.pass, None)
+                            }
+                            Err(e) => {
+                                script.report_error(&e);
+                                (false, Some(ErrorType::Script))
+                            }
+                        }
+                    }
+                    _ => (true, None),
+                };
+                let error_type = ErrorType::from_status(code)
+                    .filter(|_| !is_success)
+                    .or(body_error)
+                    .or(graphql_failed.then_some(ErrorType::GraphQl))
+                    .or(script_error)
+                    .or((!checks_passed || !script_passed).then_some(ErrorType::CheckFailed));
+                let success = is_success
+                    && checks_passed
+                    && script_passed
+                    && !graphql_failed
+                    && body_error.is_none();
+                if capture_failure && !success && FailureCapture::wants(error_type) {
+                    failed_response = Some((script::header_strings(&headers), body));
+                }
+
+                (
+                    success,
+                    code,
+                    header_bytes,
+                    body_bytes,
+                    decoded_body_bytes,
+                    error_type,
+                    protocol,
+                )
+            }
+            Some(Err(e)) => {
+                let error_type = e.error_type();
+                if sampled_request.is_some() {
+                    sampled_response = Some(format!("< error: {}", e));
+                }
+                tracing::debug!(
+                    method = %spec.method,
+                    url = %url,
+                    error_type = error_type.as_str(),
+                    error = %e,
+                    "request failed"
+                );
+                (false, 0, 0, 0, 0, Some(error_type), "none")
+            }
+        };
+        if let (Some(sample), Some(request), Some(response)) =
+            (debug_sample, &sampled_request, &sampled_response)
+        {
+            sample.offer(&endpoint, success, request, response);
+        }
+        if let (Some(failures), Some((method, url, request_headers)), Some((headers, body))) =
+            (&self.failures, failed_request, failed_response)
+        {
+            failures
+                .save(FailedRequest {
+                    timestamp,
+                    vu: context.vu,
+                    endpoint: endpoint.to_string(),
+                    method,
+                    url,
+                    request_headers,
+                    status: status_code,
+                    error_type,
+                    duration_ms,
+                    response_headers: headers,
+                    body,
+                })
+                .await;
+        }
+        if !success && status_code != 0 {
+            tracing::debug!(
+                method = %spec.method,
+                url = %url,
+                status = status_code,
+                error_type = error_type.map(|error_type| error_type.as_str()),
+                "request failed"
+            );
+        }
+
+        // A request's rate covers all of it, from sending it to the body's last byte
+        let total_seconds = (duration_ms + download_ms) / 1000.0;
+        if self.throughput && success && body_bytes_received > 0 && total_seconds > 0.0 {
+            self.aggregates
+                .shard(context.vu)
+                .throughput
+                .record((body_bytes_received as f64 / total_seconds) as u64)
+                .ok();
+        }
+
+        let bytes = ByteCounts {
+            header_sent: header_bytes_sent,
+            body_sent: body_bytes_sent,
+            header_received: header_bytes_received,
+            body_received: body_bytes_received,
+            decoded_body_sent: decoded_body_bytes_sent,
+            decoded_body_received: decoded_body_bytes_received,
+        };
+
+        let phases = PhaseTimings::new(duration_ms, connection_phases, download_ms);
+
+        self.record_attempt(
+            spec,
+            context.vu,
+            attempt,
+            AttemptRecord {
+                method: spec.method.as_str(),
+                endpoint: &endpoint,
+                url,
+                timestamp,
+                success,
+                status_code,
+                protocol,
+                duration,
+                bytes,
+                error_type,
+                phases,
+                trace_context,
+            },
+        )
+    }
+
+    // A --protocol tcp exchange in place of an HTTP request; its response time runs until the
+    // first response byte, like an HTTP response's until its headers
+    async fn send_tcp(
+        &self,
+        tcp: &TcpProber,
+        spec: &RequestSpec,
+        context: &TemplateContext<'_>,
+        attempt: u32,
+    ) -> RequestStats {
+        let url = spec.url.render(context);
+        let endpoint = self.endpoints.resolve(spec, &url);
+        let start = Instant::now();
+        let timestamp = Utc::now();
+        let exchange = tcp.exchange(context.vu, &url, start).await;
+        if let Some(local_addr) = exchange.local_addr {
+            self.connections.observe(local_addr);
+        }
+        if let Some(error_type) = exchange.error {
+            tracing::debug!(url = %url, error_type = error_type.as_str(), "TCP exchange failed");
+        }
+
+        let duration = exchange.first_byte.unwrap_or(exchange.duration);
+        let phases = PhaseTimings::new(
+            duration.as_secs_f64() * 1000.0,
+            ConnectionPhases {
+                dns: exchange.dns,
+                connect: exchange.connect,
+                tls: None,
+            },
+            exchange.duration.saturating_sub(duration).as_secs_f64() * 1000.0,
+        );
+        self.record_attempt(
+            spec,
+            context.vu,
+            attempt,
+            AttemptRecord {
+                method: "TCP",
+                endpoint: &endpoint,
+                url,
+                timestamp,
+                success: exchange.error.is_none(),
+                status_code: 0,
+                protocol: "tcp",
+                duration,
+                bytes: ByteCounts {
+                    body_sent: exchange.bytes_sent,
+                    body_received: exchange.bytes_received,
+                    decoded_body_sent: exchange.bytes_sent,
+                    decoded_body_received: exchange.bytes_received,
+                    ..ByteCounts::default()
+                },
+                error_type: exchange.error,
+                phases,
+                trace_context: None,
+            },
+        )
+    }
+
+    // Report an attempt to the exporters, the aggregates, the request log and the tracer
+    fn record_attempt(
+        &self,
+        spec: &RequestSpec,
+        vu: usize,
+        attempt: u32,
+        record: AttemptRecord<'_>,
+    ) -> RequestStats {
+        let AttemptRecord {
+            method,
+            endpoint,
+            url,
+            timestamp,
+            success,
+            status_code,
+            protocol,
+            duration,
+            bytes,
+            error_type,
+            phases,
+            trace_context,
+        } = record;
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+
+        let event = RequestEvent {
+            method,
+            target: &spec.target,
+            endpoint,
+            scenario: self
+                .scenario_run
+                .as_ref()
+                .map_or("default", |run| run.name.as_str()),
+            status_code,
+            protocol,
+            duration,
+            bytes,
+            error_type,
+            phases: &phases,
+            trace: trace_context.as_ref(),
+            retry: attempt > 0,
+        };
+        for exporter in self.exporters.iter() {
+            exporter.record_request(&event);
+        }
+
+        // Update local metrics (record duration in microseconds for better precision)
+        let request = RequestRecord {
+            target: &spec.target,
+            endpoint,
+            status_code,
+            success,
+            error_type,
+            duration_micros: (duration_ms * 1000.0) as u64,
+            bytes,
+            phases: &phases,
+            retry: attempt > 0,
+        };
+        self.aggregates.record(vu, &request);
+        if let Some(run) = &self.scenario_run {
+            run.stats.record(vu, &request);
+        }
+
+        if let Some(request_log) = &self.request_log {
+            request_log.log(RequestLogEntry {
+                timestamp,
+                target: spec.target.clone(),
+                endpoint: endpoint.to_string(),
+                method: method.to_string(),
+                status: status_code,
+                duration_ms,
+                download_ms: phases.download_ms,
+                success,
+                error_type,
+                bytes_sent: bytes.sent(),
+                bytes_received: bytes.received(),
+                attempt,
+            });
+        }
+
+        if let (Some(tracer), Some(context)) = (&self.tracer, trace_context) {
+            let start_time_unix_nano = timestamp.timestamp_nanos_opt().unwrap_or_default() as u64;
+            tracer.finish(ClientSpan {
+                context,
+                method: method.to_string(),
+                url,
+                target: spec.target.clone(),
+                status_code,
+                error_type,
+                start_time_unix_nano,
+                end_time_unix_nano: start_time_unix_nano + duration.as_nanos() as u64,
+            });
+        }
+
+        RequestStats {
+            success,
+            status_code,
+            duration_ms,
+            timestamp,
+            phases,
+            error_type,
+        }
+    }
+
+    async fn run_virtual_user(
+        &self,
+        stop_signal: Arc<Mutex<bool>>,
+        prometheus_enabled: bool,
+        vu_index: usize,
+    ) {
+        self.active_vus.fetch_add(1, Ordering::Relaxed);
+        if prometheus_enabled {
+            ACTIVE_VUS.inc();
+        }
+
+        // Create a deterministic but distributed offset for this VU
+        // Spread VUs evenly across the first second
+        let offset_ms = (vu_index * 1000 / 100.max(vu_index + 1)) as u64;
+
+        // Initial delay to spread VUs across the first second
+        sleep(Duration::from_millis(offset_ms % 1000)).await;
+
+        let mut variables = None;
+        let mut iteration = 0;
+        while !*stop_signal.lock() {
+            // The VU only starts its requests once its vu_init succeeded, trying again after
+            // each pacing interval
+            if variables.is_none() {
+                variables = self.run_vu_init(vu_index).await;
+                if variables.is_none() {
+                    sleep(self.pacing(vu_index)).await;
+                    continue;
+                }
+            }
+            if !self.claim_iteration(iteration) {
+                break;
+            }
+            let total_delay = self.pacing(vu_index);
+
+            let context = self.template_context(vu_index, iteration, variables.as_ref());
+            let spec = self
+                .scenario
+                .pick(iteration, &mut *self.rngs.vu(vu_index + 1).lock());
+            let stat = self.make_request(spec, &context).await;
+
+            // A request slower than the pacing interval delayed the requests this VU meant
+            // to send meanwhile; back-fill them as wrk2 does
+            self.aggregates
+                .shard(vu_index)
+                .corrected_histogram
+                .record_correct(
+                    (stat.duration_ms * 1000.0) as u64,
+                    total_delay.as_micros() as u64,
+                )
+                .ok();
+
+            self.record_stat(stat);
+            iteration += 1;
+
+            // No point pacing towards a request this VU will not make
+            if self.iterations_exhausted(iteration) {
+                break;
+            }
+            // A stream is reopened after the delay its server asked for
+            let total_delay = self
+                .sse
+                .as_ref()
+                .and_then(|sse| sse.retry(vu_index + 1))
+                .unwrap_or(total_delay);
+            if total_delay.is_zero() {
+                // Without think time, still let other tasks run between requests
+                tokio::task::yield_now().await;
+            } else {
+                sleep(total_delay).await;
+            }
+        }
+
+        self.active_vus.fetch_sub(1, Ordering::Relaxed);
+        if prometheus_enabled {
+            ACTIVE_VUS.dec();
+        }
+    }
+
+    // Time between the starts of a VU's requests
+    fn pacing(&self, vu_index: usize) -> Duration {
+        match &self.think_time {
+            Some(think_time) => think_time.sample(&mut *self.rngs.vu(vu_index + 1).lock()),
+            None => {
+                // Wait ~1 second with some jitter to distribute requests
+                let base_delay = 1000; // 1 second base
+                let jitter = (vu_index * 37) % 400; // Deterministic jitter 0-400ms
+                Duration::from_millis(base_delay - 200 + jitter as u64) // 800-1200ms range
+            }
+        }
+    }
+
+    // Whether the iteration budget allows this VU another request, taking it from the
+    // shared budget if there is one
+    fn claim_iteration(&self, iteration: u64) -> bool {
+        if self
+            .iterations_per_vu
+            .is_some_and(|per_vu| iteration >= per_vu)
+        {
+            return false;
+        }
+        match &self.iterations_left {
+            Some(left) => left
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    fn iterations_exhausted(&self, iteration: u64) -> bool {
+        self.iterations_per_vu
+            .is_some_and(|per_vu| iteration >= per_vu)
+            || self
+                .iterations_left
+                .as_ref()
+                .is_some_and(|left| left.load(Ordering::Relaxed) == 0)
+    }
+
+    fn template_context<'a>(
+        &'a self,
+        vu_index: usize,
+        iteration: u64,
+        vars: Option<&'a Variables>,
+    ) -> TemplateContext<'a> {
+        TemplateContext {
+            vu: vu_index + 1,
+            iteration,
+            row: self.data.as_ref().map(|feed| feed.row(vu_index, iteration)),
+            vars,
+            rng: Some(self.rngs.vu(vu_index + 1)),
+        }
+    }
+
+    // Send the scenario's vu_init requests for a VU, returning the values they extracted,
+    // or None if one of them failed
+    async fn run_vu_init(&self, vu_index: usize) -> Option<Variables> {
+        let mut variables = Variables::new();
+        for step in &self.scenario.vu_init {
+            let vu = vu_index + 1;
+            let context = self.template_context(vu_index, 0, Some(&variables));
+            let mut request = setup::request(
+                &self.clients.get(vu),
+                &step.spec,
+                &context,
+                &oauth2_headers(&self.oauth2),
+            )
+            .build();
+            if let (Some(cookies), Ok(request)) = (&self.cookies, &mut request) {
+                if let Some(jar_cookies) = cookies.jar(vu).header(request.url()) {
+                    if let Ok(value) = HeaderValue::from_str(&jar_cookies) {
+                        request.headers_mut().insert(COOKIE, value);
+                    }
+                }
+            }
+
+            let start = Instant::now();
+            let client = self.clients.get(vu);
+            // The body is read under the request's timeout too
+            let deadline = tokio::time::Instant::from_std(
+                start + step.spec.timeout.unwrap_or(client.timeout()),
+            );
+            let response = match request {
+                Ok(request) => client.execute(request).await,
+                Err(e) => Err(e.into()),
+            };
+            let (status_code, body_received, outcome) = match response {
+                Ok(response) => {
+                    let status_code = response.status().as_u16();
+                    if let Some(cookies) = &self.cookies {
+                        cookies.jar(vu).store(response.url(), response.headers());
+                    }
+                    let headers = response.headers().clone();
+                    match tokio::time::timeout_at(deadline, response.text()).await {
+                        Ok(Ok(body)) => {
+                            let outcome = setup::verify(&step.spec, status_code, &headers, &body)
+                                .and_then(|_| step.extract_into(&headers, &body, &mut variables))
+                                .map_err(|_| {
+                                    ErrorType::from_status(status_code)
+                                        .unwrap_or(ErrorType::CheckFailed)
+                                });
+                            (status_code, body.len() as u64, outcome)
+                        }
+                        Ok(Err(e)) => (status_code, 0, Err(ErrorType::from_reqwest(&e))),
+                        Err(_) => (status_code, 0, Err(ErrorType::Timeout)),
+                    }
+                }
+                Err(e) => (0, 0, Err(e.error_type())),
+            };
+            let duration = start.elapsed();
+
+            self.init_stats.record(
+                vu,
+                &RequestRecord {
+                    target: &step.spec.target,
+                    endpoint: &step.spec.target,
+                    status_code,
+                    success: outcome.is_ok(),
+                    error_type: outcome.err(),
+                    duration_micros: duration.as_micros() as u64,
+                    bytes: ByteCounts {
+                        body_received,
+                        ..ByteCounts::default()
+                    },
+                    phases: &PhaseTimings::default(),
+                    retry: false,
+                },
+            );
+            if outcome.is_err() {
+                return None;
+            }
+        }
+        Some(variables)
+    }
+
+    // Individual requests are only kept as a fixed-size sample, so memory stays constant
+    fn record_stat(&self, stat: RequestStats) {
+        if let Some(samples) = &self.samples {
+            samples.lock().offer(stat, &mut *self.rngs.shared().lock());
+        }
+    }
+
+    // Arrival-rate worker: performs one request per scheduled arrival until the schedule ends
+    async fn run_arrival_worker(
+        &self,
+        arrivals: Arc<tokio::sync::Mutex<tokio::sync::mpsc::Receiver<Arrival>>>,
+        prometheus_enabled: bool,
+        worker_index: usize,
+    ) {
+        // Workers stand in for VUs in templates and run vu_init like them
+        let mut variables = None;
+        let mut iteration = 0;
+        loop {
+            let Some(arrival) = arrivals.lock().await.recv().await else {
+                break;
+            };
+            // Arrivals are skipped until the worker's vu_init succeeds
+            if variables.is_none() {
+                variables = self.run_vu_init(worker_index).await;
+                if variables.is_none() {
+                    continue;
+                }
+            }
+
+            // Busy workers are reported as active VUs
+            self.active_vus.fetch_add(1, Ordering::Relaxed);
+            if prometheus_enabled {
+                ACTIVE_VUS.inc();
+            }
+
+            // Latency as seen from the schedule includes the time the arrival waited for a worker
+            let queued = arrival.scheduled_at.elapsed();
+            let context = self.template_context(worker_index, iteration, variables.as_ref());
+            let spec = match arrival.request {
+                Some(index) => &self.scenario.requests[index],
+                None => self
+                    .scenario
+                    .pick(iteration, &mut *self.rngs.vu(worker_index + 1).lock()),
+            };
+            let stat = self.make_request(spec, &context).await;
+            let corrected_micros = queued.as_micros() as u64 + (stat.duration_ms * 1000.0) as u64;
+            self.aggregates
+                .shard(worker_index)
+                .corrected_histogram
+                .record(corrected_micros)
+                .ok();
+            self.record_stat(stat);
+            iteration += 1;
+
+            self.active_vus.fetch_sub(1, Ordering::Relaxed);
+            if prometheus_enabled {
+                ACTIVE_VUS.dec();
+            }
+        }
+    }
+
+    // Samples the time series every second and, if Prometheus is enabled, updates and
+    // pushes metrics every `frequency_secs`
+    async fn collect_metrics_periodically(
+        &self,
+        app: &str,
+        frequency_secs: u64,
+        prometheus_enabled: bool,
+    ) {
+        let mut interval = interval(Duration::from_secs(1));
+        // Requests restored from a checkpoint were made before this run
+        let mut last_request_count = self.aggregates.total_requests();
+        let mut last_permits = 0;
+        let (mut last_opened, mut last_closed) = (0, 0);
+        let (mut last_sent, mut last_received) = (
+            self.aggregates.bytes_sent(),
+            self.aggregates.bytes_received(),
+        );
+        let mut last_slo_counts = self.slo_counts(&self.aggregates.histogram());
+
+        for tick in 0u64.. {
+            interval.tick().await;
+
+            // The first tick fires right away, before any interval has passed
+            if tick > 0 {
+                self.timeseries
+                    .lock()
+                    .sample(&self.aggregates, self.active_vus.load(Ordering::Relaxed));
+            }
+            if !prometheus_enabled {
+                continue;
+            }
+
+            // Connections are checked every second; ones opened and closed in between are
+            // still counted as opened, and as closed at the next check
+            if let Some(established) = self.connections.sweep() {
+                CONNECTIONS_ESTABLISHED.set(established as i64);
+            }
+            let (opened, closed) = (self.connections.opened(), self.connections.closed());
+            CONNECTIONS_OPENED.inc_by(opened - last_opened);
+            CONNECTIONS_CLOSED.inc_by(closed - last_closed);
+            (last_opened, last_closed) = (opened, closed);
+
+            if tick % frequency_secs.max(1) != 0 {
+                continue;
+            }
+
+            let total = self.aggregates.total_requests();
+            let successful = self.aggregates.successful_requests();
+
+            // Calculate success rate
+            if total > 0 {
+                let success_rate = (successful as f64 / total as f64) * 100.0;
+                SUCCESS_RATE.set(success_rate);
+            }
+
+            // Calculate requests per second (since last push)
+            let requests_since_last = total - last_request_count;
+            REQUESTS_PER_SECOND.set(requests_since_last as f64 / frequency_secs as f64);
+            last_request_count = total;
+
+            let (sent, received) = (
+                self.aggregates.bytes_sent(),
+                self.aggregates.bytes_received(),
+            );
+            THROUGHPUT
+                .with_label_values(&["upload"])
+                .set((sent - last_sent) as f64 / frequency_secs as f64);
+            THROUGHPUT
+                .with_label_values(&["download"])
+                .set((received - last_received) as f64 / frequency_secs as f64);
+            (last_sent, last_received) = (sent, received);
+
+            if let Some(rate_limiter) = &self.rate_limiter {
+                let permits = rate_limiter.permits();
+                RATE_LIMIT_ACHIEVED.set((permits - last_permits) as f64 / frequency_secs as f64);
+                last_permits = permits;
+            }
+
+            // Update percentiles and SLO burn rates
+            {
+                let histogram = self.aggregates.histogram();
+                if !histogram.is_empty() {
+                    // Convert from microseconds to milliseconds for Prometheus metrics
+                    RESPONSE_TIME_P50.set(histogram.value_at_percentile(50.0) as f64 / 1000.0);
+                    RESPONSE_TIME_P90.set(histogram.value_at_percentile(90.0) as f64 / 1000.0);
+                    RESPONSE_TIME_P95.set(histogram.value_at_percentile(95.0) as f64 / 1000.0);
+                    RESPONSE_TIME_P99.set(histogram.value_at_percentile(99.0) as f64 / 1000.0);
+                }
+
+                let slo_counts = self.slo_counts(&histogram);
+                for ((slo, &(requests, bad)), &(last_requests, last_bad)) in
+                    self.slos.iter().zip(&slo_counts).zip(&last_slo_counts)
+                {
+                    let burn_rate = slo.burn_rate(
+                        requests.saturating_sub(last_requests),
+                        bad.saturating_sub(last_bad),
+                    );
+                    SLO_BURN_RATE
+                        .with_label_values(&[&slo.expression])
+                        .set(burn_rate);
+                    SLO_ERROR_BUDGET_REMAINING
+                        .with_label_values(&[&slo.expression])
+                        .set(1.0 - slo.burn_rate(requests, bad));
+                }
+                last_slo_counts = slo_counts;
+            }
+
+            // Push metrics via Remote Write if URL is provided
+            if let Err(e) = send_metrics_via_remote_write(app).await {
+                tracing::warn!(error = %e, "failed to send metrics via Remote Write");
+            }
+            if let Err(e) = send_metrics_via_otlp().await {
+                tracing::warn!(error = %e, "failed to export metrics via OTLP");
+            }
+            if let Err(e) = send_metrics_via_influx().await {
+                tracing::warn!(error = %e, "failed to write metrics to InfluxDB");
+            }
+            if let Err(e) = send_metrics_via_pushgateway().await {
+                tracing::warn!(error = %e, "failed to push metrics to the Pushgateway");
+            }
+        }
+    }
+
+    // Tell --notify-webhook the first time each threshold fails on the results so far, once
+    // requests have completed; the final results still decide whether it passed
+    async fn watch_thresholds(&self, mut thresholds: Vec<Threshold>, test_start: Instant) {
+        if self.notifier.is_none() {
+            return;
+        }
+        let mut ticker = interval(Duration::from_secs(1));
+        while !thresholds.is_empty() {
+            ticker.tick().await;
+            let requests = self.aggregates.total_requests();
+            if requests == 0 {
+                continue;
+            }
+            let failed = requests.saturating_sub(self.aggregates.successful_requests());
+            let measurements = Measurements::from_histogram(
+                &self.aggregates.histogram(),
+                requests,
+                failed,
+                test_start.elapsed().as_secs_f64(),
+            );
+            let mut breached = Vec::new();
+            thresholds.retain(|threshold| {
+                let result = threshold.evaluate_measurements(&measurements);
+                let passed = result.passed;
+                if !passed {
+                    breached.push(result);
+                }
+                passed
+            });
+            for result in &breached {
+                self.notify(&Event::ThresholdBreached {
+                    result,
+                    elapsed: test_start.elapsed(),
+                })
+                .await;
+            }
+        }
+    }
+
+    async fn notify(&self, event: &Event<'_>) {
+        if let Some(notifier) = &self.notifier {
+            if let Err(e) = notifier.send(event).await {
+                tracing::warn!(error = %e, "failed to send webhook notification");
+            }
+        }
+    }
+
+    // Send the final results to --notify-webhook, once thresholds are evaluated
+    pub async fn notify_finished(&self, results: &TestResults) {
+        self.notify(&Event::Finished(results)).await;
+    }
+
+    // Prints a stats line every `every` and on SIGUSR1, above the progress bar. The rate is
+    // that since the previous line; the percentile and error rate cover the whole run so far
+    async fn print_live_stats(
+        &self,
+        pb: &ProgressBar,
+        test_start: Instant,
+        every: Option<Duration>,
+    ) {
+        #[cfg(unix)]
+        let mut usr1 =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()).ok();
+        let mut ticker = every.map(|every| {
+            let mut ticker = interval(every);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker
+        });
+        let mut last = (test_start, self.aggregates.total_requests());
+        // The first tick fires right away
+        if let Some(ticker) = &mut ticker {
+            ticker.tick().await;
+        }
+
+        loop {
+            let tick = async {
+                match &mut ticker {
+                    Some(ticker) => {
+                        ticker.tick().await;
+                    }
+                    None => std::future::pending::<()>().await,
+                }
+            };
+            #[cfg(unix)]
+            let signal = async {
+                match &mut usr1 {
+                    Some(usr1) => {
+                        usr1.recv().await;
+                    }
+                    None => std::future::pending::<()>().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let signal = std::future::pending::<()>();
+            tokio::select! {
+                _ = tick => {}
+                _ = signal => {}
+            }
+
+            let now = Instant::now();
+            let total = self.aggregates.total_requests();
+            let failed = total - self.aggregates.successful_requests();
+            let seconds = now.duration_since(last.0).as_secs_f64();
+            let rps = if seconds > 0.0 {
+                (total - last.1) as f64 / seconds
+            } else {
+                0.0
+            };
+            last = (now, total);
+            let histogram = self.aggregates.histogram();
+            let p95_ms = if histogram.is_empty() {
+                0.0
+            } else {
+                histogram.value_at_percentile(95.0) as f64 / 1000.0
+            };
+            let elapsed = now.duration_since(test_start).as_secs();
+            let line = format!(
+                "[{:02}:{:02}:{:02}] {:.1} req/s, {} active VUs, p95 {:.2} ms, {:.2}% errors, {} requests",
+                elapsed / 3600,
+                elapsed / 60 % 60,
+                elapsed % 60,
+                rps,
+                self.active_vus.load(Ordering::Relaxed),
+                p95_ms,
+                failed as f64 / total.max(1) as f64 * 100.0,
+                total
+            );
+            // Printed even when the progress bar is hidden, e.g. with output to a file
+            pb.suspend(|| println!("{}", line));
+        }
+    }
+
+    // Requests and bad requests so far for each --slo
+    fn slo_counts(&self, histogram: &Histogram<u64>) -> Vec<(u64, u64)> {
+        let total = self.aggregates.total_requests();
+        let failed = total - self.aggregates.successful_requests();
+        self.slos
+            .iter()
+            .map(|slo| slo.counts(histogram, total as u64, failed as u64))
+            .collect()
+    }
+
+    pub async fn run_load_test(&self, args: &Args) -> TestResults {
+        let ramp_up = parse_duration(&args.ramp_up).expect("Invalid ramp-up duration");
+        let hold = parse_duration(&args.hold).expect("Invalid hold duration");
+        let ramp_down = parse_duration(&args.ramp_down).expect("Invalid ramp-down duration");
+        let max_duration = args
+            .max_duration
+            .as_ref()
+            .map(|d| parse_duration(d).expect("Invalid max duration"));
+
+        let load_shape = args.load_shape();
+        let autoscale = args.autoscale();
+        let autoscale_unit = autoscale.map(|options| options.unit(args.rps));
+        // The ceiling of the search
+        let autoscale_max = args.rps.unwrap_or(args.vus as f64);
+        let total_duration = match (&self.replay, &load_shape, autoscale) {
+            // Until the longest of the parallel scenarios ends
+            _ if !self.scenario.parallel.is_empty() => self
+                .scenario
+                .parallel
+                .iter()
+                .map(|parallel| {
+                    let (ramp_up, hold, ramp_down) =
+                        parallel_phases(parallel, (ramp_up, hold, ramp_down));
+                    ramp_up + hold + ramp_down
+                })
+                .max()
+                .unwrap_or_default(),
+            (_, _, Some(options)) => options.max_duration(autoscale_max),
+            (Some(replay), _, _) => replay.duration(),
+            (None, Some(shape), _) => shape.duration(),
+            (None, None, None) => ramp_up + hold + ramp_down,
+        };
+        let autoscale_steps = Mutex::new(Vec::new());
+        // A resumed run continues the load profile where its checkpoint left off
+        let resumed_elapsed = self.resumed_elapsed();
+        let test_start = Instant::now()
+            .checked_sub(resumed_elapsed)
+            .unwrap_or_else(Instant::now);
+        let prometheus_enabled = args.metrics_enabled();
+
+        println!("\nStarting load test");
+        tracing::info!(vus = args.vus, duration = ?total_duration, "load test started");
+        if let (Some(replay), Some(Command::Replay { log, target, .. })) =
+            (&self.replay, &args.command)
+        {
+            println!(
+                "   Replay: {} requests ({} distinct) from {}",
+                replay.entries.len(),
+                self.scenario.requests.len(),
+                log
+            );
+            println!("   Target: {}", target);
+            println!("   Speed: {}x, {:?}", replay.speed, total_duration);
+            if replay.skipped > 0 {
+                println!("   Skipped lines: {}", replay.skipped);
+            }
+        } else if let [spec] = self.scenario.requests.as_slice() {
+            println!("   URL: {}", spec.url);
+            if self.tcp.is_none() {
+                println!("   Method: {}", spec.method);
+            }
+        } else if let Some(Command::Openapi { spec, .. }) = &args.command {
+            println!("   Operations from {}:", spec);
+            for request in &self.scenario.requests {
+                println!("     {} {}", request.method, request.target);
+            }
+        } else if let Some(path) = &args.har {
+            println!(
+                "   Scenario: {} requests from {}, in order",
+                self.scenario.requests.len(),
+                path
+            );
+        } else if !self.scenario.parallel.is_empty() {
+            println!(
+                "   Scenarios from {}, in parallel:",
+                args.config.as_deref().unwrap_or_default()
+            );
+            for parallel in &self.scenario.parallel {
+                let (ramp_up, hold, ramp_down) =
+                    parallel_phases(parallel, (ramp_up, hold, ramp_down));
+                println!(
+                    "     {}: {} requests, {} VUs{}, ramp-up {}, hold {}, ramp-down {}",
+                    parallel.name,
+                    parallel.request_count(),
+                    parallel.vus,
+                    parallel
+                        .rps
+                        .map(|rps| format!(" at {} req/s", rps))
+                        .unwrap_or_default(),
+                    humantime::format_duration(ramp_up),
+                    humantime::format_duration(hold),
+                    humantime::format_duration(ramp_down)
+                );
+            }
+        } else if args.config.is_none() {
+            println!("   URLs:");
+            for (spec, share) in self.scenario.shares() {
+                println!("     {} ({:.1}%)", spec.url, share);
+            }
+            if self.tcp.is_none() {
+                println!("   Method: {}", args.method);
+            }
+        } else {
+            println!(
+                "   Scenario: {} requests from {}",
+                self.scenario.requests.len(),
+                args.config.as_deref().unwrap_or_default()
+            );
+            if self.scenario.requests.len() > 1 {
+                for (spec, share) in self.scenario.shares() {
+                    println!("     {:5.1}% {} {}", share, spec.method, spec.target);
+                }
+            }
+        }
+        match args.rps {
+            _ if autoscale.is_some() => {
+                let options = autoscale.unwrap();
+                let unit = autoscale_unit.unwrap_or(LoadUnit::Vus);
+                let slo: Vec<&str> = options
+                    .slo
+                    .0
+                    .iter()
+                    .map(|threshold| threshold.expression.as_str())
+                    .collect();
+                println!("   Autoscale SLO: {}", slo.join(", "));
+                println!(
+                    "   Steps: {} {} up by {} to {}, {:?} each",
+                    options.start, unit, options.step, autoscale_max, options.step_duration
+                );
+                if unit == LoadUnit::Rps {
+                    println!("   Max VUs: {}", args.vus);
+                }
+            }
+            Some(rps) => {
+                println!("   Arrival rate: {} req/s", rps);
+                println!("   Max VUs: {}", args.vus);
+            }
+            None if self.replay.is_some() => println!("   Max VUs: {}", args.vus),
+            None if !self.scenario.parallel.is_empty() => println!(
+                "   Target VUs: {} in total",
+                self.scenario.parallel_vus().unwrap_or_default()
+            ),
+            None if load_shape.is_some() => {}
+            None => println!("   Target VUs: {}", args.vus),
+        }
+        if let Some(iterations) = args.iterations {
+            println!("   Iterations: {} (shared by all VUs)", iterations);
+        } else if let Some(per_vu) = args.iterations_per_vu {
+            println!("   Iterations: {} per VU", per_vu);
+        } else if self.replay.is_some() || autoscale.is_some() || !self.scenario.parallel.is_empty()
+        {
+            // The recorded schedule, the search or the scenarios' phases replace the load
+            // profile
+        } else if let Some(profile) = &args.profile {
+            println!("   Profile: {}", profile);
+            println!("   Duration: {:?}", total_duration);
+        } else if args.stage.is_empty() {
+            println!("   Ramp-up: {:?}", ramp_up);
+            println!("   Hold: {:?}", hold);
+            println!("   Ramp-down: {:?}", ramp_down);
+        } else {
+            println!("   Stages:");
+            for (index, stage) in args.stage.iter().enumerate() {
+                println!(
+                    "     {}. {:?} to {} VUs",
+                    index + 1,
+                    stage.duration,
+                    stage.target
+                );
+            }
+        }
+        if let Some(max_duration) = max_duration {
+            println!("   Max duration: {:?}", max_duration);
+        }
+        for condition in &args.abort_on {
+            println!("   Abort on: {}", condition.expression);
+        }
+        if let Some(notifier) = &self.notifier {
+            let format = match args.notify_format {
+                WebhookFormat::Json => "JSON",
+                WebhookFormat::Slack => "Slack",
+            };
+            println!("   Notify webhook: {} ({})", notifier.url(), format);
+        }
+        if !self.scenario.setup.is_empty() {
+            let mut names: Vec<&str> = self.setup_variables.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            println!(
+                "   Setup: {} requests, extracted {}",
+                self.scenario.setup.len(),
+                if names.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    names.join(", ")
+                }
+            );
+        }
+        if !self.scenario.teardown.is_empty() {
+            println!("   Teardown: {} requests", self.scenario.teardown.len());
+        }
+        if !self.scenario.vu_init.is_empty() {
+            let mut names: Vec<&str> = self
+                .scenario
+                .vu_init
+                .iter()
+                .flat_map(|step| step.extract.iter().map(|(name, _)| name.as_str()))
+                .collect();
+            names.sort_unstable();
+            println!(
+                "   VU init: {} requests per VU, extracted {}",
+                self.scenario.vu_init.len(),
+                if names.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    names.join(", ")
+                }
+            );
+        }
+        if let (Some(checkpoint), Some(path)) = (&self.resumed, &args.resume) {
+            println!(
+                "   Resumed from: {} at {:?} ({} requests)",
+                path,
+                Duration::from_secs(resumed_elapsed.as_secs()),
+                checkpoint.counters.total_requests
+            );
+        }
+        if let Some(every) = args.stats_interval {
+            println!("   Live stats: every {:?}", every);
+        }
+        if let Some(path) = &args.checkpoint {
+            println!(
+                "   Checkpoint: {} every {:?}",
+                path, args.checkpoint_interval
+            );
+        }
+        if let Some(think_time) = &args.think_time {
+            println!("   Think time: {}", think_time);
+        }
+        if args.throughput {
+            println!("   Throughput mode: download rates per request");
+        }
+        if let Some(compression) = args.compression {
+            println!(
+                "   Accept-Encoding: {}",
+                compression.accept_encoding().to_str().unwrap_or_default()
+            );
+        }
+        if args.gzip_body {
+            println!("   Request bodies: gzip");
+        }
+        if let Some(seed) = args.seed {
+            println!("   Seed: {}", seed);
+        }
+        match self.payload.as_deref() {
+            Some(Payload::Form(form)) => {
+                let files = form.file_sizes();
+                println!(
+                    "   Form: {} fields, {} files ({})",
+                    form.field_count() - files.len(),
+                    files.len(),
+                    format_bytes(files.iter().sum())
+                );
+            }
+            Some(Payload::File { path, size }) => {
+                println!("   Body file: {} ({})", path.display(), format_bytes(*size));
+            }
+            Some(Payload::Gzipped {
+                bytes,
+                decoded_length,
+            }) => println!(
+                "   Body: {} gzipped to {}",
+                format_bytes(*decoded_length),
+                format_bytes(bytes.len() as u64)
+            ),
+            Some(Payload::Buffered(bytes)) => match args.body_size {
+                Some(_) => println!(
+                    "   Body: {} of {}",
+                    format_bytes(bytes.len() as u64),
+                    match args.body_pattern {
+                        BodyPattern::Random => "random bytes",
+                        BodyPattern::Zeros => "zeros",
+                    }
+                ),
+                None => println!("   Body: stdin ({})", format_bytes(bytes.len() as u64)),
+            },
+            None => {}
+        }
+        if let Some(max_rps) = args.max_rps {
+            println!("   Max RPS: {}", max_rps);
+        }
+        if let Some(statuses) = &args.expect_status {
+            println!("   Expected status: {}", statuses);
+        }
+        if !args.slo.is_empty() {
+            println!("   SLOs: {}", args.slo.join(", "));
+        }
+        if let Some(limit) = args.save_failures {
+            println!(
+                "   Failed responses: up to {} saved to {}",
+                limit, args.failures_dir
+            );
+        }
+        if let Some(max_response_duration) = args.max_response_duration {
+            println!(
+                "   Max response duration: {}",
+                humantime::format_duration(max_response_duration)
+            );
+        }
+        if args.retries > 0 {
+            println!(
+                "   Retries: up to {} on {} (backoff {:?}{})",
+                args.retries,
+                args.retry_on,
+                args.retry_backoff,
+                if args.retry_non_idempotent || args.idempotency_key_header.is_some() {
+                    ", all methods"
+                } else {
+                    ""
+                }
+            );
+        }
+        if let Some(user) = args.user.as_deref() {
+            let name = user.split_once(':').map_or(user, |(name, _)| name);
+            let scheme = if args.digest { "digest" } else { "basic" };
+            println!("   Auth: {} as {}", scheme, name);
+        }
+        if let Some(jwt) = &self.jwt {
+            println!(
+                "   Auth: {} JWT per VU, rotated every {}",
+                jwt.algorithm(),
+                humantime::format_duration(args.jwt_ttl.mul_f64(0.8))
+            );
+        }
+        if self.sse.is_some() {
+            println!("   Mode: server-sent events");
+        }
+        if let Some(tcp) = &self.tcp {
+            println!("   Mode: raw TCP ({})", tcp.describe());
+        }
+        if let Some(signer) = &self.signer {
+            println!("   Signing: {}", signer.describe());
+        }
+        if let Some(cookie_jar) = args.cookie_jar {
+            println!("   Cookie jar: {}", cookie_jar);
+        }
+        if let Some(proxy) = &args.proxy {
+            println!("   Proxy: {}", proxy);
+        }
+        for resolve in &args.resolve {
+            println!("   Resolve: {}", resolve);
+        }
+        if let Some(dns_server) = &args.dns_server {
+            println!("   DNS server: {}", dns_server);
+        }
+        if args.ipv4 {
+            println!("   Address family: IPv4 only");
+        } else if args.ipv6 {
+            println!("   Address family: IPv6 only");
+        }
+        if !args.local_address.is_empty() {
+            let addresses: Vec<String> =
+                args.local_address.iter().map(|ip| ip.to_string()).collect();
+            println!("   Local addresses: {}", addresses.join(", "));
+        }
+        if args.client_per_vu {
+            println!("   Connection pools: one per VU");
+        }
+        if args.disable_keepalive {
+            println!("   Keep-alive: disabled");
+        }
+        if let Some(lifetime) = args.max_connection_lifetime {
+            println!("   Max connection lifetime: {:?}", lifetime);
+        }
+        if let Some(prometheus_url) = &args.prometheus_url {
+            println!("   Prometheus Remote Write: {}", prometheus_url);
+            if args.remote_write_version == RemoteWriteVersion::V2 {
+                println!("   Remote Write version: 2.0 (1.0 if not accepted)");
+            }
+            println!("   App Label: {}", args.app);
+            let labels: Vec<String> = args
+                .static_labels()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            println!("   Labels: {}", labels.join(", "));
+        }
+        if let Some(endpoint) = &args.otlp_endpoint {
+            let protocol = match args.otlp_protocol {
+                OtlpProtocol::Grpc => "gRPC",
+                OtlpProtocol::Http => "HTTP",
+            };
+            println!("   OTLP endpoint: {} ({})", endpoint, protocol);
+        }
+        if let (Some(url), Some(bucket)) = (&args.influx_url, &args.influx_bucket) {
+            println!("   InfluxDB: {} (bucket {})", url, bucket);
+        }
+        if let Some(writer) = PUSHGATEWAY_WRITER.lock().as_ref() {
+            println!("   Pushgateway: {}", writer.group_url());
+        }
+        if let Some(address) = &args.statsd {
+            println!("   StatsD: {} (prefix {})", address, args.statsd_prefix);
+        }
+        if args.trace_propagation {
+            println!(
+                "   Trace propagation: on ({}% sampled)",
+                args.trace_sample_rate * 100.0
+            );
+        }
+        if let Some(addr) = &args.metrics_listen {
+            println!("   Metrics endpoint: http://{}/metrics", addr);
+        }
+        println!();
+
+        if prometheus_enabled && load_shape.is_none() {
+            TARGET_VUS.set(self.scenario.parallel_vus().unwrap_or(args.vus) as i64);
+        }
+        if let (true, Some(max_rps)) = (prometheus_enabled, args.max_rps) {
+            RATE_LIMIT_CONFIGURED.set(max_rps);
+        }
+
+        // Save checkpoints for the whole test; the first tick fires right away
+        let checkpoint_handle = args.checkpoint.clone().map(|path| {
+            let tester = self.clone();
+            let mut ticker = interval(args.checkpoint_interval);
+            tokio::spawn(async move {
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = tester.save_checkpoint(&path, test_start).await {
+                        tracing::warn!(error = %e, "failed to save checkpoint");
+                    }
+                }
+            })
+        });
+
+        // Keep the OAuth2 token fresh for the whole test
+        let token_refresh_handle = self.oauth2.clone().map(|oauth2| {
+            tokio::spawn(async move {
+                oauth2.refresh_periodically().await;
+            })
+        });
+
+        // Start the time series sampler, which also updates and pushes Prometheus metrics
+        *self.timeseries.lock() = match &self.resumed {
+            Some(checkpoint) => {
+                Timeseries::resume(test_start, checkpoint.timeseries.clone(), &self.aggregates)
+            }
+            None => Timeseries::new(test_start),
+        };
+        if args.heatmap.is_some() {
+            self.timeseries.lock().enable_heatmap(&self.percentiles);
+        }
+        let metrics_handle = {
+            let tester_clone = self.clone();
+            let frequency = args.metrics_frequency;
+            let app = args.app.clone();
+            tokio::spawn(async move {
+                tester_clone
+                    .collect_metrics_periodically(&app, frequency, prometheus_enabled)
+                    .await;
+            })
+        };
+
+        // Iteration-based runs count requests, all others seconds
+        let pb = ProgressBar::new(args.total_iterations().unwrap_or(total_duration.as_secs()));
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] [{bar:40}] [{eta_precise}] {msg}")
+                .unwrap()
+                .progress_chars("=>-"),
+        );
+
+        let stats_handle = {
+            let tester = self.clone();
+            let pb = pb.clone();
+            let every = args.stats_interval;
+            tokio::spawn(async move {
+                tester.print_live_stats(&pb, test_start, every).await;
+            })
+        };
+        let thresholds_handle = {
+            let tester = self.clone();
+            let thresholds: Vec<Threshold> = args
+                .threshold
+                .iter()
+                .filter_map(|t| Threshold::parse(t).ok())
+                .filter(Threshold::checked_during_run)
+                .collect();
+            tokio::spawn(async move {
+                tester.watch_thresholds(thresholds, test_start).await;
+            })
+        };
+
+        let mut handles = Vec::new();
+        let mut vu_stop_signals: Vec<Arc<Mutex<bool>>> = Vec::new();
+        let mut scenario_handles = Vec::new();
+
+        // The deadline bounds both the load phases and the wait for VUs to wind down
+        let deadline = max_duration.map(|d| tokio::time::Instant::from_std(test_start + d));
+
+        let phases = async {
+            match args.rps {
+                _ if !self.scenario.parallel.is_empty() => {
+                    self.run_scenarios(
+                        args,
+                        test_start,
+                        (ramp_up, hold, ramp_down),
+                        &pb,
+                        &mut scenario_handles,
+                    )
+                    .await
+                }
+                _ if autoscale.is_some() => {
+                    self.run_autoscale(
+                        args,
+                        autoscale.unwrap(),
+                        test_start,
+                        &pb,
+                        &mut handles,
+                        &mut vu_stop_signals,
+                        &autoscale_steps,
+                    )
+                    .await
+                }
+                None if self.replay.is_some() => {
+                    self.run_replay(
+                        args,
+                        self.replay.as_deref().unwrap(),
+                        test_start,
+                        &pb,
+                        &mut handles,
+                    )
+                    .await
+                }
+                Some(rps) => {
+                    self.run_arrival_rate(
+                        args,
+                        test_start,
+                        (ramp_up, hold, ramp_down),
+                        rps,
+                        &pb,
+                        &mut handles,
+                    )
+                    .await
+                }
+                None if load_shape.is_some() => {
+                    self.run_load_shape(
+                        args,
+                        load_shape.as_deref().unwrap(),
+                        test_start,
+                        &pb,
+                        &mut handles,
+                        &mut vu_stop_signals,
+                    )
+                    .await
+                }
+                None if args.total_iterations().is_some() => {
+                    self.run_iterations(args, &pb, &mut handles, &mut vu_stop_signals)
+                        .await
+                }
+                None => {
+                    self.run_phases(
+                        args,
+                        test_start,
+                        (ramp_up, hold, ramp_down),
+                        &pb,
+                        &mut handles,
+                        &mut vu_stop_signals,
+                    )
+                    .await
+                }
+            }
+        };
+        let deadline_elapsed = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        // Checked whenever the time series gains a second
+        let abort_condition_held = async {
+            if args.abort_on.is_empty() {
+                return std::future::pending().await;
+            }
+            let mut ticker = interval(Duration::from_secs(1));
+            loop {
+                ticker.tick().await;
+                let timeseries = self.timeseries.lock();
+                if let Some(condition) = args
+                    .abort_on
+                    .iter()
+                    .find(|condition| condition.triggered(timeseries.points()))
+                {
+                    return condition.expression.clone();
+                }
+            }
+        };
+        let mut aborted_by = None;
+        let mut stop_reason = tokio::select! {
+            _ = phases => StopReason::Completed,
+            _ = deadline_elapsed => StopReason::MaxDuration,
+            _ = shutdown_signal() => StopReason::Interrupted,
+            expression = abort_condition_held => {
+                pb.println(format!(
+                    "Aborting: {} held, waiting for in-flight requests",
+                    expression
+                ));
+                aborted_by = Some(expression);
+                StopReason::Aborted
+            }
+        };
+
+        if let Some(condition) = &aborted_by {
+            self.notify(&Event::Aborted {
+                condition,
+                elapsed: test_start.elapsed(),
+            })
+            .await;
+        }
+        if stop_reason == StopReason::Interrupted {
+            pb.println(
+                "Interrupted, waiting for in-flight requests (press Ctrl+C again to cancel them)",
+            );
+        }
+        for (scenario_vus, scenario_stop_signals) in scenario_handles {
+            handles.extend(scenario_vus);
+            vu_stop_signals.extend(scenario_stop_signals);
+        }
+
+        // Ensure all VUs are stopped
+        for vu_stop_signal in &vu_stop_signals {
+            *vu_stop_signal.lock() = true;
+        }
+
+        if prometheus_enabled {
+            for phase in ["rampup", "hold", "rampdown"] {
+                TEST_PHASE.with_label_values(&[phase]).set(0);
+            }
+            TEST_PHASE.with_label_values(&["idle"]).set(1);
+        }
+
+        // Wait for all VUs to finish their in-flight request, unless the deadline passes
+        // or a second signal asks to stop right away
+        let mut cancel_vus = stop_reason == StopReason::MaxDuration;
+        if !cancel_vus {
+            let wait_for_vus = async {
+                for handle in handles.iter_mut() {
+                    handle.await.ok();
+                }
+            };
+            let deadline_elapsed = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = wait_for_vus => {}
+                _ = deadline_elapsed => {
+                    cancel_vus = true;
+                    if stop_reason == StopReason::Completed {
+                        stop_reason = StopReason::MaxDuration;
+                    }
+                }
+                _ = shutdown_signal() => {
+                    cancel_vus = true;
+                    stop_reason = StopReason::Interrupted;
+                }
+            }
+        }
+
+        if cancel_vus {
+            // Cancel the VU tasks outright, dropping any in-flight requests
+            for handle in &handles {
+                handle.abort();
+            }
+            for handle in handles {
+                handle.await.ok();
+            }
+
+            // Cancelled VUs never reach their own decrement
+            self.active_vus.store(0, Ordering::Relaxed);
+            if prometheus_enabled {
+                ACTIVE_VUS.set(0);
+            }
+        }
+
+        match stop_reason {
+            StopReason::Completed => pb.finish_with_message("Test completed"),
+            StopReason::MaxDuration => {
+                pb.abandon_with_message("Max duration reached, VUs cancelled")
+            }
+            StopReason::Interrupted => pb.abandon_with_message("Test interrupted"),
+            StopReason::Aborted => pb.abandon_with_message("Test aborted"),
+        }
+
+        metrics_handle.abort();
+        if prometheus_enabled {
+            reset_activity_gauges();
+        }
+        stats_handle.abort();
+        thresholds_handle.abort();
+        if let Some(request_log) = &self.request_log {
+            request_log.flush().await;
+        }
+        self.timeseries
+            .lock()
+            .finish(&self.aggregates, self.active_vus.load(Ordering::Relaxed));
+        // Teardown runs however the test ended, while the OAuth2 token is still refreshed
+        if !self.scenario.teardown.is_empty() {
+            let failures = setup::run_teardown(
+                &self.clients.get(1),
+                &self.scenario.teardown,
+                &self.setup_variables,
+                &oauth2_headers(&self.oauth2),
+            )
+            .await;
+            for failure in failures {
+                tracing::warn!("{}", failure);
+            }
+        }
+        if let Some(handle) = token_refresh_handle {
+            handle.abort();
+        }
+        if let Some(handle) = checkpoint_handle {
+            handle.abort();
+        }
+        // An interrupted run can be resumed from where it stopped
+        if let (StopReason::Interrupted, Some(path)) = (stop_reason, &args.checkpoint) {
+            match self.save_checkpoint(path, test_start).await {
+                Ok(()) => println!("Checkpoint saved to: {}", path),
+                Err(e) => tracing::error!(error = %e, "failed to save checkpoint"),
+            }
+        }
+
+        // Calculate results
+        // With stages, report the peak VU target, with parallel scenarios the VUs of all
+        let mut results = self.calculate_results(
+            test_start.elapsed().as_secs_f64(),
+            self.scenario
+                .parallel_vus()
+                .unwrap_or_else(|| args.peak_vus()),
+        );
+        results.stop_reason = stop_reason;
+        results.aborted_by = aborted_by;
+        tracing::info!(
+            requests = results.total_requests,
+            failed = results.failed_requests,
+            stop_reason = ?stop_reason,
+            "load test finished"
+        );
+        if let (Some(options), Some(unit)) = (autoscale, autoscale_unit) {
+            results.autoscale = Some(AutoscaleReport::new(
+                unit,
+                &options.slo,
+                options.step_duration,
+                autoscale_steps.into_inner(),
+            ));
+        }
+        results
+    }
+
+    // --dry-run and `forgy validate`: the options were parsed and the scenario loaded (with
+    // its data files, OAuth2 token and setup) when the tester was built; what remains are the
+    // durations, and sending every step once. Returns whether all of it worked
+    pub async fn dry_run(&self, args: &Args) -> bool {
+        let mut valid = true;
+        let durations = [
+            ("--ramp-up", Some(&args.ramp_up)),
+            ("--hold", Some(&args.hold)),
+            ("--ramp-down", Some(&args.ramp_down)),
+            ("--max-duration", args.max_duration.as_ref()),
+        ];
+        for (option, value) in durations {
+            if let Some(Err(e)) = value.map(|value| parse_duration(value)) {
+                println!("Invalid {}: {}", option, e);
+                valid = false;
+            }
+        }
+
+        println!(
+            "\nDry run: sending each scenario request once ({} in total)",
+            self.scenario.requests.len()
+        );
+        let variables = if self.scenario.vu_init.is_empty() {
+            Some(Variables::new())
+        } else {
+            self.run_vu_init(0).await
+        };
+        let Some(variables) = variables else {
+            println!("vu_init requests failed; the scenario requests were not sent");
+            return false;
+        };
+        let mut failed = 0;
+        for spec in &self.scenario.requests {
+            let context = self.template_context(0, 0, Some(&variables));
+            let idempotency_key = self.idempotency_key(context.vu);
+            let stat = self
+                .send_attempt(spec, &context, 0, idempotency_key.as_ref())
+                .await;
+            if !stat.success {
+                failed += 1;
+            }
+        }
+        if !self.scenario.teardown.is_empty() {
+            let failures = setup::run_teardown(
+                &self.clients.get(1),
+                &self.scenario.teardown,
+                &self.setup_variables,
+                &oauth2_headers(&self.oauth2),
+            )
+            .await;
+            for failure in &failures {
+                println!("{}", failure);
+            }
+            valid &= failures.is_empty();
+        }
+
+        println!(
+            "\n{} of {} requests succeeded",
+            self.scenario.requests.len() - failed,
+            self.scenario.requests.len()
+        );
+        valid && failed == 0
+    }
+
+    // Where --save-failures saved responses, and how many
+    pub fn saved_failures(&self) -> Option<(&std::path::Path, usize)> {
+        self.failures
+            .as_ref()
+            .map(|failures| (failures.dir(), failures.saved()))
+    }
+
+    // How far into the test the checkpoint of a resumed run was taken
+    fn resumed_elapsed(&self) -> Duration {
+        self.resumed
+            .as_ref()
+            .map(|checkpoint| checkpoint.elapsed())
+            .unwrap_or_default()
+    }
+
+    async fn save_checkpoint(
+        &self,
+        path: &str,
+        test_start: Instant,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let checkpoint = {
+            let timeseries = self.timeseries.lock();
+            Checkpoint::capture(&self.aggregates, timeseries.points(), test_start.elapsed())?
+        };
+        checkpoint.save(path).await
+    }
+
+    fn spawn_virtual_user(
+        &self,
+        vu_index: usize,
+        prometheus_enabled: bool,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+        vu_stop_signals: &mut Vec<Arc<Mutex<bool>>>,
+    ) {
+        let vu_index = vu_index + self.scenario_run.as_ref().map_or(0, |run| run.vu_offset);
+        let tester = self.clone();
+        let vu_stop_signal = Arc::new(Mutex::new(false));
+        let stop = vu_stop_signal.clone();
+        if let Some(sse) = &self.sse {
+            sse.register(vu_index + 1, vu_stop_signal.clone());
+        }
+
+        vu_stop_signals.push(vu_stop_signal);
+
+        let span = tracing::info_span!("vu", vu = vu_index + 1);
+        handles.push(tokio::spawn(
+            async move {
+                tester
+                    .run_virtual_user(stop, prometheus_enabled, vu_index)
+                    .await;
+            }
+            .instrument(span),
+        ));
+    }
+
+    async fn run_phases(
+        &self,
+        args: &Args,
+        test_start: Instant,
+        (ramp_up, hold, ramp_down): (Duration, Duration, Duration),
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+        vu_stop_signals: &mut Vec<Arc<Mutex<bool>>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+
+        // Ramp-up phase
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["rampup"]).set(1);
+            TEST_PHASE.with_label_values(&["idle"]).set(0);
+        }
+
+        let total_ramp_millis = ramp_up.as_millis() as f64;
+        let vu_interval_millis = total_ramp_millis / args.vus as f64;
+        let mut current_vus = 0;
+        let mut next_vu_time = 0.0;
+        let mut progress_interval = interval(Duration::from_millis(500)); // Update progress twice per second
+
+        while test_start.elapsed() < ramp_up && current_vus < args.vus {
+            let elapsed_millis = test_start.elapsed().as_millis() as f64;
+
+            // Add VUs gradually based on time intervals
+            while elapsed_millis >= next_vu_time && current_vus < args.vus {
+                self.spawn_virtual_user(current_vus, prometheus_enabled, handles, vu_stop_signals);
+                current_vus += 1;
+                next_vu_time = current_vus as f64 * vu_interval_millis;
+            }
+
+            // Update progress less frequently
+            tokio::select! {
+                _ = progress_interval.tick() => {
+                    pb.set_position(test_start.elapsed().as_secs());
+                    pb.set_message(format!("{}/{} VUs (ramp-up)", current_vus, args.vus));
+                }
+                _ = sleep(Duration::from_millis(50)) => {} // Small sleep to prevent busy waiting
+            }
+        }
+
+        // Start any VUs the ramp-up did not get to (e.g. with a zero ramp-up)
+        while current_vus < args.vus {
+            self.spawn_virtual_user(current_vus, prometheus_enabled, handles, vu_stop_signals);
+            current_vus += 1;
+        }
+
+        // Hold phase
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["rampup"]).set(0);
+            TEST_PHASE.with_label_values(&["hold"]).set(1);
+        }
+
+        // Phases end at fixed times into the test, so that a resumed run picks up mid-phase
+        let hold_end = ramp_up + hold;
+        while test_start.elapsed() < hold_end {
+            sleep(Duration::from_secs(1)).await;
+            pb.set_position(test_start.elapsed().as_secs());
+            pb.set_message(format!("{}/{} VUs (hold)", args.vus, args.vus));
+        }
+
+        // Ramp-down phase
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["hold"]).set(0);
+            TEST_PHASE.with_label_values(&["rampdown"]).set(1);
+        }
+
+        let ramp_down_start = ramp_up + hold;
+        let total_ramp_down_millis = ramp_down.as_millis() as f64;
+        let vu_stop_interval_millis = total_ramp_down_millis / args.vus as f64;
+        let mut vus_to_stop = args.vus;
+        let mut next_stop_time = 0.0;
+        let mut progress_interval = interval(Duration::from_millis(500));
+
+        while test_start.elapsed() < (ramp_down_start + ramp_down) && vus_to_stop > 0 {
+            let ramp_down_elapsed_millis = test_start
+                .elapsed()
+                .saturating_sub(ramp_down_start)
+                .as_millis() as f64;
+
+            // Stop VUs gradually based on time intervals
+            while ramp_down_elapsed_millis >= next_stop_time && vus_to_stop > 0 {
+                // Stop the oldest VU by setting its individual stop signal
+                let vu_to_stop_index = args.vus - vus_to_stop;
+                if vu_to_stop_index < vu_stop_signals.len() {
+                    *vu_stop_signals[vu_to_stop_index].lock() = true;
+                }
+
+                vus_to_stop -= 1;
+                next_stop_time = (args.vus - vus_to_stop) as f64 * vu_stop_interval_millis;
+            }
+
+            // Update progress less frequently
+            tokio::select! {
+                _ = progress_interval.tick() => {
+                    pb.set_position(test_start.elapsed().as_secs());
+                    let remaining_vus = self.active_vus.load(Ordering::Relaxed);
+                    pb.set_message(format!("{}/{} VUs (ramp-down)", remaining_vus, args.vus));
+                }
+                _ = sleep(Duration::from_millis(50)) => {} // Small sleep to prevent busy waiting
+            }
+        }
+    }
+
+    // Run the scenario file's parallel scenarios side by side, each with the executor its
+    // options ask for; they share the progress bar, so their own progress is not shown
+    async fn run_scenarios(
+        &self,
+        args: &Args,
+        test_start: Instant,
+        phases: (Duration, Duration, Duration),
+        pb: &ProgressBar,
+        scenario_handles: &mut Vec<VuHandles>,
+    ) {
+        scenario_handles.resize_with(self.scenario.parallel.len(), Default::default);
+        let executors = self
+            .scenario
+            .parallel
+            .iter()
+            .zip(self.scenario_runs.iter())
+            .zip(scenario_handles.iter_mut())
+            .map(|((parallel, run), (handles, vu_stop_signals))| {
+                let tester = LoadTester {
+                    scenario: Arc::new(self.scenario.part(parallel)),
+                    scenario_run: Some(run.clone()),
+                    ..self.clone()
+                };
+                let mut args = args.clone();
+                args.vus = parallel.vus;
+                args.rps = parallel.rps;
+                let phases = parallel_phases(parallel, phases);
+                async move {
+                    let hidden = ProgressBar::hidden();
+                    match parallel.rps {
+                        Some(rps) => {
+                            tester
+                                .run_arrival_rate(&args, test_start, phases, rps, &hidden, handles)
+                                .await
+                        }
+                        None => {
+                            tester
+                                .run_phases(
+                                    &args,
+                                    test_start,
+                                    phases,
+                                    &hidden,
+                                    handles,
+                                    vu_stop_signals,
+                                )
+                                .await;
+                            // The other scenarios may go on; this one's VUs are done
+                            for vu_stop_signal in vu_stop_signals.iter() {
+                                *vu_stop_signal.lock() = true;
+                            }
+                        }
+                    }
+                }
+            });
+
+        let total_vus = self.scenario.parallel_vus().unwrap_or_default();
+        let progress = async {
+            let mut progress_interval = interval(Duration::from_millis(500));
+            loop {
+                progress_interval.tick().await;
+                pb.set_position(test_start.elapsed().as_secs());
+                pb.set_message(format!(
+                    "{}/{} VUs ({} scenarios)",
+                    self.active_vus.load(Ordering::Relaxed),
+                    total_vus,
+                    self.scenario.parallel.len()
+                ));
+            }
+        };
+        tokio::select! {
+            _ = futures_util::future::join_all(executors) => {}
+            _ = progress => {}
+        }
+    }
+
+    // Start every VU at once and wait until they have used up the iteration budget
+    async fn run_iterations(
+        &self,
+        args: &Args,
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+        vu_stop_signals: &mut Vec<Arc<Mutex<bool>>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["hold"]).set(1);
+            TEST_PHASE.with_label_values(&["idle"]).set(0);
+        }
+
+        for vu_index in 0..args.vus {
+            self.spawn_virtual_user(vu_index, prometheus_enabled, handles, vu_stop_signals);
+        }
+
+        let total = args.total_iterations().unwrap_or_default();
+        let mut progress_interval = interval(Duration::from_millis(500));
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            progress_interval.tick().await;
+            let done = self.aggregates.total_requests() as u64;
+            pb.set_position(done);
+            pb.set_message(format!(
+                "{}/{} iterations, {} VUs",
+                done,
+                total,
+                self.active_vus.load(Ordering::Relaxed)
+            ));
+        }
+        pb.set_position(self.aggregates.total_requests() as u64);
+    }
+
+    // Follow a --stage or --profile shape: every tick the VU count is moved to the shape's
+    // current target, and the phase follows the direction the target is moving in
+    async fn run_load_shape(
+        &self,
+        args: &Args,
+        shape: &dyn LoadShape,
+        test_start: Instant,
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+        vu_stop_signals: &mut Vec<Arc<Mutex<bool>>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+        // Indices into vu_stop_signals of running VUs, oldest first
+        let mut running = std::collections::VecDeque::new();
+        let mut current_stage = None;
+        let mut current_phase = "idle";
+        let mut previous_target = 0.0;
+        let mut progress_interval = interval(Duration::from_millis(500));
+
+        while let Some((stage_index, target)) = shape.target_at(test_start.elapsed()) {
+            let desired_vus = target.round() as usize;
+            while running.len() < desired_vus {
+                running.push_back(vu_stop_signals.len());
+                self.spawn_virtual_user(
+                    vu_stop_signals.len(),
+                    prometheus_enabled,
+                    handles,
+                    vu_stop_signals,
+                );
+            }
+            while running.len() > desired_vus {
+                // Stop the oldest VU, as the ramp-down phase does
+                if let Some(index) = running.pop_front() {
+                    *vu_stop_signals[index].lock() = true;
+                }
+            }
+
+            if current_stage != Some(stage_index) {
+                current_stage = Some(stage_index);
+                if prometheus_enabled {
+                    STAGE.set(stage_index as i64 + 1);
+                }
+            }
+            let phase = match target.partial_cmp(&previous_target) {
+                Some(std::cmp::Ordering::Greater) => "rampup",
+                Some(std::cmp::Ordering::Less) => "rampdown",
+                _ => "hold",
+            };
+            if phase != current_phase {
+                if prometheus_enabled {
+                    TEST_PHASE.with_label_values(&[current_phase]).set(0);
+                    TEST_PHASE.with_label_values(&[phase]).set(1);
+                }
+                current_phase = phase;
+            }
+            previous_target = target;
+            if prometheus_enabled {
+                TARGET_VUS.set(desired_vus as i64);
+            }
+
+            tokio::select! {
+                _ = progress_interval.tick() => {
+                    pb.set_position(test_start.elapsed().as_secs());
+                    pb.set_message(format!(
+                        "{} VUs (stage {}/{}, {})",
+                        running.len(),
+                        stage_index + 1,
+                        shape.segments(),
+                        current_phase
+                    ));
+                }
+                _ = sleep(Duration::from_millis(50)) => {}
+            }
+        }
+
+        if prometheus_enabled {
+            STAGE.set(0);
+        }
+    }
+
+    // `forgy autoscale`: hold each load for a step, judge the step against the SLO on the
+    // requests it completed, and let the search choose the next load
+    #[allow(clippy::too_many_arguments)]
+    async fn run_autoscale(
+        &self,
+        args: &Args,
+        options: &AutoscaleOptions,
+        test_start: Instant,
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+        vu_stop_signals: &mut Vec<Arc<Mutex<bool>>>,
+        steps: &Mutex<Vec<AutoscaleStep>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["hold"]).set(1);
+            TEST_PHASE.with_label_values(&["idle"]).set(0);
+        }
+        let unit = options.unit(args.rps);
+        let max = args.rps.unwrap_or(args.vus as f64);
+        let mut search = options.search(unit, max);
+        // In RPS mode, --vus workers take the arrivals
+        let arrival_sender = (unit == LoadUnit::Rps)
+            .then(|| self.spawn_arrival_workers(args.vus, prometheus_enabled, handles));
+        // Indices into vu_stop_signals of running VUs, oldest first
+        let mut running = std::collections::VecDeque::new();
+        let mut load = options.start.min(max);
+
+        loop {
+            if unit == LoadUnit::Vus {
+                let desired_vus = load.round() as usize;
+                while running.len() < desired_vus {
+                    running.push_back(vu_stop_signals.len());
+                    self.spawn_virtual_user(
+                        vu_stop_signals.len(),
+                        prometheus_enabled,
+                        handles,
+                        vu_stop_signals,
+                    );
+                }
+                while running.len() > desired_vus {
+                    if let Some(index) = running.pop_front() {
+                        *vu_stop_signals[index].lock() = true;
+                    }
+                }
+                if prometheus_enabled {
+                    TARGET_VUS.set(desired_vus as i64);
+                }
+            }
+
+            let requests_before = self.aggregates.total_requests();
+            let successful_before = self.aggregates.successful_requests();
+            let histogram_before = self.aggregates.histogram();
+            let step_start = Instant::now();
+            let mut scheduler = interval(Duration::from_millis(10));
+            let mut scheduled_arrivals = 0u64;
+            let mut last_progress = Instant::now();
+
+            while step_start.elapsed() < options.step_duration {
+                scheduler.tick().await;
+                if let Some(arrival_sender) = &arrival_sender {
+                    let expected_arrivals = (load * step_start.elapsed().as_secs_f64()).floor();
+                    while (scheduled_arrivals as f64) < expected_arrivals {
+                        scheduled_arrivals += 1;
+                        let arrival = Arrival {
+                            scheduled_at: Instant::now(),
+                            request: None,
+                        };
+                        if arrival_sender.try_send(arrival).is_err() {
+                            self.aggregates.record_dropped_iteration();
+                            if prometheus_enabled {
+                                DROPPED_ITERATIONS.inc();
+                            }
+                        }
+                    }
+                }
+                if last_progress.elapsed() >= Duration::from_millis(500) {
+                    last_progress = Instant::now();
+                    pb.set_position(test_start.elapsed().as_secs());
+                    pb.set_message(format!(
+                        "{} {} (step {}), {} busy",
+                        load,
+                        unit,
+                        steps.lock().len() + 1,
+                        self.active_vus.load(Ordering::Relaxed)
+                    ));
+                }
+            }
+
+            let requests = self.aggregates.total_requests() - requests_before;
+            let failed = requests - (self.aggregates.successful_requests() - successful_before);
+            let mut histogram = self.aggregates.histogram();
+            // Both snapshots come from the same histograms, so this cannot underflow
+            let _ = histogram.subtract(&histogram_before);
+            let measurements = Measurements::from_histogram(
+                &histogram,
+                requests,
+                failed,
+                step_start.elapsed().as_secs_f64(),
+            );
+            let violations = options.slo.violations(&measurements);
+            let passed = violations.is_empty();
+            steps.lock().push(AutoscaleStep {
+                load,
+                rps: measurements.rps,
+                p95_response_time_ms: measurements.p95_ms,
+                p99_response_time_ms: measurements.p99_ms,
+                error_rate: measurements.error_rate,
+                passed,
+                violations,
+            });
+
+            match search.next(load, passed) {
+                Some(next) => load = next,
+                None => break,
+            }
+        }
+        pb.set_position(test_start.elapsed().as_secs());
+    }
+
+    // Workers of the open-model executors; each takes the next arrival once it is free
+    fn spawn_arrival_workers(
+        &self,
+        workers: usize,
+        prometheus_enabled: bool,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+    ) -> tokio::sync::mpsc::Sender<Arrival> {
+        let (arrival_sender, arrival_receiver) = tokio::sync::mpsc::channel::<Arrival>(1);
+        let arrival_receiver = Arc::new(tokio::sync::Mutex::new(arrival_receiver));
+
+        let offset = self.scenario_run.as_ref().map_or(0, |run| run.vu_offset);
+        for worker_index in (0..workers).map(|index| index + offset) {
+            let tester = self.clone();
+            let arrivals = arrival_receiver.clone();
+            let span = tracing::info_span!("worker", worker = worker_index + 1);
+            handles.push(tokio::spawn(
+                async move {
+                    tester
+                        .run_arrival_worker(arrivals, prometheus_enabled, worker_index)
+                        .await;
+                }
+                .instrument(span),
+            ));
+        }
+        arrival_sender
+    }
+
+    // Send the recorded requests of an access log at their (scaled) original times. Like
+    // --rps, this is an open model: requests that find every worker busy are dropped and
+    // counted rather than delayed, which would distort the recorded schedule.
+    async fn run_replay(
+        &self,
+        args: &Args,
+        replay: &Replay,
+        test_start: Instant,
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+        if prometheus_enabled {
+            TEST_PHASE.with_label_values(&["hold"]).set(1);
+            TEST_PHASE.with_label_values(&["idle"]).set(0);
+        }
+        let arrival_sender = self.spawn_arrival_workers(args.vus, prometheus_enabled, handles);
+
+        let mut last_progress = Instant::now();
+        // A resumed run skips the requests that were due before its checkpoint
+        let resumed_at = self.resumed_elapsed();
+        for (sent, entry) in replay.entries.iter().enumerate() {
+            if entry.offset < resumed_at {
+                continue;
+            }
+            tokio::time::sleep_until(tokio::time::Instant::from_std(test_start + entry.offset))
+                .await;
+            let arrival = Arrival {
+                scheduled_at: test_start + entry.offset,
+                request: Some(entry.request),
+            };
+            if arrival_sender.try_send(arrival).is_err() {
+                self.aggregates.record_dropped_iteration();
+                if prometheus_enabled {
+                    DROPPED_ITERATIONS.inc();
+                }
+            }
+
+            if last_progress.elapsed() >= Duration::from_millis(500) {
+                last_progress = Instant::now();
+                pb.set_position(test_start.elapsed().as_secs());
+                pb.set_message(format!(
+                    "{}/{} requests, {}/{} VUs busy",
+                    sent + 1,
+                    replay.entries.len(),
+                    self.active_vus.load(Ordering::Relaxed),
+                    args.vus
+                ));
+            }
+        }
+        pb.set_position(test_start.elapsed().as_secs());
+    }
+
+    // Open-model executor: requests are started at the target rate regardless of response
+    // times. The rate ramps with the same ramp-up/hold/ramp-down phases as VUs do, and
+    // arrivals that find every worker busy are dropped and counted instead of queued.
+    async fn run_arrival_rate(
+        &self,
+        args: &Args,
+        test_start: Instant,
+        (ramp_up, hold, ramp_down): (Duration, Duration, Duration),
+        rps: f64,
+        pb: &ProgressBar,
+        handles: &mut Vec<tokio::task::JoinHandle<()>>,
+    ) {
+        let prometheus_enabled = args.metrics_enabled();
+        let arrival_sender = self.spawn_arrival_workers(args.vus, prometheus_enabled, handles);
+
+        let rate_at = |elapsed: f64| -> f64 {
+            let ramp_up = ramp_up.as_secs_f64();
+            let hold_end = ramp_up + hold.as_secs_f64();
+            if elapsed < ramp_up {
+                rps * elapsed / ramp_up
+            } else if elapsed < hold_end {
+                rps
+            } else {
+                (rps * (1.0 - (elapsed - hold_end) / ramp_down.as_secs_f64())).max(0.0)
+            }
+        };
+
+        let total_duration = (ramp_up + hold + ramp_down).as_secs_f64();
+        let mut scheduler = interval(Duration::from_millis(10));
+        // A resumed run starts at its checkpoint's time, not at zero
+        let mut last_elapsed = self.resumed_elapsed().as_secs_f64().min(total_duration);
+        let mut expected_arrivals = 0.0;
+        let mut scheduled_arrivals = 0u64;
+        let mut current_phase = "idle";
+        let mut last_progress = Instant::now();
+
+        loop {
+            scheduler.tick().await;
+            let elapsed = test_start.elapsed().as_secs_f64().min(total_duration);
+
+            if prometheus_enabled {
+                let phase = if elapsed < ramp_up.as_secs_f64() {
+                    "rampup"
+                } else if elapsed < (ramp_up + hold).as_secs_f64() {
+                    "hold"
+                } else {
+                    "rampdown"
+                };
+                if phase != current_phase {
+                    TEST_PHASE.with_label_values(&[current_phase]).set(0);
+                    TEST_PHASE.with_label_values(&[phase]).set(1);
+                    current_phase = phase;
+                }
+            }
+
+            // Integrate the rate curve so arrivals stay on schedule even if ticks run late
+            expected_arrivals +=
+                (rate_at(last_elapsed) + rate_at(elapsed)) / 2.0 * (elapsed - last_elapsed);
+            last_elapsed = elapsed;
+
+            while (scheduled_arrivals as f64) < expected_arrivals.floor() {
+                scheduled_arrivals += 1;
+                let arrival = Arrival {
+                    scheduled_at: Instant::now(),
+                    request: None,
+                };
+                if arrival_sender.try_send(arrival).is_err() {
+                    self.aggregates.record_dropped_iteration();
+                    if prometheus_enabled {
+                        DROPPED_ITERATIONS.inc();
+                    }
+                }
+            }
+
+            if last_progress.elapsed() >= Duration::from_millis(500) {
+                last_progress = Instant::now();
+                pb.set_position(test_start.elapsed().as_secs());
+                pb.set_message(format!(
+                    "{:.1} req/s, {}/{} VUs busy",
+                    rate_at(elapsed),
+                    self.active_vus.load(Ordering::Relaxed),
+                    args.vus
+                ));
+            }
+
+            if elapsed >= total_duration {
+                break;
+            }
+        }
+    }
+
+    fn calculate_results(&self, duration_seconds: f64, vus: usize) -> TestResults {
+        let aggregates = self.aggregates.merged();
+        let histogram = &aggregates.histogram;
+        let corrected_percentiles =
+            LatencyPercentiles::from_histogram(&aggregates.corrected_histogram);
+
+        let total_requests = self.aggregates.total_requests();
+        let successful_requests = self.aggregates.successful_requests();
+        let failed_requests = total_requests - successful_requests;
+
+        let avg_response_time_ms = self.aggregates.mean_duration_micros() / 1000.0;
+        let min_response_time_ms = self.aggregates.min_duration_micros() as f64 / 1000.0;
+        let max_response_time_ms = self.aggregates.max_duration_micros() as f64 / 1000.0;
+
+        // Convert from microseconds back to milliseconds for percentiles
+        let p50_response_time_ms = if !histogram.is_empty() {
+            histogram.value_at_percentile(50.0) as f64 / 1000.0
+        } else {
+            0.0
+        };
+        let p90_response_time_ms = if !histogram.is_empty() {
+            histogram.value_at_percentile(90.0) as f64 / 1000.0
+        } else {
+            0.0
+        };
+        let p95_response_time_ms = if !histogram.is_empty() {
+            histogram.value_at_percentile(95.0) as f64 / 1000.0
+        } else {
+            0.0
+        };
+        let p99_response_time_ms = if !histogram.is_empty() {
+            histogram.value_at_percentile(99.0) as f64 / 1000.0
+        } else {
+            0.0
+        };
+
+        let requests_per_second = if duration_seconds > 0.0 {
+            total_requests as f64 / duration_seconds
+        } else {
+            0.0
+        };
+
+        let bytes = self.aggregates.bytes();
+        let dropped_iterations = self.aggregates.dropped_iterations();
+        let retries = self.aggregates.retries();
+        let recovered_requests = self.aggregates.recovered_requests();
+        let cut_off_responses = self.aggregates.cut_off_responses();
+        let checks = aggregates.checks.values().cloned().collect();
+        let phases = PHASES
+            .iter()
+            .filter_map(|phase| Some((phase, aggregates.phases.get(phase)?)))
+            .filter(|(_, histogram)| !histogram.is_empty())
+            .map(|(phase, histogram)| PhaseSummary {
+                phase: phase.to_string(),
+                count: histogram.len(),
+                avg_ms: histogram.mean() / 1000.0,
+                p50_ms: histogram.value_at_percentile(50.0) as f64 / 1000.0,
+                p95_ms: histogram.value_at_percentile(95.0) as f64 / 1000.0,
+                p99_ms: histogram.value_at_percentile(99.0) as f64 / 1000.0,
+            })
+            .collect();
+        let targets = aggregates
+            .targets
+            .iter()
+            .map(|(target, stats)| TargetSummary {
+                target: target.clone(),
+                total_requests: stats.total_requests,
+                successful_requests: stats.successful_requests,
+                failed_requests: stats.total_requests - stats.successful_requests,
+                avg_response_time_ms: stats.histogram.mean() / 1000.0,
+                p95_response_time_ms: stats.histogram.value_at_percentile(95.0) as f64 / 1000.0,
+                p99_response_time_ms: stats.histogram.value_at_percentile(99.0) as f64 / 1000.0,
+            })
+            .collect();
+        let endpoints = endpoint_summaries(&aggregates);
+        let timeseries = self.timeseries.lock();
+
+        TestResults {
+            total_requests,
+            successful_requests,
+            failed_requests,
+            vus,
+            avg_response_time_ms,
+            min_response_time_ms,
+            max_response_time_ms,
+            p50_response_time_ms,
+            p90_response_time_ms,
+            p95_response_time_ms,
+            p99_response_time_ms,
+            percentiles: percentile_values(histogram, &self.percentiles),
+            status_classes: aggregates
+                .status_classes
+                .iter()
+                .map(|(class, histogram)| {
+                    StatusClassSummary::new(class, histogram, &self.percentiles)
+                })
+                .collect(),
+            corrected_percentiles,
+            requests_per_second,
+            test_duration_seconds: duration_seconds,
+            status_code_distribution: aggregates.status_codes.clone(),
+            error_types: aggregates.error_types.clone(),
+            targets,
+            endpoints,
+            scenarios: self
+                .scenario_runs
+                .iter()
+                .map(|run| run.summary(duration_seconds))
+                .collect(),
+            phases,
+            timeseries: timeseries.points().to_vec(),
+            heatmap: timeseries.heatmap().to_vec(),
+            autoscale: None,
+            vu_init: endpoint_summaries(&self.init_stats.merged()),
+            total_bytes_sent: bytes.sent(),
+            total_bytes_received: bytes.received(),
+            upload_mbps: megabits_per_second(bytes.sent(), duration_seconds),
+            throughput: self.throughput.then(|| {
+                ThroughputSummary::new(
+                    bytes.body_received,
+                    duration_seconds,
+                    &aggregates.throughput,
+                )
+            }),
+            header_bytes_sent: bytes.header_sent,
+            body_bytes_sent: bytes.body_sent,
+            header_bytes_received: bytes.header_received,
+            body_bytes_received: bytes.body_received,
+            decoded_body_bytes_sent: bytes.decoded_body_sent,
+            decoded_body_bytes_received: bytes.decoded_body_received,
+            dropped_iterations,
+            retries,
+            recovered_requests,
+            cut_off_responses,
+            checks,
+            thresholds: Vec::new(),
+            slos: self
+                .slos
+                .iter()
+                .map(|slo| slo.evaluate(histogram, total_requests as u64, failed_requests as u64))
+                .collect(),
+            sse: self.sse.as_ref().map(|sse| sse.summary(duration_seconds)),
+            tcp: self.tcp.as_ref().map(|tcp| tcp.summary()),
+            stop_reason: StopReason::Completed,
+            aborted_by: None,
+            samples: self
+                .samples
+                .as_ref()
+                .map(|samples| samples.lock().items().to_vec())
+                .unwrap_or_default(),
+            histograms: EncodedHistograms::from_stats(&aggregates).ok(),
+        }
+    }
+}
+
+impl Clone for LoadTester {
+    fn clone(&self) -> Self {
+        Self {
+            clients: self.clients.clone(),
+            connections: self.connections.clone(),
+            scenario: self.scenario.clone(),
+            samples: self.samples.clone(),
+            active_vus: self.active_vus.clone(),
+            aggregates: self.aggregates.clone(),
+            timeseries: self.timeseries.clone(),
+            data: self.data.clone(),
+            oauth2: self.oauth2.clone(),
+            basic_auth: self.basic_auth.clone(),
+            digest_auth: self.digest_auth.clone(),
+            jwt: self.jwt.clone(),
+            signer: self.signer.clone(),
+            idempotency_key_header: self.idempotency_key_header.clone(),
+            sse: self.sse.clone(),
+            tcp: self.tcp.clone(),
+            notifier: self.notifier.clone(),
+            max_response_duration: self.max_response_duration,
+            request_timeout: self.request_timeout,
+            request_log: self.request_log.clone(),
+            tracer: self.tracer,
+            exporters: self.exporters.clone(),
+            iterations_left: self.iterations_left.clone(),
+            iterations_per_vu: self.iterations_per_vu,
+            think_time: self.think_time,
+            rngs: self.rngs.clone(),
+            payload: self.payload.clone(),
+            discard_body: self.discard_body,
+            debug_sample: self.debug_sample.clone(),
+            failures: self.failures.clone(),
+            throughput: self.throughput,
+            compression: self.compression,
+            gzip_body: self.gzip_body,
+            rate_limiter: self.rate_limiter.clone(),
+            retry_policy: self.retry_policy.clone(),
+            expected_status: self.expected_status.clone(),
+            percentiles: self.percentiles.clone(),
+            slos: self.slos.clone(),
+            replay: self.replay.clone(),
+            endpoints: self.endpoints.clone(),
+            cookies: self.cookies.clone(),
+            resumed: self.resumed.clone(),
+            script: self.script.clone(),
+            setup_variables: self.setup_variables.clone(),
+            init_stats: self.init_stats.clone(),
+            scenario_runs: self.scenario_runs.clone(),
+            scenario_run: self.scenario_run.clone(),
+        }
+    }
+}
+
+// =============================================================================
+// PROMETHEUS REMOTE WRITE FUNCTIONALITY
+// =============================================================================
+
+pub fn init_remote_write(args: &Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(url) = &args.prometheus_url {
+        let config = RemoteWriteConfig {
+            url: url.clone(),
+            max_samples_per_request: args.remote_write_batch_size,
+            max_bytes_per_request: args.remote_write_max_bytes.map(|bytes| bytes as usize),
+            shards: args.remote_write_shards,
+            wal_dir: args.remote_write_wal.as_ref().map(std::path::PathBuf::from),
+            queue_capacity: args.remote_write_queue_capacity,
+            queue_policy: args.remote_write_queue_policy,
+            max_retries: args.remote_write_max_retries,
+            labels: args.static_labels(),
+            version: args.remote_write_version,
+        };
+        *REMOTE_WRITE_CLIENT.lock() = Some(RemoteWriteClient::new(config)?);
+    }
+    Ok(())
+}
+
+async fn send_metrics_via_remote_write(
+    app: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Remote write is only active when a URL was configured
+    let client = match REMOTE_WRITE_CLIENT.lock().as_ref() {
+        Some(client) => client.clone(),
+        None => return Ok(()),
+    };
+    client.send_metrics(&REGISTRY, app).await
+}
+
+// Flush the final snapshot and staleness markers, then stop the Remote Write sender
+pub async fn finish_remote_write(
+    app: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = REMOTE_WRITE_CLIENT.lock().take();
+    match client {
+        Some(client) => client.finish(&REGISTRY, app).await,
+        None => Ok(()),
+    }
+}
+
+// =============================================================================
+// OTLP EXPORT
+// =============================================================================
+
+pub fn init_otlp(args: &Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(endpoint) = &args.otlp_endpoint {
+        let exporter = OtlpExporter::new(OtlpConfig {
+            endpoint: endpoint.clone(),
+            protocol: args.otlp_protocol,
+            service_name: args.app.clone(),
+            labels: args.static_labels(),
+        })?;
+        *OTLP_EXPORTER.lock() = Some(Arc::new(exporter));
+    }
+    Ok(())
+}
+
+async fn send_metrics_via_otlp() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Export is only active when an endpoint was configured
+    let exporter = OTLP_EXPORTER.lock().clone();
+    match exporter {
+        Some(exporter) => {
+            exporter.export(&REGISTRY).await?;
+            exporter.export_spans(&trace::take_spans()).await
+        }
+        None => Ok(()),
+    }
+}
+
+// Export the final values and stop exporting
+pub async fn finish_otlp() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let exporter = OTLP_EXPORTER.lock().take();
+    match exporter {
+        Some(exporter) => {
+            exporter.export(&REGISTRY).await?;
+            exporter.export_spans(&trace::take_spans()).await
+        }
+        None => Ok(()),
+    }
+}
+
+// =============================================================================
+// INFLUXDB OUTPUT
+// =============================================================================
+
+pub fn init_influx(args: &Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let (Some(url), Some(bucket)) = (&args.influx_url, &args.influx_bucket) {
+        let writer = InfluxWriter::new(InfluxConfig {
+            url: url.clone(),
+            bucket: bucket.clone(),
+            org: args.influx_org.clone(),
+            token: args.influx_token.clone(),
+            app: args.app.clone(),
+            labels: args.static_labels(),
+        })?;
+        *INFLUX_WRITER.lock() = Some(Arc::new(writer));
+    }
+    Ok(())
+}
+
+async fn send_metrics_via_influx() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Output is only active when a URL was configured
+    let writer = INFLUX_WRITER.lock().clone();
+    match writer {
+        Some(writer) => writer.write(&REGISTRY).await,
+        None => Ok(()),
+    }
+}
+
+// Write the final values and stop writing
+pub async fn finish_influx() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let writer = INFLUX_WRITER.lock().take();
+    match writer {
+        Some(writer) => writer.write(&REGISTRY).await,
+        None => Ok(()),
+    }
+}
+
+// =============================================================================
+// PUSHGATEWAY OUTPUT
+// =============================================================================
+
+pub fn init_pushgateway(args: &Args) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(url) = &args.pushgateway_url {
+        let mut labels = vec![("app".to_string(), args.app.clone())];
+        labels.extend(args.static_labels());
+        let writer = PushgatewayWriter::new(PushgatewayConfig {
+            url: url.clone(),
+            job: args
+                .pushgateway_job
+                .clone()
+                .unwrap_or_else(|| args.app.clone()),
+            instance: args
+                .pushgateway_instance
+                .clone()
+                .unwrap_or_else(pushgateway::default_instance),
+            labels,
+            keep: args.pushgateway_keep,
+        })?;
+        *PUSHGATEWAY_WRITER.lock() = Some(Arc::new(writer));
+    }
+    Ok(())
+}
+
+async fn send_metrics_via_pushgateway() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Output is only active when a URL was configured
+    let writer = PUSHGATEWAY_WRITER.lock().clone();
+    match writer {
+        Some(writer) => writer.push(&REGISTRY).await,
+        None => Ok(()),
+    }
+}
+
+// Delete the run's group (or push the final values, with --pushgateway-keep) and stop pushing
+pub async fn finish_pushgateway() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let writer = PUSHGATEWAY_WRITER.lock().take();
+    match writer {
+        Some(writer) => writer.finish(&REGISTRY).await,
+        None => Ok(()),
+    }
+}
+
+pub fn init_prometheus() {
+    // Embedded programs may run several tests against the same global registry
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(register_metrics);
+
+    // Initialize test phase
+    TEST_PHASE.with_label_values(&["idle"]).set(1);
+    TEST_PHASE.with_label_values(&["rampup"]).set(0);
+    TEST_PHASE.with_label_values(&["hold"]).set(0);
+    TEST_PHASE.with_label_values(&["rampdown"]).set(0);
+}
+
+// Zero the gauges of what the test is doing right now once it has ended, so the final push
+// and later scrapes don't report load that is no longer there; results such as the
+// percentiles and success rate keep their last values
+fn reset_activity_gauges() {
+    ACTIVE_VUS.set(0);
+    TARGET_VUS.set(0);
+    REQUESTS_PER_SECOND.set(0.0);
+    for direction in ["upload", "download"] {
+        THROUGHPUT.with_label_values(&[direction]).set(0.0);
+    }
+    RATE_LIMIT_ACHIEVED.set(0.0);
+    CONNECTIONS_ESTABLISHED.set(0);
+    SSE_STREAMS.set(0);
+}
+
+fn register_metrics() {
+    REGISTRY
+        .register(Box::new(REQUEST_COUNTER.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(ERRORS_COUNTER.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(RETRIES_COUNTER.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(CHECKS_COUNTER.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(REQUEST_DURATION.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REQUEST_PHASE_DURATION.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(ACTIVE_VUS.clone())).unwrap();
+    REGISTRY.register(Box::new(TARGET_VUS.clone())).unwrap();
+    REGISTRY.register(Box::new(SUCCESS_RATE.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(REQUESTS_PER_SECOND.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RESPONSE_TIME_P50.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RESPONSE_TIME_P90.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RESPONSE_TIME_P95.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RESPONSE_TIME_P99.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(TEST_PHASE.clone())).unwrap();
+    REGISTRY.register(Box::new(STAGE.clone())).unwrap();
+    REGISTRY.register(Box::new(SLO_BURN_RATE.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(SLO_ERROR_BUDGET_REMAINING.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(DROPPED_ITERATIONS.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RATE_LIMIT_CONFIGURED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(RATE_LIMIT_ACHIEVED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(CONNECTIONS_ESTABLISHED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(CONNECTIONS_OPENED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(CONNECTIONS_CLOSED.clone()))
+        .unwrap();
+    REGISTRY.register(Box::new(DATA_SENT.clone())).unwrap();
+    REGISTRY.register(Box::new(DATA_RECEIVED.clone())).unwrap();
+    REGISTRY.register(Box::new(DATA_DECODED.clone())).unwrap();
+    REGISTRY.register(Box::new(THROUGHPUT.clone())).unwrap();
+    REGISTRY.register(Box::new(SSE_STREAMS.clone())).unwrap();
+    REGISTRY.register(Box::new(SSE_EVENTS.clone())).unwrap();
+    REGISTRY
+        .register(Box::new(SSE_TIME_TO_FIRST_EVENT.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(SSE_EVENT_INTERVAL.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REMOTE_WRITE_SAMPLES_SENT.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REMOTE_WRITE_SAMPLES_FAILED.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REMOTE_WRITE_QUEUE_DEPTH.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REMOTE_WRITE_DROPPED_BATCHES.clone()))
+        .unwrap();
+    REGISTRY
+        .register(Box::new(REMOTE_WRITE_RETRIES.clone()))
+        .unwrap();
+}
+
+// Resolves on Ctrl+C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok();
+    }
+}
+
+// =============================================================================
+// OUTPUT FUNCTIONS
+// =============================================================================
+
+// Per-endpoint results from merged statistics
+fn endpoint_summaries(stats: &ShardStats) -> Vec<EndpointSummary> {
+    stats
+        .endpoints
+        .iter()
+        .map(|(endpoint, stats)| EndpointSummary {
+            endpoint: endpoint.clone(),
+            total_requests: stats.total_requests,
+            successful_requests: stats.successful_requests,
+            failed_requests: stats.total_requests - stats.successful_requests,
+            error_rate: if stats.total_requests > 0 {
+                (stats.total_requests - stats.successful_requests) as f64
+                    / stats.total_requests as f64
+            } else {
+                0.0
+            },
+            avg_response_time_ms: stats.histogram.mean() / 1000.0,
+            p50_response_time_ms: stats.histogram.value_at_percentile(50.0) as f64 / 1000.0,
+            p95_response_time_ms: stats.histogram.value_at_percentile(95.0) as f64 / 1000.0,
+            p99_response_time_ms: stats.histogram.value_at_percentile(99.0) as f64 / 1000.0,
+            error_types: stats.error_types.clone(),
+        })
+        .collect()
+}
+
+pub(crate) fn megabits_per_second(bytes: u64, seconds: f64) -> f64 {
+    if seconds > 0.0 {
+        bytes as f64 * 8.0 / seconds / 1_000_000.0
+    } else {
+        0.0
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    const THRESHOLD: f64 = 1024.0;
+
+    if bytes == 0 {
+        return "0 B".to_string();
+    }
+
+    let bytes_f = bytes as f64;
+    let unit_index = (bytes_f.log10() / THRESHOLD.log10()).floor() as usize;
+    let unit_index = unit_index.min(UNITS.len() - 1);
+
+    let size = bytes_f / THRESHOLD.powi(unit_index as i32);
+
+    if size >= 100.0 {
+        format!("{:.0} {}", size, UNITS[unit_index])
+    } else if size >= 10.0 {
+        format!("{:.1} {}", size, UNITS[unit_index])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit_index])
+    }
+}
+
+fn print_endpoints(endpoints: &[EndpointSummary]) {
+    for endpoint in endpoints {
+        println!("{}", endpoint.endpoint);
+        println!(
+            "   {} requests, {:.2}% errors, avg {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+            endpoint.total_requests,
+            endpoint.error_rate * 100.0,
+            endpoint.avg_response_time_ms,
+            endpoint.p50_response_time_ms,
+            endpoint.p95_response_time_ms,
+            endpoint.p99_response_time_ms
+        );
+        if !endpoint.error_types.is_empty() {
+            let errors: Vec<String> = endpoint
+                .error_types
+                .iter()
+                .map(|(error_type, count)| format!("{} {}", error_type.as_str(), count))
+                .collect();
+            println!("   errors: {}", errors.join(", "));
+        }
+    }
+}
+
+pub fn print_results(results: &TestResults) {
+    println!("\n\nLoad Test Results");
+    println!("═══════════════════════════════════════");
+    match results.stop_reason {
+        StopReason::Completed => {}
+        StopReason::MaxDuration => println!("Stopped early:         max duration reached"),
+        StopReason::Interrupted => println!("Stopped early:         interrupted (partial results)"),
+        StopReason::Aborted => println!(
+            "Stopped early:         aborted, {} (partial results)",
+            results.aborted_by.as_deref().unwrap_or("--abort-on")
+        ),
+    }
+    println!("Total Requests:        {}", results.total_requests);
+    println!(
+        "Successful:            {} ({:.2}%)",
+        results.successful_requests,
+        (results.successful_requests as f64 / results.total_requests.max(1) as f64) * 100.0
+    );
+    println!(
+        "Failed:                {} ({:.2}%)",
+        results.failed_requests,
+        (results.failed_requests as f64 / results.total_requests.max(1) as f64) * 100.0
+    );
+    println!("VUs:                   {}", results.vus);
+    if results.dropped_iterations > 0 {
+        println!("Dropped Iterations:    {}", results.dropped_iterations);
+    }
+    if results.retries > 0 {
+        println!(
+            "Retries:               {} ({} recovered)",
+            results.retries, results.recovered_requests
+        );
+    }
+    if results.cut_off_responses > 0 {
+        println!("Cut Off Responses:     {}", results.cut_off_responses);
+    }
+    println!("Requests/sec:          {:.2}", results.requests_per_second);
+    println!(
+        "Test Duration:         {:.2}s",
+        results.test_duration_seconds
+    );
+
+    println!("\nResponse Times (ms)");
+    println!("───────────────────────────────────────");
+    println!("Min:                   {:.2}", results.min_response_time_ms);
+    println!("Max:                   {:.2}", results.max_response_time_ms);
+    println!("Average:               {:.2}", results.avg_response_time_ms);
+    if results.percentiles.is_empty() {
+        println!("P50 (Median):          {:.2}", results.p50_response_time_ms);
+        println!("P90:                   {:.2}", results.p90_response_time_ms);
+        println!("P95:                   {:.2}", results.p95_response_time_ms);
+        println!("P99:                   {:.2}", results.p99_response_time_ms);
+    }
+    for value in &results.percentiles {
+        println!(
+            "{:<23}{:.2}",
+            format!("{}:", percentile_label(value.percentile)),
+            value.ms
+        );
+    }
+
+    // A single class is already covered by the totals above
+    if results.status_classes.len() > 1 {
+        println!("\nResponse Times by Status Class (ms)");
+        println!("───────────────────────────────────────");
+        let mut header = format!("{:<8} {:>9} {:>9}", "Class", "Requests", "Avg");
+        for value in &results.percentiles {
+            header.push_str(&format!(" {:>9}", format!("P{}", value.percentile)));
+        }
+        println!("{}", header);
+        for class in &results.status_classes {
+            let mut line = format!(
+                "{:<8} {:>9} {:>9.2}",
+                class.status_class, class.requests, class.avg_ms
+            );
+            for value in &class.percentiles {
+                line.push_str(&format!(" {:>9.2}", value.ms));
+            }
+            println!("{}", line);
+        }
+    }
+
+    // Includes the requests that stalls kept VUs from sending on schedule
+    let corrected = &results.corrected_percentiles;
+    println!("\nCorrected for Coordinated Omission (ms)");
+    println!("───────────────────────────────────────");
+    println!("P50 (Median):          {:.2}", corrected.p50_ms);
+    println!("P90:                   {:.2}", corrected.p90_ms);
+    println!("P95:                   {:.2}", corrected.p95_ms);
+    println!("P99:                   {:.2}", corrected.p99_ms);
+    println!("Max:                   {:.2}", corrected.max_ms);
+
+    if !results.phases.is_empty() {
+        println!("\nLatency Phases (ms)     Avg      P50      P95      P99");
+        println!("───────────────────────────────────────");
+        for phase in &results.phases {
+            println!(
+                "{:<20} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+                phase.phase, phase.avg_ms, phase.p50_ms, phase.p95_ms, phase.p99_ms
+            );
+        }
+    }
+
+    println!("\nNetwork Transfer");
+    println!("───────────────────────────────────────");
+    println!(
+        "Total Data Sent:       {} (headers {}, bodies {})",
+        format_bytes(results.total_bytes_sent),
+        format_bytes(results.header_bytes_sent),
+        format_bytes(results.body_bytes_sent)
+    );
+    println!(
+        "Total Data Received:   {} (headers {}, bodies {})",
+        format_bytes(results.total_bytes_received),
+        format_bytes(results.header_bytes_received),
+        format_bytes(results.body_bytes_received)
+    );
+    println!(
+        "Total Data Transfer:   {}",
+        format_bytes(results.total_bytes_sent + results.total_bytes_received)
+    );
+    // Only worth a line when some body was compressed
+    if results.decoded_body_bytes_sent != results.body_bytes_sent
+        || results.decoded_body_bytes_received != results.body_bytes_received
+    {
+        println!(
+            "Decoded Bodies:        sent {} ({} on the wire), received {} ({} on the wire)",
+            format_bytes(results.decoded_body_bytes_sent),
+            format_bytes(results.body_bytes_sent),
+            format_bytes(results.decoded_body_bytes_received),
+            format_bytes(results.body_bytes_received)
+        );
+    }
+    println!("Upload Throughput:     {:.2} Mbps", results.upload_mbps);
+
+    if let Some(throughput) = &results.throughput {
+        println!("\nDownload Throughput (MB/s)");
+        println!("───────────────────────────────────────");
+        println!(
+            "Aggregate:             {:.2}",
+            throughput.download_mb_per_sec
+        );
+        println!(
+            "Per Request:           avg {:.2}, p5 {:.2}, p50 {:.2}, p95 {:.2}, max {:.2}",
+            throughput.avg_mb_per_sec,
+            throughput.p5_mb_per_sec,
+            throughput.p50_mb_per_sec,
+            throughput.p95_mb_per_sec,
+            throughput.max_mb_per_sec
+        );
+    }
+    if results.total_requests > 0 {
+        println!(
+            "Avg Sent per Request:  {}",
+            format_bytes(results.total_bytes_sent / results.total_requests as u64)
+        );
+        println!(
+            "Avg Received per Req:  {}",
+            format_bytes(results.total_bytes_received / results.total_requests as u64)
+        );
+    }
+
+    // A single target is already covered by the totals above, and named targets by the
+    // endpoints below
+    let targets_are_endpoints = results
+        .targets
+        .iter()
+        .map(|target| &target.target)
+        .eq(results.endpoints.iter().map(|endpoint| &endpoint.endpoint));
+    if results.targets.len() > 1 && !targets_are_endpoints {
+        println!("\nTargets");
+        println!("───────────────────────────────────────");
+        for target in &results.targets {
+            println!("{}", target.target);
+            println!(
+                "   {} requests, {} failed, avg {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+                target.total_requests,
+                target.failed_requests,
+                target.avg_response_time_ms,
+                target.p95_response_time_ms,
+                target.p99_response_time_ms
+            );
+        }
+    }
+
+    if results.endpoints.len() > 1 {
+        println!("\nEndpoints");
+        println!("───────────────────────────────────────");
+        print_endpoints(&results.endpoints);
+    }
+
+    if !results.scenarios.is_empty() {
+        println!("\nScenarios");
+        println!("───────────────────────────────────────");
+        for scenario in &results.scenarios {
+            match scenario.rps {
+                Some(rps) => println!(
+                    "{} ({} VUs, {} req/s)",
+                    scenario.scenario, scenario.vus, rps
+                ),
+                None => println!("{} ({} VUs)", scenario.scenario, scenario.vus),
+            }
+            println!(
+                "   {} requests, {} failed, {:.2} req/s, avg {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+                scenario.total_requests,
+                scenario.failed_requests,
+                scenario.requests_per_second,
+                scenario.avg_response_time_ms,
+                scenario.p95_response_time_ms,
+                scenario.p99_response_time_ms
+            );
+        }
+    }
+
+    if let Some(tcp) = &results.tcp {
+        println!("\nTCP Connections");
+        println!("───────────────────────────────────────");
+        println!("Connections:           {}", tcp.connections);
+        let latencies = [("Connect", &tcp.connect), ("Round trip", &tcp.round_trip)];
+        for (name, latency) in latencies.iter().filter(|(_, l)| l.count > 0) {
+            println!(
+                "{:<23}avg {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+                format!("{}:", name),
+                latency.avg_ms,
+                latency.p50_ms,
+                latency.p95_ms,
+                latency.p99_ms
+            );
+        }
+    }
+
+    if let Some(sse) = &results.sse {
+        println!("\nServer-Sent Events");
+        println!("───────────────────────────────────────");
+        println!("Streams:               {}", sse.streams);
+        println!("Events:                {}", sse.events);
+        println!("Events/sec:            {:.2}", sse.events_per_second);
+        for (name, latency) in [
+            ("Time to first event", &sse.time_to_first_event),
+            ("Event interval", &sse.event_interval),
+        ] {
+            println!(
+                "{:<23}avg {:.2} ms, p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+                format!("{}:", name),
+                latency.avg_ms,
+                latency.p50_ms,
+                latency.p95_ms,
+                latency.p99_ms
+            );
+        }
+    }
+
+    if !results.vu_init.is_empty() {
+        println!("\nVU Init (not included above)");
+        println!("───────────────────────────────────────");
+        print_endpoints(&results.vu_init);
+    }
+
+    if let Some(autoscale) = &results.autoscale {
+        println!("\nAutoscale ({})", autoscale.slo.join(", "));
+        println!("───────────────────────────────────────");
+        for step in &autoscale.steps {
+            let verdict = if step.passed { "PASS" } else { "FAIL" };
+            println!(
+                "{} {:>8.1} {:<5}  {:8.2} req/s, p95 {:.2} ms, p99 {:.2} ms, {:.2}% errors",
+                verdict,
+                step.load,
+                autoscale.unit.to_string(),
+                step.rps,
+                step.p95_response_time_ms,
+                step.p99_response_time_ms,
+                step.error_rate
+            );
+        }
+        match (
+            autoscale.max_sustainable_load,
+            autoscale.max_sustainable_rps,
+        ) {
+            (Some(load), Some(rps)) => println!(
+                "Max sustainable load:  {} {} ({:.2} req/s)",
+                load, autoscale.unit, rps
+            ),
+            _ => println!("Max sustainable load:  none, no step met the SLO"),
+        }
+    }
+
+    if !results.checks.is_empty() {
+        println!("\nChecks");
+        println!("───────────────────────────────────────");
+        for check in &results.checks {
+            let mark = if check.failed == 0 { "✓" } else { "✗" };
+            println!(
+                "{} {} ({} passed, {} failed)",
+                mark, check.name, check.passed, check.failed
+            );
+        }
+    }
+
+    if !results.thresholds.is_empty() {
+        println!("\nThresholds");
+        println!("───────────────────────────────────────");
+        for threshold in &results.thresholds {
+            let verdict = if threshold.passed { "PASS" } else { "FAIL" };
+            println!(
+                "{} {} (actual: {:.2})",
+                verdict, threshold.threshold, threshold.actual
+            );
+        }
+    }
+
+    if !results.slos.is_empty() {
+        println!("\nSLOs");
+        println!("───────────────────────────────────────");
+        for slo in &results.slos {
+            let verdict = if slo.met { "MET" } else { "MISSED" };
+            println!("{} {}", verdict, slo.slo);
+            println!(
+                "   SLI: {:.3}% of {} requests (objective {}%)",
+                slo.sli, slo.requests, slo.objective
+            );
+            println!(
+                "   Error budget: {:.1}% consumed ({} bad requests, {:.1} allowed), burn rate {:.2}x",
+                slo.budget_consumed, slo.bad_requests, slo.allowed_bad_requests, slo.burn_rate
+            );
+        }
+    }
+
+    if !results.status_code_distribution.is_empty() {
+        println!("\nStatus Code Distribution");
+        println!("───────────────────────────────────────");
+        let mut codes: Vec<_> = results.status_code_distribution.iter().collect();
+        codes.sort_by_key(|&(code, _)| code);
+        for (code, count) in codes {
+            let percentage = (*count as f64 / results.total_requests.max(1) as f64) * 100.0;
+            println!("{:3}: {:6} ({:5.2}%)", code, count, percentage);
+        }
+    }
+
+    if !results.error_types.is_empty() {
+        println!("\nErrors");
+        println!("───────────────────────────────────────");
+        for (error_type, count) in &results.error_types {
+            let percentage = (*count as f64 / results.total_requests.max(1) as f64) * 100.0;
+            println!(
+                "{:<20} {:6} ({:5.2}%)",
+                error_type.as_str(),
+                count,
+                percentage
+            );
+        }
+    }
+    println!("═══════════════════════════════════════");
+}
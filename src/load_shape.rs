@@ -0,0 +1,289 @@
+//! Load shapes: the VU target over time, followed by the shape executor
+//
+// --stage lists are one shape; --profile generates the others (spike, steps and sine)
+// from a few parameters. The executor only asks a shape for its target at a point in time,
+// so adding a shape does not touch the ramp logic.
+
+use crate::stages::{self, Stage};
+use humantime::{format_duration, parse_duration};
+use std::f64::consts::PI;
+use std::fmt;
+use std::time::Duration;
+
+pub trait LoadShape: Send + Sync {
+    // Total run time
+    fn duration(&self) -> Duration;
+
+    // Index of the segment (stage, step, ...) running at `elapsed` and the VU target,
+    // or None once the shape has finished
+    fn target_at(&self, elapsed: Duration) -> Option<(usize, f64)>;
+
+    fn segments(&self) -> usize;
+
+    // Highest VU target the shape reaches
+    fn peak_vus(&self) -> usize;
+}
+
+pub struct Stages(pub Vec<Stage>);
+
+impl LoadShape for Stages {
+    fn duration(&self) -> Duration {
+        stages::total_duration(&self.0)
+    }
+
+    fn target_at(&self, elapsed: Duration) -> Option<(usize, f64)> {
+        stages::target_at(&self.0, elapsed)
+    }
+
+    fn segments(&self) -> usize {
+        self.0.len()
+    }
+
+    fn peak_vus(&self) -> usize {
+        self.0.iter().map(|stage| stage.target).max().unwrap_or(0)
+    }
+}
+
+// A --profile generator
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    // "spike:BASE:PEAK:LENGTH": BASE VUs, then PEAK VUs for LENGTH in the middle of --hold,
+    // then BASE again to watch the recovery
+    Spike {
+        base: usize,
+        peak: usize,
+        length: Duration,
+    },
+    // "steps:SIZExLENGTH": SIZE more VUs every LENGTH until --vus is reached
+    Steps {
+        size: usize,
+        length: Duration,
+    },
+    // "sine:period=10m,min=10,max=200": oscillates between min and max VUs over --hold,
+    // starting at min
+    Sine {
+        period: Duration,
+        min: usize,
+        max: usize,
+    },
+}
+
+impl Profile {
+    // Used as the clap value parser for --profile
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, params) = spec.trim().split_once(':').ok_or_else(|| {
+            format!(
+                "profile must be spike:BASE:PEAK:LENGTH, steps:SIZExLENGTH or sine:period=P,min=N,max=N: {}",
+                spec
+            )
+        })?;
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "spike" => {
+                let parts: Vec<&str> = params.split(':').collect();
+                let [base, peak, length] = parts[..] else {
+                    return Err(format!(
+                        "spike profile must be spike:BASE:PEAK:LENGTH: {}",
+                        spec
+                    ));
+                };
+                Ok(Self::Spike {
+                    base: parse_vus(base)?,
+                    peak: parse_vus(peak)?,
+                    length: parse_length(length)?,
+                })
+            }
+            "steps" => {
+                let (size, length) = params
+                    .split_once('x')
+                    .ok_or_else(|| format!("steps profile must be steps:SIZExLENGTH: {}", spec))?;
+                let size = parse_vus(size)?;
+                let length = parse_length(length)?;
+                if size == 0 || length.is_zero() {
+                    return Err(format!(
+                        "steps profile needs a non-zero size and length: {}",
+                        spec
+                    ));
+                }
+                Ok(Self::Steps { size, length })
+            }
+            "sine" => {
+                let (mut period, mut min, mut max) = (None, None, None);
+                for param in params.split(',') {
+                    let (name, value) = param
+                        .split_once('=')
+                        .ok_or_else(|| format!("sine parameter must be NAME=VALUE: {}", param))?;
+                    match name.trim() {
+                        "period" => period = Some(parse_length(value)?),
+                        "min" => min = Some(parse_vus(value)?),
+                        "max" => max = Some(parse_vus(value)?),
+                        other => return Err(format!("unknown sine parameter: {}", other)),
+                    }
+                }
+                let (Some(period), Some(min), Some(max)) = (period, min, max) else {
+                    return Err(format!("sine profile needs period, min and max: {}", spec));
+                };
+                if period.is_zero() || min > max {
+                    return Err(format!(
+                        "sine profile needs a non-zero period and min <= max: {}",
+                        spec
+                    ));
+                }
+                Ok(Self::Sine { period, min, max })
+            }
+            other => Err(format!("unknown load profile: {}", other)),
+        }
+    }
+
+    // Spike and sine profiles last `hold`; steps climb to `vus`
+    pub fn shape(&self, vus: usize, hold: Duration) -> Box<dyn LoadShape> {
+        match *self {
+            Self::Spike { base, peak, length } => Box::new(Spike {
+                base,
+                peak,
+                length: length.min(hold),
+                duration: hold,
+            }),
+            Self::Steps { size, length } => Box::new(Steps {
+                size,
+                length,
+                max: vus,
+            }),
+            Self::Sine { period, min, max } => Box::new(Sine {
+                period,
+                min,
+                max,
+                duration: hold,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Spike { base, peak, length } => write!(
+                f,
+                "spike from {} to {} VUs for {}",
+                base,
+                peak,
+                format_duration(length)
+            ),
+            Self::Steps { size, length } => {
+                write!(f, "steps of {} VUs every {}", size, format_duration(length))
+            }
+            Self::Sine { period, min, max } => write!(
+                f,
+                "sine between {} and {} VUs, period {}",
+                min,
+                max,
+                format_duration(period)
+            ),
+        }
+    }
+}
+
+struct Spike {
+    base: usize,
+    peak: usize,
+    length: Duration,
+    duration: Duration,
+}
+
+impl LoadShape for Spike {
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn target_at(&self, elapsed: Duration) -> Option<(usize, f64)> {
+        let spike_start = (self.duration - self.length) / 2;
+        if elapsed >= self.duration {
+            None
+        } else if elapsed < spike_start {
+            Some((0, self.base as f64))
+        } else if elapsed < spike_start + self.length {
+            Some((1, self.peak as f64))
+        } else {
+            Some((2, self.base as f64))
+        }
+    }
+
+    fn segments(&self) -> usize {
+        3
+    }
+
+    fn peak_vus(&self) -> usize {
+        self.base.max(self.peak)
+    }
+}
+
+struct Steps {
+    size: usize,
+    length: Duration,
+    max: usize,
+}
+
+impl LoadShape for Steps {
+    fn duration(&self) -> Duration {
+        self.length * self.segments() as u32
+    }
+
+    fn target_at(&self, elapsed: Duration) -> Option<(usize, f64)> {
+        let step = (elapsed.as_secs_f64() / self.length.as_secs_f64()) as usize;
+        (step < self.segments()).then(|| (step, ((step + 1) * self.size).min(self.max) as f64))
+    }
+
+    fn segments(&self) -> usize {
+        self.max.div_ceil(self.size).max(1)
+    }
+
+    fn peak_vus(&self) -> usize {
+        self.max
+    }
+}
+
+struct Sine {
+    period: Duration,
+    min: usize,
+    max: usize,
+    duration: Duration,
+}
+
+impl LoadShape for Sine {
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    // Each period is one segment
+    fn target_at(&self, elapsed: Duration) -> Option<(usize, f64)> {
+        if elapsed >= self.duration {
+            return None;
+        }
+        let cycles = elapsed.as_secs_f64() / self.period.as_secs_f64();
+        let amplitude = (self.max - self.min) as f64;
+        let target = self.min as f64 + amplitude * (1.0 - (2.0 * PI * cycles).cos()) / 2.0;
+        Some((cycles as usize, target))
+    }
+
+    fn segments(&self) -> usize {
+        (self.duration.as_secs_f64() / self.period.as_secs_f64()).ceil() as usize
+    }
+
+    fn peak_vus(&self) -> usize {
+        self.max
+    }
+}
+
+fn parse_vus(text: &str) -> Result<usize, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("invalid VU count: {}", text))
+}
+
+// A duration such as 30s or 5m; a bare number is seconds
+fn parse_length(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    match text.parse::<u64>() {
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(_) => parse_duration(text).map_err(|e| format!("invalid duration {}: {}", text, e)),
+    }
+}
@@ -0,0 +1,342 @@
+//! `--log-level`, `--log-format` and `--log-file`: diagnostics as `tracing` events
+//
+// Failures that happen while a test runs (metric exports, checkpoints, token refreshes,
+// scripts, failed requests at debug level) are `tracing` events, so long unattended runs keep
+// them in a file and in a form that can be grepped or parsed. The results and the progress
+// are still printed, and so are the errors that stop forgy before a test starts. Library users
+// get the same events with whichever subscriber they install; this one is forgy's own, a
+// small stand-in for tracing-subscriber's fmt layer. Events carry the spans they happened in,
+// e.g. `load_test` and `vu{vu=3}`.
+
+use chrono::{SecondsFormat, Utc};
+use parking_lot::Mutex;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Ordered from least to most verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn level(self) -> Level {
+        match self {
+            Self::Error => Level::ERROR,
+            Self::Warn => Level::WARN,
+            Self::Info => Level::INFO,
+            Self::Debug => Level::DEBUG,
+            Self::Trace => Level::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line
+    Json,
+}
+
+// Installs the logger for the whole process; events go to stderr, or are appended to `file`
+pub fn init(level: LogLevel, format: LogFormat, file: Option<&str>) -> Result<(), BoxError> {
+    let output: Box<dyn Write + Send> = match file {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("cannot open log file {}: {}", path, e))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    let logger = Logger {
+        level: level.level(),
+        format,
+        output: Mutex::new(output),
+        spans: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+    };
+    tracing::subscriber::set_global_default(logger)?;
+    Ok(())
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Vec<(&'static str, Value)>,
+    // A span keeps its parent open, so the chain can always be followed to the root
+    parent: Option<u64>,
+    references: usize,
+}
+
+// The innermost span entered on this thread
+fn current_span() -> Option<u64> {
+    ENTERED.with(|entered| entered.borrow().last().copied())
+}
+
+thread_local! {
+    // Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Logger {
+    level: Level,
+    format: LogFormat,
+    output: Mutex<Box<dyn Write + Send>>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+// Collects the fields of a span or an event as JSON values
+#[derive(Default)]
+struct Fields(Vec<(&'static str, Value)>);
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .push((field.name(), Value::from(format!("{:?}", value))));
+    }
+}
+
+// Strings without their quotes, as in "key=value"
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Logger {
+    fn format_text(&self, event: &Event<'_>, fields: Fields, spans: &[(&str, Value)]) -> String {
+        let metadata = event.metadata();
+        let mut line = format!(
+            "{} {:>5} ",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            metadata.level()
+        );
+        for (name, span_fields) in spans {
+            line.push_str(name);
+            if let Some(object) = span_fields.as_object().filter(|object| !object.is_empty()) {
+                let pairs: Vec<String> = object
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, text(value)))
+                    .collect();
+                let _ = write!(line, "{{{}}}", pairs.join(" "));
+            }
+            line.push_str(": ");
+        }
+        let _ = write!(line, "{}:", metadata.target());
+        for (name, value) in &fields.0 {
+            if *name == "message" {
+                let _ = write!(line, " {}", text(value));
+            }
+        }
+        for (name, value) in fields.0.iter().filter(|(name, _)| *name != "message") {
+            let _ = write!(line, " {}={}", name, text(value));
+        }
+        line
+    }
+
+    fn format_json(&self, event: &Event<'_>, fields: Fields, spans: Vec<(&str, Value)>) -> String {
+        let metadata = event.metadata();
+        let mut object = Map::new();
+        object.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        object.insert("level".into(), metadata.level().as_str().into());
+        object.insert("target".into(), metadata.target().into());
+        let mut event_fields = Map::new();
+        for (name, value) in fields.0 {
+            if name == "message" {
+                object.insert("message".into(), value);
+            } else {
+                event_fields.insert(name.into(), value);
+            }
+        }
+        if !event_fields.is_empty() {
+            object.insert("fields".into(), Value::Object(event_fields));
+        }
+        if !spans.is_empty() {
+            let spans = spans
+                .into_iter()
+                .map(|(name, fields)| {
+                    let mut span = Map::new();
+                    span.insert("name".into(), name.into());
+                    if let Value::Object(fields) = fields {
+                        span.extend(fields);
+                    }
+                    Value::Object(span)
+                })
+                .collect();
+            object.insert("spans".into(), Value::Array(spans));
+        }
+        Value::Object(object).to_string()
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // More verbose levels compare greater. The HTTP libraries' own debug output would bury
+        // forgy's, so they only get to warn
+        let level = if metadata.target().starts_with("forgy") {
+            self.level
+        } else {
+            self.level.min(Level::WARN)
+        };
+        *metadata.level() <= level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attributes.record(&mut fields);
+        let parent = if attributes.is_contextual() {
+            current_span()
+        } else {
+            attributes.parent().map(Id::into_u64)
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut spans = self.spans.lock();
+        let parent = parent.filter(|parent| match spans.get_mut(parent) {
+            Some(data) => {
+                data.references += 1;
+                true
+            }
+            None => false,
+        });
+        spans.insert(
+            id,
+            SpanData {
+                name: attributes.metadata().name(),
+                fields: fields.0,
+                parent,
+                references: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self.spans.lock().get_mut(&span.into_u64()) {
+            data.fields.extend(fields.0);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let mut next = if event.is_contextual() {
+            current_span()
+        } else {
+            event.parent().map(Id::into_u64)
+        };
+        // Outermost first
+        let mut spans: Vec<(&str, Value)> = Vec::new();
+        {
+            let known = self.spans.lock();
+            while let Some(data) = next.and_then(|id| known.get(&id)) {
+                let fields = data
+                    .fields
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect();
+                spans.push((data.name, Value::Object(fields)));
+                next = data.parent;
+            }
+        }
+        spans.reverse();
+        let line = match self.format {
+            LogFormat::Text => self.format_text(event, fields, &spans),
+            LogFormat::Json => self.format_json(event, fields, spans),
+        };
+        // Flushed line by line, so nothing is lost if the run is killed
+        let mut output = self.output.lock();
+        let _ = writeln!(output, "{}", line);
+        let _ = output.flush();
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|entered| *entered == id) {
+                entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock();
+        let mut next = Some(span.into_u64());
+        let mut closed = false;
+        // Closing a span releases its parent
+        while let Some(id) = next.take() {
+            let Some(data) = spans.get_mut(&id) else {
+                break;
+            };
+            data.references -= 1;
+            if data.references > 0 {
+                break;
+            }
+            next = spans.remove(&id).and_then(|data| data.parent);
+            closed = true;
+        }
+        closed
+    }
+}
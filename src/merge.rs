@@ -0,0 +1,636 @@
+//! Combining the result files of runs made in parallel, for `forgy merge`
+//
+// Counts and distributions are summed. Percentiles can only be recombined exactly from the
+// histograms serialized into each result file; files without them (written by older
+// versions) fall back to request-weighted averages of the reported percentiles.
+
+use crate::checks::CheckSummary;
+use crate::slo::{Slo, SloSummary};
+use crate::sse::{self, SseSummary};
+use crate::stats::ShardStats;
+use crate::tcp::{self, TcpSummary};
+use crate::thresholds::Threshold;
+use crate::timeseries::TimeseriesPoint;
+use crate::{
+    percentile_values, EndpointSummary, LatencyPercentiles, PercentileValue, PhaseSummary,
+    ScenarioSummary, StatusClassSummary, StopReason, TargetSummary, TestResults,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hdrhistogram::serialization::{Deserializer, Serializer, V2DeflateSerializer};
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Response time histograms in microseconds, each as base64 of the compressed HdrHistogram V2
+// encoding
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodedHistograms {
+    pub response_time: String,
+    pub corrected: String,
+    pub targets: BTreeMap<String, String>,
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, String>,
+    pub phases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub status_classes: BTreeMap<String, String>,
+}
+
+impl EncodedHistograms {
+    pub(crate) fn from_stats(stats: &ShardStats) -> Result<Self, BoxError> {
+        Ok(Self {
+            response_time: encode(&stats.histogram)?,
+            corrected: encode(&stats.corrected_histogram)?,
+            targets: stats
+                .targets
+                .iter()
+                .map(|(target, stats)| Ok((target.clone(), encode(&stats.histogram)?)))
+                .collect::<Result<_, BoxError>>()?,
+            endpoints: stats
+                .endpoints
+                .iter()
+                .map(|(endpoint, stats)| Ok((endpoint.clone(), encode(&stats.histogram)?)))
+                .collect::<Result<_, BoxError>>()?,
+            phases: stats
+                .phases
+                .iter()
+                .filter(|(_, histogram)| !histogram.is_empty())
+                .map(|(phase, histogram)| Ok((phase.to_string(), encode(histogram)?)))
+                .collect::<Result<_, BoxError>>()?,
+            status_classes: stats
+                .status_classes
+                .iter()
+                .map(|(class, histogram)| Ok((class.to_string(), encode(histogram)?)))
+                .collect::<Result<_, BoxError>>()?,
+        })
+    }
+}
+
+pub(crate) fn encode(histogram: &Histogram<u64>) -> Result<String, BoxError> {
+    let mut bytes = Vec::new();
+    V2DeflateSerializer::new()
+        .serialize(histogram, &mut bytes)
+        .map_err(|e| format!("failed to serialize histogram: {:?}", e))?;
+    Ok(BASE64.encode(bytes))
+}
+
+pub(crate) fn decode(encoded: &str) -> Result<Histogram<u64>, BoxError> {
+    let bytes = BASE64.decode(encoded)?;
+    let histogram: Histogram<u64> = Deserializer::new()
+        .deserialize(&mut bytes.as_slice())
+        .map_err(|e| format!("invalid histogram: {:?}", e))?;
+    Ok(histogram)
+}
+
+// Sum of decoded histograms; auto-resizing, so inputs of any range fit
+pub(crate) fn combine<'a>(
+    encoded: impl Iterator<Item = &'a String>,
+) -> Result<Histogram<u64>, BoxError> {
+    let mut combined = Histogram::<u64>::new(3)?;
+    for histogram in encoded {
+        combined.add(decode(histogram)?)?;
+    }
+    Ok(combined)
+}
+
+// Results of runs that executed at the same time, reported as one run: throughput and VUs
+// add up, and the duration is that of the longest run
+pub fn merge(runs: Vec<TestResults>) -> Result<TestResults, BoxError> {
+    if runs.is_empty() {
+        return Err("nothing to merge".into());
+    }
+    let exact = runs.iter().all(|run| run.histograms.is_some());
+    let total_requests: usize = runs.iter().map(|run| run.total_requests).sum();
+    let weighted = |value: &dyn Fn(&TestResults) -> f64| weighted_mean(&runs, value);
+
+    let (histograms, percentiles, corrected_percentiles) = if exact {
+        let encoded: Vec<&EncodedHistograms> = runs
+            .iter()
+            .filter_map(|run| run.histograms.as_ref())
+            .collect();
+        let response_time = combine(encoded.iter().map(|h| &h.response_time))?;
+        let corrected = combine(encoded.iter().map(|h| &h.corrected))?;
+        let mut merged = EncodedHistograms {
+            response_time: encode(&response_time)?,
+            corrected: encode(&corrected)?,
+            ..Default::default()
+        };
+        for histograms in &encoded {
+            for (target, _) in histograms.targets.iter() {
+                if !merged.targets.contains_key(target) {
+                    let combined = combine(encoded.iter().filter_map(|h| h.targets.get(target)))?;
+                    merged.targets.insert(target.clone(), encode(&combined)?);
+                }
+            }
+            for (endpoint, _) in histograms.endpoints.iter() {
+                if !merged.endpoints.contains_key(endpoint) {
+                    let combined =
+                        combine(encoded.iter().filter_map(|h| h.endpoints.get(endpoint)))?;
+                    merged
+                        .endpoints
+                        .insert(endpoint.clone(), encode(&combined)?);
+                }
+            }
+            for (phase, _) in histograms.phases.iter() {
+                if !merged.phases.contains_key(phase) {
+                    let combined = combine(encoded.iter().filter_map(|h| h.phases.get(phase)))?;
+                    merged.phases.insert(phase.clone(), encode(&combined)?);
+                }
+            }
+            for (class, _) in histograms.status_classes.iter() {
+                if !merged.status_classes.contains_key(class) {
+                    let combined =
+                        combine(encoded.iter().filter_map(|h| h.status_classes.get(class)))?;
+                    merged
+                        .status_classes
+                        .insert(class.clone(), encode(&combined)?);
+                }
+            }
+        }
+        (
+            Some(merged),
+            LatencyPercentiles::from_histogram(&response_time),
+            LatencyPercentiles::from_histogram(&corrected),
+        )
+    } else {
+        (
+            None,
+            LatencyPercentiles {
+                p50_ms: weighted(&|run| run.p50_response_time_ms),
+                p90_ms: weighted(&|run| run.p90_response_time_ms),
+                p95_ms: weighted(&|run| run.p95_response_time_ms),
+                p99_ms: weighted(&|run| run.p99_response_time_ms),
+                max_ms: 0.0,
+            },
+            LatencyPercentiles {
+                p50_ms: weighted(&|run| run.corrected_percentiles.p50_ms),
+                p90_ms: weighted(&|run| run.corrected_percentiles.p90_ms),
+                p95_ms: weighted(&|run| run.corrected_percentiles.p95_ms),
+                p99_ms: weighted(&|run| run.corrected_percentiles.p99_ms),
+                max_ms: runs
+                    .iter()
+                    .map(|run| run.corrected_percentiles.max_ms)
+                    .fold(0.0, f64::max),
+            },
+        )
+    };
+
+    let (percentile_list, status_classes) = merge_percentile_list(&runs, histograms.as_ref())?;
+
+    let mut status_code_distribution = HashMap::new();
+    let mut error_types = BTreeMap::new();
+    let mut checks: BTreeMap<String, CheckSummary> = BTreeMap::new();
+    for run in &runs {
+        for (code, count) in &run.status_code_distribution {
+            *status_code_distribution.entry(*code).or_insert(0) += count;
+        }
+        for (error_type, count) in &run.error_types {
+            *error_types.entry(*error_type).or_insert(0) += count;
+        }
+        for check in &run.checks {
+            let merged = checks.entry(check.name.clone()).or_insert(CheckSummary {
+                name: check.name.clone(),
+                ..Default::default()
+            });
+            merged.passed += check.passed;
+            merged.failed += check.failed;
+        }
+    }
+
+    let mut merged = TestResults {
+        total_requests,
+        successful_requests: runs.iter().map(|run| run.successful_requests).sum(),
+        failed_requests: runs.iter().map(|run| run.failed_requests).sum(),
+        vus: runs.iter().map(|run| run.vus).sum(),
+        avg_response_time_ms: weighted(&|run| run.avg_response_time_ms),
+        min_response_time_ms: runs
+            .iter()
+            .filter(|run| run.total_requests > 0)
+            .map(|run| run.min_response_time_ms)
+            .fold(f64::INFINITY, f64::min),
+        max_response_time_ms: runs
+            .iter()
+            .map(|run| run.max_response_time_ms)
+            .fold(0.0, f64::max),
+        p50_response_time_ms: percentiles.p50_ms,
+        p90_response_time_ms: percentiles.p90_ms,
+        p95_response_time_ms: percentiles.p95_ms,
+        p99_response_time_ms: percentiles.p99_ms,
+        percentiles: percentile_list,
+        status_classes,
+        corrected_percentiles,
+        requests_per_second: runs.iter().map(|run| run.requests_per_second).sum(),
+        test_duration_seconds: runs
+            .iter()
+            .map(|run| run.test_duration_seconds)
+            .fold(0.0, f64::max),
+        status_code_distribution,
+        error_types,
+        targets: merge_targets(&runs, histograms.as_ref())?,
+        endpoints: merge_endpoints(&runs, histograms.as_ref())?,
+        scenarios: merge_scenarios(&runs),
+        phases: merge_phases(&runs, histograms.as_ref())?,
+        timeseries: merge_timeseries(&runs),
+        // Interval percentiles cannot be combined without the interval histograms
+        heatmap: Vec::new(),
+        autoscale: None,
+        vu_init: Vec::new(),
+        total_bytes_sent: runs.iter().map(|run| run.total_bytes_sent).sum(),
+        total_bytes_received: runs.iter().map(|run| run.total_bytes_received).sum(),
+        // Runs made in parallel upload at the sum of their rates
+        upload_mbps: runs.iter().map(|run| run.upload_mbps).sum(),
+        // Per-request rates are not part of the encoded histograms
+        throughput: None,
+        header_bytes_sent: runs.iter().map(|run| run.header_bytes_sent).sum(),
+        body_bytes_sent: runs.iter().map(|run| run.body_bytes_sent).sum(),
+        header_bytes_received: runs.iter().map(|run| run.header_bytes_received).sum(),
+        body_bytes_received: runs.iter().map(|run| run.body_bytes_received).sum(),
+        decoded_body_bytes_sent: runs.iter().map(|run| run.decoded_body_bytes_sent).sum(),
+        decoded_body_bytes_received: runs.iter().map(|run| run.decoded_body_bytes_received).sum(),
+        dropped_iterations: runs.iter().map(|run| run.dropped_iterations).sum(),
+        retries: runs.iter().map(|run| run.retries).sum(),
+        recovered_requests: runs.iter().map(|run| run.recovered_requests).sum(),
+        cut_off_responses: runs.iter().map(|run| run.cut_off_responses).sum(),
+        checks: checks.into_values().collect(),
+        thresholds: Vec::new(),
+        slos: Vec::new(),
+        sse: None,
+        tcp: None,
+        // A run that ended early makes the combined run incomplete too
+        stop_reason: runs
+            .iter()
+            .map(|run| run.stop_reason)
+            .find(|reason| *reason != StopReason::Completed)
+            .unwrap_or(StopReason::Completed),
+        aborted_by: runs.iter().find_map(|run| run.aborted_by.clone()),
+        samples: runs.iter().flat_map(|run| run.samples.clone()).collect(),
+        histograms,
+    };
+    if !merged.min_response_time_ms.is_finite() {
+        merged.min_response_time_ms = 0.0;
+    }
+
+    // Re-evaluate every threshold any of the runs had against the combined results
+    let mut expressions: Vec<&str> = Vec::new();
+    for run in &runs {
+        for threshold in &run.thresholds {
+            if !expressions.contains(&threshold.threshold.as_str()) {
+                expressions.push(&threshold.threshold);
+            }
+        }
+    }
+    merged.thresholds = expressions
+        .iter()
+        .map(|expression| Ok(Threshold::parse(expression)?.evaluate(&merged)))
+        .collect::<Result<_, BoxError>>()?;
+    merged.slos = merge_slos(&runs, &merged)?;
+    let sse: Vec<&SseSummary> = runs.iter().filter_map(|run| run.sse.as_ref()).collect();
+    if !sse.is_empty() {
+        merged.sse = Some(sse::merge(&sse, merged.test_duration_seconds)?);
+    }
+    let tcp: Vec<&TcpSummary> = runs.iter().filter_map(|run| run.tcp.as_ref()).collect();
+    if !tcp.is_empty() {
+        merged.tcp = Some(tcp::merge(&tcp)?);
+    }
+
+    Ok(merged)
+}
+
+// Every SLO any of the runs had, evaluated against the combined results; latency SLOs need
+// the histograms
+fn merge_slos(runs: &[TestResults], merged: &TestResults) -> Result<Vec<SloSummary>, BoxError> {
+    let histogram = match &merged.histograms {
+        Some(histograms) => Some(decode(&histograms.response_time)?),
+        None => None,
+    };
+    let empty = Histogram::new(3)?;
+    let mut expressions: Vec<&str> = Vec::new();
+    for run in runs {
+        for slo in &run.slos {
+            if !expressions.contains(&slo.slo.as_str()) {
+                expressions.push(&slo.slo);
+            }
+        }
+    }
+    let mut slos = Vec::new();
+    for expression in expressions {
+        let slo = Slo::parse(expression)?;
+        if slo.needs_histogram() && histogram.is_none() {
+            continue;
+        }
+        slos.push(slo.evaluate(
+            histogram.as_ref().unwrap_or(&empty),
+            merged.total_requests as u64,
+            merged.failed_requests as u64,
+        ));
+    }
+    Ok(slos)
+}
+
+// The --percentiles of the first run, overall and per status class; without histograms
+// there is nothing to compute them from
+fn merge_percentile_list(
+    runs: &[TestResults],
+    histograms: Option<&EncodedHistograms>,
+) -> Result<(Vec<PercentileValue>, Vec<StatusClassSummary>), BoxError> {
+    let Some(histograms) = histograms else {
+        return Ok((Vec::new(), Vec::new()));
+    };
+    let percentiles: Vec<f64> = runs[0]
+        .percentiles
+        .iter()
+        .map(|value| value.percentile)
+        .collect();
+    let status_classes = histograms
+        .status_classes
+        .iter()
+        .map(|(class, encoded)| {
+            Ok(StatusClassSummary::new(
+                class,
+                &decode(encoded)?,
+                &percentiles,
+            ))
+        })
+        .collect::<Result<_, BoxError>>()?;
+    Ok((
+        percentile_values(&decode(&histograms.response_time)?, &percentiles),
+        status_classes,
+    ))
+}
+
+fn weighted_mean(runs: &[TestResults], value: &dyn Fn(&TestResults) -> f64) -> f64 {
+    let total: usize = runs.iter().map(|run| run.total_requests).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    runs.iter()
+        .map(|run| value(run) * run.total_requests as f64)
+        .sum::<f64>()
+        / total as f64
+}
+
+fn merge_targets(
+    runs: &[TestResults],
+    histograms: Option<&EncodedHistograms>,
+) -> Result<Vec<TargetSummary>, BoxError> {
+    let mut by_target: BTreeMap<&str, Vec<&TargetSummary>> = BTreeMap::new();
+    for run in runs {
+        for target in &run.targets {
+            by_target.entry(&target.target).or_default().push(target);
+        }
+    }
+
+    by_target
+        .into_iter()
+        .map(|(name, summaries)| {
+            let total_requests: usize = summaries.iter().map(|s| s.total_requests).sum();
+            let successful_requests: usize = summaries.iter().map(|s| s.successful_requests).sum();
+            let weighted = |value: fn(&TargetSummary) -> f64| {
+                let sum: f64 = summaries
+                    .iter()
+                    .map(|s| value(s) * s.total_requests as f64)
+                    .sum();
+                if total_requests == 0 {
+                    0.0
+                } else {
+                    sum / total_requests as f64
+                }
+            };
+            let (avg, p95, p99) = match histograms.and_then(|h| h.targets.get(name)) {
+                Some(encoded) => {
+                    let histogram = decode(encoded)?;
+                    (
+                        histogram.mean() / 1000.0,
+                        histogram.value_at_percentile(95.0) as f64 / 1000.0,
+                        histogram.value_at_percentile(99.0) as f64 / 1000.0,
+                    )
+                }
+                None => (
+                    weighted(|s| s.avg_response_time_ms),
+                    weighted(|s| s.p95_response_time_ms),
+                    weighted(|s| s.p99_response_time_ms),
+                ),
+            };
+            Ok(TargetSummary {
+                target: name.to_string(),
+                total_requests,
+                successful_requests,
+                failed_requests: total_requests - successful_requests,
+                avg_response_time_ms: avg,
+                p95_response_time_ms: p95,
+                p99_response_time_ms: p99,
+            })
+        })
+        .collect()
+}
+
+fn merge_endpoints(
+    runs: &[TestResults],
+    histograms: Option<&EncodedHistograms>,
+) -> Result<Vec<EndpointSummary>, BoxError> {
+    let mut by_endpoint: BTreeMap<&str, Vec<&EndpointSummary>> = BTreeMap::new();
+    for run in runs {
+        for endpoint in &run.endpoints {
+            by_endpoint
+                .entry(&endpoint.endpoint)
+                .or_default()
+                .push(endpoint);
+        }
+    }
+
+    by_endpoint
+        .into_iter()
+        .map(|(name, summaries)| {
+            let total_requests: usize = summaries.iter().map(|s| s.total_requests).sum();
+            let successful_requests: usize = summaries.iter().map(|s| s.successful_requests).sum();
+            let mut error_types = BTreeMap::new();
+            for summary in &summaries {
+                for (error_type, count) in &summary.error_types {
+                    *error_types.entry(*error_type).or_insert(0) += count;
+                }
+            }
+            let weighted = |value: fn(&EndpointSummary) -> f64| {
+                let sum: f64 = summaries
+                    .iter()
+                    .map(|s| value(s) * s.total_requests as f64)
+                    .sum();
+                if total_requests == 0 {
+                    0.0
+                } else {
+                    sum / total_requests as f64
+                }
+            };
+            let (avg, p50, p95, p99) = match histograms.and_then(|h| h.endpoints.get(name)) {
+                Some(encoded) => {
+                    let histogram = decode(encoded)?;
+                    (
+                        histogram.mean() / 1000.0,
+                        histogram.value_at_percentile(50.0) as f64 / 1000.0,
+                        histogram.value_at_percentile(95.0) as f64 / 1000.0,
+                        histogram.value_at_percentile(99.0) as f64 / 1000.0,
+                    )
+                }
+                None => (
+                    weighted(|s| s.avg_response_time_ms),
+                    weighted(|s| s.p50_response_time_ms),
+                    weighted(|s| s.p95_response_time_ms),
+                    weighted(|s| s.p99_response_time_ms),
+                ),
+            };
+            let failed_requests = total_requests - successful_requests;
+            Ok(EndpointSummary {
+                endpoint: name.to_string(),
+                total_requests,
+                successful_requests,
+                failed_requests,
+                error_rate: if total_requests > 0 {
+                    failed_requests as f64 / total_requests as f64
+                } else {
+                    0.0
+                },
+                avg_response_time_ms: avg,
+                p50_response_time_ms: p50,
+                p95_response_time_ms: p95,
+                p99_response_time_ms: p99,
+                error_types,
+            })
+        })
+        .collect()
+}
+
+// Scenarios of the same name add up like the runs do; without their histograms, their
+// percentiles are request-weighted averages
+fn merge_scenarios(runs: &[TestResults]) -> Vec<ScenarioSummary> {
+    let mut by_scenario: BTreeMap<&str, Vec<&ScenarioSummary>> = BTreeMap::new();
+    for run in runs {
+        for scenario in &run.scenarios {
+            by_scenario
+                .entry(&scenario.scenario)
+                .or_default()
+                .push(scenario);
+        }
+    }
+
+    by_scenario
+        .into_iter()
+        .map(|(name, summaries)| {
+            let total_requests: usize = summaries.iter().map(|s| s.total_requests).sum();
+            let successful_requests: usize = summaries.iter().map(|s| s.successful_requests).sum();
+            let weighted = |value: fn(&ScenarioSummary) -> f64| {
+                let sum: f64 = summaries
+                    .iter()
+                    .map(|s| value(s) * s.total_requests as f64)
+                    .sum();
+                if total_requests == 0 {
+                    0.0
+                } else {
+                    sum / total_requests as f64
+                }
+            };
+            ScenarioSummary {
+                scenario: name.to_string(),
+                vus: summaries.iter().map(|s| s.vus).sum(),
+                rps: summaries.iter().filter_map(|s| s.rps).reduce(|a, b| a + b),
+                total_requests,
+                successful_requests,
+                failed_requests: total_requests - successful_requests,
+                requests_per_second: summaries.iter().map(|s| s.requests_per_second).sum(),
+                avg_response_time_ms: weighted(|s| s.avg_response_time_ms),
+                p50_response_time_ms: weighted(|s| s.p50_response_time_ms),
+                p95_response_time_ms: weighted(|s| s.p95_response_time_ms),
+                p99_response_time_ms: weighted(|s| s.p99_response_time_ms),
+            }
+        })
+        .collect()
+}
+
+fn merge_phases(
+    runs: &[TestResults],
+    histograms: Option<&EncodedHistograms>,
+) -> Result<Vec<PhaseSummary>, BoxError> {
+    let mut by_phase: Vec<(&str, Vec<&PhaseSummary>)> = Vec::new();
+    for run in runs {
+        for phase in &run.phases {
+            match by_phase.iter_mut().find(|(name, _)| *name == phase.phase) {
+                Some((_, summaries)) => summaries.push(phase),
+                None => by_phase.push((&phase.phase, vec![phase])),
+            }
+        }
+    }
+
+    by_phase
+        .into_iter()
+        .map(|(name, summaries)| {
+            if let Some(encoded) = histograms.and_then(|h| h.phases.get(name)) {
+                let histogram = decode(encoded)?;
+                return Ok(PhaseSummary {
+                    phase: name.to_string(),
+                    count: histogram.len(),
+                    avg_ms: histogram.mean() / 1000.0,
+                    p50_ms: histogram.value_at_percentile(50.0) as f64 / 1000.0,
+                    p95_ms: histogram.value_at_percentile(95.0) as f64 / 1000.0,
+                    p99_ms: histogram.value_at_percentile(99.0) as f64 / 1000.0,
+                });
+            }
+            let count: u64 = summaries.iter().map(|s| s.count).sum();
+            let weighted = |value: fn(&PhaseSummary) -> f64| {
+                let sum: f64 = summaries.iter().map(|s| value(s) * s.count as f64).sum();
+                if count == 0 {
+                    0.0
+                } else {
+                    sum / count as f64
+                }
+            };
+            Ok(PhaseSummary {
+                phase: name.to_string(),
+                count,
+                avg_ms: weighted(|s| s.avg_ms),
+                p50_ms: weighted(|s| s.p50_ms),
+                p95_ms: weighted(|s| s.p95_ms),
+                p99_ms: weighted(|s| s.p99_ms),
+            })
+        })
+        .collect()
+}
+
+// Points are aligned by their position, i.e. the runs are assumed to have started together;
+// the per-interval P95 cannot be recombined, so the highest one is kept
+fn merge_timeseries(runs: &[TestResults]) -> Vec<TimeseriesPoint> {
+    let length = runs
+        .iter()
+        .map(|run| run.timeseries.len())
+        .max()
+        .unwrap_or(0);
+    (0..length)
+        .map(|index| {
+            let points: Vec<&TimeseriesPoint> = runs
+                .iter()
+                .filter_map(|run| run.timeseries.get(index))
+                .collect();
+            let requests: usize = points.iter().map(|p| p.requests).sum();
+            let errors: f64 = points
+                .iter()
+                .map(|p| p.error_rate * p.requests as f64)
+                .sum();
+            TimeseriesPoint {
+                elapsed_seconds: points.iter().map(|p| p.elapsed_seconds).fold(0.0, f64::max),
+                requests,
+                rps: points.iter().map(|p| p.rps).sum(),
+                error_rate: if requests > 0 {
+                    errors / requests as f64
+                } else {
+                    0.0
+                },
+                p95_response_time_ms: points
+                    .iter()
+                    .map(|p| p.p95_response_time_ms)
+                    .fold(0.0, f64::max),
+                bytes_sent: points.iter().map(|p| p.bytes_sent).sum(),
+                bytes_received: points.iter().map(|p| p.bytes_received).sum(),
+                active_vus: points.iter().map(|p| p.active_vus).sum(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,53 @@
+//! Local HTTP endpoint exposing the metrics registry for Prometheus to scrape
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{Encoder, Registry, TextEncoder};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Bind the listener up front so address errors surface before the test starts, then
+// serve `/metrics` in the background for the rest of the process lifetime
+pub fn spawn(addr: SocketAddr, registry: &'static Registry) -> Result<(), BoxError> {
+    let builder = Server::try_bind(&addr)?;
+
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |request| handle(request, registry)))
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = builder.serve(make_service).await {
+            tracing::error!(error = %e, "metrics endpoint failed");
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(
+    request: Request<Body>,
+    registry: &'static Registry,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not Found\n"))
+            .unwrap());
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+        return Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(format!("Failed to encode metrics: {}\n", e)))
+            .unwrap());
+    }
+
+    Ok(Response::builder()
+        .header("Content-Type", encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap())
+}
@@ -0,0 +1,186 @@
+//! `--notify-webhook`: POST a summary of the run to a webhook when it ends, and right away when
+//! a threshold or `--abort-on` condition trips
+//
+// Long runs then need nobody watching them: a chat channel or an automation hears about a
+// failing target while the test is still going and gets the results once it is over. The
+// default payload is a JSON object with an `event` field (`threshold_breached`, `aborted` or
+// `finished`) and the figures of that event; `--notify-format slack` sends a `{"text": ...}`
+// message instead, which Slack, Mattermost and other chat webhooks post as it is. Failed
+// deliveries are logged and never fail the run.
+
+use crate::thresholds::ThresholdResult;
+use crate::{StopReason, TestResults};
+use chrono::Utc;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WebhookFormat {
+    // The event and its figures as JSON fields
+    Json,
+    // A chat message in a `text` field
+    Slack,
+}
+
+#[derive(Debug, Clone)]
+pub struct NotifyConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+    pub app: String,
+    pub run_id: String,
+}
+
+pub enum Event<'a> {
+    // A threshold failed on the results so far
+    ThresholdBreached {
+        result: &'a ThresholdResult,
+        elapsed: Duration,
+    },
+    // An --abort-on condition held; the run is stopping
+    Aborted {
+        condition: &'a str,
+        elapsed: Duration,
+    },
+    Finished(&'a TestResults),
+}
+
+pub struct Notifier {
+    client: Client,
+    config: NotifyConfig,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig) -> Result<Self, BoxError> {
+        reqwest::Url::parse(&config.url)
+            .map_err(|e| format!("invalid --notify-webhook URL {}: {}", config.url, e))?;
+        Ok(Self {
+            client: Client::builder().timeout(SEND_TIMEOUT).build()?,
+            config,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.config.url
+    }
+
+    pub async fn send(&self, event: &Event<'_>) -> Result<(), BoxError> {
+        let response = self
+            .client
+            .post(&self.config.url)
+            .json(&self.payload(event))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("webhook failed with status {}: {}", status, body).into());
+        }
+        Ok(())
+    }
+
+    fn payload(&self, event: &Event<'_>) -> Value {
+        let text = self.text(event);
+        if self.config.format == WebhookFormat::Slack {
+            return json!({ "text": text });
+        }
+        let mut payload = match event {
+            Event::ThresholdBreached { result, elapsed } => json!({
+                "event": "threshold_breached",
+                "threshold": result.threshold,
+                "actual": result.actual,
+                "elapsed_seconds": elapsed.as_secs_f64(),
+            }),
+            Event::Aborted { condition, elapsed } => json!({
+                "event": "aborted",
+                "condition": condition,
+                "elapsed_seconds": elapsed.as_secs_f64(),
+            }),
+            Event::Finished(results) => json!({
+                "event": "finished",
+                "passed": results.passed(),
+                "stop_reason": results.stop_reason,
+                "aborted_by": results.aborted_by,
+                "total_requests": results.total_requests,
+                "failed_requests": results.failed_requests,
+                "error_rate": error_rate(results),
+                "requests_per_second": results.requests_per_second,
+                "p95_response_time_ms": results.p95_response_time_ms,
+                "p99_response_time_ms": results.p99_response_time_ms,
+                "test_duration_seconds": results.test_duration_seconds,
+                "thresholds": results.thresholds,
+            }),
+        };
+        payload["app"] = json!(self.config.app);
+        payload["run_id"] = json!(self.config.run_id);
+        payload["timestamp"] = json!(Utc::now().to_rfc3339());
+        payload["text"] = json!(text);
+        payload
+    }
+
+    // One line per fact, readable in a chat message
+    fn text(&self, event: &Event<'_>) -> String {
+        let run = format!("forgy {} (run {})", self.config.app, self.config.run_id);
+        match event {
+            Event::ThresholdBreached { result, elapsed } => format!(
+                "{}: threshold {} failed after {}s (actual {:.2})",
+                run,
+                result.threshold,
+                elapsed.as_secs(),
+                result.actual
+            ),
+            Event::Aborted { condition, elapsed } => format!(
+                "{}: aborting after {}s, {} held",
+                run,
+                elapsed.as_secs(),
+                condition
+            ),
+            Event::Finished(results) => {
+                let outcome = match results.stop_reason {
+                    StopReason::Completed => "completed".to_string(),
+                    StopReason::MaxDuration => "stopped, max duration reached".to_string(),
+                    StopReason::Interrupted => "interrupted".to_string(),
+                    StopReason::Aborted => format!(
+                        "aborted, {}",
+                        results.aborted_by.as_deref().unwrap_or("--abort-on")
+                    ),
+                };
+                let mut lines = vec![
+                    format!(
+                        "{}: {} ({})",
+                        run,
+                        outcome,
+                        if results.passed() { "passed" } else { "failed" }
+                    ),
+                    format!(
+                        "{} requests in {:.0}s, {:.2}% failed, {:.1} req/s, p95 {:.1} ms, p99 {:.1} ms",
+                        results.total_requests,
+                        results.test_duration_seconds,
+                        error_rate(results),
+                        results.requests_per_second,
+                        results.p95_response_time_ms,
+                        results.p99_response_time_ms
+                    ),
+                ];
+                lines.extend(results.thresholds.iter().map(|t| {
+                    format!(
+                        "{} {} (actual {:.2})",
+                        t.threshold,
+                        if t.passed { "passed" } else { "failed" },
+                        t.actual
+                    )
+                }));
+                lines.join("\n")
+            }
+        }
+    }
+}
+
+// Percent of failed requests
+fn error_rate(results: &TestResults) -> f64 {
+    results.failed_requests as f64 / results.total_requests.max(1) as f64 * 100.0
+}
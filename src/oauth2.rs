@@ -0,0 +1,108 @@
+//! OAuth2 client-credentials tokens, fetched before the test and refreshed before they expire
+
+use crate::client::Client;
+use parking_lot::Mutex;
+use reqwest::header::HeaderValue;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Lifetime assumed when the token endpoint does not send expires_in
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+// Delay before retrying a failed refresh
+const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct OAuth2Config {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+// Holds the current bearer token as a ready-to-send Authorization header
+pub struct TokenSource {
+    client: Client,
+    config: OAuth2Config,
+    authorization: Mutex<HeaderValue>,
+    expires_in: Mutex<Duration>,
+}
+
+impl TokenSource {
+    // Fetch the first token; the test does not start without one
+    pub async fn fetch(client: Client, config: OAuth2Config) -> Result<Self, BoxError> {
+        let (authorization, expires_in) = Self::request_token(&client, &config).await?;
+        Ok(Self {
+            client,
+            config,
+            authorization: Mutex::new(authorization),
+            expires_in: Mutex::new(expires_in),
+        })
+    }
+
+    pub fn authorization(&self) -> HeaderValue {
+        self.authorization.lock().clone()
+    }
+
+    // Replace the token once 80% of its lifetime has passed; runs until aborted
+    pub async fn refresh_periodically(&self) {
+        let mut delay = self.expires_in.lock().mul_f64(0.8);
+        loop {
+            sleep(delay).await;
+            match Self::request_token(&self.client, &self.config).await {
+                Ok((authorization, expires_in)) => {
+                    *self.authorization.lock() = authorization;
+                    *self.expires_in.lock() = expires_in;
+                    delay = expires_in.mul_f64(0.8);
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to refresh OAuth2 token");
+                    delay = REFRESH_RETRY_DELAY;
+                }
+            }
+        }
+    }
+
+    async fn request_token(
+        client: &Client,
+        config: &OAuth2Config,
+    ) -> Result<(HeaderValue, Duration), BoxError> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+        ];
+        if let Some(scope) = &config.scope {
+            form.push(("scope", scope.as_str()));
+        }
+
+        let request = client.post(&config.token_url).form(&form).build()?;
+        // The body is read under the request timeout too
+        let token = tokio::time::timeout(client.timeout(), async {
+            let response = client.execute(request).await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("token endpoint returned {}: {}", status, body).into());
+            }
+            Ok::<TokenResponse, BoxError>(response.json().await?)
+        })
+        .await
+        .map_err(|_| "token endpoint did not answer in time")??;
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", token.access_token))
+            .map_err(|_| "token endpoint returned an invalid access_token")?;
+        let expires_in = token
+            .expires_in
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOKEN_LIFETIME);
+        Ok((authorization, expires_in))
+    }
+}
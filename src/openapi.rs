@@ -0,0 +1,414 @@
+//! Scenarios generated from an OpenAPI 3 document, for `forgy openapi`
+//
+// Every operation becomes one request of the scenario. Parameter and body values come from
+// the document's examples where it has them; otherwise they are generated from the schema,
+// as placeholders where possible (${RAND_INT(..)} for numbers, ${UUID} for uuids) so each
+// request gets fresh values. Only required query and header parameters are sent.
+
+use crate::scenario::{RequestSpec, Scenario};
+use crate::template::Template;
+use reqwest::header::{HeaderName, CONTENT_TYPE};
+use reqwest::Method;
+use serde_json::Value;
+use std::fmt::Write;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+
+// How many schema references deep request bodies are generated
+const MAX_SCHEMA_DEPTH: usize = 8;
+
+// Placeholder for numbers without bounds in the schema
+const DEFAULT_INT_RANGE: (i64, i64) = (1, 1000);
+
+// `operations` selects operations by operationId, all of them when empty; `server`
+// overrides the document's first server URL
+pub fn scenario(
+    path: &str,
+    operations: &[String],
+    server: Option<&str>,
+) -> Result<Scenario, BoxError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    // JSON documents are valid YAML as well
+    let document: Value = serde_yaml::from_str(&contents)
+        .map_err(|e| format!("invalid OpenAPI document {}: {}", path, e))?;
+    if !document["openapi"]
+        .as_str()
+        .unwrap_or_default()
+        .starts_with('3')
+    {
+        return Err(format!("{} is not an OpenAPI 3 document", path).into());
+    }
+
+    let base_url = match server {
+        Some(server) => server.to_string(),
+        None => server_url(&document)
+            .ok_or_else(|| format!("{} has no absolute server URL; set one with --server", path))?,
+    };
+    let base_url = base_url.trim_end_matches('/');
+
+    let mut requests = Vec::new();
+    let mut found = Vec::new();
+    let paths = document["paths"].as_object().cloned().unwrap_or_default();
+    for (path_template, path_item) in &paths {
+        let path_item = resolve(&document, path_item);
+        for method in METHODS {
+            let Some(operation) = path_item.get(method) else {
+                continue;
+            };
+            let operation_id = operation["operationId"].as_str();
+            if !operations.is_empty() {
+                match operation_id {
+                    Some(id) if operations.iter().any(|wanted| wanted == id) => {
+                        found.push(id.to_string())
+                    }
+                    _ => continue,
+                }
+            }
+
+            // Operation parameters override path-level ones of the same name and location
+            let mut parameters: Vec<&Value> = Vec::new();
+            for parameter in operation["parameters"]
+                .as_array()
+                .into_iter()
+                .chain(path_item["parameters"].as_array())
+                .flatten()
+            {
+                let parameter = resolve(&document, parameter);
+                let is_duplicate = parameters.iter().any(|existing| {
+                    existing["name"] == parameter["name"] && existing["in"] == parameter["in"]
+                });
+                if !is_duplicate {
+                    parameters.push(parameter);
+                }
+            }
+
+            let target = operation_id
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{} {}", method.to_uppercase(), path_template));
+            let request = build_request(
+                &document,
+                target,
+                base_url,
+                path_template,
+                method,
+                operation,
+                &parameters,
+            )
+            .map_err(|e| format!("{} {}: {}", method.to_uppercase(), path_template, e))?;
+            requests.push(request);
+        }
+    }
+
+    if let Some(missing) = operations.iter().find(|wanted| !found.contains(wanted)) {
+        return Err(format!("no operation with operationId {} in {}", missing, path).into());
+    }
+    if requests.is_empty() {
+        return Err(format!("no operations found in {}", path).into());
+    }
+    Scenario::new(requests)
+}
+
+fn build_request(
+    document: &Value,
+    target: String,
+    base_url: &str,
+    path_template: &str,
+    method: &str,
+    operation: &Value,
+    parameters: &[&Value],
+) -> Result<RequestSpec, BoxError> {
+    let mut path = path_template.to_string();
+    let mut query = Vec::new();
+    let mut headers = Vec::new();
+    for parameter in parameters {
+        let name = parameter["name"].as_str().unwrap_or_default();
+        let required = parameter["required"].as_bool().unwrap_or(false);
+        match parameter["in"].as_str() {
+            Some("path") => {
+                path = path.replace(
+                    &format!("{{{}}}", name),
+                    &parameter_value(document, parameter, true),
+                );
+            }
+            Some("query") if required => {
+                query.push(format!(
+                    "{}={}",
+                    percent_encode(name),
+                    parameter_value(document, parameter, true)
+                ));
+            }
+            Some("header") if required => {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| format!("invalid header parameter: {}", name))?;
+                headers.push((
+                    name,
+                    Template::parse(&parameter_value(document, parameter, false))?,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let mut url = format!("{}{}", base_url, path);
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query.join("&"));
+    }
+
+    // JSON bodies only; other media types would need an example to be meaningful
+    let body = match resolve(document, &operation["requestBody"])["content"].get("application/json")
+    {
+        Some(media) => {
+            if !headers.iter().any(|(name, _)| name == CONTENT_TYPE) {
+                headers.push((CONTENT_TYPE, Template::parse("application/json")?));
+            }
+            let body = match media_example(document, media) {
+                Some(example) => serde_json::to_string(example)?,
+                None => JsonWriter::render(document, &media["schema"]),
+            };
+            Some(Template::parse(&body)?)
+        }
+        None => None,
+    };
+
+    Ok(RequestSpec {
+        target,
+        // Requests of an operation are one endpoint, whatever values fill its path
+        name: Some(format!("{} {}", method.to_uppercase(), path_template)),
+        method: Method::from_bytes(method.to_uppercase().as_bytes())?,
+        url: Template::parse(&url)?,
+        headers,
+        body,
+        weight: 1,
+        checks: Vec::new(),
+        graphql: false,
+        timeout: None,
+    })
+}
+
+// The first server's URL with its variables set to their defaults, if it is absolute
+fn server_url(document: &Value) -> Option<String> {
+    let server = &document["servers"][0];
+    let mut url = server["url"].as_str()?.to_string();
+    if let Some(variables) = server["variables"].as_object() {
+        for (name, variable) in variables {
+            let default = variable["default"].as_str().unwrap_or_default();
+            url = url.replace(&format!("{{{}}}", name), default);
+        }
+    }
+    (url.starts_with("http://") || url.starts_with("https://")).then_some(url)
+}
+
+// Follows a local "$ref" ("#/components/..."), returning the value itself otherwise
+fn resolve<'a>(document: &'a Value, value: &'a Value) -> &'a Value {
+    let mut value = value;
+    for _ in 0..MAX_SCHEMA_DEPTH {
+        match value["$ref"].as_str().and_then(|r| r.strip_prefix('#')) {
+            Some(pointer) => match document.pointer(pointer) {
+                Some(target) => value = target,
+                None => break,
+            },
+            None => break,
+        }
+    }
+    value
+}
+
+// First example of a parameter or media type object: `example`, then `examples`, then
+// the schema's own example
+fn media_example<'a>(document: &'a Value, object: &'a Value) -> Option<&'a Value> {
+    if let Some(example) = object.get("example") {
+        return Some(example);
+    }
+    if let Some(example) = object["examples"]
+        .as_object()
+        .and_then(|examples| examples.values().next())
+    {
+        return resolve(document, example).get("value");
+    }
+    let schema = resolve(document, &object["schema"]);
+    schema
+        .get("example")
+        .or_else(|| schema.get("default"))
+        .or_else(|| schema["enum"].get(0))
+}
+
+// Template source for a path, query or header value; literal values in URLs are
+// percent-encoded
+fn parameter_value(document: &Value, parameter: &Value, in_url: bool) -> String {
+    let literal = |text: &str| {
+        if in_url {
+            percent_encode(text)
+        } else {
+            text.to_string()
+        }
+    };
+    if let Some(example) = media_example(document, parameter) {
+        return match example {
+            Value::String(text) => literal(text),
+            other => literal(&other.to_string()),
+        };
+    }
+    let schema = resolve(document, &parameter["schema"]);
+    match schema["type"].as_str() {
+        Some("integer") | Some("number") => {
+            let (min, max) = int_range(schema);
+            format!("${{RAND_INT({},{})}}", min, max)
+        }
+        Some("boolean") => "true".to_string(),
+        _ => match schema["format"].as_str() {
+            Some("uuid") => "${UUID}".to_string(),
+            Some("date") => "2024-01-01".to_string(),
+            Some("date-time") => literal("2024-01-01T00:00:00Z"),
+            _ => "test".to_string(),
+        },
+    }
+}
+
+// Builds a JSON template for a schema: examples where given, placeholders for numbers and
+// uuids, and every property of objects
+struct JsonWriter<'a> {
+    document: &'a Value,
+    // Schemas being written, outermost first; a property referring back to one of them is
+    // left out so recursive schemas end
+    refs: Vec<&'a str>,
+    out: String,
+}
+
+impl<'a> JsonWriter<'a> {
+    fn render(document: &'a Value, schema: &'a Value) -> String {
+        let mut writer = Self {
+            document,
+            refs: Vec::new(),
+            out: String::new(),
+        };
+        writer.write(schema);
+        writer.out
+    }
+
+    fn write(&mut self, schema: &'a Value) {
+        let reference = schema["$ref"].as_str();
+        if let Some(reference) = reference {
+            self.refs.push(reference);
+        }
+        self.write_resolved(resolve(self.document, schema));
+        if reference.is_some() {
+            self.refs.pop();
+        }
+    }
+
+    fn write_resolved(&mut self, schema: &'a Value) {
+        if let Some(example) = schema
+            .get("example")
+            .or_else(|| schema.get("default"))
+            .or_else(|| schema["enum"].get(0))
+        {
+            self.out.push_str(&example.to_string());
+            return;
+        }
+        if self.refs.len() >= MAX_SCHEMA_DEPTH {
+            self.out.push_str("null");
+            return;
+        }
+        // allOf parts are objects in practice, so their properties are merged into one object
+        if let Some(parts) = schema["allOf"].as_array() {
+            self.out.push('{');
+            let mut first = true;
+            for part in parts {
+                self.write_properties(resolve(self.document, part), &mut first);
+            }
+            self.out.push('}');
+            return;
+        }
+        // The first alternative stands in for the whole
+        if let Some(first) = schema["oneOf"].get(0).or_else(|| schema["anyOf"].get(0)) {
+            self.write(first);
+            return;
+        }
+
+        let schema_type = schema["type"].as_str().or_else(|| {
+            // Types are often left out of objects with properties
+            schema.get("properties").map(|_| "object")
+        });
+        match schema_type {
+            Some("object") => {
+                self.out.push('{');
+                self.write_properties(schema, &mut true);
+                self.out.push('}');
+            }
+            Some("array") => {
+                self.out.push('[');
+                if !self.is_recursive(&schema["items"]) {
+                    self.write(&schema["items"]);
+                }
+                self.out.push(']');
+            }
+            Some("integer") | Some("number") => {
+                let (min, max) = int_range(schema);
+                let _ = write!(self.out, "${{RAND_INT({},{})}}", min, max);
+            }
+            Some("boolean") => self.out.push_str("true"),
+            Some("string") => self.out.push_str(match schema["format"].as_str() {
+                Some("uuid") => "\"${UUID}\"",
+                Some("date") => "\"2024-01-01\"",
+                Some("date-time") => "\"2024-01-01T00:00:00Z\"",
+                Some("email") => "\"user${RAND_INT(1,100000)}@example.com\"",
+                _ => "\"test\"",
+            }),
+            _ => self.out.push_str("null"),
+        }
+    }
+
+    fn write_properties(&mut self, schema: &'a Value, first: &mut bool) {
+        for (name, property) in schema["properties"].as_object().into_iter().flatten() {
+            if resolve(self.document, property)["readOnly"].as_bool() == Some(true)
+                || self.is_recursive(property)
+            {
+                continue;
+            }
+            if !*first {
+                self.out.push(',');
+            }
+            *first = false;
+            self.out.push_str(&Value::String(name.clone()).to_string());
+            self.out.push(':');
+            self.write(property);
+        }
+    }
+
+    fn is_recursive(&self, schema: &Value) -> bool {
+        schema["$ref"]
+            .as_str()
+            .is_some_and(|reference| self.refs.contains(&reference))
+    }
+}
+
+fn int_range(schema: &Value) -> (i64, i64) {
+    let min = schema["minimum"]
+        .as_f64()
+        .map_or(DEFAULT_INT_RANGE.0, |min| min.ceil() as i64);
+    let max = schema["maximum"]
+        .as_f64()
+        .map_or(DEFAULT_INT_RANGE.1.max(min), |max| max.floor() as i64);
+    (min, max.max(min))
+}
+
+// Everything but unreserved characters (RFC 3986) is encoded, which also keeps "${" in
+// example values from being read as a placeholder
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
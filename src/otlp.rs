@@ -0,0 +1,351 @@
+//! OpenTelemetry (OTLP) metrics exporter, an alternative to Prometheus Remote Write
+//
+// Exports the same registry as Remote Write: counters become monotonic cumulative sums,
+// gauges stay gauges and histograms keep their explicit bucket bounds. The app label and
+// --label values are sent as resource attributes. Client spans of sampled requests go to
+// the same collector.
+
+mod proto;
+
+use crate::trace::{self, ClientSpan};
+use prometheus::proto::MetricType;
+use prost::Message;
+use proto::{
+    metric, AggregationTemporality, Exemplar, ExportMetricsServiceRequest,
+    ExportTraceServiceRequest, Gauge, Histogram, HistogramDataPoint, InstrumentationScope,
+    KeyValue, Metric, NumberDataPoint, Resource, ResourceMetrics, ResourceSpans, ScopeMetrics,
+    ScopeSpans, Span, SpanKind, Status, StatusCode, Sum,
+};
+use reqwest::Client;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const GRPC_METRICS_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+const HTTP_METRICS_PATH: &str = "/v1/metrics";
+const GRPC_TRACES_PATH: &str = "/opentelemetry.proto.collector.trace.v1.TraceService/Export";
+const HTTP_TRACES_PATH: &str = "/v1/traces";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Transport used to reach the collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OtlpProtocol {
+    /// OTLP/gRPC, usually on port 4317
+    Grpc,
+    /// OTLP/HTTP with protobuf bodies, usually on port 4318
+    Http,
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    // Collector base URL, e.g. http://localhost:4317
+    pub endpoint: String,
+    pub protocol: OtlpProtocol,
+    // Sent as the service.name resource attribute
+    pub service_name: String,
+    pub labels: Vec<(String, String)>,
+}
+
+pub struct OtlpExporter {
+    client: Client,
+    config: OtlpConfig,
+    // Start of the cumulative series
+    start_time_unix_nano: u64,
+}
+
+impl OtlpExporter {
+    pub fn new(config: OtlpConfig) -> Result<Self, BoxError> {
+        let mut builder = Client::builder().timeout(EXPORT_TIMEOUT);
+        // Collectors serve gRPC over plain-text HTTP/2 without an upgrade
+        if config.protocol == OtlpProtocol::Grpc {
+            builder = builder.http2_prior_knowledge();
+        }
+        Ok(Self {
+            client: builder.build()?,
+            config,
+            start_time_unix_nano: unix_nanos(),
+        })
+    }
+
+    pub async fn export(&self, registry: &prometheus::Registry) -> Result<(), BoxError> {
+        let request = self.build_request(&registry.gather(), unix_nanos());
+        self.send(
+            GRPC_METRICS_PATH,
+            HTTP_METRICS_PATH,
+            request.encode_to_vec(),
+        )
+        .await
+    }
+
+    pub async fn export_spans(&self, spans: &[ClientSpan]) -> Result<(), BoxError> {
+        if spans.is_empty() {
+            return Ok(());
+        }
+        let request = ExportTraceServiceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource: Some(self.resource()),
+                scope_spans: vec![ScopeSpans {
+                    scope: Some(scope()),
+                    spans: spans.iter().map(convert_span).collect(),
+                }],
+            }],
+        };
+        self.send(GRPC_TRACES_PATH, HTTP_TRACES_PATH, request.encode_to_vec())
+            .await
+    }
+
+    async fn send(
+        &self,
+        grpc_path: &str,
+        http_path: &str,
+        payload: Vec<u8>,
+    ) -> Result<(), BoxError> {
+        let endpoint = self.config.endpoint.trim_end_matches('/');
+
+        match self.config.protocol {
+            OtlpProtocol::Grpc => {
+                // Length-prefixed message: no compression flag, then the size in big endian
+                let mut body = Vec::with_capacity(payload.len() + 5);
+                body.push(0);
+                body.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                body.extend_from_slice(&payload);
+
+                let response = self
+                    .client
+                    .post(format!("{}{}", endpoint, grpc_path))
+                    .header("content-type", "application/grpc")
+                    .header("te", "trailers")
+                    .body(body)
+                    .send()
+                    .await?;
+                let status = response.status();
+                // Errors come as a trailers-only response, i.e. grpc-status in the headers
+                let grpc_status = response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                let grpc_message = response
+                    .headers()
+                    .get("grpc-message")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                response.bytes().await?;
+
+                if !status.is_success() {
+                    return Err(format!("OTLP export failed with status {}", status).into());
+                }
+                match grpc_status.as_deref() {
+                    None | Some("0") => Ok(()),
+                    Some(code) => Err(format!(
+                        "OTLP export failed with gRPC status {}: {}",
+                        code, grpc_message
+                    )
+                    .into()),
+                }
+            }
+            OtlpProtocol::Http => {
+                let response = self
+                    .client
+                    .post(format!("{}{}", endpoint, http_path))
+                    .header("content-type", "application/x-protobuf")
+                    .body(payload)
+                    .send()
+                    .await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(
+                        format!("OTLP export failed with status {}: {}", status, body).into(),
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn build_request(
+        &self,
+        families: &[prometheus::proto::MetricFamily],
+        time_unix_nano: u64,
+    ) -> ExportMetricsServiceRequest {
+        let metrics = families
+            .iter()
+            .filter_map(|family| self.convert_family(family, time_unix_nano))
+            .collect();
+
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(scope()),
+                    metrics,
+                }],
+            }],
+        }
+    }
+
+    fn resource(&self) -> Resource {
+        let mut attributes = vec![KeyValue::string("service.name", &self.config.service_name)];
+        for (name, value) in &self.config.labels {
+            attributes.push(KeyValue::string(name, value));
+        }
+        Resource { attributes }
+    }
+
+    fn convert_family(
+        &self,
+        family: &prometheus::proto::MetricFamily,
+        time_unix_nano: u64,
+    ) -> Option<Metric> {
+        let number_point = |metric: &prometheus::proto::Metric, value: f64| NumberDataPoint {
+            attributes: attributes(metric),
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano,
+            as_double: value,
+        };
+
+        let data = match family.get_field_type() {
+            MetricType::COUNTER => metric::Data::Sum(Sum {
+                data_points: family
+                    .get_metric()
+                    .iter()
+                    .map(|m| number_point(m, m.get_counter().get_value()))
+                    .collect(),
+                aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                is_monotonic: true,
+            }),
+            MetricType::GAUGE => metric::Data::Gauge(Gauge {
+                data_points: family
+                    .get_metric()
+                    .iter()
+                    .map(|m| number_point(m, m.get_gauge().get_value()))
+                    .collect(),
+            }),
+            MetricType::HISTOGRAM => metric::Data::Histogram(Histogram {
+                data_points: family
+                    .get_metric()
+                    .iter()
+                    .map(|m| self.histogram_point(family.get_name(), m, time_unix_nano))
+                    .collect(),
+                aggregation_temporality: AggregationTemporality::Cumulative as i32,
+            }),
+            _ => return None,
+        };
+
+        Some(Metric {
+            name: family.get_name().to_string(),
+            description: family.get_help().to_string(),
+            unit: String::new(),
+            data: Some(data),
+        })
+    }
+
+    // Prometheus buckets are cumulative with an implicit +Inf; OTLP wants per-bucket counts
+    // with the overflow bucket last
+    fn histogram_point(
+        &self,
+        name: &str,
+        metric: &prometheus::proto::Metric,
+        time_unix_nano: u64,
+    ) -> HistogramDataPoint {
+        let histogram = metric.get_histogram();
+        let mut explicit_bounds = Vec::new();
+        let mut bucket_counts = Vec::new();
+        let mut exemplars = Vec::new();
+        let mut previous = 0;
+        for bucket in histogram.get_bucket() {
+            if bucket.get_upper_bound() == f64::INFINITY {
+                continue;
+            }
+            explicit_bounds.push(bucket.get_upper_bound());
+            bucket_counts.push(bucket.get_cumulative_count() - previous);
+            previous = bucket.get_cumulative_count();
+        }
+        bucket_counts.push(histogram.get_sample_count() - previous);
+
+        for upper_bound in explicit_bounds.iter().copied().chain([f64::INFINITY]) {
+            if let Some(exemplar) = trace::exemplar(name, metric, upper_bound) {
+                exemplars.push(Exemplar {
+                    time_unix_nano: exemplar.timestamp_millis as u64 * 1_000_000,
+                    as_double: exemplar.value,
+                    span_id: exemplar.span_id.to_vec(),
+                    trace_id: exemplar.trace_id.to_vec(),
+                });
+            }
+        }
+
+        HistogramDataPoint {
+            attributes: attributes(metric),
+            start_time_unix_nano: self.start_time_unix_nano,
+            time_unix_nano,
+            count: histogram.get_sample_count(),
+            sum: Some(histogram.get_sample_sum()),
+            bucket_counts,
+            explicit_bounds,
+            exemplars,
+        }
+    }
+}
+
+fn scope() -> InstrumentationScope {
+    InstrumentationScope {
+        name: "forgy".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+// HTTP client span following the OpenTelemetry semantic conventions
+fn convert_span(span: &ClientSpan) -> Span {
+    let mut attributes = vec![
+        KeyValue::string("http.request.method", &span.method),
+        KeyValue::string("url.full", &span.url),
+        KeyValue::string("forgy.target", &span.target),
+    ];
+    if span.status_code > 0 {
+        attributes.push(KeyValue::int(
+            "http.response.status_code",
+            span.status_code as i64,
+        ));
+    }
+    let status = match span.error_type {
+        Some(error_type) => {
+            attributes.push(KeyValue::string("error.type", error_type.as_str()));
+            Status {
+                message: error_type.as_str().to_string(),
+                code: StatusCode::Error as i32,
+            }
+        }
+        None => Status {
+            message: String::new(),
+            code: StatusCode::Unset as i32,
+        },
+    };
+
+    Span {
+        trace_id: span.context.trace_id.to_vec(),
+        span_id: span.context.span_id.to_vec(),
+        name: span.method.clone(),
+        kind: SpanKind::Client as i32,
+        start_time_unix_nano: span.start_time_unix_nano,
+        end_time_unix_nano: span.end_time_unix_nano,
+        attributes,
+        status: Some(status),
+    }
+}
+
+fn attributes(metric: &prometheus::proto::Metric) -> Vec<KeyValue> {
+    metric
+        .get_label()
+        .iter()
+        .map(|pair| KeyValue::string(pair.get_name(), pair.get_value()))
+        .collect()
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
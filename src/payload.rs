@@ -0,0 +1,196 @@
+//! Request bodies that are not templates: --form, --body-file, --body-size
+//
+// A --body-file is streamed from disk for every request, a chunk at a time, rather than kept
+// in memory and copied per request; its size is taken at startup, so it is sent with a
+// Content-Length and must not change during the run. Standard input (--body-file @-) can only
+// be read once, so it is buffered, and every request shares the one buffer, as do the
+// payloads generated for --body-size. With --gzip-body a buffered payload is compressed once,
+// up front; streamed ones cannot be, as their compressed length is not known in advance.
+
+use crate::compression;
+use crate::form::Form;
+use crate::template::TemplateContext;
+use hyper::body::{Bytes, Sender};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use reqwest::header::HeaderValue;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Size of the chunks files are streamed in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Contents of a --body-size payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BodyPattern {
+    // Incompressible, so compression along the way cannot shrink it
+    Random,
+    Zeros,
+}
+
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Form(Form),
+    File { path: PathBuf, size: u64 },
+    Buffered(Bytes),
+    // A buffered payload compressed with gzip, and its size before that
+    Gzipped { bytes: Bytes, decoded_length: u64 },
+}
+
+// A body ready to be sent, with its length known upfront
+pub struct PayloadBody {
+    pub content_type: Option<HeaderValue>,
+    pub content_encoding: Option<HeaderValue>,
+    pub length: u64,
+    // Length before content encoding
+    pub decoded_length: u64,
+    pub body: Contents,
+}
+
+// Bytes shared by every request, or a stream read while the request is sent
+pub enum Contents {
+    Bytes(Bytes),
+    Stream(hyper::Body),
+}
+
+impl Payload {
+    // "PATH", or "@-" for standard input
+    pub fn from_file(path: &str) -> Result<Self, BoxError> {
+        if path == "@-" {
+            let mut buffer = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut buffer)
+                .map_err(|e| format!("failed to read --body-file from stdin: {}", e))?;
+            return Ok(Self::Buffered(Bytes::from(buffer)));
+        }
+        let path = path.strip_prefix('@').unwrap_or(path);
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| format!("failed to read --body-file {}: {}", path, e))?;
+        if !metadata.is_file() {
+            return Err(format!("--body-file {} is not a file", path).into());
+        }
+        Ok(Self::File {
+            path: PathBuf::from(path),
+            size: metadata.len(),
+        })
+    }
+
+    // Generated once; random bytes follow --seed when given
+    pub fn generated(size: u64, pattern: BodyPattern, seed: Option<u64>) -> Self {
+        let mut buffer = vec![0; size as usize];
+        if pattern == BodyPattern::Random {
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            rng.fill_bytes(&mut buffer);
+        }
+        Self::Buffered(Bytes::from(buffer))
+    }
+
+    // For --gzip-body
+    pub fn gzip(self) -> Result<Self, BoxError> {
+        match self {
+            Self::Buffered(bytes) => Ok(Self::Gzipped {
+                bytes: Bytes::from(compression::gzip(&bytes)),
+                decoded_length: bytes.len() as u64,
+            }),
+            Self::Gzipped { .. } => Ok(self),
+            Self::Form(_) | Self::File { .. } => {
+                Err("--gzip-body cannot compress a --form or --body-file streamed from disk".into())
+            }
+        }
+    }
+
+    pub fn body(&self, context: &TemplateContext<'_>) -> PayloadBody {
+        match self {
+            Self::Form(form) => form.body(context),
+            Self::File { path, size } => {
+                let (mut sender, body) = hyper::Body::channel();
+                let (path, size) = (path.clone(), *size);
+                tokio::spawn(async move {
+                    if !send_file(&mut sender, &path, size).await {
+                        sender.abort();
+                    }
+                });
+                PayloadBody {
+                    content_type: None,
+                    content_encoding: None,
+                    length: size,
+                    decoded_length: size,
+                    body: Contents::Stream(body),
+                }
+            }
+            // Cloning Bytes shares the buffer instead of copying it
+            Self::Buffered(bytes) => PayloadBody {
+                content_type: None,
+                content_encoding: None,
+                length: bytes.len() as u64,
+                decoded_length: bytes.len() as u64,
+                body: Contents::Bytes(bytes.clone()),
+            },
+            Self::Gzipped {
+                bytes,
+                decoded_length,
+            } => PayloadBody {
+                content_type: None,
+                content_encoding: Some(compression::content_encoding_gzip()),
+                length: bytes.len() as u64,
+                decoded_length: *decoded_length,
+                body: Contents::Bytes(bytes.clone()),
+            },
+        }
+    }
+}
+
+// Streams exactly `size` bytes of the file; false if it could not
+pub(crate) async fn send_file(sender: &mut Sender, path: &Path, size: u64) -> bool {
+    let Ok(file) = tokio::fs::File::open(path).await else {
+        return false;
+    };
+    let mut file = file.take(size);
+    let mut sent = 0;
+    loop {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        match file.read(&mut buffer).await {
+            Ok(0) => return sent == size,
+            Ok(read) => {
+                buffer.truncate(read);
+                sent += read as u64;
+                if sender.send_data(Bytes::from(buffer)).await.is_err() {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+// Used as the clap value parser for --body-size: "512", "512B", "256KB", "1.5MB" or "1GB",
+// with binary units (1KB = 1024 bytes), as in the transfer sizes forgy reports
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}', expected e.g. 256KB", size))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        other => {
+            return Err(format!(
+                "unknown size unit '{}', expected B, KB, MB or GB",
+                other
+            ))
+        }
+    };
+    Ok((number * multiplier as f64).round() as u64)
+}
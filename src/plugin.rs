@@ -0,0 +1,403 @@
+//! `--plugin`: request generators and validators compiled to WebAssembly, run by wasmtime
+//
+// A plugin is a WASM module (binary, or text format) that exports its linear `memory` and:
+//
+//   alloc(len: i32) -> i32                 a buffer of `len` bytes for forgy to write into
+//   generate_request(ptr: i32, len: i32) -> i64     optional
+//   validate_response(ptr: i32, len: i32) -> i64    optional, but one of the two is needed
+//   dealloc(ptr: i32, len: i32)            optional; called for every buffer forgy is done with
+//
+// forgy writes a JSON call into a buffer from alloc and passes it to the export, which
+// returns its answer as (ptr << 32) | len in its own memory; a length of 0 means "no
+// change" (generate_request) or "pass" (validate_response).
+//
+// call:     {"vu": 3, "iteration": 7, "name": "...",
+//            "request": {"method": "POST", "url": "...", "headers": {...}, "body": "..."},
+//            "response": {"status": 200, "headers": {...}, "body": "..."}}  (validate only)
+// answer:   {"request": {...}} to send a changed request (generate_request), or
+//           {"pass": false, "message": "..."} (validate_response); {"error": "..."} fails the
+//           request with error type `script`
+//
+// Bodies that are not UTF-8 are passed as "body_base64" instead of "body", and an answer may
+// use either. The only import a module may have is forgy.log(ptr: i32, len: i32), which logs
+// the UTF-8 text at info level; modules built for WASI need a target without it (e.g. Rust's
+// wasm32-unknown-unknown).
+//
+// Instances are not shared: each concurrent call takes one from a pool, so a plugin may keep
+// state between calls but cannot rely on seeing every VU's calls. An export that traps or
+// runs for longer than --script-timeout fails the request, and its instance is discarded.
+
+use crate::script::{HookContext, ScriptRequest, ScriptResponse, Verdict};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wasmtime::{
+    Caller, Config, Engine, Extern, InstancePre, Linker, Memory, Module, Store, Trap, TypedFunc,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// generate_request and validate_response
+type Export = TypedFunc<(i32, i32), i64>;
+
+// How often running exports look at their deadline
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+// Longest forgy.log message that is logged whole
+const MAX_LOG_MESSAGE: usize = 4096;
+
+pub struct Plugin {
+    instance_pre: InstancePre<()>,
+    generates: bool,
+    validates: bool,
+    timeout: Duration,
+    // Instances not running an export right now
+    idle: Mutex<Vec<Instance>>,
+    // Stops the epoch ticker
+    stop: Arc<AtomicBool>,
+}
+
+// One instance of the module, with its own store and memory
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    dealloc: Option<TypedFunc<(i32, i32), ()>>,
+    generate_request: Option<Export>,
+    validate_response: Option<Export>,
+}
+
+#[derive(Serialize)]
+struct Call<'a> {
+    vu: usize,
+    iteration: u64,
+    name: &'a str,
+    request: WireRequest,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<WireResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireRequest {
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+}
+
+#[derive(Serialize)]
+struct WireResponse {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    #[serde(default)]
+    request: Option<WireRequest>,
+    #[serde(default)]
+    pass: Option<bool>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl Plugin {
+    // Compile the module and make its first instance, which checks its imports and exports
+    pub fn load(path: &str, timeout: Duration) -> Result<Self, BoxError> {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)
+            .map_err(|e| format!("invalid plugin {}: {:#}", path, e))?;
+
+        let mut linker = Linker::new(&engine);
+        linker.func_wrap("forgy", "log", log)?;
+        let instance_pre = linker
+            .instantiate_pre(&module)
+            .map_err(|e| format!("plugin {}: {:#}", path, e))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let ticker = (engine.clone(), stop.clone());
+        std::thread::Builder::new()
+            .name("plugin-epoch".to_string())
+            .spawn(move || {
+                let (engine, stop) = ticker;
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            })?;
+
+        let mut plugin = Self {
+            instance_pre,
+            generates: false,
+            validates: false,
+            timeout,
+            idle: Mutex::new(Vec::new()),
+            stop,
+        };
+        let first = plugin
+            .instantiate()
+            .map_err(|e| format!("plugin {}: {}", path, e))?;
+        plugin.generates = first.generate_request.is_some();
+        plugin.validates = first.validate_response.is_some();
+        if !plugin.generates && !plugin.validates {
+            return Err(format!(
+                "plugin {} exports neither generate_request nor validate_response",
+                path
+            )
+            .into());
+        }
+        plugin.idle.lock().push(first);
+        Ok(plugin)
+    }
+
+    pub fn generates(&self) -> bool {
+        self.generates
+    }
+
+    pub fn validates(&self) -> bool {
+        self.validates
+    }
+
+    // The request to send instead of `request`, if generate_request changed it
+    pub fn on_request(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+    ) -> Result<Option<ScriptRequest>, BoxError> {
+        let call = Call {
+            vu: context.vu,
+            iteration: context.iteration,
+            name: context.name,
+            request: WireRequest::from(request),
+            response: None,
+        };
+        let Some(answer) = self.call("generate_request", &call, |i| i.generate_request.clone())?
+        else {
+            return Ok(None);
+        };
+        answer.request.map(ScriptRequest::try_from).transpose()
+    }
+
+    pub fn on_response(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+        response: &ScriptResponse,
+    ) -> Result<Verdict, BoxError> {
+        let (body, body_base64) = encode_body(response.body.clone());
+        let call = Call {
+            vu: context.vu,
+            iteration: context.iteration,
+            name: context.name,
+            request: WireRequest::from(request),
+            response: Some(WireResponse {
+                status: response.status,
+                headers: response.headers.clone(),
+                body,
+                body_base64,
+            }),
+        };
+        let answer = self.call("validate_response", &call, |i| i.validate_response.clone())?;
+        Ok(match answer {
+            Some(answer) => Verdict {
+                pass: answer.pass.unwrap_or(true),
+                message: answer.message,
+            },
+            None => Verdict {
+                pass: true,
+                message: None,
+            },
+        })
+    }
+
+    // The export's answer, None when it answered with nothing
+    fn call(
+        &self,
+        export: &str,
+        call: &Call<'_>,
+        function: fn(&Instance) -> Option<Export>,
+    ) -> Result<Option<Answer>, BoxError> {
+        let input = serde_json::to_vec(call)?;
+        let mut instance = match self.idle.lock().pop() {
+            Some(instance) => instance,
+            None => self.instantiate()?,
+        };
+        let function = function(&instance).ok_or_else(|| format!("{} is not exported", export))?;
+        instance.store.set_epoch_deadline(self.deadline_ticks());
+        // An instance that failed may be left in any state, so it is not used again
+        let output = instance
+            .call(function, &input)
+            .map_err(|e| self.error(export, e))?;
+        self.idle.lock().push(instance);
+
+        if output.is_empty() {
+            return Ok(None);
+        }
+        let answer: Answer = serde_json::from_slice(&output)
+            .map_err(|e| format!("invalid answer from {}: {}", export, e))?;
+        match answer.error {
+            Some(error) => Err(format!("plugin error: {}", error).into()),
+            None => Ok(Some(answer)),
+        }
+    }
+
+    fn instantiate(&self) -> Result<Instance, BoxError> {
+        let mut store = Store::new(self.instance_pre.module().engine(), ());
+        store.set_epoch_deadline(self.deadline_ticks());
+        let instance = self
+            .instance_pre
+            .instantiate(&mut store)
+            .map_err(|e| self.error("start", e))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("module does not export its memory")?;
+        let mut optional = |name: &str| match instance.get_func(&mut store, name) {
+            Some(function) => function
+                .typed(&store)
+                .map(Some)
+                .map_err(|e| format!("{}: {:#}", name, e)),
+            None => Ok(None),
+        };
+        let generate_request = optional("generate_request")?;
+        let validate_response = optional("validate_response")?;
+        let dealloc = match instance.get_func(&mut store, "dealloc") {
+            Some(function) => Some(
+                function
+                    .typed(&store)
+                    .map_err(|e| format!("dealloc: {:#}", e))?,
+            ),
+            None => None,
+        };
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(|e| format!("alloc: {:#}", e))?;
+        Ok(Instance {
+            store,
+            memory,
+            alloc,
+            dealloc,
+            generate_request,
+            validate_response,
+        })
+    }
+
+    // Epoch ticks in --script-timeout, rounded up
+    fn deadline_ticks(&self) -> u64 {
+        (self.timeout.as_nanos().div_ceil(EPOCH_TICK.as_nanos()) as u64).max(1)
+    }
+
+    fn error(&self, export: &str, error: wasmtime::Error) -> BoxError {
+        match error.downcast_ref::<Trap>() {
+            Some(Trap::Interrupt) => format!(
+                "{} did not finish within {}",
+                export,
+                humantime::format_duration(self.timeout)
+            )
+            .into(),
+            _ => format!("{}: {:#}", export, error).into(),
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Instance {
+    // Pass `input` to `function` and read back its answer
+    fn call(&mut self, function: Export, input: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        let len = i32::try_from(input.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)?;
+        let packed = function.call(&mut self.store, (ptr, len))? as u64;
+        let (output_ptr, output_len) = ((packed >> 32) as usize, packed as u32 as usize);
+        let mut output = vec![0; output_len];
+        self.memory.read(&self.store, output_ptr, &mut output)?;
+        if let Some(dealloc) = &self.dealloc {
+            dealloc.call(&mut self.store, (ptr, len))?;
+            if output_len > 0 {
+                dealloc.call(&mut self.store, (output_ptr as i32, output_len as i32))?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+// forgy.log(ptr, len)
+fn log(mut caller: Caller<'_, ()>, ptr: i32, len: i32) {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return;
+    };
+    let data = memory.data(&caller);
+    let start = (ptr as u32 as usize).min(data.len());
+    let end = start
+        .saturating_add((len as u32 as usize).min(MAX_LOG_MESSAGE))
+        .min(data.len());
+    let text = String::from_utf8_lossy(&data[start..end]);
+    tracing::info!(target: "forgy::plugin", "{}", text);
+}
+
+impl From<&ScriptRequest> for WireRequest {
+    fn from(request: &ScriptRequest) -> Self {
+        let (body, body_base64) = match &request.body {
+            Some(body) => encode_body(body.clone()),
+            None => (None, None),
+        };
+        Self {
+            method: request.method.clone(),
+            url: request.url.clone(),
+            headers: request.headers.clone(),
+            body,
+            body_base64,
+        }
+    }
+}
+
+impl TryFrom<WireRequest> for ScriptRequest {
+    type Error = BoxError;
+
+    fn try_from(request: WireRequest) -> Result<Self, BoxError> {
+        let body = match (request.body_base64, request.body) {
+            (Some(encoded), _) => Some(
+                STANDARD
+                    .decode(encoded)
+                    .map_err(|e| format!("plugin returned invalid body_base64: {}", e))?,
+            ),
+            (None, body) => body.map(String::into_bytes),
+        };
+        Ok(Self {
+            method: request.method,
+            url: request.url,
+            headers: request.headers,
+            body,
+        })
+    }
+}
+
+// A body as text, or as base64 when it is not UTF-8
+fn encode_body(body: Vec<u8>) -> (Option<String>, Option<String>) {
+    match String::from_utf8(body) {
+        Ok(text) => (Some(text), None),
+        Err(e) => (None, Some(STANDARD.encode(e.as_bytes()))),
+    }
+}
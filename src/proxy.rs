@@ -0,0 +1,456 @@
+//! Forward proxies: which requests go through one, and the tunnel through it
+//
+// `--proxy` applies to every request; without it, HTTP_PROXY (not when run as a CGI script,
+// whose HTTP_PROXY may come from a request's Proxy header), HTTPS_PROXY and ALL_PROXY from
+// the environment apply to the requests of their scheme, lowercase names included. Hosts
+// matched by NO_PROXY are reached directly in both cases: `*`, domains (which match their
+// subdomains too, with or without a leading dot), and IP addresses or CIDR ranges.
+//
+// Plain HTTP requests are forwarded to an HTTP(S) proxy in absolute form, with the credentials
+// in Proxy-Authorization; HTTPS requests open a CONNECT tunnel first. A SOCKS5 proxy (RFC
+// 1928) tunnels every request, with username/password authentication (RFC 1929) when there
+// are credentials; `socks5://` resolves the target locally, `socks5h://` leaves it to the
+// proxy. Credentials come from --proxy-auth or from the proxy URL.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use std::env;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Longest CONNECT response head that is read
+const MAX_TUNNEL_RESPONSE: usize = 8192;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTHENTICATION: u8 = 0;
+const SOCKS_USERNAME_PASSWORD: u8 = 2;
+const SOCKS_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+const SOCKS_CONNECTION_REFUSED: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyScheme {
+    Http,
+    // TLS to the proxy itself
+    Https,
+    Socks5,
+    // SOCKS5 with the target resolved by the proxy
+    Socks5h,
+}
+
+impl ProxyScheme {
+    // Whether plain HTTP requests are forwarded to the proxy rather than tunneled
+    pub fn forwards_http(self) -> bool {
+        matches!(self, Self::Http | Self::Https)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    pub scheme: ProxyScheme,
+    pub host: String,
+    pub port: u16,
+    // Username and password, for SOCKS5
+    credentials: Option<(String, String)>,
+    // The same as Basic credentials, as a Proxy-Authorization value
+    pub authorization: Option<HeaderValue>,
+}
+
+// Where a tunnel leads to
+pub enum Address<'a> {
+    Ip(IpAddr),
+    Domain(&'a str),
+}
+
+impl fmt::Display for Address<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+            Self::Ip(ip) => write!(f, "{}", ip),
+            Self::Domain(domain) => write!(f, "{}", domain),
+        }
+    }
+}
+
+impl Proxy {
+    // "http://[USER:PASSWORD@]HOST[:PORT]", or https://, socks5:// or socks5h://; `auth`
+    // (USER:PASSWORD) replaces the credentials of the URL
+    pub fn parse(url: &str, auth: Option<&str>) -> Result<Self, BoxError> {
+        let invalid = |reason: &str| format!("invalid proxy {}: {}", url, reason);
+        // A bare HOST:PORT is an HTTP proxy, as for reqwest and curl
+        let parsed = match Url::parse(url) {
+            Ok(parsed) if parsed.has_host() => parsed,
+            _ => Url::parse(&format!("http://{}", url)).map_err(|e| invalid(&e.to_string()))?,
+        };
+        let scheme = match parsed.scheme() {
+            "http" => ProxyScheme::Http,
+            "https" => ProxyScheme::Https,
+            "socks5" => ProxyScheme::Socks5,
+            "socks5h" => ProxyScheme::Socks5h,
+            other => return Err(invalid(&format!("unsupported scheme {}", other)).into()),
+        };
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| invalid("no host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = parsed.port_or_known_default().unwrap_or(match scheme {
+            ProxyScheme::Http => 80,
+            ProxyScheme::Https => 443,
+            ProxyScheme::Socks5 | ProxyScheme::Socks5h => 1080,
+        });
+
+        let credentials = match auth {
+            Some(auth) => Some(
+                auth.split_once(':')
+                    .map(|(username, password)| (username.to_string(), password.to_string()))
+                    .ok_or("--proxy-auth must be USER:PASSWORD")?,
+            ),
+            None if !parsed.username().is_empty() => Some((
+                percent_decode(parsed.username()),
+                percent_decode(parsed.password().unwrap_or_default()),
+            )),
+            None => None,
+        };
+        let authorization = match &credentials {
+            Some((username, password)) => {
+                let encoded = STANDARD.encode(format!("{}:{}", username, password));
+                let mut value = HeaderValue::from_str(&format!("Basic {}", encoded))
+                    .map_err(|_| invalid("credentials are not valid in a header"))?;
+                value.set_sensitive(true);
+                Some(value)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            scheme,
+            host,
+            port,
+            credentials,
+            authorization,
+        })
+    }
+}
+
+// The proxies requests go through, by the scheme of the request
+#[derive(Debug, Default)]
+pub struct Proxies {
+    http: Option<Proxy>,
+    https: Option<Proxy>,
+    no_proxy: NoProxy,
+}
+
+impl Proxies {
+    // --proxy for every request, or the proxies of the environment
+    pub fn new(proxy: Option<&str>, auth: Option<&str>) -> Result<Self, BoxError> {
+        let no_proxy = NoProxy::from_env();
+        if let Some(url) = proxy {
+            let proxy = Proxy::parse(url, auth)?;
+            return Ok(Self {
+                http: Some(proxy.clone()),
+                https: Some(proxy),
+                no_proxy,
+            });
+        }
+
+        // Invalid values are ignored, as reqwest does
+        let from_env = |names: &[&str]| {
+            names
+                .iter()
+                .filter_map(|name| env::var(name).ok())
+                .find(|value| !value.trim().is_empty())
+                .and_then(|value| Proxy::parse(value.trim(), None).ok())
+        };
+        let all = from_env(&["ALL_PROXY", "all_proxy"]);
+        let http = match env::var_os("REQUEST_METHOD") {
+            Some(_) => None,
+            None => from_env(&["HTTP_PROXY", "http_proxy"]),
+        };
+        let https = from_env(&["HTTPS_PROXY", "https_proxy"]);
+        Ok(Self {
+            http: http.or_else(|| all.clone()),
+            https: https.or(all),
+            no_proxy,
+        })
+    }
+
+    // The proxy a request to `host` goes through, if any
+    pub fn route(&self, https: bool, host: &str) -> Option<&Proxy> {
+        let proxy = match https {
+            true => self.https.as_ref(),
+            false => self.http.as_ref(),
+        }?;
+        (!self.no_proxy.contains(host)).then_some(proxy)
+    }
+}
+
+// Hosts listed in NO_PROXY
+#[derive(Debug, Default)]
+struct NoProxy {
+    networks: Vec<(IpAddr, u8)>,
+    domains: Vec<String>,
+}
+
+impl NoProxy {
+    fn from_env() -> Self {
+        let list = env::var("NO_PROXY")
+            .or_else(|_| env::var("no_proxy"))
+            .unwrap_or_default();
+        let mut no_proxy = Self::default();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (address, prefix) = match entry.split_once('/') {
+                Some((address, prefix)) => (address, prefix.parse::<u8>().ok()),
+                None => (entry, None),
+            };
+            match address.parse::<IpAddr>() {
+                Ok(ip) => {
+                    let max = if ip.is_ipv4() { 32 } else { 128 };
+                    no_proxy.networks.push((ip, prefix.unwrap_or(max).min(max)));
+                }
+                Err(_) => no_proxy.domains.push(entry.to_ascii_lowercase()),
+            }
+        }
+        no_proxy
+    }
+
+    fn contains(&self, host: &str) -> bool {
+        if self.domains.iter().any(|domain| domain == "*") {
+            return true;
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return self
+                .networks
+                .iter()
+                .any(|(network, prefix)| in_network(ip, *network, *prefix));
+        }
+        let host = host.to_ascii_lowercase();
+        self.domains.iter().any(|domain| {
+            let bare = domain.trim_start_matches('.');
+            host == bare
+                || host
+                    .strip_suffix(bare)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    let shift = bits - u32::from(prefix);
+    shift >= bits || ip >> shift == network >> shift
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Ask the proxy at the other end of `stream` for a tunnel to `address` and `port`
+pub async fn tunnel<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &Proxy,
+    address: &Address<'_>,
+    port: u16,
+) -> io::Result<()> {
+    match proxy.scheme {
+        ProxyScheme::Http | ProxyScheme::Https => {
+            connect_tunnel(stream, proxy, address, port).await
+        }
+        ProxyScheme::Socks5 | ProxyScheme::Socks5h => {
+            socks5_tunnel(stream, proxy, address, port).await
+        }
+    }
+}
+
+async fn connect_tunnel<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &Proxy,
+    address: &Address<'_>,
+    port: u16,
+) -> io::Result<()> {
+    let authority = format!("{}:{}", address, port);
+    let mut head = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority).into_bytes();
+    if let Some(authorization) = &proxy.authorization {
+        head.extend_from_slice(b"Proxy-Authorization: ");
+        head.extend_from_slice(authorization.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    stream.write_all(&head).await?;
+
+    // Read byte by byte, so that nothing after the head (the start of the TLS handshake) is
+    // taken from the stream
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_TUNNEL_RESPONSE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response head is too long",
+            ));
+        }
+        let byte = stream.read_u8().await?;
+        response.push(byte);
+    }
+    let status_line = String::from_utf8_lossy(&response);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some("407") => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "proxy authentication required",
+        )),
+        _ => Err(io::Error::other(format!(
+            "proxy refused the tunnel: {}",
+            status_line
+        ))),
+    }
+}
+
+async fn socks5_tunnel<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &Proxy,
+    address: &Address<'_>,
+    port: u16,
+) -> io::Result<()> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let greeting: &[u8] = match proxy.credentials {
+        Some(_) => &[
+            SOCKS_VERSION,
+            2,
+            SOCKS_NO_AUTHENTICATION,
+            SOCKS_USERNAME_PASSWORD,
+        ],
+        None => &[SOCKS_VERSION, 1, SOCKS_NO_AUTHENTICATION],
+    };
+    stream.write_all(greeting).await?;
+    let mut choice = [0; 2];
+    stream.read_exact(&mut choice).await?;
+    match (choice, &proxy.credentials) {
+        ([SOCKS_VERSION, SOCKS_NO_AUTHENTICATION], _) => {}
+        ([SOCKS_VERSION, SOCKS_USERNAME_PASSWORD], Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(invalid(
+                    "SOCKS5 username and password are limited to 255 bytes",
+                ));
+            }
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            let mut status = [0; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "proxy authentication failed",
+                ));
+            }
+        }
+        ([SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHOD], _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "proxy authentication required",
+            ))
+        }
+        _ => return Err(invalid("not a SOCKS5 proxy")),
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    match address {
+        Address::Ip(IpAddr::V4(ip)) => {
+            request.push(SOCKS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Address::Ip(IpAddr::V6(ip)) => {
+            request.push(SOCKS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Address::Domain(domain) => {
+            if domain.len() > 255 {
+                return Err(invalid(
+                    "host names sent to a SOCKS5 proxy are limited to 255 bytes",
+                ));
+            }
+            request.extend_from_slice(&[SOCKS_DOMAIN, domain.len() as u8]);
+            request.extend_from_slice(domain.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    match reply[1] {
+        0 => {}
+        SOCKS_CONNECTION_REFUSED => {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "proxy: connection refused",
+            ))
+        }
+        code => {
+            let reason = match code {
+                1 => "general failure",
+                2 => "connection not allowed by ruleset",
+                3 => "network unreachable",
+                4 => "host unreachable",
+                6 => "TTL expired",
+                7 => "command not supported",
+                8 => "address type not supported",
+                _ => "unknown error",
+            };
+            return Err(io::Error::other(format!(
+                "proxy refused the tunnel: {}",
+                reason
+            )));
+        }
+    }
+    // The address the proxy connected from, which is not needed
+    let bound = match reply[3] {
+        SOCKS_IPV4 => 4,
+        SOCKS_IPV6 => 16,
+        SOCKS_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(invalid("invalid SOCKS5 reply")),
+    };
+    let mut rest = vec![0; bound + 2];
+    stream.read_exact(&mut rest).await?;
+    Ok(())
+}
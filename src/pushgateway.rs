@@ -0,0 +1,128 @@
+//! Prometheus Pushgateway output, for setups where Prometheus cannot receive Remote Write
+//
+// Every push replaces the run's group on the Pushgateway with the current registry in the
+// text exposition format, so Prometheus scrapes the latest values from there. The group is
+// keyed by `job` (--app unless --pushgateway-job is given), `instance` (the host name unless
+// --pushgateway-instance is given), `app`, the `run_id` and the --label values, which the
+// Pushgateway adds to every series. When the test ends the group is deleted, so its series
+// go stale like those of a finished scrape target, unless --pushgateway-keep asks to push
+// the final values and leave them.
+
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use prometheus::{Encoder, TextEncoder};
+use reqwest::{Client, Url};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct PushgatewayConfig {
+    // Server base URL, e.g. http://localhost:9091
+    pub url: String,
+    pub job: String,
+    pub instance: String,
+    // Further grouping labels: app, run_id and --label values
+    pub labels: Vec<(String, String)>,
+    // Leave the final values on the Pushgateway instead of deleting the group
+    pub keep: bool,
+}
+
+pub struct PushgatewayWriter {
+    client: Client,
+    group_url: Url,
+    keep: bool,
+}
+
+// The host name, the usual `instance` of a pushing batch job
+pub fn default_instance() -> String {
+    sysinfo::System::host_name().unwrap_or_else(|| "localhost".to_string())
+}
+
+impl PushgatewayWriter {
+    pub fn new(config: PushgatewayConfig) -> Result<Self, BoxError> {
+        Ok(Self {
+            client: Client::builder().timeout(PUSH_TIMEOUT).build()?,
+            group_url: group_url(&config)?,
+            keep: config.keep,
+        })
+    }
+
+    pub fn group_url(&self) -> &Url {
+        &self.group_url
+    }
+
+    // Replace the group with the current value of every series
+    pub async fn push(&self, registry: &prometheus::Registry) -> Result<(), BoxError> {
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+        encoder.encode(&registry.gather(), &mut body)?;
+        let request = self
+            .client
+            .put(self.group_url.clone())
+            .header("content-type", encoder.format_type())
+            .body(body);
+        check(request.send().await?, "push").await
+    }
+
+    // Delete the group, or push the final values when they are to be kept
+    pub async fn finish(&self, registry: &prometheus::Registry) -> Result<(), BoxError> {
+        if self.keep {
+            return self.push(registry).await;
+        }
+        let request = self.client.delete(self.group_url.clone());
+        check(request.send().await?, "delete").await
+    }
+}
+
+async fn check(response: reqwest::Response, action: &str) -> Result<(), BoxError> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!(
+            "Pushgateway {} failed with status {}: {}",
+            action, status, body
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// `/metrics/job/<job>/instance/<instance>/<label>/<value>...`; values that contain a slash go
+// base64 encoded after a `<label>@base64` segment, and empty ones as `=`, as the Pushgateway
+// expects
+fn group_url(config: &PushgatewayConfig) -> Result<Url, BoxError> {
+    let mut url = Url::parse(&config.url)
+        .map_err(|e| format!("invalid Pushgateway URL {}: {}", config.url, e))?;
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|_| format!("invalid Pushgateway URL {}", config.url))?;
+        segments.pop_if_empty().push("metrics");
+        let grouping = [("job", &config.job), ("instance", &config.instance)];
+        let mut names = Vec::new();
+        for (name, value) in grouping.into_iter().chain(
+            config
+                .labels
+                .iter()
+                .map(|(name, value)| (name.as_str(), value)),
+        ) {
+            // A group key names each label once; the first value wins
+            if names.contains(&name) {
+                continue;
+            }
+            names.push(name);
+            if value.is_empty() {
+                segments.push(&format!("{}@base64", name)).push("=");
+            } else if value.contains('/') {
+                segments.push(&format!("{}@base64", name));
+                segments.push(&URL_SAFE.encode(value));
+            } else {
+                segments.push(name).push(value);
+            }
+        }
+    }
+    Ok(url)
+}
@@ -0,0 +1,53 @@
+//! Global request rate ceiling for --max-rps, shared by all VUs
+//
+// A token bucket holding a single token, refilled at the configured rate. Instead of
+// polling for tokens, each caller reserves the time its token becomes available and sleeps
+// until then, so waiting VUs are served in order and the rate holds exactly on average.
+
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub struct RateLimiter {
+    interval: Duration,
+    // When the next token becomes available
+    next_token: Mutex<Instant>,
+    // Requests let through so far
+    permits: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(max_rps: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / max_rps),
+            next_token: Mutex::new(Instant::now()),
+            permits: AtomicU64::new(0),
+        }
+    }
+
+    // Wait until the next request may be sent
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_token = self.next_token.lock();
+            // An idle bucket holds one token, not the backlog of the idle time
+            let slot = (*next_token).max(Instant::now());
+            *next_token = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+        self.permits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn permits(&self) -> u64 {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+// Used as the clap value parser for --max-rps
+pub fn parse_max_rps(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("expected a positive request rate, got '{}'", rate)),
+    }
+}
@@ -1,19 +1,41 @@
 //! Prometheus Remote Write implementation
 
+mod v2;
+mod wal;
+
+use crate::trace;
+use lazy_static::lazy_static;
+use prometheus::{IntCounter, IntGauge};
 use prost::Message;
 use reqwest::Client;
 use snap::raw::Encoder;
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio::task::JoinHandle;
+use wal::Wal;
+
+// Upper bound on buffered segments when the write-ahead log is enabled
+const MAX_WAL_SEGMENTS: usize = 50_000;
+
+// Backoff between retries of a failed push: doubles from the initial delay up to the cap,
+// which also bounds a Retry-After sent by the endpoint
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// A push the endpoint has not answered within this long fails like a connection error
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the end of the run waits for queued metrics to be delivered
+const FINISH_TIMEOUT: Duration = Duration::from_secs(30);
 
 // Protobuf definitions for Prometheus Remote Write
 #[derive(Clone, PartialEq, prost::Message)]
 pub struct WriteRequest {
     #[prost(message, repeated, tag = "1")]
     pub timeseries: Vec<TimeSeries>,
-    #[prost(message, repeated, tag = "2")]
+    // Field 2 is reserved upstream
+    #[prost(message, repeated, tag = "3")]
     pub metadata: Vec<MetricMetadata>,
 }
 
@@ -75,13 +97,13 @@ pub struct Bucket {
 
 #[derive(Clone, PartialEq, prost::Message)]
 pub struct MetricMetadata {
-    #[prost(string, tag = "1")]
-    pub metric_name: String,
-    #[prost(enumeration = "MetricType", tag = "2")]
+    #[prost(enumeration = "MetricType", tag = "1")]
     pub r#type: i32,
-    #[prost(string, tag = "3")]
-    pub help: String,
+    #[prost(string, tag = "2")]
+    pub metric_family_name: String,
     #[prost(string, tag = "4")]
+    pub help: String,
+    #[prost(string, tag = "5")]
     pub unit: String,
 }
 
@@ -98,6 +120,107 @@ pub enum MetricType {
     Stateset = 7,
 }
 
+lazy_static! {
+    // Self-observability counters for the remote write pipeline
+    pub static ref REMOTE_WRITE_SAMPLES_SENT: IntCounter = IntCounter::new(
+        "forgy_remote_write_samples_sent_total", "Total number of samples delivered via Remote Write"
+    ).unwrap();
+
+    pub static ref REMOTE_WRITE_SAMPLES_FAILED: IntCounter = IntCounter::new(
+        "forgy_remote_write_samples_failed_total", "Total number of samples that failed to deliver via Remote Write"
+    ).unwrap();
+
+    pub static ref REMOTE_WRITE_QUEUE_DEPTH: IntGauge = IntGauge::new(
+        "forgy_remote_write_queue_depth", "Number of metric snapshots waiting to be sent via Remote Write"
+    ).unwrap();
+
+    pub static ref REMOTE_WRITE_RETRIES: IntCounter = IntCounter::new(
+        "forgy_remote_write_retries_total", "Total number of Remote Write requests retried after a failure"
+    ).unwrap();
+
+    pub static ref REMOTE_WRITE_DROPPED_BATCHES: IntCounter = IntCounter::new(
+        "forgy_remote_write_dropped_batches_total", "Total number of metric snapshots dropped because the Remote Write queue was full"
+    ).unwrap();
+}
+
+// What to do with a new snapshot when the Remote Write queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QueuePolicy {
+    /// Discard the new snapshot and count it as dropped
+    Drop,
+    /// Wait until the sender catches up
+    Block,
+}
+
+// Protocol version of the pushes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RemoteWriteVersion {
+    /// Remote Write 1.0 (prometheus.WriteRequest)
+    #[value(name = "1.0")]
+    V1,
+    /// Remote Write 2.0 (io.prometheus.write.v2.Request), falling back to 1.0 for endpoints
+    /// that do not accept it
+    #[value(name = "2.0")]
+    V2,
+}
+
+// Remote Write tuning options
+#[derive(Debug, Clone)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    // Upper bound on samples carried by a single WriteRequest
+    pub max_samples_per_request: usize,
+    // Upper bound on the encoded (uncompressed) size of a WriteRequest, for receivers that
+    // reject large payloads; a single series larger than this still goes out on its own
+    pub max_bytes_per_request: Option<usize>,
+    // Number of parallel senders a snapshot's batches are spread across, which is also the
+    // most requests in flight at once
+    pub shards: usize,
+    // Directory for the on-disk write-ahead buffer; batches are sent directly when unset
+    pub wal_dir: Option<std::path::PathBuf>,
+    // Maximum number of snapshots waiting for the sender task
+    pub queue_capacity: usize,
+    pub queue_policy: QueuePolicy,
+    // Retries of a request that failed with a connection error, 429 or 5xx
+    pub max_retries: u32,
+    // Static labels attached to every series, after `app`
+    pub labels: Vec<(String, String)>,
+    pub version: RemoteWriteVersion,
+}
+
+// The protocol version pushes use, shared by every shard. 2.0 is dropped for the rest of the
+// run once the endpoint turns out not to accept it, and confirmed by the first 2.0 push the
+// endpoint acknowledges; only before that does a rejected 2.0 push mean an older receiver
+struct Protocol {
+    v2: AtomicBool,
+    v2_confirmed: AtomicBool,
+}
+
+impl Protocol {
+    fn new(version: RemoteWriteVersion) -> Self {
+        Self {
+            v2: AtomicBool::new(version == RemoteWriteVersion::V2),
+            v2_confirmed: AtomicBool::new(false),
+        }
+    }
+}
+
+// A failed push, with whether and when the endpoint allows trying again
+#[derive(Debug)]
+struct SendError {
+    message: String,
+    retryable: bool,
+    retry_after: Option<Duration>,
+    // The endpoint may not take the protocol version the payload was sent in
+    unsupported: bool,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 // Message for the metrics queue
 #[derive(Debug)]
 pub struct MetricsMessage {
@@ -105,46 +228,66 @@ pub struct MetricsMessage {
     pub app: String,
 }
 
+#[derive(Debug)]
+enum QueueMessage {
+    Snapshot(MetricsMessage),
+    // Mark every series of the last snapshot as stale
+    MarkStale,
+    Shutdown,
+}
+
+// Prometheus staleness marker: a NaN with this exact bit pattern
+const STALE_NAN_BITS: u64 = 0x7ff0_0000_0000_0002;
+
 // Remote Write client with queue
 pub struct RemoteWriteClient {
     client: Client,
-    url: String,
-    metrics_sender: Sender<MetricsMessage>,
+    config: RemoteWriteConfig,
+    metrics_sender: Sender<QueueMessage>,
     last_timestamp: Arc<Mutex<i64>>,
+    processor: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl Clone for RemoteWriteClient {
     fn clone(&self) -> Self {
         Self {
             client: self.client.clone(),
-            url: self.url.clone(),
+            config: self.config.clone(),
             metrics_sender: self.metrics_sender.clone(),
             last_timestamp: self.last_timestamp.clone(),
+            processor: self.processor.clone(),
         }
     }
 }
 
 impl RemoteWriteClient {
-    pub fn new(url: String) -> Self {
-        let client = Client::new();
-        let (sender, receiver) = mpsc::channel();
+    // Must be called from within the Tokio runtime the sender task is to run on
+    pub fn new(
+        config: RemoteWriteConfig,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
         let last_timestamp = Arc::new(Mutex::new(0));
 
-        // Spawn background thread for processing metrics
-        let url_clone = url.clone();
+        // Spawn background task for processing metrics
+        let config_clone = config.clone();
         let client_clone = client.clone();
         let timestamp_clone = last_timestamp.clone();
 
-        thread::spawn(move || {
-            Self::metrics_processor_thread(receiver, client_clone, url_clone, timestamp_clone);
-        });
+        let processor = tokio::spawn(Self::process_queue(
+            receiver,
+            client_clone,
+            config_clone,
+            timestamp_clone,
+        ));
 
-        Self {
+        Ok(Self {
             client,
-            url,
+            config,
             metrics_sender: sender,
             last_timestamp,
-        }
+            processor: Arc::new(Mutex::new(Some(processor))),
+        })
     }
 
     pub async fn send_metrics(
@@ -155,76 +298,379 @@ impl RemoteWriteClient {
         let metric_families = metrics.gather();
 
         // Send metrics to the queue for sequential processing
-        let message = MetricsMessage {
+        let message = QueueMessage::Snapshot(MetricsMessage {
             metric_families,
             app: app.to_string(),
-        };
+        });
 
-        self.metrics_sender
-            .send(message)
-            .map_err(|e| format!("Failed to send metrics to queue: {}", e))?;
+        REMOTE_WRITE_QUEUE_DEPTH.inc();
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> =
+            match self.config.queue_policy {
+                QueuePolicy::Drop => match self.metrics_sender.try_send(message) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(_)) => {
+                        REMOTE_WRITE_DROPPED_BATCHES.inc();
+                        Err("Remote Write queue is full, dropping metrics snapshot".into())
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        Err("Failed to send metrics to queue: sender task stopped".into())
+                    }
+                },
+                QueuePolicy::Block => self
+                    .metrics_sender
+                    .send(message)
+                    .await
+                    .map_err(|e| format!("Failed to send metrics to queue: {}", e).into()),
+            };
+        if result.is_err() {
+            REMOTE_WRITE_QUEUE_DEPTH.dec();
+        }
 
-        Ok(())
+        result
     }
 
-    // Background thread that processes metrics sequentially with monotonic timestamps
-    fn metrics_processor_thread(
-        receiver: Receiver<MetricsMessage>,
+    // Push a last snapshot, mark all series stale and wait until everything queued has been
+    // delivered, so dashboards see the run end instead of values frozen at their last sample.
+    // An endpoint that does not keep up gets FINISH_TIMEOUT before the sender is stopped, so
+    // an unresponsive Prometheus cannot hold up the exit
+    pub async fn finish(
+        self,
+        metrics: &prometheus::Registry,
+        app: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let final_snapshot = QueueMessage::Snapshot(MetricsMessage {
+            metric_families: metrics.gather(),
+            app: app.to_string(),
+        });
+        let mut processor = self.processor.lock().unwrap().take();
+
+        let flush = async {
+            // The final snapshot is never subject to the drop policy
+            REMOTE_WRITE_QUEUE_DEPTH.inc();
+            for message in [
+                final_snapshot,
+                QueueMessage::MarkStale,
+                QueueMessage::Shutdown,
+            ] {
+                self.metrics_sender
+                    .send(message)
+                    .await
+                    .map_err(|e| format!("Failed to send final metrics to queue: {}", e))?;
+            }
+
+            // Snapshots still queued ahead of these are delivered before the task ends
+            if let Some(processor) = processor.as_mut() {
+                processor
+                    .await
+                    .map_err(|_| "Remote Write sender task panicked")?;
+            }
+            Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+        };
+
+        match tokio::time::timeout(FINISH_TIMEOUT, flush).await {
+            Ok(result) => result,
+            Err(_) => {
+                if let Some(processor) = processor {
+                    processor.abort();
+                }
+                let snapshots = REMOTE_WRITE_QUEUE_DEPTH.get().max(0);
+                match &self.config.wal_dir {
+                    Some(dir) => tracing::warn!(
+                        snapshots,
+                        dir = %dir.display(),
+                        "Remote Write did not finish within {}s; batches not yet delivered \
+                         stay in the WAL for the next run",
+                        FINISH_TIMEOUT.as_secs()
+                    ),
+                    None => tracing::warn!(
+                        snapshots,
+                        "Remote Write did not finish within {}s; the batches in flight and \
+                         the queued snapshots were not delivered",
+                        FINISH_TIMEOUT.as_secs()
+                    ),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Background task that processes metrics sequentially with monotonic timestamps
+    async fn process_queue(
+        mut receiver: Receiver<QueueMessage>,
         client: Client,
-        url: String,
+        config: RemoteWriteConfig,
         last_timestamp: Arc<Mutex<i64>>,
     ) {
-        let rt = tokio::runtime::Runtime::new().unwrap();
-
-        while let Ok(message) = receiver.recv() {
-            // Generate monotonic timestamp
-            let timestamp = {
-                let mut last = last_timestamp.lock().unwrap();
-                let current = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64;
-
-                let timestamp = if current <= *last {
-                    *last + 1 // Increment by 1ms if current time is not ahead
-                } else {
-                    current
-                };
+        let mut wal =
+            config
+                .wal_dir
+                .as_ref()
+                .and_then(|dir| match Wal::open(dir, MAX_WAL_SEGMENTS) {
+                    Ok(wal) => Some(wal),
+                    Err(e) => {
+                        tracing::warn!(
+                            error = %e,
+                            dir = %dir.display(),
+                            "failed to open Remote Write WAL"
+                        );
+                        None
+                    }
+                });
 
-                *last = timestamp;
-                timestamp
+        let protocol = Arc::new(Protocol::new(config.version));
+
+        // Label sets of the most recent snapshot, needed to emit staleness markers
+        let mut last_series: Vec<Vec<Label>> = Vec::new();
+        // Types and help texts of its metrics, which 2.0 carries with every series
+        let mut metadata: Vec<MetricMetadata> = Vec::new();
+
+        while let Some(message) = receiver.recv().await {
+            let timeseries = match message {
+                QueueMessage::Snapshot(message) => {
+                    REMOTE_WRITE_QUEUE_DEPTH.dec();
+                    let timestamp = Self::next_timestamp(&last_timestamp);
+
+                    // Process metrics with the monotonic timestamp
+                    let timeseries = Self::process_metric_families(
+                        &message.metric_families,
+                        &message.app,
+                        &config.labels,
+                        timestamp,
+                    );
+                    last_series = timeseries
+                        .iter()
+                        .map(|series| series.labels.clone())
+                        .collect();
+                    if config.version == RemoteWriteVersion::V2 {
+                        metadata = Self::family_metadata(&message.metric_families);
+                    }
+                    timeseries
+                }
+                QueueMessage::MarkStale => {
+                    let timestamp = Self::next_timestamp(&last_timestamp);
+                    Self::create_stale_timeseries(std::mem::take(&mut last_series), timestamp)
+                }
+                QueueMessage::Shutdown => break,
             };
 
-            // Process metrics with the monotonic timestamp
-            let timeseries =
-                Self::process_metric_families(&message.metric_families, &message.app, timestamp);
+            // Send to Prometheus in size-bounded batches
+            let batches = Self::split_into_batches(timeseries, &config, &metadata);
+            match wal.as_mut() {
+                Some(wal) => {
+                    Self::append_to_wal(wal, batches);
+                    Self::drain_wal(&client, &config, wal, &protocol).await;
+                }
+                None => Self::send_batches_sharded(&client, &config, batches, &protocol).await,
+            }
+        }
+    }
+
+    // Generate monotonic timestamp
+    fn next_timestamp(last_timestamp: &Mutex<i64>) -> i64 {
+        let mut last = last_timestamp.lock().unwrap();
+        let current = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
 
-            let write_request = WriteRequest {
-                timeseries,
-                metadata: Vec::new(),
+        let timestamp = if current <= *last {
+            *last + 1 // Increment by 1ms if current time is not ahead
+        } else {
+            current
+        };
+
+        *last = timestamp;
+        timestamp
+    }
+
+    fn create_stale_timeseries(series_labels: Vec<Vec<Label>>, timestamp: i64) -> Vec<TimeSeries> {
+        series_labels
+            .into_iter()
+            .map(|labels| TimeSeries {
+                labels,
+                samples: vec![Sample {
+                    value: f64::from_bits(STALE_NAN_BITS),
+                    timestamp,
+                }],
+                exemplars: Vec::new(),
+                histograms: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn append_to_wal(wal: &mut Wal, batches: Vec<WriteRequest>) {
+        for write_request in batches {
+            let samples = Self::count_samples(&write_request);
+            let appended = Self::compress_write_request(&write_request)
+                .and_then(|payload| Ok(wal.append(&payload, samples)?));
+            match appended {
+                Ok(dropped) => REMOTE_WRITE_SAMPLES_FAILED.inc_by(dropped),
+                Err(e) => {
+                    REMOTE_WRITE_SAMPLES_FAILED.inc_by(samples);
+                    tracing::warn!(error = %e, "failed to append metrics to Remote Write WAL");
+                }
+            }
+        }
+    }
+
+    // Deliver buffered segments oldest first, stopping at the first failure so the
+    // remaining samples keep their order for the next attempt
+    async fn drain_wal(
+        client: &Client,
+        config: &RemoteWriteConfig,
+        wal: &Wal,
+        protocol: &Protocol,
+    ) {
+        let segments = match wal.segments() {
+            Ok(segments) => segments,
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to read Remote Write WAL");
+                return;
+            }
+        };
+
+        for segment in segments {
+            let payload = match segment.read() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to read Remote Write WAL segment");
+                    continue;
+                }
             };
 
-            // Send to Prometheus
-            if let Err(e) = rt.block_on(Self::send_write_request_static(
-                &client,
-                &url,
-                write_request,
-            )) {
-                eprintln!("Failed to send metrics via Remote Write: {}", e);
+            let sent =
+                Self::send_with_retry(client, &config.url, payload, config.max_retries, protocol);
+            match sent.await {
+                Ok(()) => {
+                    REMOTE_WRITE_SAMPLES_SENT.inc_by(segment.samples);
+                    if let Err(e) = segment.remove() {
+                        tracing::warn!(error = %e, "failed to remove delivered WAL segment");
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error = %e,
+                        "failed to send metrics via Remote Write, keeping them buffered"
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    fn count_samples(write_request: &WriteRequest) -> u64 {
+        write_request
+            .timeseries
+            .iter()
+            .map(|series| series.samples.len() as u64)
+            .sum()
+    }
+
+    // Batches within both the sample and the byte limit. Every batch carries the metadata,
+    // when there is any, so it can be sent as 2.0 on its own
+    fn split_into_batches(
+        timeseries: Vec<TimeSeries>,
+        config: &RemoteWriteConfig,
+        metadata: &[MetricMetadata],
+    ) -> Vec<WriteRequest> {
+        let max_samples = config.max_samples_per_request.max(1);
+        let max_bytes = config.max_bytes_per_request.unwrap_or(usize::MAX);
+        let metadata_bytes: usize = metadata
+            .iter()
+            .map(|metadata| prost::encoding::message::encoded_len(3, metadata))
+            .sum();
+        let mut batches = Vec::new();
+        let mut current = Vec::new();
+        let mut current_samples = 0;
+        let mut current_bytes = metadata_bytes;
+
+        for series in timeseries {
+            let samples = series.samples.len().max(1);
+            let bytes = prost::encoding::message::encoded_len(1, &series);
+            let full = current_samples + samples > max_samples
+                || current_bytes.saturating_add(bytes) > max_bytes;
+            if full && !current.is_empty() {
+                batches.push(WriteRequest {
+                    timeseries: std::mem::take(&mut current),
+                    metadata: metadata.to_vec(),
+                });
+                current_samples = 0;
+                current_bytes = metadata_bytes;
             }
+            current_samples += samples;
+            current_bytes += bytes;
+            current.push(series);
+        }
+
+        if !current.is_empty() {
+            batches.push(WriteRequest {
+                timeseries: current,
+                metadata: metadata.to_vec(),
+            });
+        }
+
+        batches
+    }
+
+    // Spread batches round-robin across shards; each shard sends its batches in order while
+    // shards run in parallel. The next snapshot is only processed once every shard is done,
+    // so samples of a series are never sent out of order.
+    async fn send_batches_sharded(
+        client: &Client,
+        config: &RemoteWriteConfig,
+        batches: Vec<WriteRequest>,
+        protocol: &Arc<Protocol>,
+    ) {
+        let shard_count = config.shards.max(1);
+        let mut shards: Vec<Vec<WriteRequest>> = (0..shard_count).map(|_| Vec::new()).collect();
+        for (index, batch) in batches.into_iter().enumerate() {
+            shards[index % shard_count].push(batch);
+        }
+
+        let mut handles = Vec::new();
+        for shard in shards.into_iter().filter(|shard| !shard.is_empty()) {
+            let client = client.clone();
+            let url = config.url.clone();
+            let max_retries = config.max_retries;
+            let protocol = protocol.clone();
+            handles.push(tokio::spawn(async move {
+                for write_request in shard {
+                    let samples = Self::count_samples(&write_request);
+                    let sent = match Self::compress_write_request(&write_request) {
+                        Ok(payload) => {
+                            Self::send_with_retry(&client, &url, payload, max_retries, &protocol)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match sent {
+                        Ok(()) => REMOTE_WRITE_SAMPLES_SENT.inc_by(samples),
+                        Err(e) => {
+                            REMOTE_WRITE_SAMPLES_FAILED.inc_by(samples);
+                            tracing::warn!(error = %e, "failed to send metrics via Remote Write");
+                        }
+                    }
+                }
+            }));
+        }
+
+        for handle in handles {
+            handle.await.ok();
         }
     }
 
     fn process_metric_families(
         metric_families: &[prometheus::proto::MetricFamily],
         app: &str,
+        static_labels: &[(String, String)],
         timestamp: i64,
     ) -> Vec<TimeSeries> {
         let mut timeseries = Vec::new();
 
         for family in metric_families {
             for metric in family.get_metric() {
-                let base_labels = Self::create_base_labels(family.get_name(), app, metric);
+                let base_labels =
+                    Self::create_base_labels(family.get_name(), app, static_labels, metric);
 
                 if metric.has_counter() {
                     timeseries.push(Self::create_counter_timeseries(
@@ -239,7 +685,7 @@ impl RemoteWriteClient {
                         timestamp,
                     ));
                 } else if metric.has_histogram() {
-                    let mut hist_timeseries = Self::create_histogram_timeseries_simple(
+                    let mut hist_timeseries = Self::create_histogram_timeseries(
                         base_labels,
                         family.get_name(),
                         metric,
@@ -253,9 +699,32 @@ impl RemoteWriteClient {
         timeseries
     }
 
+    // Only the families' own names: histogram series are matched to theirs by suffix
+    fn family_metadata(metric_families: &[prometheus::proto::MetricFamily]) -> Vec<MetricMetadata> {
+        metric_families
+            .iter()
+            .map(|family| {
+                let r#type = match family.get_field_type() {
+                    prometheus::proto::MetricType::COUNTER => MetricType::Counter,
+                    prometheus::proto::MetricType::GAUGE => MetricType::Gauge,
+                    prometheus::proto::MetricType::HISTOGRAM => MetricType::Histogram,
+                    prometheus::proto::MetricType::SUMMARY => MetricType::Summary,
+                    prometheus::proto::MetricType::UNTYPED => MetricType::Unknown,
+                };
+                MetricMetadata {
+                    metric_family_name: family.get_name().to_string(),
+                    r#type: r#type as i32,
+                    help: family.get_help().to_string(),
+                    unit: String::new(),
+                }
+            })
+            .collect()
+    }
+
     fn create_base_labels(
         metric_name: &str,
         app: &str,
+        static_labels: &[(String, String)],
         metric: &prometheus::proto::Metric,
     ) -> Vec<Label> {
         let mut labels = vec![
@@ -269,6 +738,20 @@ impl RemoteWriteClient {
             },
         ];
 
+        // A series must not repeat a label name, so the metric's own labels win
+        for (name, value) in static_labels {
+            if metric
+                .get_label()
+                .iter()
+                .all(|pair| pair.get_name() != name)
+            {
+                labels.push(Label {
+                    name: name.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+
         for label_pair in metric.get_label() {
             labels.push(Label {
                 name: label_pair.get_name().to_string(),
@@ -311,7 +794,9 @@ impl RemoteWriteClient {
         }
     }
 
-    fn create_histogram_timeseries_simple(
+    // Classic Prometheus histogram series: cumulative `<name>_bucket{le="..."}` including the
+    // `+Inf` bucket, plus `<name>_count` and `<name>_sum`, as histogram_quantile expects
+    fn create_histogram_timeseries(
         base_labels: Vec<Label>,
         metric_name: &str,
         metric: &prometheus::proto::Metric,
@@ -320,58 +805,131 @@ impl RemoteWriteClient {
         let hist = metric.get_histogram();
         let mut timeseries = Vec::new();
 
-        for bucket in hist.get_bucket() {
-            let mut bucket_labels = base_labels.clone();
-            bucket_labels.push(Label {
-                name: "le".to_string(),
-                value: bucket.get_upper_bound().to_string(),
-            });
-
-            timeseries.push(TimeSeries {
-                labels: bucket_labels,
-                samples: vec![Sample {
-                    value: bucket.get_cumulative_count() as f64,
-                    timestamp,
-                }],
-                exemplars: Vec::new(),
-                histograms: Vec::new(),
-            });
-        }
-
-        let mut count_labels = base_labels.clone();
-        count_labels[0].value = format!("{}_count", metric_name);
-
-        timeseries.push(TimeSeries {
-            labels: count_labels,
-            samples: vec![Sample {
-                value: hist.get_sample_count() as f64,
-                timestamp,
-            }],
+        let sample = |labels: Vec<Label>, value: f64| TimeSeries {
+            labels,
+            samples: vec![Sample { value, timestamp }],
             exemplars: Vec::new(),
             histograms: Vec::new(),
-        });
+        };
+        let series_labels = |suffix: &str, le: Option<String>| {
+            let mut labels = base_labels.clone();
+            labels[0].value = format!("{}{}", metric_name, suffix);
+            if let Some(le) = le {
+                labels.push(Label {
+                    name: "le".to_string(),
+                    value: le,
+                });
+            }
+            labels
+        };
 
-        let mut sum_labels = base_labels;
-        sum_labels[0].value = format!("{}_sum", metric_name);
+        // Trace of the latest sampled request that fell into the bucket, if any
+        let bucket_series = |upper_bound: f64, value: f64| {
+            let mut series = sample(
+                series_labels("_bucket", Some(format_le(upper_bound))),
+                value,
+            );
+            if let Some(exemplar) = trace::exemplar(metric_name, metric, upper_bound) {
+                series.exemplars.push(Exemplar {
+                    labels: vec![
+                        Label {
+                            name: "trace_id".to_string(),
+                            value: exemplar.trace_id_hex(),
+                        },
+                        Label {
+                            name: "span_id".to_string(),
+                            value: exemplar.span_id_hex(),
+                        },
+                    ],
+                    value: exemplar.value,
+                    timestamp: exemplar.timestamp_millis,
+                });
+            }
+            series
+        };
 
-        timeseries.push(TimeSeries {
-            labels: sum_labels,
-            samples: vec![Sample {
-                value: hist.get_sample_sum(),
-                timestamp,
-            }],
-            exemplars: Vec::new(),
-            histograms: Vec::new(),
-        });
+        // The client library leaves the +Inf bucket implicit; it always equals the count
+        let mut has_inf_bucket = false;
+        for bucket in hist.get_bucket() {
+            let upper_bound = bucket.get_upper_bound();
+            has_inf_bucket |= upper_bound == f64::INFINITY;
+            timeseries.push(bucket_series(
+                upper_bound,
+                bucket.get_cumulative_count() as f64,
+            ));
+        }
+        if !has_inf_bucket {
+            timeseries.push(bucket_series(f64::INFINITY, hist.get_sample_count() as f64));
+        }
+
+        timeseries.push(sample(
+            series_labels("_count", None),
+            hist.get_sample_count() as f64,
+        ));
+        timeseries.push(sample(series_labels("_sum", None), hist.get_sample_sum()));
 
         timeseries
     }
 
-    async fn send_write_request_static(
+    // Push a 1.0 payload, as 2.0 while `protocol` says so, retrying connection errors, 429
+    // and 5xx with exponential backoff; a Retry-After from the endpoint takes precedence over
+    // the computed delay. An endpoint that turns out not to accept 2.0 gets the payload again
+    // as 1.0, and so do all later pushes
+    async fn send_with_retry(
         client: &Client,
         url: &str,
-        write_request: WriteRequest,
+        compressed: Vec<u8>,
+        max_retries: u32,
+        protocol: &Protocol,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let transcoded = if protocol.v2.load(Ordering::Relaxed) {
+            Some(v2::transcode(&compressed)?)
+        } else {
+            None
+        };
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            let (payload, version) = match transcoded
+                .as_ref()
+                .filter(|_| protocol.v2.load(Ordering::Relaxed))
+            {
+                Some(transcoded) => (transcoded.clone(), RemoteWriteVersion::V2),
+                None => (compressed.clone(), RemoteWriteVersion::V1),
+            };
+            match Self::send_payload(client, url, payload, version).await {
+                Ok(()) => {
+                    if version == RemoteWriteVersion::V2 {
+                        protocol.v2_confirmed.store(true, Ordering::Relaxed);
+                    }
+                    return Ok(());
+                }
+                Err(e) if e.unsupported && !protocol.v2_confirmed.load(Ordering::Relaxed) => {
+                    if protocol.v2.swap(false, Ordering::Relaxed) {
+                        tracing::warn!(
+                            error = %e,
+                            "endpoint does not accept Remote Write 2.0, falling back to 1.0"
+                        );
+                    }
+                }
+                Err(e) if e.retryable && attempt < max_retries => {
+                    attempt += 1;
+                    REMOTE_WRITE_RETRIES.inc();
+                    let wait = e.retry_after.unwrap_or(delay).min(MAX_RETRY_DELAY);
+                    tokio::time::sleep(wait).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(e) if attempt > 0 => {
+                    return Err(format!("{} (after {} retries)", e, attempt).into())
+                }
+                Err(e) => return Err(e.message.into()),
+            }
+        }
+    }
+
+    fn compress_write_request(
+        write_request: &WriteRequest,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
         let encoded = write_request.encode_to_vec();
 
         let mut encoder = Encoder::new();
@@ -379,22 +937,92 @@ impl RemoteWriteClient {
             .compress_vec(&encoded)
             .map_err(|e| format!("Failed to compress data: {}", e))?;
 
+        Ok(compressed)
+    }
+
+    async fn send_payload(
+        client: &Client,
+        url: &str,
+        compressed: Vec<u8>,
+        version: RemoteWriteVersion,
+    ) -> Result<(), SendError> {
+        let (content_type, protocol_version) = match version {
+            RemoteWriteVersion::V1 => ("application/x-protobuf", "0.1.0"),
+            RemoteWriteVersion::V2 => (v2::CONTENT_TYPE, v2::VERSION),
+        };
         let response = client
             .post(url)
-            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Type", content_type)
             .header("Content-Encoding", "snappy")
-            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .header("X-Prometheus-Remote-Write-Version", protocol_version)
             .body(compressed)
             .send()
             .await
-            .map_err(|e| format!("Failed to send request: {}", e))?;
+            .map_err(|e| SendError {
+                message: format!("Failed to send request: {}", e),
+                retryable: true,
+                retry_after: None,
+                unsupported: false,
+            })?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
             let body = response.text().await.unwrap_or_default();
-            return Err(format!("Remote write failed with status {}: {}", status, body).into());
+            return Err(SendError {
+                message: format!("Remote write failed with status {}: {}", status, body),
+                // Other 4xx mean the payload itself was rejected; sending it again won't help
+                retryable: status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || status.is_server_error(),
+                retry_after,
+                // Receivers older than 2.0 mostly answer a 2.0 body with 400 rather than 415
+                unsupported: version == RemoteWriteVersion::V2
+                    && status.is_client_error()
+                    && status != reqwest::StatusCode::TOO_MANY_REQUESTS,
+            });
+        }
+
+        // A 1.0 receiver reads nothing from a 2.0 body and still accepts it; only a 2.0
+        // receiver reports what it wrote
+        if version == RemoteWriteVersion::V2
+            && !response.headers().contains_key(v2::SAMPLES_WRITTEN_HEADER)
+        {
+            return Err(SendError {
+                message: format!(
+                    "Remote write endpoint answered {} without {}",
+                    response.status(),
+                    v2::SAMPLES_WRITTEN_HEADER
+                ),
+                retryable: false,
+                retry_after: None,
+                unsupported: true,
+            });
         }
 
         Ok(())
     }
 }
+
+// Retry-After is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+// `le` label values as the Prometheus text format writes them
+fn format_le(upper_bound: f64) -> String {
+    if upper_bound == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        upper_bound.to_string()
+    }
+}
@@ -0,0 +1,158 @@
+//! Remote Write 2.0 payloads (`io.prometheus.write.v2.Request`)
+//
+// Batches are built, buffered in the WAL and retried as 1.0 WriteRequests; pushes to a 2.0
+// endpoint translate them on the way out. 2.0 interns every label name and value in one
+// symbol table per request, whose first entry is the empty string, and carries the type and
+// help text of each series inline instead of in separate metadata.
+
+use super::{Label, MetricMetadata, MetricType, Sample, WriteRequest};
+use prost::Message;
+use snap::raw::{Decoder, Encoder};
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf;proto=io.prometheus.write.v2.Request";
+pub const VERSION: &str = "2.0.0";
+// Sent back by 2.0 receivers; a 1.0 receiver accepts a 2.0 body without it, having read
+// nothing from it
+pub const SAMPLES_WRITTEN_HEADER: &str = "x-prometheus-remote-write-samples-written";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Request {
+    #[prost(string, repeated, tag = "4")]
+    pub symbols: Vec<String>,
+    #[prost(message, repeated, tag = "5")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+    // Pairs of symbol references: name, value, name, value, ...
+    #[prost(uint32, repeated, tag = "1")]
+    pub labels_refs: Vec<u32>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+    #[prost(message, repeated, tag = "4")]
+    pub exemplars: Vec<Exemplar>,
+    #[prost(message, optional, tag = "5")]
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Exemplar {
+    #[prost(uint32, repeated, tag = "1")]
+    pub labels_refs: Vec<u32>,
+    #[prost(double, tag = "2")]
+    pub value: f64,
+    #[prost(int64, tag = "3")]
+    pub timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metadata {
+    // Numbered as in 1.0, with 0 for unspecified
+    #[prost(enumeration = "MetricType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "3")]
+    pub help_ref: u32,
+    #[prost(uint32, tag = "4")]
+    pub unit_ref: u32,
+}
+
+#[derive(Default)]
+struct Symbols {
+    table: Vec<String>,
+    refs: HashMap<String, u32>,
+}
+
+impl Symbols {
+    fn new() -> Self {
+        let mut symbols = Self::default();
+        symbols.intern("");
+        symbols
+    }
+
+    fn intern(&mut self, symbol: &str) -> u32 {
+        if let Some(index) = self.refs.get(symbol) {
+            return *index;
+        }
+        let index = self.table.len() as u32;
+        self.table.push(symbol.to_string());
+        self.refs.insert(symbol.to_string(), index);
+        index
+    }
+
+    fn labels(&mut self, labels: &[Label]) -> Vec<u32> {
+        labels
+            .iter()
+            .flat_map(|label| [self.intern(&label.name), self.intern(&label.value)])
+            .collect()
+    }
+}
+
+// The 2.0 form of a batch; series get the type and help of the request's metadata entry for
+// their metric, or for the histogram they are a `_bucket`, `_count` or `_sum` series of
+pub fn from_v1(request: &WriteRequest) -> Request {
+    let metadata: HashMap<&str, &MetricMetadata> = request
+        .metadata
+        .iter()
+        .map(|metadata| (metadata.metric_family_name.as_str(), metadata))
+        .collect();
+    let lookup = |name: &str| {
+        metadata.get(name).copied().or_else(|| {
+            ["_bucket", "_count", "_sum"]
+                .iter()
+                .find_map(|suffix| metadata.get(name.strip_suffix(suffix)?).copied())
+        })
+    };
+
+    let mut symbols = Symbols::new();
+    let timeseries = request
+        .timeseries
+        .iter()
+        .map(|series| {
+            let name = series
+                .labels
+                .iter()
+                .find(|label| label.name == "__name__")
+                .map_or("", |label| label.value.as_str());
+            let metadata = lookup(name).map(|metadata| Metadata {
+                r#type: metadata.r#type,
+                help_ref: symbols.intern(&metadata.help),
+                unit_ref: symbols.intern(&metadata.unit),
+            });
+            TimeSeries {
+                labels_refs: symbols.labels(&series.labels),
+                samples: series.samples.clone(),
+                exemplars: series
+                    .exemplars
+                    .iter()
+                    .map(|exemplar| Exemplar {
+                        labels_refs: symbols.labels(&exemplar.labels),
+                        value: exemplar.value,
+                        timestamp: exemplar.timestamp,
+                    })
+                    .collect(),
+                metadata,
+            }
+        })
+        .collect();
+
+    Request {
+        symbols: symbols.table,
+        timeseries,
+    }
+}
+
+// A compressed 1.0 payload, as built for the WAL, compressed again as 2.0
+pub fn transcode(payload: &[u8]) -> Result<Vec<u8>, BoxError> {
+    let encoded = Decoder::new()
+        .decompress_vec(payload)
+        .map_err(|e| format!("Failed to decompress data: {}", e))?;
+    let request = WriteRequest::decode(encoded.as_slice())
+        .map_err(|e| format!("Failed to decode WriteRequest: {}", e))?;
+    Ok(Encoder::new()
+        .compress_vec(&from_v1(&request).encode_to_vec())
+        .map_err(|e| format!("Failed to compress data: {}", e))?)
+}
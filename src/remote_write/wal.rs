@@ -0,0 +1,109 @@
+//! On-disk write-ahead buffer for Remote Write payloads
+//!
+//! Every batch is persisted as a snappy-compressed segment before it is sent and only
+//! removed once the endpoint has accepted it, so samples survive an unreachable
+//! endpoint or a restart of forgy and are delivered on the next attempt.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_EXTENSION: &str = "rw";
+
+// A single persisted WriteRequest payload
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub path: PathBuf,
+    pub seq: u64,
+    pub samples: u64,
+}
+
+impl Segment {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+
+    // Segment files are named "<seq>-<samples>.rw"
+    fn from_path(path: PathBuf) -> Option<Self> {
+        if path.extension()? != SEGMENT_EXTENSION {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let (seq, samples) = stem.split_once('-')?;
+        Some(Self {
+            seq: seq.parse().ok()?,
+            samples: samples.parse().ok()?,
+            path,
+        })
+    }
+}
+
+pub struct Wal {
+    dir: PathBuf,
+    next_seq: u64,
+    max_segments: usize,
+}
+
+impl Wal {
+    pub fn open(dir: impl AsRef<Path>, max_segments: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        // Drop partially written segments left behind by an interrupted run
+        for entry in fs::read_dir(&dir)?.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let mut wal = Self {
+            dir,
+            next_seq: 0,
+            max_segments: max_segments.max(1),
+        };
+        wal.next_seq = wal.segments()?.last().map_or(0, |segment| segment.seq + 1);
+        Ok(wal)
+    }
+
+    // Persist a compressed payload; returns the number of samples discarded to stay within the
+    // segment limit (oldest segments go first)
+    pub fn append(&mut self, payload: &[u8], samples: u64) -> io::Result<u64> {
+        let name = format!("{:020}-{}.{}", self.next_seq, samples, SEGMENT_EXTENSION);
+        let tmp_path = self.dir.join(format!("{}.tmp", name));
+
+        // Write to a temporary file first so a crash never leaves a truncated segment behind
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(payload)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, self.dir.join(name))?;
+        self.next_seq += 1;
+
+        let segments = self.segments()?;
+        let mut dropped_samples = 0;
+        if segments.len() > self.max_segments {
+            for segment in &segments[..segments.len() - self.max_segments] {
+                segment.remove()?;
+                dropped_samples += segment.samples;
+            }
+        }
+
+        Ok(dropped_samples)
+    }
+
+    // Pending segments, oldest first
+    pub fn segments(&self) -> io::Result<Vec<Segment>> {
+        let mut segments: Vec<Segment> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Segment::from_path(entry.path()))
+            .collect();
+        segments.sort_by_key(|segment| segment.seq);
+        Ok(segments)
+    }
+}
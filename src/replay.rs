@@ -0,0 +1,172 @@
+//! Access-log replay: the recorded requests of an nginx/Apache log sent again on their
+//! original schedule, for `forgy replay`
+//
+// Only the request line and timestamp are used; access logs carry no headers or bodies,
+// so POSTs are replayed without a body. Each distinct request becomes one scenario entry
+// and the schedule refers to it by index, so long logs do not duplicate URLs.
+
+use crate::scenario::{RequestSpec, Scenario};
+use crate::template::Template;
+use chrono::{DateTime, FixedOffset};
+use reqwest::Method;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    // NCSA combined, nginx's default: common plus quoted referer and user agent
+    Combined,
+    // NCSA common log format
+    Common,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayEntry {
+    // Time after the start of the replay, already scaled by the speed
+    pub offset: Duration,
+    // Index into the scenario's requests
+    pub request: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    // Distinct method and path pairs, in order of first appearance
+    requests: Vec<(Method, String)>,
+    // The recorded paths are sent to this URL
+    base_url: String,
+    pub entries: Vec<ReplayEntry>,
+    // Lines that were not a request in the expected format
+    pub skipped: usize,
+    pub speed: f64,
+}
+
+impl Replay {
+    pub fn load(
+        path: &str,
+        format: LogFormat,
+        base_url: &str,
+        speed: f64,
+    ) -> Result<Self, BoxError> {
+        let file =
+            std::fs::File::open(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+
+        let mut requests = Vec::new();
+        let mut indices = HashMap::new();
+        let mut recorded = Vec::new();
+        let mut skipped = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("failed to read {}: {}", path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Some((time, method, path)) = parse_line(&line, format) else {
+                skipped += 1;
+                continue;
+            };
+            let key = (method, path);
+            let index = *indices.entry(key.clone()).or_insert_with(|| {
+                requests.push(key);
+                requests.len() - 1
+            });
+            recorded.push((time, index));
+        }
+
+        // Servers log requests when they complete, so lines are not strictly in arrival order
+        recorded.sort_by_key(|(time, _)| *time);
+        let start = recorded
+            .first()
+            .map(|(time, _)| *time)
+            .ok_or_else(|| format!("no requests found in {}", path))?;
+        let entries = recorded
+            .into_iter()
+            .map(|(time, request)| ReplayEntry {
+                offset: (time - start).to_std().unwrap_or_default().div_f64(speed),
+                request,
+            })
+            .collect();
+
+        Ok(Self {
+            requests,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            entries,
+            skipped,
+            speed,
+        })
+    }
+
+    // Each path, without the query string, is reported as its own target
+    pub fn scenario(&self) -> Result<Scenario, BoxError> {
+        let requests = self
+            .requests
+            .iter()
+            .map(|(method, path)| RequestSpec {
+                target: path.split('?').next().unwrap_or(path).to_string(),
+                name: None,
+                method: method.clone(),
+                url: Template::literal(&format!("{}{}", self.base_url, path)),
+                headers: Vec::new(),
+                body: None,
+                weight: 1,
+                checks: Vec::new(),
+                graphql: false,
+                timeout: None,
+            })
+            .collect();
+        Scenario::new(requests)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.entries
+            .last()
+            .map(|entry| entry.offset)
+            .unwrap_or_default()
+    }
+}
+
+// 127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326 "ref" "agent"
+fn parse_line(line: &str, format: LogFormat) -> Option<(DateTime<FixedOffset>, Method, String)> {
+    let open = line.find('[')?;
+    let close = open + line[open..].find(']')?;
+    let time = DateTime::parse_from_str(&line[open + 1..close], "%d/%b/%Y:%H:%M:%S %z").ok()?;
+
+    let rest = &line[close + 1..];
+    let request_start = rest.find('"')? + 1;
+    let request_end = request_start + rest[request_start..].find('"')?;
+    let mut request = rest[request_start..request_end].split_whitespace();
+    let method = Method::from_bytes(request.next()?.as_bytes()).ok()?;
+    let target = request.next()?;
+    // Proxies may log the absolute form; only the path is replayed
+    let path = match target.find("://") {
+        Some(scheme_end) => {
+            let authority = &target[scheme_end + 3..];
+            &authority[authority.find('/').unwrap_or(authority.len())..]
+        }
+        None => target,
+    };
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    // Combined lines end with the quoted referer and user agent
+    if format == LogFormat::Combined && rest[request_end + 1..].matches('"').count() < 4 {
+        return None;
+    }
+
+    Some((time, method, path.to_string()))
+}
+
+// Used as the clap value parser for --speed: "2x", "0.5x" or a bare factor
+pub fn parse_speed(speed: &str) -> Result<f64, String> {
+    let factor = speed.trim();
+    let factor = factor.strip_suffix(['x', 'X']).unwrap_or(factor);
+    match factor.parse::<f64>() {
+        Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(factor),
+        _ => Err(format!(
+            "expected a positive speed factor such as 2x, got '{}'",
+            speed
+        )),
+    }
+}
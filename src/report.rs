@@ -0,0 +1,349 @@
+//! Self-contained HTML report rendered from the test results
+//
+// Charts are inline SVG and styles are embedded, so the file can be archived as a CI
+// artifact and opened offline.
+
+use crate::timeseries::TimeseriesPoint;
+use crate::TestResults;
+use std::fmt::Write;
+
+const CHART_WIDTH: f64 = 760.0;
+const CHART_HEIGHT: f64 = 220.0;
+const CHART_PADDING: f64 = 40.0;
+
+const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; margin: 2em auto; max-width: 860px; color: #222; }
+h1 { font-size: 1.6em; } h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ddd; padding-bottom: .3em; }
+table { border-collapse: collapse; width: 100%; } th, td { text-align: left; padding: .35em .6em; border-bottom: 1px solid #eee; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+.pass { color: #2e7d32; font-weight: bold; } .fail { color: #c62828; font-weight: bold; }
+.cards { display: flex; flex-wrap: wrap; gap: 1em; } .card { flex: 1; min-width: 140px; background: #f6f8fa; border-radius: 6px; padding: .8em; }
+.card .value { font-size: 1.4em; font-weight: bold; } .card .label { color: #666; font-size: .85em; }
+svg text { font-size: 11px; fill: #555; }
+";
+
+// Status code colours by class, as in the console report's grouping
+fn status_colour(code: u16) -> &'static str {
+    match code {
+        200..=299 => "#43a047",
+        300..=399 => "#1e88e5",
+        400..=499 => "#fb8c00",
+        500..=599 => "#e53935",
+        _ => "#757575",
+    }
+}
+
+pub fn render_html(results: &TestResults) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>forgy load test report</title>\n");
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    html.push_str("<h1>forgy load test report</h1>\n");
+
+    // Headline numbers
+    let error_rate = results.failed_requests as f64 / results.total_requests.max(1) as f64;
+    html.push_str("<div class=\"cards\">\n");
+    for (label, value) in [
+        ("Requests", results.total_requests.to_string()),
+        (
+            "Requests/sec",
+            format!("{:.2}", results.requests_per_second),
+        ),
+        ("Error rate", format!("{:.2}%", error_rate * 100.0)),
+        ("P95", format!("{:.2} ms", results.p95_response_time_ms)),
+        (
+            "Duration",
+            format!("{:.1} s", results.test_duration_seconds),
+        ),
+        ("Peak VUs", results.vus.to_string()),
+    ] {
+        let _ = writeln!(
+            html,
+            "<div class=\"card\"><div class=\"value\">{}</div><div class=\"label\">{}</div></div>",
+            value, label
+        );
+    }
+    html.push_str("</div>\n");
+
+    if !results.thresholds.is_empty() {
+        html.push_str("<h2>Thresholds</h2>\n<table>\n<tr><th>Threshold</th><th>Actual</th><th>Result</th></tr>\n");
+        for threshold in &results.thresholds {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{:.2}</td><td>{}</td></tr>",
+                escape(&threshold.threshold),
+                threshold.actual,
+                pass_fail(threshold.passed)
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if !results.timeseries.is_empty() {
+        html.push_str("<h2>Latency over Time (P95, ms)</h2>\n");
+        html.push_str(&line_chart(&results.timeseries, |p| p.p95_response_time_ms));
+        html.push_str("<h2>Throughput over Time (req/s)</h2>\n");
+        html.push_str(&line_chart(&results.timeseries, |p| p.rps));
+    }
+
+    html.push_str("<h2>Response Time Distribution (ms)</h2>\n");
+    html.push_str(&bar_chart(&[
+        ("min", results.min_response_time_ms),
+        ("avg", results.avg_response_time_ms),
+        ("p50", results.p50_response_time_ms),
+        ("p90", results.p90_response_time_ms),
+        ("p95", results.p95_response_time_ms),
+        ("p99", results.p99_response_time_ms),
+        ("max", results.max_response_time_ms),
+    ]));
+
+    if !results.status_code_distribution.is_empty() {
+        html.push_str("<h2>Status Codes</h2>\n");
+        let mut codes: Vec<_> = results.status_code_distribution.iter().collect();
+        codes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let slices: Vec<(String, usize, &str)> = codes
+            .into_iter()
+            .map(|(code, count)| {
+                let label = match code {
+                    0 => "no response".to_string(),
+                    code => code.to_string(),
+                };
+                (label, *count, status_colour(*code))
+            })
+            .collect();
+        html.push_str(&pie_chart(&slices));
+    }
+
+    if !results.error_types.is_empty() {
+        html.push_str("<h2>Errors</h2>\n<table>\n<tr><th>Type</th><th>Count</th></tr>\n");
+        for (error_type, count) in &results.error_types {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{}</td></tr>",
+                error_type.as_str(),
+                count
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if !results.checks.is_empty() {
+        html.push_str(
+            "<h2>Checks</h2>\n<table>\n<tr><th>Check</th><th>Passed</th><th>Failed</th></tr>\n",
+        );
+        for check in &results.checks {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                escape(&check.name),
+                check.passed,
+                check.failed
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if results.targets.len() > 1 {
+        html.push_str("<h2>Targets</h2>\n<table>\n<tr><th>Target</th><th>Requests</th><th>Failed</th><th>Avg (ms)</th><th>P95 (ms)</th><th>P99 (ms)</th></tr>\n");
+        for target in &results.targets {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{:.2}</td><td class=\"num\">{:.2}</td><td class=\"num\">{:.2}</td></tr>",
+                escape(&target.target),
+                target.total_requests,
+                target.failed_requests,
+                target.avg_response_time_ms,
+                target.p95_response_time_ms,
+                target.p99_response_time_ms
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if results.endpoints.len() > 1 {
+        html.push_str("<h2>Endpoints</h2>\n<table>\n<tr><th>Endpoint</th><th>Requests</th><th>Error rate</th><th>Avg (ms)</th><th>P50 (ms)</th><th>P95 (ms)</th><th>P99 (ms)</th></tr>\n");
+        for endpoint in &results.endpoints {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{:.2}%</td><td class=\"num\">{:.2}</td><td class=\"num\">{:.2}</td><td class=\"num\">{:.2}</td><td class=\"num\">{:.2}</td></tr>",
+                escape(&endpoint.endpoint),
+                endpoint.total_requests,
+                endpoint.error_rate * 100.0,
+                endpoint.avg_response_time_ms,
+                endpoint.p50_response_time_ms,
+                endpoint.p95_response_time_ms,
+                endpoint.p99_response_time_ms
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn pass_fail(passed: bool) -> &'static str {
+    if passed {
+        "<span class=\"pass\">PASS</span>"
+    } else {
+        "<span class=\"fail\">FAIL</span>"
+    }
+}
+
+fn line_chart(points: &[TimeseriesPoint], value: impl Fn(&TimeseriesPoint) -> f64) -> String {
+    let max_x = points
+        .last()
+        .map(|p| p.elapsed_seconds)
+        .unwrap_or(1.0)
+        .max(1.0);
+    let max_y = points
+        .iter()
+        .map(&value)
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+    let plot_width = CHART_WIDTH - 2.0 * CHART_PADDING;
+    let plot_height = CHART_HEIGHT - 2.0 * CHART_PADDING;
+
+    let mut coordinates = String::new();
+    for point in points {
+        let x = CHART_PADDING + point.elapsed_seconds / max_x * plot_width;
+        let y = CHART_HEIGHT - CHART_PADDING - value(point) / max_y * plot_height;
+        let _ = write!(coordinates, "{:.1},{:.1} ", x, y);
+    }
+
+    let mut svg = svg_open(CHART_WIDTH, CHART_HEIGHT);
+    svg.push_str(&axes());
+    let _ = writeln!(
+        svg,
+        "<text x=\"{:.0}\" y=\"{:.0}\" text-anchor=\"end\">{:.2}</text>",
+        CHART_PADDING - 4.0,
+        CHART_PADDING + 4.0,
+        max_y
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{:.0}\" y=\"{:.0}\" text-anchor=\"end\">0</text>",
+        CHART_PADDING - 4.0,
+        CHART_HEIGHT - CHART_PADDING
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{:.0}\" y=\"{:.0}\" text-anchor=\"end\">{:.0}s</text>",
+        CHART_WIDTH - CHART_PADDING,
+        CHART_HEIGHT - CHART_PADDING + 16.0,
+        max_x
+    );
+    let _ = writeln!(
+        svg,
+        "<polyline fill=\"none\" stroke=\"#1e88e5\" stroke-width=\"2\" points=\"{}\"/>",
+        coordinates.trim_end()
+    );
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn bar_chart(bars: &[(&str, f64)]) -> String {
+    let row_height = 26.0;
+    let label_width = 50.0;
+    let height = bars.len() as f64 * row_height + 10.0;
+    let max = bars
+        .iter()
+        .map(|(_, value)| *value)
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+    let bar_space = CHART_WIDTH - label_width - 100.0;
+
+    let mut svg = svg_open(CHART_WIDTH, height);
+    for (index, (label, value)) in bars.iter().enumerate() {
+        let y = 5.0 + index as f64 * row_height;
+        let width = value / max * bar_space;
+        let _ = writeln!(
+            svg,
+            "<text x=\"0\" y=\"{:.0}\">{}</text><rect x=\"{:.0}\" y=\"{:.0}\" width=\"{:.1}\" height=\"{:.0}\" fill=\"#1e88e5\"/><text x=\"{:.1}\" y=\"{:.0}\">{:.2}</text>",
+            y + 15.0,
+            label,
+            label_width,
+            y,
+            width,
+            row_height - 6.0,
+            label_width + width + 6.0,
+            y + 15.0,
+            value
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn pie_chart(slices: &[(String, usize, &str)]) -> String {
+    let total = slices
+        .iter()
+        .map(|(_, count, _)| *count)
+        .sum::<usize>()
+        .max(1) as f64;
+    let (cx, cy, r) = (110.0, 110.0, 100.0);
+
+    let height = (slices.len() as f64 * 22.0 + 20.0).max(230.0);
+    let mut svg = svg_open(CHART_WIDTH, height);
+    let mut angle: f64 = 0.0;
+    for (index, (label, count, colour)) in slices.iter().enumerate() {
+        let share = *count as f64 / total;
+        if share >= 1.0 {
+            // A single slice cannot be drawn as an arc
+            let _ = writeln!(
+                svg,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>",
+                cx, cy, r, colour
+            );
+        } else {
+            let end = angle + share * std::f64::consts::TAU;
+            let (x1, y1) = (cx + r * angle.sin(), cy - r * angle.cos());
+            let (x2, y2) = (cx + r * end.sin(), cy - r * end.cos());
+            let large_arc = if share > 0.5 { 1 } else { 0 };
+            let _ = writeln!(
+                svg,
+                "<path d=\"M{} {} L{:.2} {:.2} A{} {} 0 {} 1 {:.2} {:.2} Z\" fill=\"{}\" stroke=\"#fff\"/>",
+                cx, cy, x1, y1, r, r, large_arc, x2, y2, colour
+            );
+            angle = end;
+        }
+
+        let legend_y = 20.0 + index as f64 * 22.0;
+        let _ = writeln!(
+            svg,
+            "<rect x=\"250\" y=\"{:.0}\" width=\"14\" height=\"14\" fill=\"{}\"/><text x=\"272\" y=\"{:.0}\">{}: {} ({:.1}%)</text>",
+            legend_y,
+            colour,
+            legend_y + 11.0,
+            escape(label),
+            count,
+            share * 100.0
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn svg_open(width: f64, height: f64) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        width, height
+    )
+}
+
+fn axes() -> String {
+    format!(
+        "<path d=\"M{0} {1} V{2} H{3}\" fill=\"none\" stroke=\"#999\"/>\n",
+        CHART_PADDING,
+        CHART_PADDING,
+        CHART_HEIGHT - CHART_PADDING,
+        CHART_WIDTH - CHART_PADDING
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,104 @@
+//! NDJSON log of every request, written by a background task so VUs never wait on I/O
+
+use crate::errors::ErrorType;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, oneshot};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// One line of the log
+#[derive(Debug, Serialize)]
+pub struct RequestLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub target: String,
+    pub endpoint: String,
+    pub method: String,
+    // 0 when no response was received
+    pub status: u16,
+    pub duration_ms: f64,
+    // Time spent reading the response body, after duration_ms
+    pub download_ms: f64,
+    pub success: bool,
+    pub error_type: Option<ErrorType>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    // 0 for the first attempt, then the number of the retry
+    pub attempt: u32,
+}
+
+enum Message {
+    Entry(RequestLogEntry),
+    Flush(oneshot::Sender<()>),
+}
+
+// Handle to the writer task; cheap to clone into every VU
+#[derive(Clone)]
+pub struct RequestLog {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl RequestLog {
+    // Open the log at `path` ("-" for stdout) and start the writer task
+    pub async fn open(path: &str) -> Result<Self, BoxError> {
+        let output: Box<dyn AsyncWrite + Unpin + Send> = if path == "-" {
+            Box::new(tokio::io::stdout())
+        } else {
+            Box::new(
+                tokio::fs::File::create(path)
+                    .await
+                    .map_err(|e| format!("failed to create request log {}: {}", path, e))?,
+            )
+        };
+
+        // Unbounded so that a slow disk delays the log, not the requests
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(e) = write_entries(BufWriter::new(output), receiver).await {
+                tracing::error!(error = %e, "failed to write request log");
+            }
+        });
+        Ok(Self { sender })
+    }
+
+    pub fn log(&self, entry: RequestLogEntry) {
+        // Only fails once the writer gave up after an I/O error, which it already reported
+        self.sender.send(Message::Entry(entry)).ok();
+    }
+
+    // Wait until everything logged so far has been written out
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            flushed.await.ok();
+        }
+    }
+}
+
+async fn write_entries(
+    mut output: BufWriter<Box<dyn AsyncWrite + Unpin + Send>>,
+    mut receiver: mpsc::UnboundedReceiver<Message>,
+) -> Result<(), BoxError> {
+    let mut flushed = Vec::new();
+    while let Some(message) = receiver.recv().await {
+        // Write whatever has queued up, then flush once so the file can be followed live
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                Message::Entry(entry) => {
+                    let mut line = serde_json::to_vec(&entry)?;
+                    line.push(b'\n');
+                    output.write_all(&line).await?;
+                }
+                Message::Flush(done) => flushed.push(done),
+            }
+            next = receiver.try_recv().ok();
+        }
+        output.flush().await?;
+        for done in flushed.drain(..) {
+            done.send(()).ok();
+        }
+    }
+    Ok(())
+}
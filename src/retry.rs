@@ -0,0 +1,89 @@
+//! Retry policy: which failed requests are sent again, how often and after how long
+//
+// Every attempt is a request of its own in the results and metrics, so a retry that
+// succeeds never hides the failure before it; retries are counted separately on top.
+// Requests with non-idempotent methods (POST, PATCH, ...) are only retried with
+// --retry-non-idempotent, since the server may have acted on the failed attempt, or with
+// --idempotency-key-header, whose key lets the server recognise the retry.
+
+use crate::errors::ErrorType;
+use crate::Args;
+use rand::Rng;
+use reqwest::Method;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Used without --retry-on: gateway errors and failures before a response arrived
+pub const DEFAULT_RETRY_ON: &str =
+    "502,503,504,timeout,connect_timeout,connection_refused,connection_reset";
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // Attempts after the first
+    pub retries: u32,
+    // Before the first retry; doubled for each further one
+    backoff: Duration,
+    status_codes: Vec<u16>,
+    error_types: Vec<ErrorType>,
+    non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    // None without --retries
+    pub fn from_args(args: &Args) -> Result<Option<Self>, BoxError> {
+        if args.retries == 0 {
+            return Ok(None);
+        }
+        let mut status_codes = Vec::new();
+        let mut error_types = Vec::new();
+        for condition in args.retry_on.split(',').map(str::trim) {
+            if let Ok(code) = condition.parse::<u16>() {
+                status_codes.push(code);
+            } else if let Some(error_type) = ErrorType::from_name(condition) {
+                error_types.push(error_type);
+            } else {
+                return Err(format!(
+                    "--retry-on takes status codes and error types, got '{}'",
+                    condition
+                )
+                .into());
+            }
+        }
+        Ok(Some(Self {
+            retries: args.retries,
+            backoff: args.retry_backoff,
+            status_codes,
+            error_types,
+            non_idempotent: args.retry_non_idempotent || args.idempotency_key_header.is_some(),
+        }))
+    }
+
+    // Whether a failed attempt number `attempt` (0 for the first) is sent again
+    pub fn should_retry(
+        &self,
+        method: &Method,
+        attempt: u32,
+        status_code: u16,
+        error_type: Option<ErrorType>,
+    ) -> bool {
+        attempt < self.retries
+            && (self.non_idempotent || is_idempotent(method))
+            && (self.status_codes.contains(&status_code)
+                || error_type.is_some_and(|error_type| self.error_types.contains(&error_type)))
+    }
+
+    // Exponential backoff with jitter, so that VUs failing together do not retry together
+    pub fn backoff<R: Rng>(&self, attempt: u32, rng: &mut R) -> Duration {
+        let backoff = self.backoff.saturating_mul(1 << attempt.min(16));
+        backoff.mul_f64(rng.gen_range(0.5..=1.0))
+    }
+}
+
+// RFC 9110 section 9.2.2
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
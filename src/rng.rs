@@ -0,0 +1,44 @@
+//! Random number generators of the VUs, seeded with `--seed` for reproducible runs
+//
+// Every VU (and arrival worker) draws from a generator of its own, so with --seed the values
+// a VU uses (placeholders and fake data, weighted request choice, think time, retry backoff,
+// --max-connection-lifetime jitter and Digest cnonces) come out the same from run to run,
+// however the VUs interleave. What depends on timing
+// stays as it was: which requests fit into a duration, and the order in which VUs take rows
+// of a round-robin data feed or numbers of ${seq(N)}. Without --seed the generators are
+// seeded by the OS.
+
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+pub struct Rngs {
+    vus: Box<[Mutex<StdRng>]>,
+    // Draws not tied to a VU, e.g. the --sample-size reservoir
+    shared: Mutex<StdRng>,
+}
+
+impl Rngs {
+    pub fn new(seed: Option<u64>, vus: usize) -> Self {
+        // Stream 0 is the shared one, VU n gets stream n
+        let stream = |index: u64| match seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(index)),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            vus: (1..=vus.max(1) as u64)
+                .map(|index| Mutex::new(stream(index)))
+                .collect(),
+            shared: Mutex::new(stream(0)),
+        }
+    }
+
+    // The generator of a 1-based VU number; arrival workers use the ones of their number
+    pub fn vu(&self, vu: usize) -> &Mutex<StdRng> {
+        &self.vus[vu.saturating_sub(1) % self.vus.len()]
+    }
+
+    pub fn shared(&self) -> &Mutex<StdRng> {
+        &self.shared
+    }
+}
@@ -0,0 +1,583 @@
+//! Scenario definitions: the set of requests VUs pick from
+
+use crate::checks::{Check, CheckConfig};
+use crate::client::parse_timeout;
+use crate::setup::{Extraction, LifecycleStep};
+use crate::template::Template;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use reqwest::header::HeaderName;
+use reqwest::Method;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// A single request of a scenario; URL, header values and body are expanded per request
+#[derive(Debug, Clone)]
+pub struct RequestSpec {
+    // Reported as the `target` label and in the per-target results
+    pub target: String,
+    // Set with --name or the scenario file's `name`; reported as the endpoint instead of
+    // the normalized path
+    pub name: Option<String>,
+    pub method: Method,
+    pub url: Template,
+    pub headers: Vec<(HeaderName, Template)>,
+    pub body: Option<Template>,
+    // Relative to the other requests; percentages are kept in hundredths of a percent
+    pub weight: u32,
+    pub checks: Vec<Check>,
+    // Responses with a GraphQL errors[] array count as failures
+    pub graphql: bool,
+    // Replaces --request-timeout for this request
+    pub timeout: Option<Duration>,
+}
+
+impl RequestSpec {
+    // Only requests whose body is inspected buffer it; others just count its bytes
+    pub fn needs_body(&self) -> bool {
+        self.graphql || self.checks.iter().any(Check::needs_body)
+    }
+
+    fn templates(&self) -> impl Iterator<Item = &Template> {
+        std::iter::once(&self.url)
+            .chain(self.headers.iter().map(|(_, value)| value))
+            .chain(self.body.iter())
+    }
+
+    // Fill in the ${vars.name} placeholders with the values extracted by setup
+    pub fn bind(&mut self, variables: &HashMap<String, String>) {
+        self.url.bind(variables);
+        for (_, value) in &mut self.headers {
+            value.bind(variables);
+        }
+        if let Some(body) = &mut self.body {
+            body.bind(variables);
+        }
+    }
+}
+
+// Weighted mix of requests executed by every VU, or a sequence each VU walks in order
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub requests: Vec<RequestSpec>,
+    weights: WeightedIndex<u32>,
+    pub ordered: bool,
+    // Sent once before and after the test, from a scenario file
+    pub setup: Vec<LifecycleStep>,
+    pub teardown: Vec<LifecycleStep>,
+    // Sent once by each VU before its first request, e.g. to log in
+    pub vu_init: Vec<LifecycleStep>,
+    // Scenarios of a scenario file that run side by side, each with its own VUs; their
+    // requests are those of `requests`, one slice each
+    pub parallel: Vec<ParallelScenario>,
+}
+
+// One of the `scenarios` of a scenario file; phases not set use --ramp-up, --hold and
+// --ramp-down
+#[derive(Debug, Clone)]
+pub struct ParallelScenario {
+    pub name: String,
+    pub vus: usize,
+    // Arrival rate, as --rps sets for a whole run
+    pub rps: Option<f64>,
+    pub ramp_up: Option<Duration>,
+    pub hold: Option<Duration>,
+    pub ramp_down: Option<Duration>,
+    requests: Range<usize>,
+}
+
+impl ParallelScenario {
+    pub fn request_count(&self) -> usize {
+        self.requests.len()
+    }
+}
+
+impl Scenario {
+    pub fn new(requests: Vec<RequestSpec>) -> Result<Self, BoxError> {
+        if requests.is_empty() {
+            return Err("scenario must contain at least one request".into());
+        }
+        let weights = WeightedIndex::new(requests.iter().map(|r| r.weight))
+            .map_err(|e| format!("invalid request weights: {}", e))?;
+        Ok(Self {
+            requests,
+            weights,
+            ordered: false,
+            setup: Vec::new(),
+            teardown: Vec::new(),
+            vu_init: Vec::new(),
+            parallel: Vec::new(),
+        })
+    }
+
+    // Requests sent one after the other, starting over after the last, e.g. a user journey
+    pub fn sequence(requests: Vec<RequestSpec>) -> Result<Self, BoxError> {
+        Ok(Self {
+            ordered: true,
+            ..Self::new(requests)?
+        })
+    }
+
+    // Load a scenario from a YAML or TOML file, chosen by extension
+    pub fn from_file(path: &str) -> Result<Self, BoxError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let file: ScenarioFile = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            _ => serde_yaml::from_str(&contents)?,
+        };
+
+        if !file.requests.is_empty() && !file.scenarios.is_empty() {
+            return Err(format!("{} has both requests and scenarios", path).into());
+        }
+        let mut requests = specs(file.requests, path)?;
+        let mut parallel = Vec::new();
+        for (name, config) in file.scenarios {
+            let duration = |value: &Option<String>| {
+                value
+                    .as_deref()
+                    .map(humantime::parse_duration)
+                    .transpose()
+                    .map_err(|e| format!("invalid duration in scenario {}: {}", name, e))
+            };
+            if config.vus == 0 {
+                return Err(format!("scenario {} needs at least one VU", name).into());
+            }
+            if config.rps.is_some_and(|rps| rps <= 0.0) {
+                return Err(format!("scenario {} has a non-positive rps", name).into());
+            }
+            let specs = specs(config.requests, &format!("scenario {}", name))?;
+            // Checks the scenario's weights now rather than when it starts
+            Self::new(specs.clone()).map_err(|e| format!("scenario {}: {}", name, e))?;
+            parallel.push(ParallelScenario {
+                vus: config.vus,
+                rps: config.rps,
+                ramp_up: duration(&config.ramp_up)?,
+                hold: duration(&config.hold)?,
+                ramp_down: duration(&config.ramp_down)?,
+                requests: requests.len()..requests.len() + specs.len(),
+                name,
+            });
+            requests.extend(specs);
+        }
+        let steps = |configs: Vec<RequestConfig>| {
+            configs
+                .into_iter()
+                .map(RequestConfig::into_step)
+                .collect::<Result<Vec<_>, BoxError>>()
+        };
+        Ok(Self {
+            setup: steps(file.setup)?,
+            teardown: steps(file.teardown)?,
+            vu_init: steps(file.vu_init)?,
+            parallel,
+            ..Self::new(requests)?
+        })
+    }
+
+    // The requests of one of the parallel scenarios, as a scenario of their own
+    pub fn part(&self, part: &ParallelScenario) -> Scenario {
+        let requests = self.requests[part.requests.clone()].to_vec();
+        Scenario {
+            ordered: self.ordered,
+            vu_init: self.vu_init.clone(),
+            ..Self::new(requests).expect("scenario weights are checked when it is loaded")
+        }
+    }
+
+    // VUs of all parallel scenarios together, if there are any
+    pub fn parallel_vus(&self) -> Option<usize> {
+        (!self.parallel.is_empty()).then(|| self.parallel.iter().map(|p| p.vus).sum())
+    }
+
+    // Every ${vars.name} must be extracted by an earlier setup or vu_init request; values a
+    // VU extracted are only known to that VU, so teardown can only use setup's
+    pub fn check_variables(&self) -> Result<(), BoxError> {
+        fn undefined(spec: &RequestSpec, extracted: &HashSet<&str>) -> Result<(), BoxError> {
+            match spec
+                .templates()
+                .flat_map(Template::variables)
+                .find(|name| !extracted.contains(name))
+            {
+                Some(name) => Err(format!(
+                    "${{vars.{}}} in {} is not extracted by an earlier setup or vu_init request",
+                    name, spec.target
+                )
+                .into()),
+                None => Ok(()),
+            }
+        }
+        fn steps<'a>(
+            steps: &'a [LifecycleStep],
+            extracted: &mut HashSet<&'a str>,
+        ) -> Result<(), BoxError> {
+            for step in steps {
+                undefined(&step.spec, extracted)?;
+                extracted.extend(step.extract.iter().map(|(name, _)| name.as_str()));
+            }
+            Ok(())
+        }
+
+        let mut extracted = HashSet::new();
+        steps(&self.setup, &mut extracted)?;
+        for step in &self.teardown {
+            undefined(&step.spec, &extracted)?;
+        }
+        steps(&self.vu_init, &mut extracted)?;
+        for request in &self.requests {
+            undefined(request, &extracted)?;
+        }
+        Ok(())
+    }
+
+    // Fill in the values extracted by setup, once it ran
+    pub fn bind(&mut self, variables: &HashMap<String, String>) {
+        for request in &mut self.requests {
+            request.bind(variables);
+        }
+        for step in self.teardown.iter_mut().chain(self.vu_init.iter_mut()) {
+            step.spec.bind(variables);
+        }
+    }
+
+    // Checks given on the command line apply to every request
+    pub fn add_checks(&mut self, checks: &[Check]) {
+        for request in &mut self.requests {
+            request.checks.extend_from_slice(checks);
+        }
+    }
+
+    // One request per --url, each given as "https://..." or "weight=3,https://..."; the
+    // --name at the same position, if any, names its endpoint
+    pub fn from_urls(
+        urls: &[String],
+        names: &[String],
+        method: Method,
+        body: Option<&str>,
+    ) -> Result<Self, BoxError> {
+        if names.len() > urls.len() {
+            return Err(format!(
+                "{} --name values given for {} --url values",
+                names.len(),
+                urls.len()
+            )
+            .into());
+        }
+        let body = body.map(Template::parse).transpose()?;
+        let weighted = urls
+            .iter()
+            .map(|spec| parse_weighted_url(spec))
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        let weights = resolve_weights(
+            weighted.iter().map(|(weight, _)| *weight),
+            "the --url values",
+        )?;
+        let requests = weighted
+            .iter()
+            .zip(weights)
+            .enumerate()
+            .map(|(index, ((_, url), weight))| {
+                let name = names.get(index).cloned();
+                Ok(RequestSpec {
+                    target: name.clone().unwrap_or_else(|| url.to_string()),
+                    name,
+                    method: method.clone(),
+                    url: Template::parse(url)?,
+                    headers: Vec::new(),
+                    body: body.clone(),
+                    weight,
+                    checks: Vec::new(),
+                    graphql: false,
+                    timeout: None,
+                })
+            })
+            .collect::<Result<Vec<_>, BoxError>>()?;
+        Self::new(requests)
+    }
+
+    // Headers given on the command line apply to every request that does not set them itself
+    pub fn add_headers(&mut self, headers: &[(HeaderName, Template)]) {
+        let steps = self
+            .setup
+            .iter_mut()
+            .chain(self.teardown.iter_mut())
+            .chain(self.vu_init.iter_mut());
+        for request in self
+            .requests
+            .iter_mut()
+            .chain(steps.map(|step| &mut step.spec))
+        {
+            for (name, value) in headers {
+                if !request.headers.iter().any(|(existing, _)| existing == name) {
+                    request.headers.push((name.clone(), value.clone()));
+                }
+            }
+        }
+    }
+
+    // Every template of every request, e.g. to validate placeholders against the data feed
+    pub fn templates(&self) -> impl Iterator<Item = &Template> {
+        let vu_init = self.vu_init.iter().map(|step| &step.spec);
+        self.requests
+            .iter()
+            .chain(vu_init)
+            .flat_map(RequestSpec::templates)
+    }
+
+    // Each request's share of the mix, in percent
+    pub fn shares(&self) -> impl Iterator<Item = (&RequestSpec, f64)> {
+        let total: u32 = self.requests.iter().map(|r| r.weight).sum();
+        self.requests
+            .iter()
+            .map(move |r| (r, r.weight as f64 * 100.0 / total as f64))
+    }
+
+    // The request for a VU's `iteration`
+    pub fn pick<R: Rng>(&self, iteration: u64, rng: &mut R) -> &RequestSpec {
+        if self.ordered {
+            &self.requests[iteration as usize % self.requests.len()]
+        } else {
+            &self.requests[self.weights.sample(rng)]
+        }
+    }
+}
+
+fn parse_weighted_url(spec: &str) -> Result<(Weight, &str), BoxError> {
+    match spec.strip_prefix("weight=") {
+        Some(rest) => {
+            let (weight, url) = rest
+                .split_once(',')
+                .ok_or_else(|| format!("weighted URL must be \"weight=N,URL\": {}", spec))?;
+            let weight = Weight::parse(weight.trim()).map_err(|e| format!("{} in {}", e, spec))?;
+            Ok((weight, url.trim()))
+        }
+        None => Ok((Weight::Relative(1), spec)),
+    }
+}
+
+// A request's share of the mix, as given: a relative weight such as 3, or a percentage such
+// as "70%"
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "WeightConfig")]
+enum Weight {
+    Relative(u32),
+    Percent(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WeightConfig {
+    Number(u32),
+    Text(String),
+}
+
+impl TryFrom<WeightConfig> for Weight {
+    type Error = String;
+
+    fn try_from(config: WeightConfig) -> Result<Self, String> {
+        match config {
+            WeightConfig::Number(weight) => Ok(Self::Relative(weight)),
+            WeightConfig::Text(text) => Self::parse(&text).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Weight {
+    fn parse(text: &str) -> Result<Self, BoxError> {
+        let invalid = || format!("invalid weight {:?}", text);
+        match text.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if percent.is_finite() && percent >= 0.0 => Ok(Self::Percent(percent)),
+                _ => Err(invalid().into()),
+            },
+            None => text
+                .parse()
+                .map(Self::Relative)
+                .map_err(|_| invalid().into()),
+        }
+    }
+}
+
+// The weights of a mix of requests as the relative weights VUs sample by. Percentages must
+// add up to 100% (to within rounding), and cannot be mixed with relative weights, whose sum
+// would be meaningless next to them
+fn resolve_weights(
+    weights: impl Iterator<Item = Weight> + Clone,
+    mix: &str,
+) -> Result<Vec<u32>, BoxError> {
+    let percentages = weights
+        .clone()
+        .filter(|weight| matches!(weight, Weight::Percent(_)))
+        .count();
+    if percentages == 0 {
+        let weights: Vec<u32> = weights
+            .map(|weight| match weight {
+                Weight::Relative(weight) => weight,
+                Weight::Percent(_) => unreachable!(),
+            })
+            .collect();
+        if weights.iter().all(|weight| *weight == 0) {
+            return Err(format!("every request of {} has weight 0", mix).into());
+        }
+        return Ok(weights);
+    }
+    let mut total = 0.0;
+    let mut hundredths = Vec::new();
+    for weight in weights {
+        match weight {
+            Weight::Percent(percent) => {
+                total += percent;
+                hundredths.push((percent * 100.0).round() as u32);
+            }
+            Weight::Relative(weight) => {
+                return Err(format!(
+                "{} mixes percentages with a relative weight ({}); give every request a percentage",
+                mix, weight
+            )
+                .into())
+            }
+        }
+    }
+    if (total - 100.0).abs() > 0.01 {
+        return Err(format!("the weights of {} add up to {}%, not 100%", mix, total).into());
+    }
+    Ok(hundredths)
+}
+
+// Requests the VUs send, picked from by `weight`; unlike setup and vu_init requests, they
+// cannot extract values
+fn specs(configs: Vec<RequestConfig>, mix: &str) -> Result<Vec<RequestSpec>, BoxError> {
+    if configs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let weights = resolve_weights(configs.iter().map(|request| request.weight), mix)?;
+    configs
+        .into_iter()
+        .zip(weights)
+        .map(|(request, weight)| {
+            if !request.extract.is_empty() {
+                return Err(format!(
+                    "extract is only supported in setup and vu_init requests, not in {}",
+                    request.url
+                )
+                .into());
+            }
+            Ok(RequestSpec {
+                weight,
+                ..request.into_spec()?
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct ScenarioFile {
+    #[serde(default)]
+    requests: Vec<RequestConfig>,
+    // Named scenarios run in parallel, instead of `requests`
+    #[serde(default)]
+    scenarios: BTreeMap<String, ParallelConfig>,
+    #[serde(default)]
+    setup: Vec<RequestConfig>,
+    #[serde(default)]
+    teardown: Vec<RequestConfig>,
+    #[serde(default)]
+    vu_init: Vec<RequestConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParallelConfig {
+    vus: usize,
+    rps: Option<f64>,
+    // e.g. "30s" or "5m"
+    ramp_up: Option<String>,
+    hold: Option<String>,
+    ramp_down: Option<String>,
+    requests: Vec<RequestConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestConfig {
+    // Defaults to the URL
+    name: Option<String>,
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+    // A relative weight such as 3, or a percentage such as "70%"
+    #[serde(default = "default_weight")]
+    weight: Weight,
+    #[serde(default)]
+    checks: Vec<CheckConfig>,
+    // e.g. "5s" or "500ms"
+    timeout: Option<String>,
+    // Setup and vu_init only: variable name to "$.json.path" or "header:Name"
+    #[serde(default)]
+    extract: BTreeMap<String, String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_weight() -> Weight {
+    Weight::Relative(1)
+}
+
+impl RequestConfig {
+    fn into_step(mut self) -> Result<LifecycleStep, BoxError> {
+        let extract = std::mem::take(&mut self.extract)
+            .into_iter()
+            .map(|(name, spec)| Ok((name, Extraction::parse(&spec)?)))
+            .collect::<Result<_, BoxError>>()?;
+        Ok(LifecycleStep {
+            spec: self.into_spec()?,
+            extract,
+        })
+    }
+
+    fn into_spec(self) -> Result<RequestSpec, BoxError> {
+        let method = Method::from_bytes(self.method.to_uppercase().as_bytes())
+            .map_err(|_| format!("invalid HTTP method: {}", self.method))?;
+
+        let mut headers = Vec::new();
+        for (key, value) in &self.headers {
+            headers.push((
+                HeaderName::from_bytes(key.as_bytes())
+                    .map_err(|_| format!("invalid header name: {}", key))?,
+                Template::parse(value)?,
+            ));
+        }
+
+        let mut checks = Vec::new();
+        for check in self.checks {
+            checks.extend(check.into_checks()?);
+        }
+
+        Ok(RequestSpec {
+            target: self.name.clone().unwrap_or_else(|| self.url.clone()),
+            name: self.name,
+            method,
+            url: Template::parse(&self.url)?,
+            headers,
+            body: self.body.as_deref().map(Template::parse).transpose()?,
+            // Set by `specs`, which sees the whole mix
+            weight: 1,
+            checks,
+            graphql: false,
+            timeout: self
+                .timeout
+                .as_deref()
+                .map(parse_timeout)
+                .transpose()
+                .map_err(|e| format!("invalid timeout for {}: {}", self.url, e))?,
+        })
+    }
+}
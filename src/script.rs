@@ -0,0 +1,483 @@
+//! `--script` and `--plugin`: request logic in Rhai or in a WASM module, run in-process
+//
+// The file is compiled once at startup and may define either hook, or both:
+//
+//   fn request(req, ctx)        req: #{method, url, headers, body}; return a changed req to
+//                               send it instead, or () to send the original
+//   fn response(resp, req, ctx) resp: #{status, headers, body}; return true or () to pass,
+//                               false or a message to fail, or #{pass, message}
+//
+// ctx is #{vu, iteration, name}. Headers are a map of lowercase names to strings (repeated
+// headers joined with ", "); bodies are strings, or blobs when they are not UTF-8, and () when
+// there is none. Streamed bodies (--form, --body-file, --body-size) are not passed.
+//
+// A verdict is recorded as a check named "script: <message>" ("script" without a message), so
+// messages should be a few fixed strings like check names rather than per-request details.
+// A hook that throws, returns something else or runs for longer than --script-timeout fails
+// the request with error type `script`. Top-level statements run once, when the file is
+// loaded; hooks are plain functions and see nothing but their arguments. `print` and `debug`
+// go to the log.
+//
+// Requires the `rhai` feature, which is on by default. --plugin hooks (the `wasm` feature) are
+// exports of a WASM module with an ABI of their own, described in plugin.rs.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Method;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[cfg(feature = "wasm")]
+use crate::plugin::Plugin;
+#[cfg(feature = "rhai")]
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+#[cfg(feature = "rhai")]
+use std::cell::Cell;
+#[cfg(feature = "rhai")]
+use std::time::Instant;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Operations between two looks at the clock
+#[cfg(feature = "rhai")]
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+#[cfg(feature = "rhai")]
+thread_local! {
+    // When the hook running on this thread has to stop
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// A request as the script sees and returns it
+#[derive(Debug, Clone)]
+pub struct ScriptRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl ScriptRequest {
+    pub fn new(method: &Method, url: &str, headers: &HeaderMap, body: Option<&[u8]>) -> Self {
+        Self {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: header_strings(headers),
+            body: body.map(<[u8]>::to_vec),
+        }
+    }
+
+    pub fn method(&self) -> Result<Method, BoxError> {
+        Method::from_bytes(self.method.as_bytes())
+            .map_err(|_| format!("script returned invalid method '{}'", self.method).into())
+    }
+
+    pub fn header_map(&self) -> Result<HeaderMap, BoxError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("script returned invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("script returned invalid value for header {}", name))?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+// Headers as the script sees them; repeated headers are joined with ", "
+pub fn header_strings(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut strings = BTreeMap::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        strings
+            .entry(name.to_string())
+            .and_modify(|joined: &mut String| {
+                joined.push_str(", ");
+                joined.push_str(&value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    strings
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl ScriptResponse {
+    pub fn new(status: u16, headers: &HeaderMap, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: header_strings(headers),
+            body,
+        }
+    }
+}
+
+// Outcome of the response hook
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub pass: bool,
+    pub message: Option<String>,
+}
+
+// Identifies the request a hook is called for
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    pub vu: usize,
+    pub iteration: u64,
+    pub name: &'a str,
+}
+
+pub struct Script {
+    pub request_hook: bool,
+    pub response_hook: bool,
+    runner: Runner,
+    // Only the first failure is printed; the rest are counted as errors of type `script`
+    error_reported: AtomicBool,
+}
+
+// What runs the hooks: the Rhai interpreter for --script, wasmtime for --plugin
+enum Runner {
+    #[cfg(feature = "rhai")]
+    Rhai(Box<Rhai>),
+    #[cfg(feature = "wasm")]
+    Wasm(Plugin),
+}
+
+impl Script {
+    // A Rhai script, compiled with its top-level statements run
+    #[cfg(feature = "rhai")]
+    pub fn load(path: &str, timeout: Duration) -> Result<Self, BoxError> {
+        let (rhai, request_hook, response_hook) = Rhai::load(path, timeout)?;
+        Ok(Self::new(
+            Runner::Rhai(Box::new(rhai)),
+            request_hook,
+            response_hook,
+        ))
+    }
+
+    #[cfg(not(feature = "rhai"))]
+    pub fn load(_path: &str, _timeout: Duration) -> Result<Self, BoxError> {
+        Err("--script requires forgy to be built with --features rhai".into())
+    }
+
+    // A WASM module exporting generate_request, validate_response or both
+    #[cfg(feature = "wasm")]
+    pub fn load_plugin(path: &str, timeout: Duration) -> Result<Self, BoxError> {
+        let plugin = Plugin::load(path, timeout)?;
+        let (request_hook, response_hook) = (plugin.generates(), plugin.validates());
+        Ok(Self::new(Runner::Wasm(plugin), request_hook, response_hook))
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    pub fn load_plugin(_path: &str, _timeout: Duration) -> Result<Self, BoxError> {
+        Err("--plugin requires forgy to be built with --features wasm".into())
+    }
+
+    #[cfg(any(feature = "rhai", feature = "wasm"))]
+    fn new(runner: Runner, request_hook: bool, response_hook: bool) -> Self {
+        Self {
+            request_hook,
+            response_hook,
+            runner,
+            error_reported: AtomicBool::new(false),
+        }
+    }
+
+    // The request to send instead of `request`, if the hook changed it
+    #[cfg_attr(not(any(feature = "rhai", feature = "wasm")), allow(unused_variables))]
+    pub fn on_request(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+    ) -> Result<Option<ScriptRequest>, BoxError> {
+        match self.runner {
+            #[cfg(feature = "rhai")]
+            Runner::Rhai(ref rhai) => rhai.on_request(context, request),
+            #[cfg(feature = "wasm")]
+            Runner::Wasm(ref plugin) => plugin.on_request(context, request),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "rhai", feature = "wasm")), allow(unused_variables))]
+    pub fn on_response(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+        response: &ScriptResponse,
+    ) -> Result<Verdict, BoxError> {
+        match self.runner {
+            #[cfg(feature = "rhai")]
+            Runner::Rhai(ref rhai) => rhai.on_response(context, request, response),
+            #[cfg(feature = "wasm")]
+            Runner::Wasm(ref plugin) => plugin.on_response(context, request, response),
+        }
+    }
+
+    pub fn report_error(&self, error: &BoxError) {
+        if !self.error_reported.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                error = %error,
+                "script failed (further failures are logged at debug level)"
+            );
+        } else {
+            tracing::debug!(error = %error, "script failed");
+        }
+    }
+}
+
+#[cfg(feature = "rhai")]
+struct Rhai {
+    engine: Engine,
+    ast: AST,
+    // How long a hook may run
+    timeout: Duration,
+}
+
+#[cfg(feature = "rhai")]
+impl Rhai {
+    // The script, and whether it has the request and response hooks
+    fn load(path: &str, timeout: Duration) -> Result<(Self, bool, bool), BoxError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read script {}: {}", path, e))?;
+
+        let mut engine = Engine::new();
+        engine.on_progress(|operations| {
+            if operations % DEADLINE_CHECK_INTERVAL != 0 {
+                return None;
+            }
+            let expired = DEADLINE
+                .with(|deadline| deadline.get())
+                .is_some_and(|deadline| Instant::now() >= deadline);
+            expired.then(|| Dynamic::from("timeout"))
+        });
+        engine.on_print(|text| tracing::info!(target: "forgy::script", "{}", text));
+        engine.on_debug(
+            |text, _, position| tracing::debug!(target: "forgy::script", %position, "{}", text),
+        );
+
+        let ast = engine
+            .compile(&source)
+            .map_err(|e| format!("invalid script {}: {}", path, e))?;
+        let mut request_hook = false;
+        let mut response_hook = false;
+        for function in ast.iter_functions() {
+            let (hook, parameters) = match function.name {
+                "request" => (&mut request_hook, 2),
+                "response" => (&mut response_hook, 3),
+                _ => continue,
+            };
+            if function.params.len() != parameters {
+                return Err(format!(
+                    "script {}: fn {} must take {} parameters",
+                    path, function.name, parameters
+                )
+                .into());
+            }
+            *hook = true;
+        }
+        if !request_hook && !response_hook {
+            return Err(format!(
+                "script {} defines neither fn request(req, ctx) nor fn response(resp, req, ctx)",
+                path
+            )
+            .into());
+        }
+
+        let rhai = Self {
+            engine,
+            ast,
+            timeout,
+        };
+        rhai.run(|engine, ast| engine.run_ast(ast))
+            .map_err(|e| format!("script {} failed: {}", path, e))?;
+        Ok((rhai, request_hook, response_hook))
+    }
+
+    fn on_request(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+    ) -> Result<Option<ScriptRequest>, BoxError> {
+        let returned = self.call("request", (request_map(request), context_map(context)))?;
+        if returned.is_unit() {
+            return Ok(None);
+        }
+        let map = returned
+            .try_cast::<Map>()
+            .ok_or("fn request must return a request map or ()")?;
+        request_from_map(map).map(Some)
+    }
+
+    fn on_response(
+        &self,
+        context: HookContext<'_>,
+        request: &ScriptRequest,
+        response: &ScriptResponse,
+    ) -> Result<Verdict, BoxError> {
+        let returned = self.call(
+            "response",
+            (
+                response_map(response),
+                request_map(request),
+                context_map(context),
+            ),
+        )?;
+        verdict(returned)
+    }
+
+    fn call(&self, hook: &str, arguments: impl rhai::FuncArgs) -> Result<Dynamic, BoxError> {
+        // The top-level statements already ran when the file was loaded
+        let options = CallFnOptions::new().eval_ast(false);
+        self.run(|engine, ast| {
+            engine.call_fn_with_options(options, &mut Scope::new(), ast, hook, arguments)
+        })
+        .map_err(|e| format!("fn {}: {}", hook, e).into())
+    }
+
+    // Run a piece of the script under --script-timeout
+    fn run<T>(
+        &self,
+        run: impl FnOnce(&Engine, &AST) -> Result<T, Box<EvalAltResult>>,
+    ) -> Result<T, String> {
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+        let result = run(&self.engine, &self.ast);
+        DEADLINE.with(|deadline| deadline.set(None));
+        result.map_err(|e| match e.unwrap_inner() {
+            EvalAltResult::ErrorTerminated(..) => format!(
+                "did not finish within {}",
+                humantime::format_duration(self.timeout)
+            ),
+            _ => e.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "rhai")]
+fn context_map(context: HookContext<'_>) -> Map {
+    let mut map = Map::new();
+    map.insert("vu".into(), (context.vu as rhai::INT).into());
+    map.insert("iteration".into(), (context.iteration as rhai::INT).into());
+    map.insert("name".into(), context.name.into());
+    map
+}
+
+#[cfg(feature = "rhai")]
+fn request_map(request: &ScriptRequest) -> Map {
+    let mut map = Map::new();
+    map.insert("method".into(), request.method.clone().into());
+    map.insert("url".into(), request.url.clone().into());
+    map.insert("headers".into(), headers_map(&request.headers).into());
+    map.insert("body".into(), body_value(request.body.clone()));
+    map
+}
+
+#[cfg(feature = "rhai")]
+fn response_map(response: &ScriptResponse) -> Map {
+    let mut map = Map::new();
+    map.insert("status".into(), (response.status as rhai::INT).into());
+    map.insert("headers".into(), headers_map(&response.headers).into());
+    map.insert("body".into(), body_value(Some(response.body.clone())));
+    map
+}
+
+#[cfg(feature = "rhai")]
+fn headers_map(headers: &BTreeMap<String, String>) -> Map {
+    headers
+        .iter()
+        .map(|(name, value)| (name.as_str().into(), value.clone().into()))
+        .collect()
+}
+
+// Text as a string, anything else as a blob
+#[cfg(feature = "rhai")]
+fn body_value(body: Option<Vec<u8>>) -> Dynamic {
+    match body.map(String::from_utf8) {
+        None => Dynamic::UNIT,
+        Some(Ok(text)) => text.into(),
+        Some(Err(e)) => Dynamic::from_blob(e.into_bytes()),
+    }
+}
+
+#[cfg(feature = "rhai")]
+fn request_from_map(mut map: Map) -> Result<ScriptRequest, BoxError> {
+    let mut text = |field: &str| -> Result<String, BoxError> {
+        map.remove(field)
+            .and_then(|value| value.into_string().ok())
+            .ok_or_else(|| {
+                format!("fn request returned a request without a string {}", field).into()
+            })
+    };
+    let method = text("method")?;
+    let url = text("url")?;
+    let headers = match map.remove("headers") {
+        None => BTreeMap::new(),
+        Some(headers) => headers
+            .try_cast::<Map>()
+            .ok_or("fn request returned headers that are not a map")?
+            .into_iter()
+            .map(|(name, value)| match value.into_string() {
+                Ok(value) => Ok((name.to_string(), value)),
+                Err(_) => Err(format!(
+                    "fn request returned a header {} that is not a string",
+                    name
+                )),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    let body = match map.remove("body") {
+        None => None,
+        Some(body) if body.is_unit() => None,
+        Some(body) if body.is_blob() => body.into_blob().ok(),
+        Some(body) => Some(
+            body.into_string()
+                .map_err(|_| "fn request returned a body that is neither a string nor a blob")?
+                .into_bytes(),
+        ),
+    };
+    Ok(ScriptRequest {
+        method,
+        url,
+        headers,
+        body,
+    })
+}
+
+#[cfg(feature = "rhai")]
+fn verdict(returned: Dynamic) -> Result<Verdict, BoxError> {
+    let invalid = "fn response must return true, false, (), a message or #{pass, message}";
+    if returned.is_unit() {
+        return Ok(Verdict {
+            pass: true,
+            message: None,
+        });
+    }
+    if let Ok(pass) = returned.as_bool() {
+        return Ok(Verdict {
+            pass,
+            message: None,
+        });
+    }
+    if returned.is_string() {
+        return Ok(Verdict {
+            pass: false,
+            message: returned.into_string().ok(),
+        });
+    }
+    let mut map = returned.try_cast::<Map>().ok_or(invalid)?;
+    let pass = match map.remove("pass") {
+        Some(pass) => pass.as_bool().map_err(|_| invalid)?,
+        None => true,
+    };
+    let message = match map.remove("message") {
+        Some(message) if message.is_unit() => None,
+        Some(message) => Some(message.into_string().map_err(|_| invalid)?),
+        None => None,
+    };
+    Ok(Verdict { pass, message })
+}
@@ -0,0 +1,198 @@
+//! Setup and teardown requests of a scenario file, sent once around the test
+//
+// Setup requests run in order before the first VU starts and teardown requests after the
+// last one stopped; neither is counted in the results. A setup request can extract values
+// from its response, e.g. `extract: {tenant_id: "$.id", location: "header:Location"}`, which
+// later setup requests, the scenario's requests and teardown refer to as ${vars.tenant_id}.
+// A failing setup request (non-2xx status, failed check, missing value) aborts the run before
+// it starts; teardown requests are all attempted and their failures only reported.
+// A scenario's vu_init requests, sent by each VU for itself, are built, verified and
+// extracted from the same way.
+
+use crate::client::Client;
+use crate::scenario::RequestSpec;
+use crate::template::TemplateContext;
+use reqwest::header::{HeaderMap, HeaderName};
+use reqwest::RequestBuilder;
+use serde_json_path::JsonPath;
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Values extracted by setup requests, by name
+pub type Variables = HashMap<String, String>;
+
+// Where a setup request's value comes from: "$.json.path" or "header:Name"
+#[derive(Debug, Clone)]
+pub enum Extraction {
+    JsonPath(JsonPath),
+    Header(HeaderName),
+}
+
+impl Extraction {
+    pub fn parse(spec: &str) -> Result<Self, BoxError> {
+        match spec.strip_prefix("header:") {
+            Some(name) => Ok(Self::Header(
+                HeaderName::from_bytes(name.trim().as_bytes())
+                    .map_err(|_| format!("invalid header name in extract: {}", spec))?,
+            )),
+            None => Ok(Self::JsonPath(
+                JsonPath::parse(spec.trim())
+                    .map_err(|e| format!("invalid JSONPath {}: {}", spec, e))?,
+            )),
+        }
+    }
+
+    // JSON strings are taken without their quotes, other values as JSON
+    fn extract(&self, headers: &HeaderMap, body: &str) -> Option<String> {
+        match self {
+            Self::Header(name) => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            Self::JsonPath(path) => {
+                let json = serde_json::from_str::<serde_json::Value>(body).ok()?;
+                let nodes = path.query(&json);
+                match nodes.first()? {
+                    serde_json::Value::String(value) => Some(value.clone()),
+                    value => Some(value.to_string()),
+                }
+            }
+        }
+    }
+}
+
+// A setup or teardown request
+#[derive(Debug, Clone)]
+pub struct LifecycleStep {
+    pub spec: RequestSpec,
+    pub extract: Vec<(String, Extraction)>,
+}
+
+impl LifecycleStep {
+    // Store the step's extracted values in `variables`
+    pub fn extract_into(
+        &self,
+        headers: &HeaderMap,
+        body: &str,
+        variables: &mut Variables,
+    ) -> Result<(), BoxError> {
+        for (name, extraction) in &self.extract {
+            let value = extraction
+                .extract(headers, body)
+                .ok_or_else(|| format!("no value for {}", name))?;
+            variables.insert(name.clone(), value);
+        }
+        Ok(())
+    }
+}
+
+// Send the setup requests in order; `headers` (e.g. OAuth2) are added to each
+pub async fn run_setup(
+    client: &Client,
+    steps: &[LifecycleStep],
+    headers: &HeaderMap,
+) -> Result<Variables, BoxError> {
+    let mut variables = Variables::new();
+    for step in steps {
+        let (response_headers, body) = send(client, &step.spec, &variables, headers)
+            .await
+            .map_err(|e| format!("setup request {} failed: {}", step.spec.target, e))?;
+        step.extract_into(&response_headers, &body, &mut variables)
+            .map_err(|e| format!("setup request {} returned {}", step.spec.target, e))?;
+    }
+    Ok(variables)
+}
+
+// Send every teardown request, even after one failed; returns the failures
+pub async fn run_teardown(
+    client: &Client,
+    steps: &[LifecycleStep],
+    variables: &Variables,
+    headers: &HeaderMap,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+    for step in steps {
+        if let Err(e) = send(client, &step.spec, variables, headers).await {
+            failures.push(format!(
+                "teardown request {} failed: {}",
+                step.spec.target, e
+            ));
+        }
+    }
+    failures
+}
+
+// The request of a step, rendered for `context`, with `headers` added
+pub fn request(
+    client: &Client,
+    spec: &RequestSpec,
+    context: &TemplateContext<'_>,
+    headers: &HeaderMap,
+) -> RequestBuilder {
+    let mut request = client
+        .request(spec.method.clone(), spec.url.render(context))
+        .headers(headers.clone());
+    for (name, value) in &spec.headers {
+        request = request.header(name.clone(), value.render(context));
+    }
+    if let Some(body) = &spec.body {
+        request = request.body(body.render(context));
+    }
+    if let Some(timeout) = spec.timeout {
+        request = request.timeout(timeout);
+    }
+    request
+}
+
+// A step succeeds with a 2xx status and all of its checks passing
+pub fn verify(
+    spec: &RequestSpec,
+    status: u16,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<(), BoxError> {
+    if !(200..300).contains(&status) {
+        return Err(format!("status {}", status).into());
+    }
+    if let Some(check) = spec
+        .checks
+        .iter()
+        .find(|check| !check.evaluate(status, headers, body))
+    {
+        return Err(format!("check failed: {}", check).into());
+    }
+    Ok(())
+}
+
+async fn send(
+    client: &Client,
+    spec: &RequestSpec,
+    variables: &Variables,
+    extra_headers: &HeaderMap,
+) -> Result<(HeaderMap, String), BoxError> {
+    let mut spec = spec.clone();
+    spec.bind(variables);
+    // Not sent by a VU; ${VU} and ${ITER} expand to 0
+    let context = TemplateContext {
+        vu: 0,
+        iteration: 0,
+        row: None,
+        vars: None,
+        rng: None,
+    };
+
+    let request = request(client, &spec, &context, extra_headers).build()?;
+    // The body is read under the request's timeout too
+    let timeout = spec.timeout.unwrap_or(client.timeout());
+    let (status, headers, body) = tokio::time::timeout(timeout, async {
+        let response = client.execute(request).await?;
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        Ok::<_, BoxError>((status, headers, response.text().await?))
+    })
+    .await
+    .map_err(|_| format!("no response within {}", humantime::format_duration(timeout)))??;
+    verify(&spec, status, &headers, &body)?;
+    Ok((headers, body))
+}
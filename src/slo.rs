@@ -0,0 +1,153 @@
+//! `--slo`: service level objectives, reported as error budget consumption and burn rates
+//
+// An SLO is an objective for the share of good requests, in the terms production SLO
+// dashboards use. "availability:99.9" counts failed requests as bad; "latency:p99<500ms" counts
+// requests slower than 500ms as bad, with 99% of them allowed to be fast. The error budget is
+// the share of requests allowed to be bad (0.1% and 1% here), and the burn rate is how fast it
+// is spent: the share of bad requests divided by the budget. A burn rate of 1 spends exactly
+// the budget, so over the whole run the burn rate is also the budget consumed.
+
+use crate::thresholds;
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Indicator {
+    Availability,
+    // Requests slower than this many microseconds are bad
+    Latency { threshold_micros: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Slo {
+    pub expression: String,
+    indicator: Indicator,
+    // Share of good requests, e.g. 0.999
+    objective: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SloSummary {
+    pub slo: String,
+    // Percentages
+    pub objective: f64,
+    pub sli: f64,
+    pub requests: u64,
+    pub bad_requests: u64,
+    // What the error budget allows for `requests`
+    pub allowed_bad_requests: f64,
+    // Percentage of the error budget spent; over 100 when the objective was missed
+    pub budget_consumed: f64,
+    pub burn_rate: f64,
+    pub met: bool,
+}
+
+impl Slo {
+    pub fn parse(expression: &str) -> Result<Self, BoxError> {
+        let compact: String = expression.chars().filter(|c| !c.is_whitespace()).collect();
+        let (kind, spec) = compact.split_once(':').ok_or_else(|| {
+            format!(
+                "SLO needs a kind, as in 'availability:99.9': {}",
+                expression
+            )
+        })?;
+
+        let (indicator, objective) = match kind.to_lowercase().as_str() {
+            "availability" => {
+                let percentage = spec.strip_suffix('%').unwrap_or(spec);
+                let objective = percentage.parse::<f64>().map_err(|_| {
+                    format!("invalid availability objective in SLO: {}", expression)
+                })?;
+                (Indicator::Availability, objective)
+            }
+            "latency" => {
+                // "p99<500ms"; "<=" means the same, as latencies are not exact
+                let (percentile, threshold) = spec
+                    .split_once("<=")
+                    .or_else(|| spec.split_once('<'))
+                    .ok_or_else(|| format!("latency SLO needs 'pNN<DURATION': {}", expression))?;
+                let objective = percentile
+                    .strip_prefix(['p', 'P'])
+                    .and_then(|n| n.parse::<f64>().ok())
+                    .ok_or_else(|| format!("invalid percentile in SLO: {}", expression))?;
+                let threshold_ms = thresholds::parse_millis(threshold)
+                    .map_err(|_| format!("invalid latency in SLO: {}", expression))?;
+                let indicator = Indicator::Latency {
+                    threshold_micros: (threshold_ms * 1000.0) as u64,
+                };
+                (indicator, objective)
+            }
+            other => {
+                return Err(format!(
+                    "unknown SLO kind '{}', expected availability or latency",
+                    other
+                )
+                .into())
+            }
+        };
+        if !(objective > 0.0 && objective < 100.0) {
+            return Err(format!(
+                "SLO objective must be between 0 and 100 percent: {}",
+                expression
+            )
+            .into());
+        }
+
+        Ok(Self {
+            expression: expression.trim().to_string(),
+            indicator,
+            objective: objective / 100.0,
+        })
+    }
+
+    pub fn needs_histogram(&self) -> bool {
+        matches!(self.indicator, Indicator::Latency { .. })
+    }
+
+    // Requests the SLO applies to and how many of them were bad. Latency SLOs cover the
+    // requests in the response time histogram
+    pub fn counts(&self, histogram: &Histogram<u64>, requests: u64, failed: u64) -> (u64, u64) {
+        match self.indicator {
+            Indicator::Availability => (requests, failed),
+            Indicator::Latency { threshold_micros } => {
+                let fast = if histogram.is_empty() {
+                    0
+                } else {
+                    histogram.count_between(0, threshold_micros)
+                };
+                (histogram.len(), histogram.len().saturating_sub(fast))
+            }
+        }
+    }
+
+    // 1.0 spends the error budget exactly as fast as the objective allows
+    pub fn burn_rate(&self, requests: u64, bad_requests: u64) -> f64 {
+        if requests == 0 {
+            return 0.0;
+        }
+        bad_requests as f64 / requests as f64 / (1.0 - self.objective)
+    }
+
+    pub fn evaluate(&self, histogram: &Histogram<u64>, requests: u64, failed: u64) -> SloSummary {
+        let (requests, bad_requests) = self.counts(histogram, requests, failed);
+        let burn_rate = self.burn_rate(requests, bad_requests);
+        let sli = if requests == 0 {
+            100.0
+        } else {
+            (requests - bad_requests) as f64 / requests as f64 * 100.0
+        };
+        SloSummary {
+            slo: self.expression.clone(),
+            objective: self.objective * 100.0,
+            sli,
+            requests,
+            bad_requests,
+            allowed_bad_requests: requests as f64 * (1.0 - self.objective),
+            budget_consumed: burn_rate * 100.0,
+            burn_rate,
+            met: burn_rate <= 1.0,
+        }
+    }
+}
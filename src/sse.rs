@@ -0,0 +1,308 @@
+//! `--sse`: VUs holding server-sent event streams open instead of sending requests
+//
+// Each VU opens a `text/event-stream` response and reads events from it until the VU is
+// stopped or the server ends the stream, then reconnects after the server's `retry:` delay
+// (or its pacing) with the last event ID in `Last-Event-ID`, as an EventSource would. The
+// stream counts as one request whose response time is the time to its headers;
+// --request-timeout only bounds that wait. Events are measured as the time from sending the
+// request to the stream's first event and the time between consecutive events. Comments
+// (`:` lines, often used as heartbeats) and events without data are not counted, since an
+// EventSource does not dispatch them.
+
+use crate::errors::ErrorType;
+use crate::merge;
+use crate::stats::LatencySummary;
+use hdrhistogram::Histogram;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Per-request timeout of streams, which replaces the client's; streams end when their VU does.
+// Also used for --max-response-duration, which cuts responses off itself
+pub const STREAM_TIMEOUT: Duration = Duration::from_secs(365 * 24 * 3600);
+// How often a stream that receives nothing checks whether its VU was stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// What a VU carries from one stream to the next
+#[derive(Default)]
+struct VuStream {
+    stop: Option<Arc<Mutex<bool>>>,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+pub struct SseMonitor {
+    header_timeout: Duration,
+    vus: Vec<Mutex<VuStream>>,
+    streams: AtomicU64,
+    events: AtomicU64,
+    // Microseconds
+    time_to_first_event: Mutex<Histogram<u64>>,
+    event_interval: Mutex<Histogram<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SseSummary {
+    // Event streams opened
+    pub streams: u64,
+    pub events: u64,
+    pub events_per_second: f64,
+    pub time_to_first_event: LatencySummary,
+    pub event_interval: LatencySummary,
+    // For `forgy merge`, encoded like the response time histograms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histograms: Option<SseHistograms>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SseHistograms {
+    pub time_to_first_event: String,
+    pub event_interval: String,
+}
+
+impl SseMonitor {
+    pub fn new(vus: usize, header_timeout: Duration) -> Result<Self, BoxError> {
+        Ok(Self {
+            header_timeout,
+            vus: (0..vus.max(1)).map(|_| Mutex::default()).collect(),
+            streams: AtomicU64::new(0),
+            events: AtomicU64::new(0),
+            time_to_first_event: Mutex::new(Histogram::new(3)?),
+            event_interval: Mutex::new(Histogram::new(3)?),
+        })
+    }
+
+    fn vu(&self, vu: usize) -> &Mutex<VuStream> {
+        &self.vus[vu.saturating_sub(1) % self.vus.len()]
+    }
+
+    // Time allowed until the response headers of a stream arrive
+    pub fn header_timeout(&self) -> Duration {
+        self.header_timeout
+    }
+
+    // The stop signal a VU's streams end on
+    pub fn register(&self, vu: usize, stop: Arc<Mutex<bool>>) {
+        self.vu(vu).lock().stop = Some(stop);
+    }
+
+    pub fn last_event_id(&self, vu: usize) -> Option<String> {
+        self.vu(vu).lock().last_event_id.clone()
+    }
+
+    // Reconnection delay the server asked for with `retry:`
+    pub fn retry(&self, vu: usize) -> Option<Duration> {
+        self.vu(vu).lock().retry
+    }
+
+    fn stopped(&self, vu: usize) -> bool {
+        self.vu(vu)
+            .lock()
+            .stop
+            .as_ref()
+            .is_some_and(|stop| *stop.lock())
+    }
+
+    // Reads events until the VU is stopped or the stream ends; `on_event` gets each event's
+    // latency and whether it was the stream's first. Returns the bytes read and why the
+    // stream broke off, if it did
+    pub async fn read_stream(
+        &self,
+        vu: usize,
+        mut response: Response,
+        start: Instant,
+        mut on_event: impl FnMut(bool, Duration),
+    ) -> (u64, Option<ErrorType>) {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+        let mut parser = Parser::default();
+        let mut last_event: Option<Instant> = None;
+        let mut bytes = 0;
+        let mut stop_poll = interval(STOP_POLL_INTERVAL);
+        let error = loop {
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk,
+                _ = stop_poll.tick() => {
+                    if self.stopped(vu) {
+                        break None;
+                    }
+                    continue;
+                }
+            };
+            let chunk = match chunk {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break None,
+                Err(e) => break Some(ErrorType::from_reqwest(&e)),
+            };
+            bytes += chunk.len() as u64;
+            let received = Instant::now();
+            let events = parser.feed(&chunk);
+            for _ in 0..events {
+                let first = last_event.is_none();
+                let latency = received - last_event.unwrap_or(start);
+                last_event = Some(received);
+                self.record_event(first, latency);
+                on_event(first, latency);
+            }
+        };
+
+        let mut state = self.vu(vu).lock();
+        if parser.last_event_id.is_some() {
+            state.last_event_id = parser.last_event_id;
+        }
+        if parser.retry.is_some() {
+            state.retry = parser.retry;
+        }
+        (bytes, error)
+    }
+
+    fn record_event(&self, first: bool, latency: Duration) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+        let histogram = if first {
+            &self.time_to_first_event
+        } else {
+            &self.event_interval
+        };
+        histogram.lock().record(latency.as_micros() as u64).ok();
+    }
+
+    pub fn summary(&self, duration_seconds: f64) -> SseSummary {
+        let time_to_first_event = self.time_to_first_event.lock().clone();
+        let event_interval = self.event_interval.lock().clone();
+        let events = self.events.load(Ordering::Relaxed);
+        let histograms = merge::encode(&time_to_first_event)
+            .and_then(|first| {
+                Ok(SseHistograms {
+                    time_to_first_event: first,
+                    event_interval: merge::encode(&event_interval)?,
+                })
+            })
+            .ok();
+        SseSummary {
+            streams: self.streams.load(Ordering::Relaxed),
+            events,
+            events_per_second: rate(events, duration_seconds),
+            time_to_first_event: LatencySummary::from_histogram(&time_to_first_event),
+            event_interval: LatencySummary::from_histogram(&event_interval),
+            histograms,
+        }
+    }
+}
+
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+        })
+}
+
+fn rate(events: u64, duration_seconds: f64) -> f64 {
+    if duration_seconds > 0.0 {
+        events as f64 / duration_seconds
+    } else {
+        0.0
+    }
+}
+
+// The streams of runs made in parallel, with their latencies recombined from the histograms
+// when every run has them
+pub fn merge(summaries: &[&SseSummary], duration_seconds: f64) -> Result<SseSummary, BoxError> {
+    let events = summaries.iter().map(|s| s.events).sum();
+    let encoded: Option<Vec<&SseHistograms>> =
+        summaries.iter().map(|s| s.histograms.as_ref()).collect();
+    let (time_to_first_event, event_interval, histograms) = match encoded {
+        Some(encoded) => {
+            let first = merge::combine(encoded.iter().map(|h| &h.time_to_first_event))?;
+            let between = merge::combine(encoded.iter().map(|h| &h.event_interval))?;
+            let histograms = SseHistograms {
+                time_to_first_event: merge::encode(&first)?,
+                event_interval: merge::encode(&between)?,
+            };
+            (
+                LatencySummary::from_histogram(&first),
+                LatencySummary::from_histogram(&between),
+                Some(histograms),
+            )
+        }
+        None => (
+            LatencySummary::weighted(summaries.iter().map(|s| &s.time_to_first_event)),
+            LatencySummary::weighted(summaries.iter().map(|s| &s.event_interval)),
+            None,
+        ),
+    };
+    Ok(SseSummary {
+        streams: summaries.iter().map(|s| s.streams).sum(),
+        events,
+        events_per_second: rate(events, duration_seconds),
+        time_to_first_event,
+        event_interval,
+        histograms,
+    })
+}
+
+// The event stream format, as far as counting events goes
+#[derive(Default)]
+struct Parser {
+    line: Vec<u8>,
+    // A CR ended the last line; a LF right after it belongs to the same line break
+    after_cr: bool,
+    has_data: bool,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Parser {
+    // Returns the number of events the chunk completed
+    fn feed(&mut self, chunk: &[u8]) -> usize {
+        let mut events = 0;
+        for &byte in chunk {
+            if std::mem::take(&mut self.after_cr) && byte == b'\n' {
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    events += self.end_line() as usize;
+                }
+                byte => self.line.push(byte),
+            }
+        }
+        events
+    }
+
+    // Whether the line dispatched an event
+    fn end_line(&mut self) -> bool {
+        let line = std::mem::take(&mut self.line);
+        if line.is_empty() {
+            return std::mem::take(&mut self.has_data);
+        }
+        let line = String::from_utf8_lossy(&line);
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field.trim_start_matches('\u{feff}') {
+            "data" => self.has_data = true,
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(millis) = value.parse::<u64>() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            // Comments ("" before the colon), event types and unknown fields
+            _ => {}
+        }
+        false
+    }
+}
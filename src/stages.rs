@@ -0,0 +1,49 @@
+//! Stage-based load profiles: VU targets interpolated linearly over time
+
+use humantime::parse_duration;
+use std::time::Duration;
+
+// "2m:50" ramps linearly from the previous stage's target to 50 VUs over two minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage {
+    pub duration: Duration,
+    pub target: usize,
+}
+
+impl Stage {
+    // Used as the clap value parser for --stage
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (duration, target) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("stage must be \"DURATION:TARGET\": {}", spec))?;
+        let duration = parse_duration(duration.trim())
+            .map_err(|e| format!("invalid stage duration {}: {}", duration, e))?;
+        let target = target
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid stage target: {}", target))?;
+        Ok(Self { duration, target })
+    }
+}
+
+pub fn total_duration(stages: &[Stage]) -> Duration {
+    stages.iter().map(|stage| stage.duration).sum()
+}
+
+// Returns the index of the stage running at `elapsed` and the interpolated VU target,
+// or None once every stage has finished
+pub fn target_at(stages: &[Stage], elapsed: Duration) -> Option<(usize, f64)> {
+    let mut stage_start = Duration::ZERO;
+    let mut previous_target = 0.0;
+    for (index, stage) in stages.iter().enumerate() {
+        let stage_end = stage_start + stage.duration;
+        if elapsed < stage_end {
+            let progress = (elapsed - stage_start).as_secs_f64() / stage.duration.as_secs_f64();
+            let target = previous_target + (stage.target as f64 - previous_target) * progress;
+            return Some((index, target));
+        }
+        stage_start = stage_end;
+        previous_target = stage.target as f64;
+    }
+    None
+}
@@ -0,0 +1,604 @@
+//! Request statistics, sharded so that thousands of VUs do not contend on a single lock
+//
+// Plain counters are atomics. Everything else is kept per shard: a request locks only the
+// shard of its VU, and shards are merged when the metrics task or the final report reads them.
+// Memory stays constant however long a test runs; individual requests are only kept in an
+// optional fixed-size reservoir sample.
+
+use crate::checks::CheckSummary;
+use crate::errors::ErrorType;
+use crate::timing::{self, PhaseTimings, PHASES};
+use hdrhistogram::Histogram;
+use parking_lot::{Mutex, MutexGuard};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// A finished request, as recorded into the statistics
+pub struct RequestRecord<'a> {
+    pub target: &'a str,
+    pub endpoint: &'a str,
+    pub status_code: u16,
+    pub success: bool,
+    pub error_type: Option<ErrorType>,
+    pub duration_micros: u64,
+    pub bytes: ByteCounts,
+    pub phases: &'a PhaseTimings,
+    // A retry of a failed request rather than its first attempt
+    pub retry: bool,
+}
+
+// Distribution of a latency measured besides the response times, in milliseconds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub avg_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl LatencySummary {
+    // From a histogram in microseconds
+    pub fn from_histogram(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return Self::default();
+        }
+        Self {
+            count: histogram.len(),
+            avg_ms: histogram.mean() / 1000.0,
+            p50_ms: histogram.value_at_percentile(50.0) as f64 / 1000.0,
+            p95_ms: histogram.value_at_percentile(95.0) as f64 / 1000.0,
+            p99_ms: histogram.value_at_percentile(99.0) as f64 / 1000.0,
+        }
+    }
+
+    // Count-weighted averages, for merged results without histograms
+    pub fn weighted<'a>(summaries: impl Iterator<Item = &'a Self> + Clone) -> Self {
+        let count: u64 = summaries.clone().map(|s| s.count).sum();
+        if count == 0 {
+            return Self::default();
+        }
+        let mean = |value: fn(&Self) -> f64| {
+            summaries
+                .clone()
+                .map(|s| value(s) * s.count as f64)
+                .sum::<f64>()
+                / count as f64
+        };
+        Self {
+            count,
+            avg_ms: mean(|s| s.avg_ms),
+            p50_ms: mean(|s| s.p50_ms),
+            p95_ms: mean(|s| s.p95_ms),
+            p99_ms: mean(|s| s.p99_ms),
+        }
+    }
+}
+
+// Bytes of one request and its response, split into head and body
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByteCounts {
+    pub header_sent: u64,
+    pub body_sent: u64,
+    pub header_received: u64,
+    pub body_received: u64,
+    // Bodies before content encoding (sent) and after decoding (received); the fields above
+    // are what went over the wire
+    pub decoded_body_sent: u64,
+    pub decoded_body_received: u64,
+}
+
+impl ByteCounts {
+    pub fn sent(&self) -> u64 {
+        self.header_sent + self.body_sent
+    }
+
+    pub fn received(&self) -> u64 {
+        self.header_received + self.body_received
+    }
+}
+
+pub const STATUS_CLASSES: [&str; 5] = ["2xx", "3xx", "4xx", "5xx", "other"];
+
+// The `status_class` label of a status code
+pub fn status_class(status_code: u16) -> &'static str {
+    match status_code {
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        500..=599 => "5xx",
+        _ => "other",
+    }
+}
+
+// Per-target counters behind TargetSummary
+#[derive(Clone)]
+pub struct TargetStats {
+    pub histogram: Histogram<u64>,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+}
+
+// Per-endpoint counters behind EndpointSummary
+#[derive(Clone)]
+pub struct EndpointStats {
+    pub histogram: Histogram<u64>,
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub error_types: BTreeMap<ErrorType, usize>,
+}
+
+// Statistics of one shard, or of all shards once merged; histograms are in microseconds
+#[derive(Clone)]
+pub struct ShardStats {
+    pub histogram: Histogram<u64>,
+    // Latencies corrected for coordinated omission
+    pub corrected_histogram: Histogram<u64>,
+    pub status_codes: HashMap<u16, usize>,
+    pub error_types: BTreeMap<ErrorType, usize>,
+    pub targets: BTreeMap<String, TargetStats>,
+    pub endpoints: BTreeMap<String, EndpointStats>,
+    pub phases: BTreeMap<&'static str, Histogram<u64>>,
+    // Latencies of the responses of each status class; requests without one are left out
+    pub status_classes: BTreeMap<&'static str, Histogram<u64>>,
+    pub checks: BTreeMap<String, CheckSummary>,
+    // Download rates of single requests in bytes per second, with --throughput
+    pub throughput: Histogram<u64>,
+}
+
+impl ShardStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            histogram: new_histogram(),
+            corrected_histogram: new_histogram(),
+            status_codes: HashMap::new(),
+            error_types: BTreeMap::new(),
+            targets: BTreeMap::new(),
+            endpoints: BTreeMap::new(),
+            phases: PHASES
+                .iter()
+                .map(|phase| (*phase, new_histogram()))
+                .collect(),
+            status_classes: BTreeMap::new(),
+            checks: BTreeMap::new(),
+            throughput: new_histogram(),
+        }
+    }
+
+    pub fn record_check(&mut self, name: &str, passed: bool) {
+        if !self.checks.contains_key(name) {
+            self.checks.insert(
+                name.to_string(),
+                CheckSummary {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        let summary = self.checks.get_mut(name).unwrap();
+        if passed {
+            summary.passed += 1;
+        } else {
+            summary.failed += 1;
+        }
+    }
+
+    fn merge(&mut self, other: &ShardStats) {
+        self.histogram.add(&other.histogram).ok();
+        self.corrected_histogram
+            .add(&other.corrected_histogram)
+            .ok();
+        for (code, count) in &other.status_codes {
+            *self.status_codes.entry(*code).or_insert(0) += count;
+        }
+        for (error_type, count) in &other.error_types {
+            *self.error_types.entry(*error_type).or_insert(0) += count;
+        }
+        for (name, stats) in &other.targets {
+            match self.targets.get_mut(name) {
+                Some(target) => {
+                    target.histogram.add(&stats.histogram).ok();
+                    target.total_requests += stats.total_requests;
+                    target.successful_requests += stats.successful_requests;
+                }
+                None => {
+                    self.targets.insert(name.clone(), stats.clone());
+                }
+            }
+        }
+        for (name, stats) in &other.endpoints {
+            match self.endpoints.get_mut(name) {
+                Some(endpoint) => {
+                    endpoint.histogram.add(&stats.histogram).ok();
+                    endpoint.total_requests += stats.total_requests;
+                    endpoint.successful_requests += stats.successful_requests;
+                    for (error_type, count) in &stats.error_types {
+                        *endpoint.error_types.entry(*error_type).or_insert(0) += count;
+                    }
+                }
+                None => {
+                    self.endpoints.insert(name.clone(), stats.clone());
+                }
+            }
+        }
+        for (phase, histogram) in &other.phases {
+            if let Some(merged) = self.phases.get_mut(phase) {
+                merged.add(histogram).ok();
+            }
+        }
+        for (class, histogram) in &other.status_classes {
+            self.status_classes
+                .entry(class)
+                .or_insert_with(new_histogram)
+                .add(histogram)
+                .ok();
+        }
+        for (name, summary) in &other.checks {
+            let merged = self
+                .checks
+                .entry(name.clone())
+                .or_insert_with(|| CheckSummary {
+                    name: name.clone(),
+                    ..Default::default()
+                });
+            merged.passed += summary.passed;
+            merged.failed += summary.failed;
+        }
+        self.throughput.add(&other.throughput).ok();
+    }
+}
+
+// The atomic counters of Stats, as saved in checkpoints
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Counters {
+    pub total_requests: usize,
+    pub successful_requests: usize,
+    pub header_bytes_sent: u64,
+    pub body_bytes_sent: u64,
+    pub header_bytes_received: u64,
+    pub body_bytes_received: u64,
+    #[serde(default)]
+    pub decoded_body_bytes_sent: u64,
+    #[serde(default)]
+    pub decoded_body_bytes_received: u64,
+    pub dropped_iterations: u64,
+    pub retries: u64,
+    pub recovered_requests: u64,
+    #[serde(default)]
+    pub cut_off_responses: u64,
+    pub duration_sum_micros: u64,
+    pub min_duration_micros: u64,
+    pub max_duration_micros: u64,
+}
+
+pub struct Stats {
+    shards: Box<[Mutex<ShardStats>]>,
+    total_requests: AtomicUsize,
+    successful_requests: AtomicUsize,
+    header_bytes_sent: AtomicU64,
+    body_bytes_sent: AtomicU64,
+    header_bytes_received: AtomicU64,
+    body_bytes_received: AtomicU64,
+    decoded_body_bytes_sent: AtomicU64,
+    decoded_body_bytes_received: AtomicU64,
+    dropped_iterations: AtomicU64,
+    retries: AtomicU64,
+    // Requests that failed at first and succeeded on a retry
+    recovered_requests: AtomicU64,
+    // Response bodies still streaming at --max-response-duration
+    cut_off_responses: AtomicU64,
+    // Exact response time aggregates in microseconds; the histograms round to 3 digits
+    duration_sum_micros: AtomicU64,
+    min_duration_micros: AtomicU64,
+    max_duration_micros: AtomicU64,
+}
+
+impl Stats {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(ShardStats::new()))
+                .collect(),
+            total_requests: AtomicUsize::new(0),
+            successful_requests: AtomicUsize::new(0),
+            header_bytes_sent: AtomicU64::new(0),
+            body_bytes_sent: AtomicU64::new(0),
+            header_bytes_received: AtomicU64::new(0),
+            body_bytes_received: AtomicU64::new(0),
+            decoded_body_bytes_sent: AtomicU64::new(0),
+            decoded_body_bytes_received: AtomicU64::new(0),
+            dropped_iterations: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            recovered_requests: AtomicU64::new(0),
+            cut_off_responses: AtomicU64::new(0),
+            duration_sum_micros: AtomicU64::new(0),
+            min_duration_micros: AtomicU64::new(u64::MAX),
+            max_duration_micros: AtomicU64::new(0),
+        }
+    }
+
+    // A few shards per core keeps collisions between concurrently recording VUs rare
+    pub fn with_default_shards() -> Self {
+        Self::new(num_cpus::get() * 4)
+    }
+
+    // The shard for a VU or arrival worker; the same key always maps to the same shard
+    pub fn shard(&self, key: usize) -> MutexGuard<'_, ShardStats> {
+        self.shards[key % self.shards.len()].lock()
+    }
+
+    pub fn record(&self, key: usize, request: &RequestRecord<'_>) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        if request.success {
+            self.successful_requests.fetch_add(1, Ordering::Relaxed);
+        }
+        if request.retry {
+            self.retries.fetch_add(1, Ordering::Relaxed);
+        }
+        self.header_bytes_sent
+            .fetch_add(request.bytes.header_sent, Ordering::Relaxed);
+        self.body_bytes_sent
+            .fetch_add(request.bytes.body_sent, Ordering::Relaxed);
+        self.header_bytes_received
+            .fetch_add(request.bytes.header_received, Ordering::Relaxed);
+        self.body_bytes_received
+            .fetch_add(request.bytes.body_received, Ordering::Relaxed);
+        self.decoded_body_bytes_sent
+            .fetch_add(request.bytes.decoded_body_sent, Ordering::Relaxed);
+        self.decoded_body_bytes_received
+            .fetch_add(request.bytes.decoded_body_received, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(request.duration_micros, Ordering::Relaxed);
+        self.min_duration_micros
+            .fetch_min(request.duration_micros, Ordering::Relaxed);
+        self.max_duration_micros
+            .fetch_max(request.duration_micros, Ordering::Relaxed);
+
+        let mut shard = self.shard(key);
+        shard.histogram.record(request.duration_micros).ok();
+        *shard.status_codes.entry(request.status_code).or_insert(0) += 1;
+        if request.status_code != 0 {
+            shard
+                .status_classes
+                .entry(status_class(request.status_code))
+                .or_insert_with(new_histogram)
+                .record(request.duration_micros)
+                .ok();
+        }
+        if let Some(error_type) = request.error_type {
+            *shard.error_types.entry(error_type).or_insert(0) += 1;
+        }
+
+        if !shard.targets.contains_key(request.target) {
+            shard.targets.insert(
+                request.target.to_string(),
+                TargetStats {
+                    histogram: new_histogram(),
+                    total_requests: 0,
+                    successful_requests: 0,
+                },
+            );
+        }
+        let target = shard.targets.get_mut(request.target).unwrap();
+        target.histogram.record(request.duration_micros).ok();
+        target.total_requests += 1;
+        if request.success {
+            target.successful_requests += 1;
+        }
+
+        if !shard.endpoints.contains_key(request.endpoint) {
+            shard.endpoints.insert(
+                request.endpoint.to_string(),
+                EndpointStats {
+                    histogram: new_histogram(),
+                    total_requests: 0,
+                    successful_requests: 0,
+                    error_types: BTreeMap::new(),
+                },
+            );
+        }
+        let endpoint = shard.endpoints.get_mut(request.endpoint).unwrap();
+        endpoint.histogram.record(request.duration_micros).ok();
+        endpoint.total_requests += 1;
+        if request.success {
+            endpoint.successful_requests += 1;
+        }
+        if let Some(error_type) = request.error_type {
+            *endpoint.error_types.entry(error_type).or_insert(0) += 1;
+        }
+
+        // Connection phases are only counted for requests that opened a connection, but TTFB
+        // and download are recorded for every response
+        for (phase, ms) in request.phases.phases() {
+            if request.status_code == 0 && !timing::is_connection_phase(phase) {
+                continue;
+            }
+            if let Some(histogram) = shard.phases.get_mut(phase) {
+                histogram.record((ms * 1000.0) as u64).ok();
+            }
+        }
+    }
+
+    pub fn record_dropped_iteration(&self) {
+        self.dropped_iterations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_recovered(&self) {
+        self.recovered_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cut_off(&self) {
+        self.cut_off_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn total_requests(&self) -> usize {
+        self.total_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn successful_requests(&self) -> usize {
+        self.successful_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> ByteCounts {
+        ByteCounts {
+            header_sent: self.header_bytes_sent.load(Ordering::Relaxed),
+            body_sent: self.body_bytes_sent.load(Ordering::Relaxed),
+            header_received: self.header_bytes_received.load(Ordering::Relaxed),
+            body_received: self.body_bytes_received.load(Ordering::Relaxed),
+            decoded_body_sent: self.decoded_body_bytes_sent.load(Ordering::Relaxed),
+            decoded_body_received: self.decoded_body_bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes().sent()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes().received()
+    }
+
+    pub fn dropped_iterations(&self) -> u64 {
+        self.dropped_iterations.load(Ordering::Relaxed)
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn recovered_requests(&self) -> u64 {
+        self.recovered_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn cut_off_responses(&self) -> u64 {
+        self.cut_off_responses.load(Ordering::Relaxed)
+    }
+
+    pub fn mean_duration_micros(&self) -> f64 {
+        match self.total_requests() {
+            0 => 0.0,
+            total => self.duration_sum_micros.load(Ordering::Relaxed) as f64 / total as f64,
+        }
+    }
+
+    pub fn min_duration_micros(&self) -> u64 {
+        match self.min_duration_micros.load(Ordering::Relaxed) {
+            u64::MAX => 0,
+            min => min,
+        }
+    }
+
+    pub fn max_duration_micros(&self) -> u64 {
+        self.max_duration_micros.load(Ordering::Relaxed)
+    }
+
+    pub fn counters(&self) -> Counters {
+        Counters {
+            total_requests: self.total_requests(),
+            successful_requests: self.successful_requests(),
+            header_bytes_sent: self.header_bytes_sent.load(Ordering::Relaxed),
+            body_bytes_sent: self.body_bytes_sent.load(Ordering::Relaxed),
+            header_bytes_received: self.header_bytes_received.load(Ordering::Relaxed),
+            body_bytes_received: self.body_bytes_received.load(Ordering::Relaxed),
+            decoded_body_bytes_sent: self.decoded_body_bytes_sent.load(Ordering::Relaxed),
+            decoded_body_bytes_received: self.decoded_body_bytes_received.load(Ordering::Relaxed),
+            dropped_iterations: self.dropped_iterations(),
+            retries: self.retries(),
+            recovered_requests: self.recovered_requests(),
+            cut_off_responses: self.cut_off_responses(),
+            duration_sum_micros: self.duration_sum_micros.load(Ordering::Relaxed),
+            min_duration_micros: self.min_duration_micros.load(Ordering::Relaxed),
+            max_duration_micros: self.max_duration_micros(),
+        }
+    }
+
+    // Add the statistics of an earlier run, e.g. from a checkpoint
+    pub fn restore(&self, counters: &Counters, stats: &ShardStats) {
+        self.total_requests
+            .fetch_add(counters.total_requests, Ordering::Relaxed);
+        self.successful_requests
+            .fetch_add(counters.successful_requests, Ordering::Relaxed);
+        self.header_bytes_sent
+            .fetch_add(counters.header_bytes_sent, Ordering::Relaxed);
+        self.body_bytes_sent
+            .fetch_add(counters.body_bytes_sent, Ordering::Relaxed);
+        self.header_bytes_received
+            .fetch_add(counters.header_bytes_received, Ordering::Relaxed);
+        self.body_bytes_received
+            .fetch_add(counters.body_bytes_received, Ordering::Relaxed);
+        self.decoded_body_bytes_sent
+            .fetch_add(counters.decoded_body_bytes_sent, Ordering::Relaxed);
+        self.decoded_body_bytes_received
+            .fetch_add(counters.decoded_body_bytes_received, Ordering::Relaxed);
+        self.dropped_iterations
+            .fetch_add(counters.dropped_iterations, Ordering::Relaxed);
+        self.retries.fetch_add(counters.retries, Ordering::Relaxed);
+        self.recovered_requests
+            .fetch_add(counters.recovered_requests, Ordering::Relaxed);
+        self.cut_off_responses
+            .fetch_add(counters.cut_off_responses, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(counters.duration_sum_micros, Ordering::Relaxed);
+        self.min_duration_micros
+            .fetch_min(counters.min_duration_micros, Ordering::Relaxed);
+        self.max_duration_micros
+            .fetch_max(counters.max_duration_micros, Ordering::Relaxed);
+        self.shard(0).merge(stats);
+    }
+
+    // Response times of all shards; cheaper than `merged` for periodic percentile updates
+    pub fn histogram(&self) -> Histogram<u64> {
+        let mut merged = new_histogram();
+        for shard in self.shards.iter() {
+            merged.add(&shard.lock().histogram).ok();
+        }
+        merged
+    }
+
+    pub fn merged(&self) -> ShardStats {
+        let mut merged = ShardStats::new();
+        for shard in self.shards.iter() {
+            merged.merge(&shard.lock());
+        }
+        merged
+    }
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::<u64>::new(3).unwrap()
+}
+
+// Uniform random sample of a stream of unknown length (Vitter's algorithm R)
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn offer(&mut self, item: T, rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let index = rng.gen_range(0..self.seen);
+            if index < self.capacity as u64 {
+                self.items[index as usize] = item;
+            }
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+}
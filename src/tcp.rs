@@ -0,0 +1,323 @@
+//! `--protocol tcp`: VUs opening raw TCP connections instead of sending HTTP requests
+//
+// Every request connects to the `tcp://HOST:PORT` of its URL, sends `--payload-hex` if given,
+// reads `--tcp-read` bytes of response if asked to, and closes the connection, so custom
+// protocols, health ports and load balancers are tested from the same tool. As an HTTP
+// response's runs until its headers, a request's response time runs from the start of the
+// name lookup to the first response byte (or the last byte sent, when nothing is read); the
+// rest of the response is its `download` phase. The connect time and the round trip from
+// sending the payload to the last response byte are measured on their own. `--resolve`, `--dns-server`, `--local-address` and
+// `--connect-timeout` apply as they do to HTTP connections; `--request-timeout` bounds the
+// whole exchange. Requests have no status code and are reported with status 0.
+
+use crate::dns::{self, Resolver};
+use crate::errors::ErrorType;
+use crate::merge;
+use crate::stats::LatencySummary;
+use hdrhistogram::Histogram;
+use parking_lot::Mutex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Settings of the exchanges, as given on the command line
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub payload: Vec<u8>,
+    // Response bytes to wait for after sending the payload; 0 reads nothing
+    pub read_bytes: usize,
+    pub connect_timeout: Option<Duration>,
+    pub timeout: Duration,
+    pub local_addresses: Vec<IpAddr>,
+}
+
+// How one exchange went, with times measured from its start
+pub struct Exchange {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    // Until the first response byte, or until the payload was sent when nothing is read
+    pub first_byte: Option<Duration>,
+    pub duration: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub local_addr: Option<SocketAddr>,
+    pub error: Option<ErrorType>,
+}
+
+pub struct TcpProber {
+    config: TcpConfig,
+    resolver: Arc<Resolver>,
+    connections: AtomicU64,
+    // Microseconds
+    connect: Mutex<Histogram<u64>>,
+    round_trip: Mutex<Histogram<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpSummary {
+    // Connections that were established
+    pub connections: u64,
+    pub connect: LatencySummary,
+    // Only measured with --tcp-read
+    pub round_trip: LatencySummary,
+    // For `forgy merge`, encoded like the response time histograms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histograms: Option<TcpHistograms>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpHistograms {
+    pub connect: String,
+    pub round_trip: String,
+}
+
+// The host and port of a `tcp://HOST:PORT` URL
+pub fn parse_address(url: &str) -> Result<(String, u16), BoxError> {
+    let parsed = Url::parse(url).map_err(|e| format!("invalid TCP address {}: {}", url, e))?;
+    if parsed.scheme() != "tcp" {
+        return Err(format!("--protocol tcp needs tcp://HOST:PORT URLs, got {}", url).into());
+    }
+    let host = match parsed.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        None => return Err(format!("TCP address {} has no host", url).into()),
+    };
+    let port = parsed
+        .port()
+        .ok_or_else(|| format!("TCP address {} has no port", url))?;
+    Ok((host, port))
+}
+
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, BoxError> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|b| {
+            (b as char)
+                .to_digit(16)
+                .map(|digit| digit as u8)
+                .ok_or_else(|| format!("invalid hex digit '{}' in --payload-hex", b as char))
+        })
+        .collect::<Result<_, _>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return Err("--payload-hex needs an even number of hex digits".into());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+impl TcpProber {
+    pub fn new(config: TcpConfig, resolver: Arc<Resolver>) -> Result<Self, BoxError> {
+        Ok(Self {
+            config,
+            resolver,
+            connections: AtomicU64::new(0),
+            connect: Mutex::new(Histogram::new(3)?),
+            round_trip: Mutex::new(Histogram::new(3)?),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let sent = match self.config.payload.len() {
+            0 => "connect only".to_string(),
+            bytes => format!("send {} bytes", bytes),
+        };
+        match self.config.read_bytes {
+            0 => sent,
+            bytes => format!("{}, read {} bytes", sent, bytes),
+        }
+    }
+
+    // Connect to the URL's address, send the payload and read the response
+    pub async fn exchange(&self, vu: usize, url: &str, start: Instant) -> Exchange {
+        let mut exchange = Exchange {
+            dns: None,
+            connect: None,
+            first_byte: None,
+            duration: Duration::ZERO,
+            bytes_sent: 0,
+            bytes_received: 0,
+            local_addr: None,
+            error: None,
+        };
+        let run = self.run(vu, url, start, &mut exchange);
+        exchange.error = match tokio::time::timeout(self.config.timeout, run).await {
+            Ok(result) => result.err(),
+            Err(_) => Some(ErrorType::Timeout),
+        };
+        exchange.duration = start.elapsed();
+        exchange
+    }
+
+    async fn run(
+        &self,
+        vu: usize,
+        url: &str,
+        start: Instant,
+        exchange: &mut Exchange,
+    ) -> Result<(), ErrorType> {
+        // Templated URLs are only known now
+        let (host, port) = parse_address(url).map_err(|_| ErrorType::Other)?;
+        let ips = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => {
+                let overrides =
+                    dns::with_port(Some(port), async { self.resolver.override_for(&host) }).await;
+                match overrides {
+                    Some(ips) => ips,
+                    None => {
+                        let lookup_start = Instant::now();
+                        let ips = self
+                            .resolver
+                            .lookup(&host)
+                            .await
+                            .map_err(|_| ErrorType::Dns)?;
+                        exchange.dns = Some(lookup_start.elapsed());
+                        ips
+                    }
+                }
+            }
+        };
+
+        let connect_start = Instant::now();
+        let mut stream = self.connect(vu, &ips, port).await?;
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        exchange.connect = Some(connect_start.elapsed());
+        record(&self.connect, connect_start.elapsed());
+        exchange.local_addr = stream.local_addr().ok();
+        stream.set_nodelay(true).ok();
+
+        let send_start = Instant::now();
+        if !self.config.payload.is_empty() {
+            stream
+                .write_all(&self.config.payload)
+                .await
+                .map_err(|e| ErrorType::from_io(&e))?;
+            exchange.bytes_sent = self.config.payload.len() as u64;
+        }
+        if self.config.read_bytes == 0 {
+            exchange.first_byte = Some(start.elapsed());
+        } else {
+            let mut buffer = vec![0; self.config.read_bytes.min(64 * 1024)];
+            while (exchange.bytes_received as usize) < self.config.read_bytes {
+                let wanted =
+                    (self.config.read_bytes - exchange.bytes_received as usize).min(buffer.len());
+                let read = stream
+                    .read(&mut buffer[..wanted])
+                    .await
+                    .map_err(|e| ErrorType::from_io(&e))?;
+                if read == 0 {
+                    // Closed before the expected response arrived
+                    return Err(ErrorType::Body);
+                }
+                exchange.first_byte.get_or_insert_with(|| start.elapsed());
+                exchange.bytes_received += read as u64;
+            }
+            record(&self.round_trip, send_start.elapsed());
+        }
+        stream.shutdown().await.ok();
+        Ok(())
+    }
+
+    // The first of the addresses that accepts a connection, from the VU's source address
+    async fn connect(&self, vu: usize, ips: &[IpAddr], port: u16) -> Result<TcpStream, ErrorType> {
+        let sources = &self.config.local_addresses;
+        let source = (!sources.is_empty()).then(|| sources[vu.saturating_sub(1) % sources.len()]);
+        let mut error = ErrorType::Dns;
+        for ip in ips {
+            let socket = match ip {
+                IpAddr::V4(_) => TcpSocket::new_v4(),
+                IpAddr::V6(_) => TcpSocket::new_v6(),
+            }
+            .map_err(|e| ErrorType::from_io(&e))?;
+            if let Some(source) = source.filter(|source| source.is_ipv4() == ip.is_ipv4()) {
+                socket
+                    .bind(SocketAddr::new(source, 0))
+                    .map_err(|e| ErrorType::from_io(&e))?;
+            }
+            let connect = socket.connect(SocketAddr::new(*ip, port));
+            let result = match self.config.connect_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, connect).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        error = ErrorType::ConnectTimeout;
+                        continue;
+                    }
+                },
+                None => connect.await,
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = ErrorType::from_io(&e),
+            }
+        }
+        Err(error)
+    }
+
+    pub fn summary(&self) -> TcpSummary {
+        let connect = self.connect.lock().clone();
+        let round_trip = self.round_trip.lock().clone();
+        let histograms = merge::encode(&connect)
+            .and_then(|encoded| {
+                Ok(TcpHistograms {
+                    connect: encoded,
+                    round_trip: merge::encode(&round_trip)?,
+                })
+            })
+            .ok();
+        TcpSummary {
+            connections: self.connections.load(Ordering::Relaxed),
+            connect: LatencySummary::from_histogram(&connect),
+            round_trip: LatencySummary::from_histogram(&round_trip),
+            histograms,
+        }
+    }
+}
+
+fn record(histogram: &Mutex<Histogram<u64>>, latency: Duration) {
+    histogram.lock().record(latency.as_micros() as u64).ok();
+}
+
+// The connections of runs made in parallel, with their latencies recombined from the
+// histograms when every run has them
+pub fn merge(summaries: &[&TcpSummary]) -> Result<TcpSummary, BoxError> {
+    let encoded: Option<Vec<&TcpHistograms>> =
+        summaries.iter().map(|s| s.histograms.as_ref()).collect();
+    let (connect, round_trip, histograms) = match encoded {
+        Some(encoded) => {
+            let connect = merge::combine(encoded.iter().map(|h| &h.connect))?;
+            let round_trip = merge::combine(encoded.iter().map(|h| &h.round_trip))?;
+            let histograms = TcpHistograms {
+                connect: merge::encode(&connect)?,
+                round_trip: merge::encode(&round_trip)?,
+            };
+            (
+                LatencySummary::from_histogram(&connect),
+                LatencySummary::from_histogram(&round_trip),
+                Some(histograms),
+            )
+        }
+        None => (
+            LatencySummary::weighted(summaries.iter().map(|s| &s.connect)),
+            LatencySummary::weighted(summaries.iter().map(|s| &s.round_trip)),
+            None,
+        ),
+    };
+    Ok(TcpSummary {
+        connections: summaries.iter().map(|s| s.connections).sum(),
+        connect,
+        round_trip,
+        histograms,
+    })
+}
@@ -0,0 +1,203 @@
+//! Placeholders such as `${VU}` or `${RAND_INT(1,1000)}` expanded in every request
+
+use crate::data::DataRow;
+use crate::fake::{self, Fake};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Counter behind ${seq(N)}, shared by every placeholder, VU and request of the process
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+// Per-request values the placeholders are expanded with
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateContext<'a> {
+    // 1-based VU number
+    pub vu: usize,
+    // 0-based iteration of that VU
+    pub iteration: u64,
+    // Row of the --data feed, if any
+    pub row: Option<DataRow<'a>>,
+    // Values the VU's vu_init requests extracted
+    pub vars: Option<&'a HashMap<String, String>>,
+    // The VU's generator for random values; the thread's one if None
+    pub rng: Option<&'a Mutex<StdRng>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Vu,
+    Iter,
+    Uuid,
+    // Inclusive range
+    RandInt(i64, i64),
+    // Column of the --data feed
+    Csv(String),
+    // Value extracted by a setup request
+    Var(String),
+    Fake(Fake),
+    // Next value of the process-wide sequence, counting from the start given
+    Seq(u64),
+}
+
+// A string parsed once at startup and rendered for every request
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, BoxError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("${") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format!("unterminated placeholder in {}", source))?;
+            segments.push(Self::parse_placeholder(&rest[start + 2..end])?);
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    // Text sent as is, e.g. a URL recorded from real traffic that may contain "${"
+    pub fn literal(text: &str) -> Self {
+        Self {
+            source: text.to_string(),
+            segments: vec![Segment::Literal(text.to_string())],
+        }
+    }
+
+    fn parse_placeholder(name: &str) -> Result<Segment, BoxError> {
+        let name = name.trim();
+        match name {
+            "VU" => Ok(Segment::Vu),
+            "ITER" => Ok(Segment::Iter),
+            "UUID" => Ok(Segment::Uuid),
+            _ if name.starts_with("csv.") => Ok(Segment::Csv(name["csv.".len()..].to_string())),
+            _ if name.starts_with("vars.") => Ok(Segment::Var(name["vars.".len()..].to_string())),
+            _ if name.starts_with("fake.") => {
+                Ok(Segment::Fake(Fake::parse(&name["fake.".len()..])?))
+            }
+            _ if name.starts_with("seq(") => {
+                let start = name["seq(".len()..]
+                    .strip_suffix(')')
+                    .map(str::trim)
+                    .and_then(|start| start.parse().ok())
+                    .ok_or_else(|| format!("seq needs a starting number: ${{{}}}", name))?;
+                Ok(Segment::Seq(start))
+            }
+            _ => {
+                let arguments = name
+                    .strip_prefix("RAND_INT(")
+                    .and_then(|rest| rest.strip_suffix(')'))
+                    .ok_or_else(|| format!("unknown placeholder: ${{{}}}", name))?;
+                let (min, max) = arguments
+                    .split_once(',')
+                    .ok_or_else(|| format!("RAND_INT needs two arguments: ${{{}}}", name))?;
+                let parse = |n: &str| {
+                    n.trim()
+                        .parse::<i64>()
+                        .map_err(|_| format!("invalid RAND_INT bound {} in ${{{}}}", n, name))
+                };
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min > max {
+                    return Err(format!("RAND_INT minimum is above maximum: ${{{}}}", name).into());
+                }
+                Ok(Segment::RandInt(min, max))
+            }
+        }
+    }
+
+    // Names of the data feed columns referenced as ${csv.column}
+    pub fn csv_columns(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Csv(column) => Some(column.as_str()),
+            _ => None,
+        })
+    }
+
+    // Names of the setup variables referenced as ${vars.name}
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Var(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    // Replace ${vars.name} placeholders with their values; they are known once setup ran
+    pub fn bind(&mut self, variables: &HashMap<String, String>) {
+        for segment in &mut self.segments {
+            if let Segment::Var(name) = segment {
+                if let Some(value) = variables.get(name.as_str()) {
+                    *segment = Segment::Literal(value.clone());
+                }
+            }
+        }
+    }
+
+    pub fn render(&self, context: &TemplateContext<'_>) -> String {
+        match context.rng {
+            Some(rng) => self.render_with(context, &mut *rng.lock()),
+            None => self.render_with(context, &mut rand::thread_rng()),
+        }
+    }
+
+    fn render_with<R: Rng>(&self, context: &TemplateContext<'_>, rng: &mut R) -> String {
+        let mut rendered = String::with_capacity(self.source.len());
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => rendered.push_str(text),
+                Segment::Vu => rendered.push_str(&context.vu.to_string()),
+                Segment::Iter => rendered.push_str(&context.iteration.to_string()),
+                Segment::Uuid => rendered.push_str(&fake::uuid(rng).to_string()),
+                Segment::RandInt(min, max) => {
+                    let value = rng.gen_range(*min..=*max);
+                    rendered.push_str(&value.to_string());
+                }
+                Segment::Csv(column) => {
+                    if let Some(value) = context.row.and_then(|row| row.get(column)) {
+                        rendered.push_str(value);
+                    }
+                }
+                Segment::Fake(fake) => fake.render(rng, &mut rendered),
+                Segment::Seq(start) => {
+                    let value = start + SEQUENCE.fetch_add(1, Ordering::Relaxed);
+                    rendered.push_str(&value.to_string());
+                }
+                // Setup values are bound before the run; the rest come from the VU's vu_init
+                Segment::Var(name) => {
+                    if let Some(value) = context.vars.and_then(|vars| vars.get(name)) {
+                        rendered.push_str(value);
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+// The unexpanded source, used for labels and the test summary
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
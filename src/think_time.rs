@@ -0,0 +1,87 @@
+//! Think time between a VU's requests, drawn from a configurable distribution
+
+use humantime::{format_duration, parse_duration};
+use rand::Rng;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThinkTime {
+    // No pause: each VU sends its next request as soon as the previous one finishes
+    None,
+    Constant(Duration),
+    // Uniformly distributed between the two bounds, inclusive
+    Uniform(Duration, Duration),
+    // Exponentially distributed with the given mean, as between independent arrivals
+    Exponential(Duration),
+}
+
+impl ThinkTime {
+    // Used as the clap value parser for --think-time: "none", "constant:500ms",
+    // "uniform:200ms-2s" or "exponential:1s"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("none") {
+            return Ok(Self::None);
+        }
+        let (kind, value) = spec.split_once(':').ok_or_else(|| {
+            format!(
+                "think time must be none, constant:D, uniform:MIN-MAX or exponential:MEAN: {}",
+                spec
+            )
+        })?;
+        let duration = |text: &str| {
+            parse_duration(text.trim())
+                .map_err(|e| format!("invalid think time duration {}: {}", text, e))
+        };
+        match kind.trim().to_ascii_lowercase().as_str() {
+            "constant" => Ok(Self::Constant(duration(value)?)),
+            "uniform" => {
+                let (min, max) = value
+                    .split_once('-')
+                    .ok_or_else(|| format!("uniform think time must be \"MIN-MAX\": {}", value))?;
+                let (min, max) = (duration(min)?, duration(max)?);
+                if min > max {
+                    return Err(format!(
+                        "uniform think time minimum exceeds maximum: {}",
+                        value
+                    ));
+                }
+                Ok(Self::Uniform(min, max))
+            }
+            "exponential" => Ok(Self::Exponential(duration(value)?)),
+            _ => Err(format!("unknown think time distribution: {}", kind)),
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        match *self {
+            Self::None => Duration::ZERO,
+            Self::Constant(duration) => duration,
+            Self::Uniform(min, max) => rng.gen_range(min..=max),
+            Self::Exponential(mean) => {
+                // Inverse transform sampling; 1 - u lies in (0, 1], so the logarithm is finite
+                let u: f64 = rng.gen();
+                mean.mul_f64(-(1.0 - u).ln())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ThinkTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::None => write!(f, "none"),
+            Self::Constant(duration) => write!(f, "constant {}", format_duration(duration)),
+            Self::Uniform(min, max) => write!(
+                f,
+                "uniform {}-{}",
+                format_duration(min),
+                format_duration(max)
+            ),
+            Self::Exponential(mean) => {
+                write!(f, "exponential, mean {}", format_duration(mean))
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Pass/fail thresholds evaluated against the final results
+
+use crate::timeseries::TimeseriesPoint;
+use crate::TestResults;
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Process exit code when at least one threshold fails
+pub const THRESHOLD_FAILED_EXIT_CODE: i32 = 99;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Min,
+    Max,
+    Avg,
+    Percentile(u8),
+    ErrorRate,
+    SuccessRate,
+    Rps,
+    Requests,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+// A parsed threshold such as "p95<250ms" or "error_rate<1%"
+#[derive(Debug, Clone)]
+pub struct Threshold {
+    pub expression: String,
+    metric: Metric,
+    operator: Operator,
+    // Latencies in milliseconds, rates as percentages
+    value: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThresholdResult {
+    pub threshold: String,
+    pub actual: f64,
+    pub passed: bool,
+}
+
+impl Threshold {
+    pub fn parse(expression: &str) -> Result<Self, BoxError> {
+        let compact: String = expression.chars().filter(|c| !c.is_whitespace()).collect();
+
+        // Two-character operators must be tried first
+        let (position, token, operator) = ["<=", ">=", "==", "<", ">"]
+            .iter()
+            .find_map(|token| compact.find(token).map(|position| (position, *token)))
+            .map(|(position, token)| {
+                let operator = match token {
+                    "<=" => Operator::Le,
+                    ">=" => Operator::Ge,
+                    "==" => Operator::Eq,
+                    "<" => Operator::Lt,
+                    _ => Operator::Gt,
+                };
+                (position, token, operator)
+            })
+            .ok_or_else(|| format!("threshold has no comparison operator: {}", expression))?;
+
+        let metric_name = &compact[..position];
+        let value_text = &compact[position + token.len()..];
+
+        let metric = match metric_name.to_lowercase().as_str() {
+            "min" => Metric::Min,
+            "max" => Metric::Max,
+            "avg" | "mean" => Metric::Avg,
+            "med" | "median" => Metric::Percentile(50),
+            "error_rate" | "errors" => Metric::ErrorRate,
+            "success_rate" => Metric::SuccessRate,
+            "rps" => Metric::Rps,
+            "requests" => Metric::Requests,
+            "p50" => Metric::Percentile(50),
+            "p90" => Metric::Percentile(90),
+            "p95" => Metric::Percentile(95),
+            "p99" => Metric::Percentile(99),
+            _ => return Err(format!("unknown threshold metric: {}", metric_name).into()),
+        };
+
+        let value = match metric {
+            Metric::Min | Metric::Max | Metric::Avg | Metric::Percentile(_) => {
+                parse_millis(value_text)?
+            }
+            Metric::ErrorRate | Metric::SuccessRate => parse_percentage(value_text)?,
+            Metric::Rps | Metric::Requests => value_text
+                .parse()
+                .map_err(|_| format!("invalid threshold value: {}", value_text))?,
+        };
+
+        Ok(Self {
+            expression: expression.to_string(),
+            metric,
+            operator,
+            value,
+        })
+    }
+
+    pub fn evaluate(&self, results: &TestResults) -> ThresholdResult {
+        self.evaluate_measurements(&Measurements::from_results(results))
+    }
+
+    pub fn evaluate_measurements(&self, measurements: &Measurements) -> ThresholdResult {
+        let actual = match self.metric {
+            Metric::Min => measurements.min_ms,
+            Metric::Max => measurements.max_ms,
+            Metric::Avg => measurements.avg_ms,
+            Metric::Percentile(50) => measurements.p50_ms,
+            Metric::Percentile(90) => measurements.p90_ms,
+            Metric::Percentile(95) => measurements.p95_ms,
+            Metric::Percentile(_) => measurements.p99_ms,
+            Metric::ErrorRate => measurements.error_rate,
+            Metric::SuccessRate => 100.0 - measurements.error_rate,
+            Metric::Rps => measurements.rps,
+            Metric::Requests => measurements.requests as f64,
+        };
+
+        ThresholdResult {
+            threshold: self.expression.clone(),
+            actual,
+            passed: self.compare(actual),
+        }
+    }
+
+    // The metric over one second of the time series, for the metrics it samples
+    pub fn point_value(&self, point: &TimeseriesPoint) -> Option<f64> {
+        match self.metric {
+            Metric::ErrorRate => Some(point.error_rate * 100.0),
+            Metric::SuccessRate => Some(100.0 - point.error_rate * 100.0),
+            Metric::Percentile(95) => Some(point.p95_response_time_ms),
+            Metric::Rps => Some(point.rps),
+            Metric::Requests => Some(point.requests as f64),
+            _ => None,
+        }
+    }
+
+    // Whether the results so far say anything during a run; rps and requests grow with its
+    // length, so only the final results judge them
+    pub fn checked_during_run(&self) -> bool {
+        !matches!(self.metric, Metric::Rps | Metric::Requests)
+    }
+
+    // Whether `actual` meets the threshold
+    pub fn compare(&self, actual: f64) -> bool {
+        match self.operator {
+            Operator::Lt => actual < self.value,
+            Operator::Le => actual <= self.value,
+            Operator::Gt => actual > self.value,
+            Operator::Ge => actual >= self.value,
+            Operator::Eq => (actual - self.value).abs() < f64::EPSILON,
+        }
+    }
+}
+
+// What thresholds are evaluated against: a whole run, or part of one
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Measurements {
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub avg_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    // Percent of failed requests
+    pub error_rate: f64,
+    pub rps: f64,
+    pub requests: usize,
+}
+
+impl Measurements {
+    pub fn from_results(results: &TestResults) -> Self {
+        Self {
+            min_ms: results.min_response_time_ms,
+            max_ms: results.max_response_time_ms,
+            avg_ms: results.avg_response_time_ms,
+            p50_ms: results.p50_response_time_ms,
+            p90_ms: results.p90_response_time_ms,
+            p95_ms: results.p95_response_time_ms,
+            p99_ms: results.p99_response_time_ms,
+            error_rate: results.failed_requests as f64 / results.total_requests.max(1) as f64
+                * 100.0,
+            rps: results.requests_per_second,
+            requests: results.total_requests,
+        }
+    }
+
+    // From the response times (in microseconds) and counts of an interval of `seconds`
+    pub fn from_histogram(
+        histogram: &Histogram<u64>,
+        requests: usize,
+        failed: usize,
+        seconds: f64,
+    ) -> Self {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        let percentile = |percentile: f64| ms(histogram.value_at_percentile(percentile));
+        if histogram.is_empty() {
+            return Self {
+                error_rate: failed as f64 / requests.max(1) as f64 * 100.0,
+                requests,
+                ..Self::default()
+            };
+        }
+        Self {
+            min_ms: ms(histogram.min()),
+            max_ms: ms(histogram.max()),
+            avg_ms: histogram.mean() / 1000.0,
+            p50_ms: percentile(50.0),
+            p90_ms: percentile(90.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            error_rate: failed as f64 / requests.max(1) as f64 * 100.0,
+            rps: if seconds > 0.0 {
+                requests as f64 / seconds
+            } else {
+                0.0
+            },
+            requests,
+        }
+    }
+}
+
+// "250ms", "1.5s", "800us" or a bare number of milliseconds
+pub(crate) fn parse_millis(text: &str) -> Result<f64, BoxError> {
+    let (number, scale) = if let Some(n) = text.strip_suffix("ms") {
+        (n, 1.0)
+    } else if let Some(n) = text.strip_suffix("us") {
+        (n, 0.001)
+    } else if let Some(n) = text.strip_suffix('s') {
+        (n, 1000.0)
+    } else {
+        (text, 1.0)
+    };
+    number
+        .parse::<f64>()
+        .map(|n| n * scale)
+        .map_err(|_| format!("invalid duration in threshold: {}", text).into())
+}
+
+// "1%" or a fraction such as "0.01"
+fn parse_percentage(text: &str) -> Result<f64, BoxError> {
+    match text.strip_suffix('%') {
+        Some(number) => number.parse::<f64>().map_err(|_| ()),
+        None => text.parse::<f64>().map(|n| n * 100.0).map_err(|_| ()),
+    }
+    .map_err(|_| format!("invalid rate in threshold: {}", text).into())
+}
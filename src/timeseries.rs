@@ -0,0 +1,175 @@
+//! Per-second time series of the test, included in the JSON output for charting
+//
+// With --heatmap, each interval also gets a row of the --percentiles of its own response times,
+// computed from the difference of consecutive samples of the cumulative histogram, for
+// latency heatmaps that show degradation a single end-of-run percentile hides.
+
+use crate::stats::Stats;
+use crate::{percentile_values, PercentileValue};
+use hdrhistogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::time::Instant;
+
+// One interval of the test, normally a second; the last one may be shorter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeseriesPoint {
+    // Seconds since the start of the test at the end of the interval
+    pub elapsed_seconds: f64,
+    pub requests: usize,
+    pub rps: f64,
+    pub error_rate: f64,
+    pub p95_response_time_ms: f64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub active_vus: usize,
+}
+
+// Response times of one interval, for --heatmap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeatmapRow {
+    pub elapsed_seconds: f64,
+    pub requests: u64,
+    pub percentiles: Vec<PercentileValue>,
+    pub max_ms: f64,
+}
+
+// Turns the cumulative statistics into per-interval points by diffing consecutive samples
+pub struct Timeseries {
+    start: Instant,
+    last_sample: Instant,
+    total_requests: usize,
+    successful_requests: usize,
+    bytes_sent: u64,
+    bytes_received: u64,
+    histogram: Histogram<u64>,
+    points: Vec<TimeseriesPoint>,
+    // The percentiles of the heatmap rows, once enabled
+    heatmap_percentiles: Option<Vec<f64>>,
+    heatmap: Vec<HeatmapRow>,
+}
+
+impl Timeseries {
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            last_sample: start,
+            total_requests: 0,
+            successful_requests: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            histogram: Histogram::<u64>::new(3).unwrap(),
+            points: Vec::new(),
+            heatmap_percentiles: None,
+            heatmap: Vec::new(),
+        }
+    }
+
+    // Continue the time series of a checkpoint; `start` is as far back as it had run
+    pub fn resume(start: Instant, points: Vec<TimeseriesPoint>, stats: &Stats) -> Self {
+        Self {
+            start,
+            last_sample: Instant::now(),
+            total_requests: stats.total_requests(),
+            successful_requests: stats.successful_requests(),
+            bytes_sent: stats.bytes_sent(),
+            bytes_received: stats.bytes_received(),
+            histogram: stats.histogram(),
+            points,
+            heatmap_percentiles: None,
+            heatmap: Vec::new(),
+        }
+    }
+
+    pub fn enable_heatmap(&mut self, percentiles: &[f64]) {
+        self.heatmap_percentiles = Some(percentiles.to_vec());
+    }
+
+    pub fn sample(&mut self, stats: &Stats, active_vus: usize) {
+        let now = Instant::now();
+        let interval_seconds = now.duration_since(self.last_sample).as_secs_f64();
+        if interval_seconds <= 0.0 {
+            return;
+        }
+
+        let total_requests = stats.total_requests();
+        let successful_requests = stats.successful_requests();
+        let bytes_sent = stats.bytes_sent();
+        let bytes_received = stats.bytes_received();
+        let histogram = stats.histogram();
+
+        let requests = total_requests - self.total_requests;
+        let failed = requests - (successful_requests - self.successful_requests);
+        // Only this interval's response times count towards its p95
+        let mut interval_histogram = histogram.clone();
+        interval_histogram.subtract(&self.histogram).ok();
+        let p95_response_time_ms = if interval_histogram.is_empty() {
+            0.0
+        } else {
+            interval_histogram.value_at_percentile(95.0) as f64 / 1000.0
+        };
+
+        let elapsed_seconds = now.duration_since(self.start).as_secs_f64();
+        if let Some(percentiles) = &self.heatmap_percentiles {
+            self.heatmap.push(HeatmapRow {
+                elapsed_seconds,
+                requests: interval_histogram.len(),
+                percentiles: percentile_values(&interval_histogram, percentiles),
+                max_ms: interval_histogram.max() as f64 / 1000.0,
+            });
+        }
+        self.points.push(TimeseriesPoint {
+            elapsed_seconds,
+            requests,
+            rps: requests as f64 / interval_seconds,
+            error_rate: if requests > 0 {
+                failed as f64 / requests as f64
+            } else {
+                0.0
+            },
+            p95_response_time_ms,
+            bytes_sent: bytes_sent - self.bytes_sent,
+            bytes_received: bytes_received - self.bytes_received,
+            active_vus,
+        });
+
+        self.last_sample = now;
+        self.total_requests = total_requests;
+        self.successful_requests = successful_requests;
+        self.bytes_sent = bytes_sent;
+        self.bytes_received = bytes_received;
+        self.histogram = histogram;
+    }
+
+    // Close the last, usually partial, interval if requests finished in it
+    pub fn finish(&mut self, stats: &Stats, active_vus: usize) {
+        if stats.total_requests() > self.total_requests {
+            self.sample(stats, active_vus);
+        }
+    }
+
+    pub fn points(&self) -> &[TimeseriesPoint] {
+        &self.points
+    }
+
+    pub fn heatmap(&self) -> &[HeatmapRow] {
+        &self.heatmap
+    }
+}
+
+// One line per interval: "elapsed_seconds,requests,p50_ms,...,max_ms"
+pub fn heatmap_csv(rows: &[HeatmapRow]) -> String {
+    let mut csv = String::from("elapsed_seconds,requests");
+    for value in rows.first().map_or(&[][..], |row| &row.percentiles) {
+        let _ = write!(csv, ",p{}_ms", value.percentile);
+    }
+    csv.push_str(",max_ms\n");
+    for row in rows {
+        let _ = write!(csv, "{:.3},{}", row.elapsed_seconds, row.requests);
+        for value in &row.percentiles {
+            let _ = write!(csv, ",{:.3}", value.ms);
+        }
+        let _ = writeln!(csv, ",{:.3}", row.max_ms);
+    }
+    csv
+}
@@ -0,0 +1,166 @@
+//! Per-phase request timings: DNS lookup, TCP connect, TLS handshake, time to first byte
+//! and body download
+//
+// The connection phases happen inside the connector of the transport (transport.rs), which
+// reports them into a task-local slot scoped around each request. Only requests that opened
+// a new connection have them; a connection that loses the race against a pooled one finishes
+// in the background, outside of any slot. Proxies add to the phases: the lookup of the proxy
+// is DNS, the connection to it and the tunnel through it are connect, and a TLS handshake
+// with an HTTPS proxy is TLS.
+
+use crate::dns::Resolver;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static CONNECTION_PHASES: Cell<ConnectionPhases>;
+}
+
+// Phases as used for the `phase` label and in reports
+pub const PHASES: [&str; 5] = ["dns", "connect", "tls", "ttfb", "download"];
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PhaseTimings {
+    // Only set for requests that opened a connection to a host name
+    pub dns_ms: Option<f64>,
+    // Only set for requests that opened a connection
+    pub connect_ms: Option<f64>,
+    // Only set for requests that opened an HTTPS connection (or one to an HTTPS proxy)
+    pub tls_ms: Option<f64>,
+    // From sending the request until the response headers arrived, excluding the phases of
+    // opening a connection
+    pub ttfb_ms: f64,
+    pub download_ms: f64,
+}
+
+impl PhaseTimings {
+    // The phases of a request whose headers arrived `duration_ms` after it was started
+    pub fn new(duration_ms: f64, connection: ConnectionPhases, download_ms: f64) -> Self {
+        let ms = |duration: Option<Duration>| duration.map(|d| d.as_secs_f64() * 1000.0);
+        let (dns_ms, connect_ms, tls_ms) = (
+            ms(connection.dns),
+            ms(connection.connect),
+            ms(connection.tls),
+        );
+        Self {
+            dns_ms,
+            connect_ms,
+            tls_ms,
+            ttfb_ms: (duration_ms
+                - dns_ms.unwrap_or(0.0)
+                - connect_ms.unwrap_or(0.0)
+                - tls_ms.unwrap_or(0.0))
+            .max(0.0),
+            download_ms,
+        }
+    }
+
+    pub fn phases(&self) -> impl Iterator<Item = (&'static str, f64)> {
+        [
+            self.dns_ms.map(|ms| ("dns", ms)),
+            self.connect_ms.map(|ms| ("connect", ms)),
+            self.tls_ms.map(|ms| ("tls", ms)),
+            Some(("ttfb", self.ttfb_ms)),
+            Some(("download", self.download_ms)),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+// Phases of opening a connection, which requests that got no response can have too
+pub fn is_connection_phase(phase: &str) -> bool {
+    matches!(phase, "dns" | "connect" | "tls")
+}
+
+// Time a request spent opening its connection, by phase
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionPhases {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub tls: Option<Duration>,
+}
+
+pub fn record_dns(duration: Duration) {
+    record(duration, |phases| &mut phases.dns);
+}
+
+pub fn record_connect(duration: Duration) {
+    record(duration, |phases| &mut phases.connect);
+}
+
+pub fn record_tls(duration: Duration) {
+    record(duration, |phases| &mut phases.tls);
+}
+
+// A phase can happen more than once, e.g. connecting to a proxy and then through it
+fn record(duration: Duration, phase: fn(&mut ConnectionPhases) -> &mut Option<Duration>) {
+    CONNECTION_PHASES
+        .try_with(|slot| {
+            let mut phases = slot.get();
+            let total = phase(&mut phases);
+            *total = Some(total.unwrap_or_default() + duration);
+            slot.set(phases);
+        })
+        .ok();
+}
+
+// Addresses of `host`: the --resolve override for the port, or a lookup, which is timed
+pub async fn resolve(resolver: &Resolver, host: &str, port: u16) -> io::Result<Vec<IpAddr>> {
+    if let Some(ips) = resolver.override_for_port(host, port) {
+        return Ok(ips);
+    }
+    let start = Instant::now();
+    let ips = resolver.lookup(host).await?;
+    record_dns(start.elapsed());
+    Ok(ips)
+}
+
+// Resolves with the system resolver (or --dns-server) and records how long it took for the
+// current request; --resolve overrides are not lookups and are not timed
+pub struct TimedResolver {
+    resolver: Arc<Resolver>,
+}
+
+impl TimedResolver {
+    pub fn new(resolver: Arc<Resolver>) -> Self {
+        Self { resolver }
+    }
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.resolver.clone();
+        Box::pin(async move {
+            // Ports are set by the connector
+            let to_addrs = |ips: Vec<IpAddr>| -> Addrs {
+                Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)))
+            };
+            if let Some(ips) = resolver.override_for(name.as_str()) {
+                return Ok(to_addrs(ips));
+            }
+            let start = Instant::now();
+            let ips = resolver.lookup(name.as_str()).await?;
+            record_dns(start.elapsed());
+            Ok(to_addrs(ips))
+        })
+    }
+}
+
+// Run a request future and return its output with the time it spent opening a connection
+pub async fn with_connection_timing<F: std::future::Future>(
+    future: F,
+) -> (F::Output, ConnectionPhases) {
+    CONNECTION_PHASES
+        .scope(Cell::new(ConnectionPhases::default()), async move {
+            let output = future.await;
+            (output, CONNECTION_PHASES.with(Cell::get))
+        })
+        .await
+}
@@ -0,0 +1,167 @@
+//! W3C trace-context propagation, client spans and exemplars for sampled requests
+//
+// Every request gets a fresh trace whose root is the load generator's client span, so the
+// server's spans show up as its children. Sampled spans are buffered until the next OTLP
+// export, and the latest sampled request per histogram bucket is kept as an exemplar.
+
+use crate::errors::ErrorType;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::Rng;
+use std::collections::HashMap;
+
+// Spans beyond this many between two exports are dropped
+const MAX_BUFFERED_SPANS: usize = 10_000;
+
+lazy_static! {
+    static ref SPANS: Mutex<Vec<ClientSpan>> = Mutex::new(Vec::new());
+    static ref EXEMPLARS: Mutex<HashMap<ExemplarKey, Exemplar>> = Mutex::new(HashMap::new());
+}
+
+// Metric name, label values ordered by label name, and the bucket's upper bound bits
+type ExemplarKey = (String, Vec<String>, u64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    pub fn new(rng: &mut impl Rng, sample_rate: f64) -> Self {
+        // All-zero IDs are invalid, so force a bit on
+        let mut trace_id: [u8; 16] = rng.gen();
+        let mut span_id: [u8; 8] = rng.gen();
+        trace_id[15] |= 1;
+        span_id[7] |= 1;
+        Self {
+            trace_id,
+            span_id,
+            sampled: rng.gen_bool(sample_rate.clamp(0.0, 1.0)),
+        }
+    }
+
+    // Value of the traceparent header: version, trace ID, parent (our span) ID and flags
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+}
+
+// Settings for --trace-propagation
+#[derive(Debug, Clone, Copy)]
+pub struct Tracer {
+    pub sample_rate: f64,
+    // Spans are only buffered when an OTLP endpoint will export them
+    pub export_spans: bool,
+}
+
+impl Tracer {
+    pub fn start(&self, rng: &mut impl Rng) -> TraceContext {
+        TraceContext::new(rng, self.sample_rate)
+    }
+
+    pub fn finish(&self, span: ClientSpan) {
+        if !self.export_spans || !span.context.sampled {
+            return;
+        }
+        let mut spans = SPANS.lock();
+        if spans.len() < MAX_BUFFERED_SPANS {
+            spans.push(span);
+        }
+    }
+}
+
+// A finished request as seen from the client
+#[derive(Debug, Clone)]
+pub struct ClientSpan {
+    pub context: TraceContext,
+    pub method: String,
+    pub url: String,
+    pub target: String,
+    // 0 when no response was received
+    pub status_code: u16,
+    pub error_type: Option<ErrorType>,
+    pub start_time_unix_nano: u64,
+    pub end_time_unix_nano: u64,
+}
+
+// Spans finished since the last call
+pub fn take_spans() -> Vec<ClientSpan> {
+    std::mem::take(&mut *SPANS.lock())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Exemplar {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub value: f64,
+    pub timestamp_millis: i64,
+}
+
+impl Exemplar {
+    pub fn trace_id_hex(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    pub fn span_id_hex(&self) -> String {
+        hex(&self.span_id)
+    }
+}
+
+// Remember a sampled observation of a histogram as the exemplar of the bucket it falls into;
+// `label_values` must be ordered by label name, as in gathered metrics
+pub fn record_exemplar(
+    metric_name: &str,
+    label_values: &[&str],
+    buckets: &[f64],
+    value: f64,
+    context: &TraceContext,
+) {
+    let upper_bound = buckets
+        .iter()
+        .copied()
+        .find(|bound| value <= *bound)
+        .unwrap_or(f64::INFINITY);
+    let key = (
+        metric_name.to_string(),
+        label_values.iter().map(|v| v.to_string()).collect(),
+        upper_bound.to_bits(),
+    );
+    EXEMPLARS.lock().insert(
+        key,
+        Exemplar {
+            trace_id: context.trace_id,
+            span_id: context.span_id,
+            value,
+            timestamp_millis: chrono::Utc::now().timestamp_millis(),
+        },
+    );
+}
+
+// Exemplar of one bucket of a gathered histogram series, if a sampled request landed in it
+pub fn exemplar(
+    metric_name: &str,
+    metric: &prometheus::proto::Metric,
+    upper_bound: f64,
+) -> Option<Exemplar> {
+    let key = (
+        metric_name.to_string(),
+        metric
+            .get_label()
+            .iter()
+            .map(|pair| pair.get_value().to_string())
+            .collect(),
+        upper_bound.to_bits(),
+    );
+    EXEMPLARS.lock().get(&key).copied()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
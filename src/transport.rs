@@ -0,0 +1,695 @@
+//! The transport load-test requests are sent with: hyper's client over a connector that times
+//! how each connection was opened
+//
+// reqwest's connector cannot be replaced and does not report its phases, so requests are still
+// built with reqwest but sent by a hyper client whose connector resolves the host, connects
+// and does the TLS handshake itself, reporting each phase to timing.rs. What reqwest's client
+// did around the connection is done here the same way: the `Accept: */*` default, the
+// Proxy-Authorization of requests forwarded to a proxy, the request timeout, and following up
+// to 10 redirects. The timeout bounds the wait for the response headers; bodies are read
+// under the same deadline by their readers. --connect-timeout bounds the whole connector,
+// TLS handshake and proxy tunnel included.
+
+use crate::dns::Resolver;
+use crate::errors::ErrorType;
+use crate::proxy::{self, Address, Proxies, ProxyScheme};
+use crate::timing;
+use hyper::body::Bytes;
+use hyper::client::connect::{Connected, Connection as HyperConnection};
+use hyper::service::Service;
+use hyper::Uri;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+    COOKIE, LOCATION, PROXY_AUTHORIZATION, REFERER, TRANSFER_ENCODING, WWW_AUTHENTICATE,
+};
+use reqwest::{Method, Request, Response, ResponseBuilderExt, StatusCode, Url};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// As reqwest's default redirect policy
+const MAX_REDIRECTS: usize = 10;
+
+// TLS settings of the connections: --cacert on top of the webpki roots, the --cert/--key
+// identity, and --insecure
+#[derive(Clone)]
+pub struct TlsOptions {
+    pub roots: RootCertStore,
+    pub identity: Option<(Vec<Certificate>, PrivateKey)>,
+    pub insecure: bool,
+}
+
+impl TlsOptions {
+    // A client configuration offering the `alpn` protocols
+    pub fn config(&self, alpn: &[&[u8]]) -> Result<Arc<ClientConfig>, BoxError> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.roots.clone());
+        let mut config = match &self.identity {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs.clone(), key.clone())
+                .map_err(|e| format!("invalid client certificate or key: {}", e))?,
+            None => builder.with_no_client_auth(),
+        };
+        if self.insecure {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerifier));
+        }
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(Arc::new(config))
+    }
+}
+
+// --insecure: any certificate is accepted
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// Why a connection could not be opened, classified where the failing phase is known
+#[derive(Debug)]
+pub struct ConnectError {
+    kind: ErrorType,
+    message: String,
+}
+
+impl ConnectError {
+    fn new(kind: ErrorType, message: impl fmt::Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn error_type(&self) -> ErrorType {
+        self.kind
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+// A request that got no response, with the error type it is counted as
+#[derive(Debug)]
+pub struct SendError {
+    kind: ErrorType,
+    source: BoxError,
+}
+
+impl SendError {
+    fn new(kind: ErrorType, source: impl Into<BoxError>) -> Self {
+        Self {
+            kind,
+            source: source.into(),
+        }
+    }
+
+    pub fn error_type(&self) -> ErrorType {
+        self.kind
+    }
+}
+
+// A request that could not be built, or one sent by reqwest itself (HTTP/3)
+impl From<reqwest::Error> for SendError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::new(ErrorType::from_reqwest(&err), err)
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+// Opens connections for the hyper client, directly or through a proxy, timing each phase
+#[derive(Clone)]
+pub struct TimedConnector {
+    inner: Arc<Connector>,
+}
+
+struct Connector {
+    resolver: Arc<Resolver>,
+    tls: TlsConnector,
+    // For HTTPS proxies, which are spoken to in HTTP/1.1
+    proxy_tls: TlsConnector,
+    proxies: Arc<Proxies>,
+    connect_timeout: Option<Duration>,
+    local_address: Option<IpAddr>,
+}
+
+impl TimedConnector {
+    pub fn new(
+        resolver: Arc<Resolver>,
+        tls: Arc<ClientConfig>,
+        proxy_tls: Arc<ClientConfig>,
+        proxies: Arc<Proxies>,
+        connect_timeout: Option<Duration>,
+        local_address: Option<IpAddr>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Connector {
+                resolver,
+                tls: TlsConnector::from(tls),
+                proxy_tls: TlsConnector::from(proxy_tls),
+                proxies,
+                connect_timeout,
+                local_address,
+            }),
+        }
+    }
+}
+
+impl Service<Uri> for TimedConnector {
+    type Response = Connection;
+    type Error = ConnectError;
+    type Future = Pin<Box<dyn Future<Output = Result<Connection, ConnectError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ConnectError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.inner.clone();
+        Box::pin(async move {
+            let connect = connector.connect(uri);
+            match connector.connect_timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, connect).await {
+                    Ok(result) => result,
+                    Err(_) => Err(ConnectError::new(
+                        ErrorType::ConnectTimeout,
+                        format!(
+                            "no connection within {}",
+                            humantime::format_duration(timeout)
+                        ),
+                    )),
+                },
+                None => connect.await,
+            }
+        })
+    }
+}
+
+impl Connector {
+    async fn connect(&self, uri: Uri) -> Result<Connection, ConnectError> {
+        let https = uri.scheme_str() == Some("https");
+        let host = uri
+            .host()
+            .ok_or_else(|| ConnectError::new(ErrorType::Other, "URL has no host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let Some(proxy) = self.proxies.route(https, host) else {
+            let tcp = self.tcp(host, port).await?;
+            let io = match https {
+                true => Io::Tls(Box::new(handshake(&self.tls, tcp, host).await?)),
+                false => Io::Tcp(tcp),
+            };
+            return Ok(Connection { io, proxied: false });
+        };
+
+        let address = match (host.parse::<IpAddr>(), proxy.scheme) {
+            (Ok(ip), _) => Address::Ip(ip),
+            // A SOCKS5 proxy takes a single address, so the first one
+            (Err(_), ProxyScheme::Socks5) => Address::Ip(self.resolve(host, port).await?[0]),
+            (Err(_), _) => Address::Domain(host),
+        };
+        let tcp = self.tcp(&proxy.host, proxy.port).await?;
+        let io = match (proxy.scheme, https) {
+            (ProxyScheme::Http, false) => Io::Tcp(tcp),
+            (ProxyScheme::Http, true) => {
+                let mut tcp = tcp;
+                tunnel(&mut tcp, proxy, &address, port).await?;
+                Io::Tls(Box::new(handshake(&self.tls, tcp, host).await?))
+            }
+            (ProxyScheme::Https, false) => Io::Tls(Box::new(
+                handshake(&self.proxy_tls, tcp, &proxy.host).await?,
+            )),
+            (ProxyScheme::Https, true) => {
+                let mut tls = handshake(&self.proxy_tls, tcp, &proxy.host).await?;
+                tunnel(&mut tls, proxy, &address, port).await?;
+                Io::TlsInTls(Box::new(handshake(&self.tls, tls, host).await?))
+            }
+            (ProxyScheme::Socks5 | ProxyScheme::Socks5h, _) => {
+                let mut tcp = tcp;
+                tunnel(&mut tcp, proxy, &address, port).await?;
+                match https {
+                    true => Io::Tls(Box::new(handshake(&self.tls, tcp, host).await?)),
+                    false => Io::Tcp(tcp),
+                }
+            }
+        };
+        // Plain HTTP requests are forwarded to an HTTP(S) proxy rather than tunneled
+        Ok(Connection {
+            io,
+            proxied: !https && proxy.scheme.forwards_http(),
+        })
+    }
+
+    // The addresses of `host`, of which there is at least one
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<IpAddr>, ConnectError> {
+        match timing::resolve(&self.resolver, host, port).await {
+            Ok(ips) if !ips.is_empty() => Ok(ips),
+            Ok(_) => Err(ConnectError::new(
+                ErrorType::Dns,
+                format!("no addresses for {}", host),
+            )),
+            Err(e) => Err(ConnectError::new(
+                ErrorType::Dns,
+                format!("failed to resolve {}: {}", host, e),
+            )),
+        }
+    }
+
+    // A connection to the first address of `host` that accepts one
+    async fn tcp(&self, host: &str, port: u16) -> Result<TcpStream, ConnectError> {
+        let ips = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![ip],
+            Err(_) => self.resolve(host, port).await?,
+        };
+
+        let start = Instant::now();
+        let mut error = None;
+        for ip in ips {
+            match self.connect_to(SocketAddr::new(ip, port)).await {
+                Ok(stream) => {
+                    timing::record_connect(start.elapsed());
+                    stream.set_nodelay(true).ok();
+                    return Ok(stream);
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(match error {
+            Some(e) => ConnectError::new(
+                ErrorType::from_io(&e),
+                format!("failed to connect to {}:{}: {}", host, port, e),
+            ),
+            None => ConnectError::new(ErrorType::Dns, format!("no addresses for {}", host)),
+        })
+    }
+
+    // Bound to the local address when it is of the same family
+    async fn connect_to(&self, addr: SocketAddr) -> io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(local) = self
+            .local_address
+            .filter(|local| local.is_ipv4() == addr.is_ipv4())
+        {
+            socket.bind(SocketAddr::new(local, 0))?;
+        }
+        socket.connect(addr).await
+    }
+}
+
+// A tunnel through the proxy counts as connecting
+async fn tunnel<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &proxy::Proxy,
+    address: &Address<'_>,
+    port: u16,
+) -> Result<(), ConnectError> {
+    let start = Instant::now();
+    proxy::tunnel(stream, proxy, address, port)
+        .await
+        .map_err(|e| {
+            ConnectError::new(
+                ErrorType::from_io(&e),
+                format!(
+                    "tunnel through proxy {}:{} failed: {}",
+                    proxy.host, proxy.port, e
+                ),
+            )
+        })?;
+    timing::record_connect(start.elapsed());
+    Ok(())
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    tls: &TlsConnector,
+    stream: S,
+    host: &str,
+) -> Result<TlsStream<S>, ConnectError> {
+    let name = ServerName::try_from(host).map_err(|_| {
+        ConnectError::new(ErrorType::Tls, format!("invalid TLS server name {}", host))
+    })?;
+    let start = Instant::now();
+    let stream = tls.connect(name, stream).await.map_err(|e| {
+        // Errors of the protocol itself, rather than of the connection under it
+        let kind = match e.get_ref().is_some_and(|inner| inner.is::<rustls::Error>()) {
+            true => ErrorType::Tls,
+            false => ErrorType::from_io(&e),
+        };
+        ConnectError::new(kind, format!("TLS handshake with {} failed: {}", host, e))
+    })?;
+    timing::record_tls(start.elapsed());
+    Ok(stream)
+}
+
+// An open connection; the TCP stream under any TLS layers gives hyper its addresses
+pub struct Connection {
+    io: Io,
+    proxied: bool,
+}
+
+enum Io {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    // HTTPS through an HTTPS proxy
+    TlsInTls(Box<TlsStream<TlsStream<TcpStream>>>),
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+impl Connection {
+    fn stream(&mut self) -> Pin<&mut dyn Stream> {
+        match &mut self.io {
+            Io::Tcp(stream) => Pin::new(stream),
+            Io::Tls(stream) => Pin::new(&mut **stream),
+            Io::TlsInTls(stream) => Pin::new(&mut **stream),
+        }
+    }
+}
+
+impl HyperConnection for Connection {
+    fn connected(&self) -> Connected {
+        let (tcp, alpn) = match &self.io {
+            Io::Tcp(tcp) => (tcp, None),
+            Io::Tls(tls) => {
+                let (tcp, session) = tls.get_ref();
+                (tcp, session.alpn_protocol())
+            }
+            Io::TlsInTls(tls) => {
+                let (inner, session) = tls.get_ref();
+                (inner.get_ref().0, session.alpn_protocol())
+            }
+        };
+        let connected = tcp.connected().proxy(self.proxied);
+        match alpn {
+            Some(b"h2") => connected.negotiated_h2(),
+            _ => connected,
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().stream().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().stream().poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().stream().poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match &self.io {
+            Io::Tcp(stream) => stream.is_write_vectored(),
+            Io::Tls(stream) => stream.is_write_vectored(),
+            Io::TlsInTls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().stream().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().stream().poll_shutdown(cx)
+    }
+}
+
+// Sends requests over pooled connections of the timed connector
+#[derive(Clone)]
+pub struct Transport {
+    client: hyper::Client<TimedConnector, hyper::Body>,
+    proxies: Arc<Proxies>,
+    timeout: Duration,
+}
+
+impl Transport {
+    // Keeping up to `pool_size` idle connections per host; `timeout` applies to requests
+    // without one of their own
+    pub fn new(
+        connector: TimedConnector,
+        pool_size: usize,
+        http2_only: bool,
+        timeout: Duration,
+    ) -> Self {
+        let proxies = connector.inner.proxies.clone();
+        let client = hyper::Client::builder()
+            .pool_max_idle_per_host(pool_size)
+            .http2_only(http2_only)
+            .build(connector);
+        Self {
+            client,
+            proxies,
+            timeout,
+        }
+    }
+
+    // Send `request`, with `stream` as its body in place of the request's own when given
+    pub async fn send(
+        &self,
+        request: Request,
+        stream: Option<hyper::Body>,
+    ) -> Result<Response, SendError> {
+        let timeout = request.timeout().copied().unwrap_or(self.timeout);
+        match tokio::time::timeout(timeout, self.follow(request, stream)).await {
+            Ok(result) => result,
+            Err(_) => Err(SendError::new(
+                ErrorType::Timeout,
+                format!("no response within {}", humantime::format_duration(timeout)),
+            )),
+        }
+    }
+
+    // Redirects are followed as reqwest does, except that a streamed body cannot be sent again
+    // for a 307 or 308
+    async fn follow(
+        &self,
+        request: Request,
+        mut stream: Option<hyper::Body>,
+    ) -> Result<Response, SendError> {
+        let body = match request.body() {
+            Some(body) if stream.is_none() => Some(Bytes::copy_from_slice(
+                body.as_bytes()
+                    .ok_or("a streamed body must be given to the transport")
+                    .map_err(|e| SendError::new(ErrorType::Other, e))?,
+            )),
+            _ => None,
+        };
+        let streamed = stream.is_some();
+        let version = request.version();
+        let mut method = request.method().clone();
+        let mut url = request.url().clone();
+        let mut headers = request.headers().clone();
+        let mut body = body;
+        headers
+            .entry(ACCEPT)
+            .or_insert_with(|| HeaderValue::from_static("*/*"));
+
+        let mut redirects = 0;
+        loop {
+            let mut sent = hyper::Request::new(match stream.take() {
+                Some(stream) => stream,
+                None => body
+                    .clone()
+                    .map_or_else(hyper::Body::empty, hyper::Body::from),
+            });
+            *sent.method_mut() = method.clone();
+            *sent.uri_mut() = uri(&url)?;
+            *sent.version_mut() = version;
+            *sent.headers_mut() = headers.clone();
+            self.authorize_proxy(&url, sent.headers_mut());
+
+            let response = self
+                .client
+                .request(sent)
+                .await
+                .map_err(|e| SendError::new(ErrorType::from_hyper(&e), e))?;
+
+            let status = response.status();
+            let next = match status {
+                StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT => response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok())
+                    .filter(|next| uri(next).is_ok()),
+                _ => None,
+            };
+            let Some(next) = next else {
+                return into_response(response, url);
+            };
+            match status {
+                StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT if streamed => {
+                    return into_response(response, url);
+                }
+                StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT => {}
+                // The body is dropped, and the method becomes GET
+                _ => {
+                    body = None;
+                    for name in [
+                        TRANSFER_ENCODING,
+                        CONTENT_ENCODING,
+                        CONTENT_TYPE,
+                        CONTENT_LENGTH,
+                    ] {
+                        headers.remove(name);
+                    }
+                    if method != Method::GET && method != Method::HEAD {
+                        method = Method::GET;
+                    }
+                }
+            }
+
+            if !matches!(next.scheme(), "http" | "https") {
+                return Err(SendError::new(
+                    ErrorType::Other,
+                    format!("redirect to {} has an unsupported scheme", next),
+                ));
+            }
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(SendError::new(
+                    ErrorType::Other,
+                    format!("too many redirects, the last to {}", next),
+                ));
+            }
+            // Credentials stay with the host they were meant for
+            if next.host_str() != url.host_str()
+                || next.port_or_known_default() != url.port_or_known_default()
+            {
+                for name in [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, WWW_AUTHENTICATE] {
+                    headers.remove(name);
+                }
+                headers.remove("cookie2");
+            }
+            if let Some(referer) = referer(&next, &url) {
+                headers.insert(REFERER, referer);
+            }
+            url = next;
+        }
+    }
+
+    // Requests forwarded to a proxy carry its credentials; tunneled ones sent them already
+    fn authorize_proxy(&self, url: &Url, headers: &mut HeaderMap) {
+        if url.scheme() != "http" {
+            return;
+        }
+        // SOCKS5 proxies tunnel plain HTTP too, and authenticate in the handshake
+        let proxy = self
+            .proxies
+            .route(false, url.host_str().unwrap_or_default())
+            .filter(|proxy| proxy.scheme.forwards_http());
+        if let Some(authorization) = proxy.and_then(|proxy| proxy.authorization.as_ref()) {
+            headers
+                .entry(PROXY_AUTHORIZATION)
+                .or_insert_with(|| authorization.clone());
+        }
+    }
+}
+
+fn uri(url: &Url) -> Result<Uri, SendError> {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.as_str()
+        .parse::<Uri>()
+        .map_err(|e| SendError::new(ErrorType::Other, format!("invalid URL {}: {}", url, e)))
+}
+
+// The page redirected from, without credentials; none from HTTPS to HTTP
+fn referer(next: &Url, previous: &Url) -> Option<HeaderValue> {
+    if next.scheme() == "http" && previous.scheme() == "https" {
+        return None;
+    }
+    let mut referer = previous.clone();
+    let _ = referer.set_username("");
+    let _ = referer.set_password(None);
+    referer.set_fragment(None);
+    HeaderValue::from_str(referer.as_str()).ok()
+}
+
+// A reqwest response, for the code that reads responses; the connection's HttpInfo stays
+// in its extensions
+fn into_response(response: hyper::Response<hyper::Body>, url: Url) -> Result<Response, SendError> {
+    let (parts, body) = response.into_parts();
+    let mut builder = hyper::Response::builder()
+        .status(parts.status)
+        .version(parts.version)
+        .url(url);
+    if let Some(headers) = builder.headers_mut() {
+        *headers = parts.headers;
+    }
+    if let Some(extensions) = builder.extensions_mut() {
+        extensions.extend(parts.extensions);
+    }
+    builder
+        .body(body)
+        .map(Response::from)
+        .map_err(|e| SendError::new(ErrorType::Other, e))
+}
@@ -0,0 +1,48 @@
+//! Bytes on the wire: request and response header sizes for the data transfer metrics
+//
+// Headers are counted as their HTTP/1.1 text. HTTP/2 and HTTP/3 compress headers, so for
+// those protocols the counts are an upper bound. Response reason phrases are not exposed
+// by reqwest; the canonical phrase for the status code is counted instead.
+
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, HOST};
+use reqwest::{Request, Response};
+
+// "GET /path?query HTTP/1.1\r\n", the Host header the client adds, the request headers,
+// Content-Length for bodies and the blank line ending the head
+pub fn request_header_bytes(request: &Request) -> u64 {
+    let url = request.url();
+    let target_len = url.path().len() + url.query().map_or(0, |query| query.len() + 1);
+    let mut bytes = request.method().as_str().len() + 1 + target_len + " HTTP/1.1\r\n".len();
+
+    if !request.headers().contains_key(HOST) {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port().map_or(0, |port| port.to_string().len() + 1);
+        bytes += "host: \r\n".len() + host.len() + port;
+    }
+    if let Some(body_len) = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(<[u8]>::len)
+    {
+        if !request.headers().contains_key(CONTENT_LENGTH) {
+            bytes += "content-length: \r\n".len() + body_len.to_string().len();
+        }
+    }
+    (bytes + header_map_bytes(request.headers()) + 2) as u64
+}
+
+// "HTTP/1.1 200 OK\r\n", the response headers and the blank line ending the head
+pub fn response_header_bytes(response: &Response) -> u64 {
+    let status = response.status();
+    let reason = status.canonical_reason().unwrap_or_default();
+    let status_line = "HTTP/1.1 200 \r\n".len() + reason.len();
+    (status_line + header_map_bytes(response.headers()) + 2) as u64
+}
+
+// Each header as "name: value\r\n"
+fn header_map_bytes(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + 2 + value.len() + 2)
+        .sum()
+}